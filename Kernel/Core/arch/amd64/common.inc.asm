@@ -1,8 +1,10 @@
 
-%define MAX_CPUS	1
+%define MAX_CPUS	8
 %define KSTACK_BASE	0xFFFFA00000000000
 %define INITIAL_KSTACK_SIZE	16
 %define KERNEL_BASE	0xFFFFFFFF80000000
+; Physical address the AP startup trampoline is copied to (MUST match smp.rs)
+%define AP_TRAMPOLINE_BASE	0x8000
 
 ; Save a list of registers to the stack
 %macro SAVE 0-*
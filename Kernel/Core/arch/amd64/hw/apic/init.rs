@@ -23,8 +23,8 @@ struct MADT_DevHeader
 #[repr(C,packed)]
 pub struct MADT_LAPIC
 {
-	processor: u8,
-	apic_id: u8,
+	pub processor: u8,
+	pub apic_id: u8,
 	pub flags: u32,
 }
 #[repr(C,packed)]
@@ -11,6 +11,8 @@ module_define!{APIC, [ACPI], init}
 mod raw;
 mod init;
 
+pub use self::raw::IpiMode;
+
 pub type IRQHandler = fn(info: *const ());
 
 #[derive(Default)]
@@ -92,6 +94,50 @@ fn init()
 	unsafe { asm!("sti"); }
 }
 
+/// Returns the LAPIC IDs of all enabled processors listed in the MADT
+pub fn enumerate_cpus() -> Vec<u8>
+{
+	match ::arch::acpi::find::<init::ACPI_MADT>("APIC", 0)
+	{
+	None => Vec::new(),
+	Some(madt) => madt.data().records(madt.data_len()).filter_map(
+			|r| match r {
+				init::MADTDevRecord::DevLAPIC(a) if { a.flags } & 1 != 0 => Some(a.apic_id),
+				_ => None
+				}
+			).collect(),
+	}
+}
+
+/// Returns the LAPIC ID of the current CPU
+pub fn local_apic_id() -> u8
+{
+	get_lapic().get_id()
+}
+/// Initialise the LAPIC of an application processor (enables interrupts)
+pub fn init_ap()
+{
+	get_lapic().init();
+}
+/// Send an inter-processor interrupt
+pub fn send_ipi(apic_id: u8, mode: IpiMode)
+{
+	get_lapic().send_ipi(apic_id, mode);
+}
+/// Start or stop the current CPU's time-slice timer (one-shot, see `::arch::threads::set_slice_timer`)
+pub fn set_local_timer(enabled: bool)
+{
+	if s_lapic.ls_is_valid() {
+		get_lapic().set_timer(enabled);
+	}
+}
+/// Signal end-of-interrupt for an interrupt raised by the current CPU's LAPIC
+#[is_safe(irq)]
+pub fn eoi(isr: usize)
+{
+	get_lapic().eoi(isr);
+}
+
 fn get_ioapic(interrupt: usize) -> Option<(&'static raw::IOAPIC, usize)>
 {
 	match s_ioapics.iter().find( |a| a.contains(interrupt) )
@@ -6,6 +6,8 @@
 use prelude::*;
 
 static TIMER_VEC: u8 = 0x7E;
+/// Initial count for the time-slice timer (not calibrated, ~16ms under qemu)
+const TIMER_COUNT: u32 = 0x100000;
 
 pub struct LAPIC
 {
@@ -37,6 +39,18 @@ pub enum TriggerMode
 	EdgeLow,
 }
 
+/// Inter-processor interrupt delivery mode
+#[derive(Debug,Copy,Clone)]
+pub enum IpiMode
+{
+	/// Deliver the specified vector
+	Fixed(u8),
+	/// Reset the target into the wait-for-SIPI state
+	Init,
+	/// Start the target executing (in real mode) at `page * 0x1000`
+	Startup(u8),
+}
+
 #[allow(dead_code)]
 #[repr(u8)]
 #[derive(Copy,Clone)]
@@ -130,9 +144,10 @@ impl LAPIC
 		
 		//self.write_reg(ApicReg::SIR as usize, self.read_reg(ApicReg_SIR as usize) | (1 << 8));
 		self.write_reg(ApicReg::SIR, 0x7F | (1 << 8));	// Enable LAPIC (and set Spurious to 127)
-		self.write_reg(ApicReg::InitCount, 0x100000);
+		// Time-slice timer, one-shot and only armed by the scheduler while another thread is waiting for the CPU
 		self.write_reg(ApicReg::TmrDivide, 3);	// Timer Divide = 16
-		self.write_reg(ApicReg::LVTTimer, TIMER_VEC as u32);	// Enable Timer
+		self.write_reg(ApicReg::LVTTimer, TIMER_VEC as u32);	// Enable Timer (one-shot)
+		self.write_reg(ApicReg::InitCount, TIMER_COUNT);
 		self.write_reg(ApicReg::LVTThermalSensor, 0);	// "Disable" Thermal Sensor
 		self.write_reg(ApicReg::LVTPermCounters, 0);	// "Disable" ? Counters
 		self.write_reg(ApicReg::LVT_LINT0, 0);	// "Disable" LINT0
@@ -158,6 +173,36 @@ impl LAPIC
 	{
 		self.write_reg(ApicReg::EOI, num as u32);
 	}
+
+	/// Start (or stop) this CPU's scheduler tick
+	pub fn set_timer(&self, enabled: bool)
+	{
+		// Writing zero to the initial count stops the timer
+		self.write_reg(ApicReg::InitCount, if enabled { TIMER_COUNT } else { 0 });
+	}
+
+	/// Returns the ID of the current CPU's LAPIC
+	pub fn get_id(&self) -> u8
+	{
+		(self.read_reg(ApicReg::LAPIC_ID) >> 24) as u8
+	}
+	/// Send an inter-processor interrupt to the LAPIC with the given ID
+	pub fn send_ipi(&self, apic_id: u8, mode: IpiMode)
+	{
+		let low = match mode
+			{
+			IpiMode::Fixed(vector) => vector as u32,
+			IpiMode::Init => (5 << 8) | (1 << 14),	// INIT, Level=Assert
+			IpiMode::Startup(page) => (6 << 8) | page as u32,
+			};
+		// Both halves of the ICR must be written without another IPI being sent in between
+		let _irql = ::sync::hold_interrupts();
+		// Wait for the previous IPI to be accepted (Delivery Status)
+		while self.read_reg(ApicReg::ICR) & (1 << 12) != 0 {
+		}
+		self.write_reg(ApicReg::icr(1), (apic_id as u32) << 24);
+		self.write_reg(ApicReg::icr(0), low);
+	}
 	
 	fn read_reg(&self, reg: ApicReg) -> u32
 	{
@@ -196,8 +241,10 @@ impl LAPIC
 		assert!( !sp.is_null() );
 		// SAFE: 'sp' is the bound pointer, and should be valid
 		let s: &LAPIC = unsafe { &*(sp as *const LAPIC) };
-		log_trace!("LAPIC Timer");
 		s.eoi(isr);
+		// NOTE: `preempt_tick` re-arms the timer if the slice can still be cut short
+		::threads::preempt_tick();
+		::arch::imp::interrupts::profile_sample();
 	}
 }
 impl ApicReg
@@ -220,6 +267,12 @@ impl ApicReg
 		// SAFE: Transmutes to a u8 repr enum with a valid value
 		unsafe { ::core::mem::transmute(ApicReg::IRR as u8 + reg as u8) }
 	}
+	fn icr(reg: u8) -> ApicReg
+	{
+		assert!(reg < 2);
+		// SAFE: Transmutes to a u8 repr enum with a valid value
+		unsafe { ::core::mem::transmute(ApicReg::ICR as u8 + reg as u8) }
+	}
 }
 
 extern "C" fn lapic_timer(isr: usize, sp: *const (), _idx: usize)
@@ -14,8 +14,15 @@ struct HPET
 	mapping_handle: ::memory::virt::AllocHandle,
 	irq_handle: ::arch::imp::hw::apic::IRQHandle,
 	period: u64,
+	/// Main counter value that comparator 0 is programmed to fire at
+	next_irq: ::sync::Spinlock<u64>,
 }
 
+/// Longest interval between interrupts when there's no pending deadline (ms)
+///
+/// Bounds the granularity of CPU time accounting (see `::time::time_tick`)
+const MAX_TICK_INTERVAL: u64 = 1000;
+
 #[repr(C,packed)]
 struct ACPI_HPET
 {
@@ -39,6 +46,14 @@ enum HPETReg
 
 static S_INSTANCE: ::lib::LazyStatic<HPET> = lazystatic_init!();
 
+/// Ensure that the HPET fires by the given timestamp (ms)
+pub fn request_tick(deadline: u64)
+{
+	if S_INSTANCE.ls_is_valid() {
+		S_INSTANCE.request_irq(deadline * S_INSTANCE.ticks_per_ms());
+	}
+}
+
 /// Reutrns the current system timestamp, in miliseconds since an arbitary point (usually power-on)
 pub fn get_timestamp() -> u64
 {
@@ -85,7 +100,7 @@ fn init()
 		&*S_INSTANCE
 		};
 	
-	inst.oneshot(0, inst.current() + 100*1000 );
+	inst.program_next();
 }
 
 impl HPET
@@ -96,6 +111,7 @@ impl HPET
 			mapping_handle: mapping,
 			irq_handle: Default::default(),
 			period: 1,
+			next_irq: ::sync::Spinlock::new(0),
 			};
 		// Enable
 		rv.write_reg(HPETReg::Config as usize, rv.read_reg(HPETReg::Config as usize) | (1 << 0));
@@ -120,7 +136,46 @@ impl HPET
 		let s = unsafe{ &*(sp as *const HPET) };
 		s.write_reg(HPETReg::ISR as usize, s.read_reg(HPETReg::ISR as usize));
 		
-		s.oneshot(0, s.current() + 100*1000 );
+		::time::time_tick();
+		s.program_next();
+	}
+	
+	/// Program the next interrupt for the earliest pending deadline (ticks with nothing to do are skipped)
+	fn program_next(&self)
+	{
+		let _irq = ::sync::hold_interrupts();
+		let mut lh = self.next_irq.lock();
+		// NOTE: Read with the lock held, so a timer added after this will see the new value in `request_irq`
+		let max = self.current() / self.ticks_per_ms() + MAX_TICK_INTERVAL;
+		let deadline = match ::time::next_deadline()
+			{
+			Some(v) if v < max => v,
+			_ => max,
+			};
+		self.set_comparator(&mut lh, deadline * self.ticks_per_ms());
+	}
+	/// Request an interrupt at (or shortly after) the given counter value, unless one is already pending before it
+	fn request_irq(&self, target: u64)
+	{
+		let _irq = ::sync::hold_interrupts();
+		let mut lh = self.next_irq.lock();
+		if *lh > self.current() && *lh <= target {
+			return ;
+		}
+		self.set_comparator(&mut lh, target);
+	}
+	fn set_comparator(&self, next_irq: &mut u64, target: u64)
+	{
+		// The comparator only fires when the counter matches it, so it must be set in the future
+		let min_delta = ::core::cmp::max(1, self.ticks_per_ms() / 10);
+		let mut target = ::core::cmp::max(target, self.current() + min_delta);
+		self.oneshot(0, target);
+		// - If the counter passed the value while it was being written, try again further out
+		while self.current() >= target {
+			target = self.current() + min_delta;
+			self.oneshot(0, target);
+		}
+		*next_irq = target;
 	}
 	
 	fn read_reg(&self, reg: usize) -> u64 {
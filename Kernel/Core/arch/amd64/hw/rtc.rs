@@ -0,0 +1,157 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// arch/amd64/hw/rtc.rs
+//! PC CMOS real-time clock
+#[allow(unused_imports)]
+use prelude::*;
+use arch::x86_io::{inb,outb};
+
+module_define!{RTC, [HPET], init}
+
+const CMOS_ADDR: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+/// Set in the address register to keep NMIs masked while accessing CMOS
+const CMOS_NMI_DISABLE: u8 = 0x80;
+
+enum RtcReg
+{
+	Seconds = 0x00,
+	Minutes = 0x02,
+	Hours   = 0x04,
+	Day     = 0x07,
+	Month   = 0x08,
+	Year    = 0x09,
+	StatusA = 0x0A,
+	StatusB = 0x0B,
+}
+/// StatusA: Update in progress
+const STA_UIP: u8 = 1 << 7;
+/// StatusB: Updates inhibited (for setting)
+const STB_SET: u8 = 1 << 7;
+/// StatusB: Values are binary (instead of BCD)
+const STB_BINARY: u8 = 1 << 2;
+/// StatusB: Hours are 24-hour (instead of 12-hour with bit 7 as PM)
+const STB_24HOUR: u8 = 1 << 1;
+
+/// Serialises access to the CMOS address/data registers
+static S_CMOS_LOCK: ::sync::Spinlock<()> = ::sync::Spinlock::new( () );
+
+struct CmosRtc;
+
+fn init()
+{
+	::time::register_rtc( Box::new(CmosRtc) );
+}
+
+/// Raw register values (in the clock's own encoding)
+#[derive(PartialEq,Copy,Clone)]
+struct RawTime([u8; 6]);
+
+impl ::time::RtcDevice for CmosRtc
+{
+	fn name(&self) -> &str { "CMOS" }
+	fn read(&self) -> Option<::time::Timestamp>
+	{
+		let _irq = ::sync::hold_interrupts();
+		let _lh = S_CMOS_LOCK.lock();
+		// Read until two consecutive reads match (so an update can't tear the values)
+		let mut prev = read_raw();
+		let mut cur = read_raw();
+		while cur != prev {
+			prev = cur;
+			cur = read_raw();
+		}
+		let stb = read_reg(RtcReg::StatusB);
+
+		let v = |i: usize| if stb & STB_BINARY != 0 { cur.0[i] } else { from_bcd(cur.0[i]) };
+		let hour = if stb & STB_24HOUR != 0 {
+				v(2)
+			}
+			else {
+				// - 12-hour: Bit 7 set for PM, 12 is midnight/noon
+				let pm = cur.0[2] & 0x80 != 0;
+				let h = if stb & STB_BINARY != 0 { cur.0[2] & 0x7F } else { from_bcd(cur.0[2] & 0x7F) };
+				let h = h % 12;
+				if pm { h + 12 } else { h }
+			};
+		// NOTE: The century register's location isn't standard, so assume 1970-2069
+		let year = v(5) as u32;
+		let year = if year < 70 { 2000 + year } else { 1900 + year };
+		let (month, day) = (v(4), v(3));
+		let (minute, second) = (v(1), v(0));
+		if month < 1 || month > 12 || day < 1 || day > 31 || hour > 23 || minute > 59 || second > 59 {
+			log_warning!("CMOS RTC holds an invalid time: {}-{}-{} {}:{}:{}", year, month, day, hour, minute, second);
+			return None;
+		}
+		Some( ::time::timestamp_from_date(year, month, day, hour, minute, second) )
+	}
+	fn write(&self, time: ::time::Timestamp) -> bool
+	{
+		let d = ::time::date_from_timestamp(time);
+		if d.year < 1970 || d.year > 2069 {
+			return false;
+		}
+		let _irq = ::sync::hold_interrupts();
+		let _lh = S_CMOS_LOCK.lock();
+		let stb = read_reg(RtcReg::StatusB);
+		let enc = |v: u8| if stb & STB_BINARY != 0 { v } else { to_bcd(v) };
+		let hour = if stb & STB_24HOUR != 0 {
+				enc(d.hour)
+			}
+			else {
+				let h = if d.hour % 12 == 0 { 12 } else { d.hour % 12 };
+				enc(h) | if d.hour >= 12 { 0x80 } else { 0 }
+			};
+		// Inhibit updates while the registers are written
+		write_reg(RtcReg::StatusB, stb | STB_SET);
+		write_reg(RtcReg::Seconds, enc(d.second));
+		write_reg(RtcReg::Minutes, enc(d.minute));
+		write_reg(RtcReg::Hours, hour);
+		write_reg(RtcReg::Day, enc(d.day));
+		write_reg(RtcReg::Month, enc(d.month));
+		write_reg(RtcReg::Year, enc((d.year % 100) as u8));
+		write_reg(RtcReg::StatusB, stb & !STB_SET);
+		true
+	}
+}
+
+fn read_raw() -> RawTime
+{
+	while read_reg(RtcReg::StatusA) & STA_UIP != 0 {
+	}
+	RawTime([
+		read_reg(RtcReg::Seconds),
+		read_reg(RtcReg::Minutes),
+		read_reg(RtcReg::Hours),
+		read_reg(RtcReg::Day),
+		read_reg(RtcReg::Month),
+		read_reg(RtcReg::Year),
+		])
+}
+
+fn read_reg(reg: RtcReg) -> u8
+{
+	// SAFE: CMOS access is serialised by S_CMOS_LOCK, and the RTC registers have no side-effects when read
+	unsafe {
+		outb(CMOS_ADDR, CMOS_NMI_DISABLE | reg as u8);
+		inb(CMOS_DATA)
+	}
+}
+fn write_reg(reg: RtcReg, val: u8)
+{
+	// SAFE: CMOS access is serialised by S_CMOS_LOCK, and only RTC registers are written
+	unsafe {
+		outb(CMOS_ADDR, CMOS_NMI_DISABLE | reg as u8);
+		outb(CMOS_DATA, val);
+	}
+}
+
+fn from_bcd(v: u8) -> u8 {
+	(v >> 4) * 10 + (v & 0xF)
+}
+fn to_bcd(v: u8) -> u8 {
+	(v / 10) << 4 | (v % 10)
+}
+
+// vim: ft=rust
@@ -167,10 +167,27 @@ BLANKINT i
 %assign i i+1
 %endrep
 [extern irq_handler]
+[extern irq_user_return]
 IRQCommon:
 	API_SAVE
+	; Pass the interrupted context (iret frame and RBP) to the handler
+	lea rsi, [rsp+API_SAVE_SIZE+8]
+	mov rdx, rbp
+	; Check the saved CS to tell if this interrupted userland
+	test byte [rsp+API_SAVE_SIZE+2*8], 3
+	jz .inkernel
+	; Reset the GS/FS base
+	swapgs
+	mov rdi, rbx
+	call irq_handler
+	; Preempt the current thread (if requested) before returning to userland
+	call irq_user_return
+	swapgs
+	jmp .restore
+.inkernel:
 	mov rdi, rbx
 	call irq_handler
+.restore:
 	API_RESTORE
 	pop rbx
 	iretq
@@ -26,6 +26,17 @@ pub struct ISRHandle
 	idx: usize,
 }
 
+#[derive(Copy,Clone)]
+/// State of the code interrupted by an IRQ (for the profiler)
+struct InterruptedContext
+{
+	ip: u64,
+	bp: u64,
+	user: bool,
+}
+/// Per-CPU context of the IRQ currently being handled
+static mut S_INTERRUPTED: [Option<InterruptedContext>; super::threads::MAX_CPUS] = [None; super::threads::MAX_CPUS];
+
 static S_IRQ_HANDLERS_LOCK: ::sync::Spinlock<[IRQHandlersEnt; 256]> = ::sync::Spinlock::new( [IRQHandlersEnt{
 	handler: None,
 	info: 0 as *const _,
@@ -36,12 +47,82 @@ static S_IRQ_HANDLERS_LOCK: ::sync::Spinlock<[IRQHandlersEnt; 256]> = ::sync::Sp
 #[doc(hidden)]
 #[req_safe(irq)]
 /// ISR handler called by assembly
-pub extern "C" fn irq_handler(index: usize)
+///
+/// `frame` is the hardware interrupt frame (RIP, CS, RFLAGS, ...), and `bp` the interrupted RBP
+pub extern "C" fn irq_handler(index: usize, frame: *const u64, bp: u64)
+{
+	// Save the interrupted context for the profiler (only while it's running, avoids touching per-CPU state otherwise)
+	let cpu = if ::profiler::sample_depth().is_some() {
+			let cpu = super::threads::cpu_index();
+			// SAFE: Only ever accessed by this CPU, with interrupts disabled. Frame pointer is provided by assembly
+			unsafe {
+				S_INTERRUPTED[cpu] = Some(InterruptedContext {
+					ip: *frame.offset(0),
+					bp: bp,
+					user: *frame.offset(1) & 3 != 0,
+					});
+			}
+			Some(cpu)
+		}
+		else {
+			None
+		};
+	{
+		let lh = S_IRQ_HANDLERS_LOCK.lock_irqsafe();
+		let ent = (*lh)[index];
+		if let Some(h) = ent.handler {
+			(h)(index, ent.info, ent.idx);
+		}
+	}
+	if let Some(cpu) = cpu {
+		// SAFE: Per-CPU, interrupts disabled
+		unsafe {
+			S_INTERRUPTED[cpu] = None;
+		}
+	}
+}
+
+/// Record a profiler sample of the code interrupted by the current IRQ (called from the scheduler tick)
+pub fn profile_sample()
 {
-	let lh = S_IRQ_HANDLERS_LOCK.lock_irqsafe();
-	let ent = (*lh)[index];
-	if let Some(h) = ent.handler {
-		(h)(index, ent.info, ent.idx);
+	let depth = match ::profiler::sample_depth() { Some(v) => v, None => return };
+	// SAFE: Per-CPU, only written by `irq_handler` on this CPU (which is the caller)
+	let ctx = match unsafe { S_INTERRUPTED[super::threads::cpu_index()] } { Some(v) => v, None => return };
+
+	let mut frames = [0usize; ::profiler::MAX_DEPTH];
+	frames[0] = ctx.ip as usize;
+	let mut n = 1;
+	// - Only walk kernel stacks, userland frame pointers can't be trusted
+	if !ctx.user {
+		let mut bp = ctx.bp;
+		while n < depth
+		{
+			match super::cpu_faults::backtrace(bp)
+			{
+			Some((newbp, ip)) => {
+				frames[n] = ip as usize;
+				n += 1;
+				bp = newbp;
+				},
+			None => break,
+			}
+		}
+	}
+	::profiler::record(&frames[..n], ctx.user);
+}
+
+#[no_mangle]
+#[doc(hidden)]
+/// Called by assembly before an IRQ returns to userland, switches away from the current thread if it was preempted
+pub extern "C" fn irq_user_return()
+{
+	if ::threads::preempt_pending()
+	{
+		// SAFE: The IRQ has been handled (and acknowledged), so further interrupts can be taken while switching
+		unsafe { ::arch::sync::start_interrupts(); }
+		::threads::yield_time();
+		// SAFE: The return path expects interrupts to be disabled
+		unsafe { ::arch::sync::stop_interrupts(); }
 	}
 }
 
@@ -7,7 +7,7 @@
 //! Handles reference counting and allocation bitmaps
 use arch::imp::memory::addresses::{PMEMREF_BASE,PMEMREF_END,PMEMBM_BASE,PMEMBM_END};
 use sync::RwLock;
-use core::sync::atomic::{Ordering};
+use core::sync::atomic::{Ordering,AtomicUsize};
 use sync::AtomicU32;
 use memory::page_array::{PageArray};
 
@@ -17,6 +17,15 @@ use memory::page_array::{PageArray};
 /// Multiref count array
 static S_REFCOUNT_ARRAY: RwLock<PageArray<AtomicU32>> = RwLock::new( PageArray::new(PMEMREF_BASE, PMEMREF_END) );
 static S_USED_BITMAP: RwLock<PageArray<AtomicU32>> = RwLock::new( PageArray::new(PMEMBM_BASE, PMEMBM_END) );
+/// Tag of the thread expanding the bitmap (zero if none), see `expander_tag`
+///
+/// The allocation for the expansion calls back into `mark_used` (with the bitmap write lock held).
+static S_BM_EXPANDER: AtomicUsize = AtomicUsize::new(0);
+
+/// Non-zero tag for the current thread (the thread pointer is NULL before threading is initialised)
+fn expander_tag() -> usize {
+	::threads::cur_thread_ptr() as usize | 1
+}
 
 /// Calls the provided closure with a borrow of the reference count for the specified frame
 fn with_ref<U, F: FnOnce(&AtomicU32)->U>(frame_idx: u64, fcn: F) -> Option<U>
@@ -84,13 +93,40 @@ pub fn mark_free(frame_idx: u64) -> bool {
 		}).unwrap_or(false)
 }
 pub fn mark_used(frame_idx: u64) {
+	fn set_bit(c: &AtomicU32, mask: u32) {
+		let mut old = c.load(Ordering::Relaxed);
+		loop
+		{
+			let new_old = c.compare_and_swap(old, old | mask, Ordering::Relaxed);
+			if old == new_old {
+				break ;
+			}
+			old = new_old;
+		}
+	}
 	let mask = 1 << ((frame_idx % 32) as usize);
-	with_bm_alloc( (frame_idx / 32) as usize, |c| {
-		// Should always succeed due to write lock in `with_bm_alloc`
-		let old = c.load(Ordering::Relaxed);
-		let new_old = c.compare_and_swap(old, old | mask, Ordering::Relaxed);
-		assert_eq!(new_old, old);
-		})
+	let ofs = (frame_idx / 32) as usize;
+	let tag = expander_tag();
+	if S_BM_EXPANDER.load(Ordering::Acquire) == tag {
+		// This thread is expanding the bitmap (and holds the write lock), so this is a frame backing the bitmap.
+		// - Left unmarked, it will never be freed (which is correct)
+		return ;
+	}
+	loop
+	{
+		if with_bm( ofs, |c| set_bit(c, mask) ).is_some() {
+			return ;
+		}
+		// Claim the expansion, if another thread got there first wait for it to finish and try again (it may have
+		// covered this frame)
+		if S_BM_EXPANDER.compare_and_swap(0, tag, Ordering::Acquire) == 0 {
+			with_bm_alloc( ofs, |c| set_bit(c, mask) );
+			S_BM_EXPANDER.store(0, Ordering::Release);
+			return ;
+		}
+		while S_BM_EXPANDER.load(Ordering::Acquire) != 0 {
+		}
+	}
 }
 
 
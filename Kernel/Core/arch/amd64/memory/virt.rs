@@ -256,6 +256,11 @@ fn invlpg(addr: *mut ()) {
 		asm!("invlpg ($0)" : : "r" (addr) : "memory" : "volatile");
 	}
 }
+/// Invalidate a page on all CPUs (needed when a mapping is removed or downgraded)
+fn invlpg_all(addr: *mut ()) {
+	invlpg(addr);
+	::arch::imp::smp::tlb_shootdown(addr);
+}
 
 pub fn can_map_without_alloc(addr: *mut ()) -> bool {
 	// The following only returns PTE::null() if an intermediate step was unallocated
@@ -285,7 +290,7 @@ pub unsafe fn unmap(addr: *mut ()) -> Option<PAddr>
 		};
 	pte.set( 0, ::memory::virt::ProtectionMode::Unmapped );
 	
-	invlpg(addr);
+	invlpg_all(addr);
 	
 	rv
 }
@@ -298,7 +303,7 @@ pub unsafe fn reprotect(addr: *mut (), prot: ::memory::virt::ProtectionMode)
 	assert!( pte.is_present(), "Reprotecting unmapped page {:p}", addr );
 	let phys = pte.addr();
 	pte.set( phys, prot );
-	invlpg(addr);
+	invlpg_all(addr);
 }
 
 static PF_PRESENT : u64 = 0x001;
@@ -434,6 +439,39 @@ impl ::core::fmt::Debug for PTE
 	}
 }
 
+/// Give the current address space its own copy of a copy-on-write page (does nothing if the page isn't COW)
+///
+/// Returns `Err` if a frame couldn't be allocated for the copy.
+pub fn unshare_cow(addr: usize) -> Result<(), ()>
+{
+	let mut rv = Ok( () );
+	// 1. Lock (relevant) address space
+	// SAFE: Changes to address space are transparent
+	::memory::virt::with_lock(addr, || unsafe {
+		// - Checked under the lock, as another CPU may have already handled a fault on this page
+		let mut pte = get_page_ent(addr, false, LargeOk::Yes);
+		if !pte.is_cow() {
+			return ;
+		}
+		let frame = pte.addr();
+		let pgaddr = addr & !PAGE_MASK;
+		// 2. Get the PMM to provide us with a unique copy of that frame (can return the same addr)
+		match ::memory::phys::make_unique( frame, &*(pgaddr as *const [u8; 4096]) )
+		{
+		Ok(newframe) => {
+			// 3. Remap to this page as UserRW (because COW is user-only atm)
+			pte.set(newframe, ProtectionMode::UserRW);
+			// - Other CPUs running this address space may have cached the read-only (old frame) entry
+			invlpg_all( pgaddr as *mut () );
+			},
+		Err(_) => {
+			rv = Err( () );
+			},
+		}
+		});
+	rv
+}
+
 /// Handle a page fault in whatever way is suitable
 pub fn handle_page_fault(accessed_address: usize, error_code: u32) -> bool
 {
@@ -443,26 +481,22 @@ pub fn handle_page_fault(accessed_address: usize, error_code: u32) -> bool
 		panic!("Reserved bits clobbered {:#x}", accessed_address);
 	}
 	
-	let mut pte = get_page_ent(accessed_address, false, LargeOk::Yes);
+	let pte = get_page_ent(accessed_address, false, LargeOk::Yes);
 	
 	// - Global rules
 	//  > Copy-on-write pages
 	if error_code & (FAULT_WRITE|FAULT_LOCKED) == (FAULT_WRITE|FAULT_LOCKED) && pte.is_cow() {
-		// Poke the main VMM layer
-		//::memory::virt::cow_write(accessed_address);
-
-		// 1. Lock (relevant) address space
-		// SAFE: Changes to address space are transparent
-		::memory::virt::with_lock(accessed_address, || unsafe {
-			let frame = pte.addr();
-			let pgaddr = (accessed_address as usize) & !PAGE_MASK;
-			// 2. Get the PMM to provide us with a unique copy of that frame (can return the same addr)
-			let newframe = ::memory::phys::make_unique( frame, &*(pgaddr as *const [u8; 4096]) );
-			// 3. Remap to this page as UserRW (because COW is user-only atm)
-			pte.set(newframe, ProtectionMode::UserRW);
-			invlpg( (accessed_address & !0xFFF) as *mut () );
-			});
-		return true;
+		if unshare_cow(accessed_address).is_ok() {
+			return true;
+		}
+		log_warning!("Out of memory handling COW write to {:#x}", accessed_address);
+		// Last resort: a user process that can't obtain memory is killed (instead of the fault being fatal)
+		// - Kernel-mode faults can't exit in place (locks may be held), but syscalls unshare user buffers up-front
+		//   (see `FreezeMut`) so any left are a kernel bug, reported below.
+		if error_code & FAULT_USER != 0 {
+			::memory::pressure::kill_current_process(accessed_address);
+		}
+		return false;
 	}
 	//  > Paged-out pages
 	if error_code & FAULT_LOCKED == 0 && pte.is_reserved() {
@@ -512,7 +546,7 @@ pub unsafe fn temp_unmap<T>(addr: *mut T) {
 	// SAFE: Owned allocation
 	/*unsafe*/ {
 		get_page_ent(addr as usize, false, LargeOk::No).set(0, ProtectionMode::Unmapped);
-		invlpg(addr as *mut ());
+		invlpg_all(addr as *mut ());
 	}
 	S_TEMP_FREE.release();
 }
@@ -7,7 +7,7 @@ use core::option::Option;
 
 pub use self::log::{puts, puth};
 
-module_define!{arch, [APIC, HPET], init}
+module_define!{arch, [APIC, HPET, RTC, SMP], init}
 
 pub mod interrupts;
 #[doc(hidden)]
@@ -18,6 +18,7 @@ pub mod boot;
 pub mod sync;
 
 mod tss;
+mod smp;
 
 mod log;
 pub mod x86_io;
@@ -56,6 +57,11 @@ pub fn cur_timestamp() -> u64
 {
 	hw::hpet::get_timestamp()
 }
+/// Program the HPET to fire at the given timestamp (if it isn't already due to fire earlier)
+pub fn request_tick(deadline: u64)
+{
+	hw::hpet::request_tick(deadline)
+}
 
 /// Print a backtrace, starting at the current location.
 pub fn print_backtrace()
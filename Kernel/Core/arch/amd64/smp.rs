@@ -0,0 +1,239 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/arch/amd64/smp.rs
+//! Multiprocessor support (application processor startup and inter-processor interrupts)
+use prelude::*;
+use core::sync::atomic::{AtomicBool,AtomicUsize,Ordering};
+use super::hw::apic;
+use super::threads::MAX_CPUS;
+
+module_define!{SMP, [APIC, HPET, TSS], init}
+
+/// Physical address of the AP startup trampoline
+// NOTE: MUST match the value in common.inc.asm
+const AP_TRAMPOLINE_BASE: u64 = 0x8000;
+
+/// IPI used to wake an idle CPU when a thread is made runnable
+const RESCHED_VEC: u8 = 0x7C;
+/// IPI used to invalidate a TLB entry on other CPUs
+const TLB_VEC: u8 = 0x7D;
+
+extern "C" {
+	static ap_trampoline: [u8; 0];
+	static ap_trampoline_end: [u8; 0];
+	static mut s_ap_boot_tls: u64;
+	static mut InitialPML4: [u64; 512];
+	static InitialPDP: [u64; 512];
+}
+
+/// Number of CPUs that are running (CPU indexes are allocated sequentially)
+static S_CPU_COUNT: AtomicUsize = AtomicUsize::new(1);
+/// LAPIC ID of each CPU, only written before the CPU is started
+static mut S_APIC_IDS: [u8; MAX_CPUS] = [0; MAX_CPUS];
+/// Set by an AP once it has finished its initialisation
+static S_AP_ONLINE: AtomicBool = ::core::sync::atomic::ATOMIC_BOOL_INIT;
+
+/// Serialises TLB shootdowns (only one address is in flight at a time)
+///
+/// NOTE: Not a `Spinlock`, as waiters must keep servicing the in-flight request (see `tlb_shootdown`)
+static S_TLB_LOCK: AtomicBool = ::core::sync::atomic::ATOMIC_BOOL_INIT;
+static S_TLB_ADDR: AtomicUsize = AtomicUsize::new(0);
+/// Bitmask of CPUs that have yet to invalidate `S_TLB_ADDR`
+static S_TLB_PENDING: AtomicUsize = AtomicUsize::new(0);
+
+fn init()
+{
+	// SAFE: Only written before other CPUs are started
+	unsafe {
+		S_APIC_IDS[0] = apic::local_apic_id();
+	}
+
+	// IPI handlers are bound for the lifetime of the kernel
+	match ::arch::imp::interrupts::bind_isr(RESCHED_VEC, resched_ipi, 0 as *const _, 0)
+	{
+	Ok(h) => ::core::mem::forget(h),
+	Err(e) => panic!("Unable to bind reschedule IPI: {:?}", e),
+	}
+	match ::arch::imp::interrupts::bind_isr(TLB_VEC, tlb_ipi, 0 as *const _, 0)
+	{
+	Ok(h) => ::core::mem::forget(h),
+	Err(e) => panic!("Unable to bind TLB shootdown IPI: {:?}", e),
+	}
+
+	let apic_ids = apic::enumerate_cpus();
+	log_notice!("{} CPU(s) listed in MADT: {:?}", apic_ids.len(), apic_ids);
+	if apic_ids.len() <= 1 {
+		return ;
+	}
+
+	// 1. Copy the trampoline into low memory
+	// SAFE: The trampoline frame is reserved in the memory map, and the code only uses addresses relative to the base
+	unsafe {
+		let start = ap_trampoline.as_ptr();
+		let len = ap_trampoline_end.as_ptr() as usize - start as usize;
+		assert!(len <= ::PAGE_SIZE, "AP trampoline too large ({:#x} bytes)", len);
+		let mut page = ::arch::memory::virt::TempHandle::<u8>::new(AP_TRAMPOLINE_BASE);
+		page[..len].clone_from_slice( ::core::slice::from_raw_parts(start, len) );
+	}
+
+	// 2. Temporarily restore the identity mapping of low memory (the trampoline needs it to enable paging)
+	// SAFE: Nothing else uses the lower half of the kernel's address space during init
+	unsafe {
+		InitialPML4[0] = ::memory::virt::get_phys(&InitialPDP) as u64 | 3;
+	}
+
+	// 3. Start each AP in turn
+	// SAFE: Read-only access, written above
+	let bsp_id = unsafe { S_APIC_IDS[0] };
+	for &apic_id in apic_ids.iter().filter(|&&id| id != bsp_id)
+	{
+		let cpu = S_CPU_COUNT.load(Ordering::SeqCst);
+		if cpu == MAX_CPUS {
+			log_warning!("Too many CPUs, ignoring APIC ID {} onwards", apic_id);
+			break ;
+		}
+		if !start_ap(cpu, apic_id) {
+			log_error!("CPU with APIC ID {} failed to start", apic_id);
+			// Return it to the wait-for-SIPI state, so it can't start on the (now reused) idle thread
+			apic::send_ipi(apic_id, apic::IpiMode::Init);
+		}
+	}
+
+	// 4. Remove the identity mapping again
+	// SAFE: APs have all switched to the higher-half mappings
+	unsafe {
+		InitialPML4[0] = 0;
+		asm!("mov %cr3, %rax; mov %rax, %cr3" : : : "rax" : "volatile");
+	}
+	log_notice!("{} CPUs online", cpu_count());
+}
+
+/// Send the INIT-SIPI-SIPI sequence to an AP, and wait for it to come online
+fn start_ap(cpu: usize, apic_id: u8) -> bool
+{
+	log_log!("Starting CPU{} (APIC ID {})", cpu, apic_id);
+	// SAFE: Only one AP is started at a time, and this CPU index isn't in use yet
+	unsafe {
+		S_APIC_IDS[cpu] = apic_id;
+		s_ap_boot_tls = super::threads::prep_ap_idle(cpu);
+	}
+	S_AP_ONLINE.store(false, Ordering::SeqCst);
+
+	apic::send_ipi(apic_id, apic::IpiMode::Init);
+	delay_ms(10);
+	// - A second SIPI is only needed if the first was missed, and is ignored by a running CPU
+	apic::send_ipi(apic_id, apic::IpiMode::Startup( (AP_TRAMPOLINE_BASE >> 12) as u8 ));
+	if wait_online(1) {
+		return true;
+	}
+	apic::send_ipi(apic_id, apic::IpiMode::Startup( (AP_TRAMPOLINE_BASE >> 12) as u8 ));
+	wait_online(100)
+}
+
+fn wait_online(timeout_ms: u64) -> bool
+{
+	let end = ::arch::cur_timestamp() + timeout_ms + 1;
+	while ::arch::cur_timestamp() < end
+	{
+		if S_AP_ONLINE.load(Ordering::Acquire) {
+			return true;
+		}
+	}
+	S_AP_ONLINE.load(Ordering::Acquire)
+}
+fn delay_ms(ms: u64)
+{
+	let end = ::arch::cur_timestamp() + ms + 1;
+	while ::arch::cur_timestamp() < end {
+	}
+}
+
+#[no_mangle]
+#[doc(hidden)]
+/// Rust entrypoint for application processors (called by start.asm on the CPU's idle thread)
+pub extern "C" fn ap_main()
+{
+	let cpu = super::threads::cpu_index();
+	super::tss::init_ap(cpu);
+	// NOTE: This enables interrupts
+	apic::init_ap();
+	log_notice!("CPU{} online (APIC ID {})", cpu, apic::local_apic_id());
+
+	S_CPU_COUNT.store(cpu + 1, Ordering::SeqCst);
+	S_AP_ONLINE.store(true, Ordering::Release);
+
+	::threads::idle_thread();
+}
+
+/// Returns the number of running CPUs
+pub fn cpu_count() -> usize
+{
+	S_CPU_COUNT.load(Ordering::Relaxed)
+}
+
+/// Request that the given CPU re-check its run queue (wakes it from `idle`)
+pub fn send_reschedule(cpu: usize)
+{
+	assert!(cpu < cpu_count());
+	// SAFE: Entries for running CPUs are not modified
+	let apic_id = unsafe { S_APIC_IDS[cpu] };
+	apic::send_ipi(apic_id, apic::IpiMode::Fixed(RESCHED_VEC));
+}
+
+/// Invalidate a TLB entry on all other CPUs (the caller handles the current CPU)
+///
+/// Safe to call with interrupts disabled (e.g. from the page fault handler): while waiting (for the lock or for
+/// acknowledgements) this CPU services any request targeted at it, so two CPUs shooting down at once can't deadlock.
+pub fn tlb_shootdown(addr: *mut ())
+{
+	let count = cpu_count();
+	if count <= 1 {
+		return ;
+	}
+	let this_cpu = super::threads::cpu_index();
+
+	while S_TLB_LOCK.compare_and_swap(false, true, Ordering::Acquire) != false {
+		tlb_service(this_cpu);
+	}
+	S_TLB_ADDR.store(addr as usize, Ordering::SeqCst);
+	let mask = ((1 << count) - 1) & !(1 << this_cpu);
+	S_TLB_PENDING.store(mask, Ordering::SeqCst);
+	for cpu in (0 .. count).filter(|&c| c != this_cpu)
+	{
+		// SAFE: Entries for running CPUs are not modified
+		apic::send_ipi(unsafe { S_APIC_IDS[cpu] }, apic::IpiMode::Fixed(TLB_VEC));
+	}
+	while S_TLB_PENDING.load(Ordering::Acquire) != 0 {
+	}
+	S_TLB_LOCK.store(false, Ordering::Release);
+}
+
+/// Invalidate the in-flight shootdown address, if this CPU has yet to do so
+fn tlb_service(cpu: usize)
+{
+	let bit = 1 << cpu;
+	if S_TLB_PENDING.load(Ordering::Acquire) & bit != 0 {
+		// - The address isn't changed until every CPU has cleared its bit
+		let addr = S_TLB_ADDR.load(Ordering::SeqCst);
+		// SAFE: Cannot cause memory unsafety
+		unsafe {
+			asm!("invlpg ($0)" : : "r" (addr) : "memory" : "volatile");
+		}
+		S_TLB_PENDING.fetch_and(!bit, Ordering::Release);
+	}
+}
+
+extern "C" fn resched_ipi(isr: usize, _info: *const (), _idx: usize)
+{
+	// Nothing to do, the interrupt itself wakes the idle loop
+	apic::eoi(isr);
+}
+extern "C" fn tlb_ipi(isr: usize, _info: *const (), _idx: usize)
+{
+	// NOTE: The request may have already been serviced while this CPU was spinning in `tlb_shootdown`
+	tlb_service(super::threads::cpu_index());
+	apic::eoi(isr);
+}
+
+// vim: ft=rust
@@ -240,7 +240,20 @@ start64_higher:
 	mov al, 10
 	out dx, al
 	
-	; Bind the 'SYSCALL' handler (and set flags for it)
+	call syscall_msr_init
+	
+	mov rax, InitialPML4
+	mov QWORD [rax], 0
+	; 7. Call rust kmain
+	call kmain
+.dead_loop:
+	cli
+	hlt
+	jmp .dead_loop
+
+; Bind the 'SYSCALL' handler (and set flags for it)
+; - MSRs are per-CPU, so this is also called by APs
+syscall_msr_init:
 	; LSTAR = 0xC000_0082
 	mov rax, syscall_handler
 	mov rdx, rax
@@ -257,11 +270,88 @@ start64_higher:
 	mov edx, 0
 	mov ecx, 0xC0000084
 	wrmsr
+	ret
+
+; -------------------------------------------------
+; Application Processor startup
+; -------------------------------------------------
+; Real-mode trampoline, copied to AP_TRAMPOLINE_BASE by smp.rs before sending the startup IPI
+; - All addresses are relative to AP_TRAMPOLINE_BASE, as the code isn't executed where it's linked
+%define AP_ADDR(x)	(AP_TRAMPOLINE_BASE + (x) - ap_trampoline)
+[BITS 16]
+EXPORT ap_trampoline
+	cli
+	cld
+	xor ax, ax
+	mov ds, ax
+	lgdt [AP_ADDR(.gdtptr)]
+	mov eax, cr0
+	or al, 1
+	mov cr0, eax
+	jmp dword 0x08:AP_ADDR(.pmode)
+[BITS 32]
+.pmode:
+	mov ax, 0x10
+	mov ds, ax
+	mov es, ax
+	mov ss, ax
+	; Same setup as `start` (see there for the meaning of the bits)
+	mov eax, cr4
+	or eax, 0x80|0x20|0x10
+	or ax, (1 << 9)|(1 << 10)
+	mov cr4, eax
+	mov eax, low_InitialPML4
+	mov cr3, eax
+	mov ecx, 0xC0000080
+	rdmsr
+	or eax, (1 << 11)|(1 << 8)|(1 << 0)	; NXE, LME, SCE
+	wrmsr
+	mov eax, cr0
+	or eax, 0x80010000|(1 << 3)|(1 << 1)	; PG & WP
+	and ax, ~(1 << 2)
+	mov cr0, eax
+	lgdt [GDTPtr - KERNEL_BASE]
+	jmp 0x08:ap_start64
+ALIGN 8
+.gdt:
+	dd 0, 0
+	dd 0x0000FFFF, 0x00CF9A00	; 0x08: 32-bit Code
+	dd 0x0000FFFF, 0x00CF9200	; 0x10: 32-bit Data
+.gdtptr:
+	dw .gdtptr - .gdt - 1
+	dd AP_ADDR(.gdt)
+EXPORT ap_trampoline_end
+[BITS 64]
+
+[section .inittext]
+ap_start64:
+	mov rax, ap_start64_higher
+	jmp rax
+[section .text]
+ap_start64_higher:
+	lgdt [rel GDTPtr2]
+	mov ax, 0x10
+	mov ds, ax
+	mov ss, ax
+	mov es, ax
+	mov fs, ax
+	mov gs, ax
+	lidt [rel IDTPtr]
 	
-	mov rax, InitialPML4
-	mov QWORD [rax], 0
-	; 7. Call rust kmain
-	call kmain
+	; Switch to the idle thread's stack and TLS (prepared by smp.rs)
+	mov rax, [rel s_ap_boot_tls]
+	mov rsp, rax
+	mov rdx, rax
+	shr rdx, 32
+	mov ecx, 0xC0000100	; FS Base
+	wrmsr
+	mov ecx, 0xC0000101	; GS Base
+	wrmsr
+	
+	call syscall_msr_init
+	
+	[extern ap_main]
+	call ap_main
 .dead_loop:
 	cli
 	hlt
@@ -273,6 +363,7 @@ start64_higher:
 ; RSI: New RSP (pointer)
 ; RDX: New FSBASE
 ; RCX: New CR3
+; R8: This CPU's TSS
 [section .text.asm.task_switch]
 EXPORT task_switch
 	push rbp
@@ -280,15 +371,18 @@ EXPORT task_switch
 	SAVE rbx, r12, r13, r14, r15
 	
 	; Perfom context save/restore
-	mov [rdi], rsp	; Save RSP
+	; - A zero saved RSP marks a running thread (see threads.rs), so the save must be after all state is pushed
+	mov rax, rsp	; Old RSP
 	mov rsp, [rsi]	; New RSP
+	mov QWORD [rsi], 0
 	mov cr3, rcx	; New CR3
 	invlpg [rsp]
+	; - Only publish the old RSP once off its stack and address space (an exited thread can be reaped after this)
+	mov [rdi], rax
 	
 	; Update stack top (RSP0) and TLS base (GS)
 	; TLS base and stack top are the same address.
-	; TODO: This assumes uniprocessor
-	mov [rel TSSes+tss.rsp0], rdx
+	mov [r8+tss.rsp0], rdx
 	mov rax, rdx
 	shr rdx, 32	; EDX = High
 	mov ecx, 0xC0000101	; GS Base
@@ -320,10 +414,11 @@ EXPORT drop_to_user
 	mov ax, 0x23
 	mov ds, ax
 	mov es, ax
-	mov fs, ax
+	; NOTE: FS isn't reloaded, as that would clear the user TLS base set by task_switch
 	mov gs, ax
 	mov rsp, rsi	; User's stack
 	mov rax, rdx	; Argument passed in RAX
+	mov rdi, rdx	; - and in RDI (first argument in SysV ABI)
 	db 0x48
 	sysret
 
@@ -548,6 +643,8 @@ IDTPtr:
 	dq	IDT
 EXPORT s_tid0_tls_base
 	dq	0
+EXPORT s_ap_boot_tls
+	dq	0
 
 [section .bss]
 EXPORT TSSes
@@ -5,17 +5,25 @@
 //! Architecture-level thread handling (helpers for ::threads).
 use prelude::*;
 
+pub use super::smp::{cpu_count,send_reschedule};
+
+/// Maximum number of CPUs supported
+// NOTE: MUST match the value in common.inc.asm
+pub const MAX_CPUS: usize = 8;
+
 #[derive(Default)]//,Copy,Clone)]
 /// Low-level thread state
 pub struct State
 {
 	cr3: u64,
+	/// Saved stack pointer, zero while the thread is running on a CPU
 	rsp: u64,
 	tlsbase: u64,
+	/// Userland TLS base (loaded into FS base on switch)
+	user_tlsbase: u64,
 	// Not strictly part of the CPU state, but it prevents this thread's stack from disappearing
 	stack_handle: Option< ::memory::virt::ArrayHandle<u8> >,
 	// TODO: SSE state 
-	// TODO: Usermode TLS bsae
 }
 
 // TODO: This needs to be 16 byte aligned
@@ -27,11 +35,12 @@ impl Default for SSERegisters {
 extern "C" {
 	static InitialPML4: [u64; 512];
 	static s_tid0_tls_base: u64;
-	fn task_switch(oldrsp: &mut u64, newrsp: &u64, tlsbase: u64, cr3: u64);
+	fn task_switch(oldrsp: &mut u64, newrsp: *mut u64, tlsbase: u64, cr3: u64, tss: *mut ());
 }
 
 pub static S_IRQS_ENABLED: ::core::sync::atomic::AtomicBool = ::core::sync::atomic::ATOMIC_BOOL_INIT;
-static mut S_IDLE_THREAD: *mut ::threads::Thread = 0 as *mut _;
+/// Per-CPU idle threads
+static mut S_IDLE_THREADS: [*mut ::threads::Thread; MAX_CPUS] = [0 as *mut _; MAX_CPUS];
 
 #[repr(C)]
 /// Thread-local-storage block
@@ -48,6 +57,8 @@ struct TLSData {
 	// Free to reorder these
 	thread_ptr: *mut ::threads::Thread,
 	thread_ptr_lent: bool,
+	/// Index of the CPU this thread is running on (updated by `switch_to`)
+	cpu: usize,
 	
 	sse_registers: Option<Box<SSERegisters>>,
 }
@@ -58,7 +69,7 @@ pub fn init_tid0_state() -> State
 {
 	// SAFE: Called in single-threaded context... hopefully (TODO)
 	unsafe {
-		S_IDLE_THREAD = ::core::mem::transmute( ::threads::new_idle_thread(0) );
+		S_IDLE_THREADS[0] = ::core::mem::transmute( ::threads::new_idle_thread(0) );
 	}
 	// SAFE: Just taking the address
 	let cr3 = unsafe { &InitialPML4 as *const _ as u64 - super::memory::addresses::IDENT_START as u64 };
@@ -68,10 +79,29 @@ pub fn init_tid0_state() -> State
 		rsp: 0,
 		// SAFE: Doesn't change outside rust control
 		tlsbase: unsafe { s_tid0_tls_base },
+		user_tlsbase: 0,
 		stack_handle: None,
 		}
 }
 
+/// Create the idle thread for an application processor
+///
+/// Returns the TLS base for the new CPU, which is also the top of its initial stack
+pub fn prep_ap_idle(cpu: usize) -> u64
+{
+	assert!(cpu < MAX_CPUS);
+	let mut thread = ::threads::new_idle_thread(cpu);
+	// The AP starts executing on this thread directly, so mark it as running
+	thread.cpu_state.rsp = 0;
+	let tlsbase = thread.cpu_state.tlsbase;
+	// SAFE: TLS block was created by `start_thread`, and the CPU hasn't started yet
+	unsafe {
+		(*(tlsbase as *mut TLSData)).cpu = cpu;
+		S_IDLE_THREADS[cpu] = ::core::mem::transmute(thread);
+	}
+	tlsbase
+}
+
 impl State
 {
 	/// Construct a new empty CPU state using the provided address space
@@ -81,6 +111,24 @@ impl State
 		rv.cr3 = address_space.get_cr3();
 		rv
 	}
+
+	/// Set the userland TLS base (FS base) used when this thread is running
+	pub fn set_user_tls(&mut self, base: usize) {
+		self.user_tlsbase = base as u64;
+	}
+}
+impl ::core::ops::Drop for State
+{
+	fn drop(&mut self)
+	{
+		// An exiting thread is reaped as soon as it's queued, which could be before its CPU has switched away from it
+		// - `task_switch` only saves RSP once it's off both the stack and the address space
+		if self.stack_handle.is_some() {
+			// SAFE: Volatile read of a valid pointer
+			while unsafe { ::core::intrinsics::volatile_load(&self.rsp) } == 0 {
+			}
+		}
+	}
 }
 
 /// Idle for a short period, called when the CPU has nothing else to do
@@ -91,10 +139,19 @@ pub fn idle()
 	//	let flags = unsafe { let v: u64; asm!("pushf; pop $0" : "=r" (v)); v };
 	//	assert!(flags & 0x200 != 0, "idle() with IF clear, RFLAGS = {:#x}", flags);
 	//}
+	// NOTE: The slice timer was stopped when the scheduler found nothing to run, so this halts until an IRQ/IPI
+	// - Timer deadlines are still handled by the HPET (which programs itself for the next one)
 	// SAFE: Safe assembly, just halts
 	unsafe { asm!("sti;hlt" : : : : "volatile"); }
 }
 
+/// Arm (or stop) the LAPIC timer used to end the current time slice
+pub fn set_slice_timer(enabled: bool)
+{
+	// - The profiler samples on the same interrupt, so keep it running while profiling
+	super::hw::apic::set_local_timer(enabled || ::profiler::sample_depth().is_some());
+}
+
 /// Prepares the TLS block at the stop of a kernel stack
 #[no_mangle]
 pub unsafe extern "C" fn prep_tls(top: usize, _bottom: usize, thread_ptr: *mut ::threads::Thread) -> usize
@@ -115,6 +172,7 @@ pub unsafe extern "C" fn prep_tls(top: usize, _bottom: usize, thread_ptr: *mut :
 		
 		thread_ptr: thread_ptr,
 		thread_ptr_lent: false,
+		cpu: 0,
 		sse_registers: None,
 		});
 	
@@ -181,23 +239,34 @@ pub fn start_thread<F: FnOnce()+Send>(thread: &mut ::threads::Thread, code: F)
 		let code = unsafe { ::core::ptr::read(code_ptr) };
 		// 1. Run closure
 		code();
-		// 2. terminate thread
-		panic!("TODO: Terminate thread at end of thread_root");
+		// 2. terminate thread (waking anything joining it)
+		::threads::exit_thread(0);
 	}
 }
 
+/// Returns the idle thread for the current CPU
 pub fn get_idle_thread() -> ::threads::ThreadPtr
 {
+	let cpu = cpu_index();
 	// TODO: Shared mutability shouldn't be an issue (this thread pointer should not be created twice)
 	// SAFE: Passes a static pointer. `static mut` should be initialised
 	unsafe {
-		assert!(S_IDLE_THREAD != 0 as *mut _);
-		::threads::ThreadPtr::new_static( &mut *S_IDLE_THREAD )
+		assert!(S_IDLE_THREADS[cpu] != 0 as *mut _);
+		::threads::ThreadPtr::new_static( &mut *S_IDLE_THREADS[cpu] )
+	}
+}
+
+/// Returns the index of the current CPU
+pub fn cpu_index() -> usize
+{
+	// SAFE: Valid pointer access, threads can only change CPU when rescheduled
+	unsafe {
+		(*get_tls_ptr()).cpu
 	}
 }
 
 /// Switch to the passed thread (suspending the current thread until it is rescheduled)
-pub fn switch_to(newthread: ::threads::ThreadPtr)
+pub fn switch_to(mut newthread: ::threads::ThreadPtr)
 {
 	if is_task_switching_disabled()
 	{
@@ -213,22 +282,37 @@ pub fn switch_to(newthread: ::threads::ThreadPtr)
 			assert!(flags & 0x200 != 0, "switch_to() with IF clear, RFLAGS = {:#x}", flags);
 		}
 
+		// Switching to self (e.g. the only runnable thread yielded), the saved state is stale
+		if &*newthread as *const _ == borrow_thread() {
+			// SAFE: Valid pointer access
+			unsafe {
+				(*get_tls_ptr()).thread_ptr_lent = false;
+			}
+			::core::mem::forget(newthread);
+			return ;
+		}
+
 		disable_sse();
 		
 		// SAFE: Valid pointer accesses, task_switch trusted
 		unsafe
 		{
+			let cpu = cpu_index();
 			let outstate = &mut (*(*get_tls_ptr()).thread_ptr).cpu_state;
-			let state = &newthread.cpu_state;
-			// Don't assert RSP, could be switching to self
-			// - Wait, wouldn't that break the aliasing rules?
+			let state = &mut newthread.cpu_state;
+			// The thread could have been woken by another CPU before it finished switching away, wait until its state is saved
+			while ::core::intrinsics::volatile_load(&state.rsp) == 0 {
+			}
 			assert!(state.cr3 != 0);
 			assert!(state.tlsbase != 0);
 			//log_trace!("Switching to RSP={:#x},CR3={:#x},TLS={:#x}", state.rsp, state.cr3, state.tlsbase);
 			
 			assert!( *(outstate.tlsbase as *const usize) != 0, "outstate TLS Base clobbered before switch" );
 			assert!( *(state.tlsbase as *const usize) != 0, "TLS Base clobbered before switch" );
-			task_switch(&mut outstate.rsp, &state.rsp, state.tlsbase, state.cr3);
+			// Userland TLS (FS base), kernel doesn't use FS so this can be done before the switch
+			asm!("wrmsr" : : "{ecx}" (0xC0000100u32), "{eax}" (state.user_tlsbase as u32), "{edx}" ((state.user_tlsbase >> 32) as u32) : : "volatile");
+			(*(state.tlsbase as *mut TLSData)).cpu = cpu;
+			task_switch(&mut outstate.rsp, &mut state.rsp, state.tlsbase, state.cr3, super::tss::get_ptr(cpu));
 		}
 		
 		// SAFE: Valid pointer access
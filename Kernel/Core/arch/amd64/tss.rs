@@ -7,8 +7,7 @@
 // Just a run-of-the-mill module, as it's not needed until the switch to usermode
 module_define!(TSS, [], init);
 
-// NOTE: MUST match the value in common.inc.asm
-const MAX_CPUS: usize = 1;
+use super::threads::MAX_CPUS;
 
 #[repr(C,packed)]
 struct TSS
@@ -28,7 +27,7 @@ struct GDTEnt(u32,u32);
 
 extern "C" {
 	static mut GDT: [GDTEnt; 7+MAX_CPUS*2];
-	static mut TSSes: [TSS; 4];
+	static mut TSSes: [TSS; MAX_CPUS];
 	
 	static s_tid0_tls_base: u64;
 }
@@ -45,9 +44,28 @@ fn init()
 		TSSes[0].rsp0 = s_tid0_tls_base as u64;
 	}
 	
-	// SAFE: Just setting the task register
+	load_tr(0);
+}
+
+/// Load the task register for an application processor
+pub fn init_ap(cpu: usize)
+{
+	load_tr(cpu);
+}
+
+/// Obtain a pointer to the TSS for the given CPU (passed to `task_switch`)
+pub fn get_ptr(cpu: usize) -> *mut () {
+	assert!(cpu < MAX_CPUS);
+	// SAFE: Only taking the address
+	unsafe { &mut TSSes[cpu] as *mut TSS as *mut () }
+}
+
+fn load_tr(cpu: usize)
+{
+	assert!(cpu < MAX_CPUS);
+	// SAFE: Just setting the task register (each CPU has its own descriptor)
 	unsafe {
-		asm!("ltr %cx" : : "{ecx}" (7*8) );
+		asm!("ltr %cx" : : "{ecx}" ((7+cpu*2)*8) );
 	}
 }
 
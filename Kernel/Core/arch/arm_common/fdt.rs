@@ -112,6 +112,17 @@ impl<'a> FDTRoot<'a>
 			cur_depth: 0,
 		}
 	}
+
+	/// Address and size cell counts for the root node's children (`#address-cells`, `#size-cells`)
+	pub fn root_cells(&self) -> (u32, u32) {
+		let get = |name: &str, default: u32| self.get_props(&["", name]).next().map(|v| BigEndian::read_u32(v)).unwrap_or(default);
+		// Defaults from the devicetree specification
+		(get("#address-cells", 2), get("#size-cells", 1))
+	}
+	/// Locate the first enabled top-level node that is compatible with `compat`
+	pub fn find_compatible<'s>(&'s self, compat: &str) -> Option<Node<'s, 'a>> {
+		self.get_nodes(&[""]).filter(|n| n.is_enabled()).find(|n| n.is_compatible(compat))
+	}
 }
 
 impl<'a> FDTRoot<'a>
@@ -294,6 +305,42 @@ impl<'a, 'fdt: 'a> Node<'a, 'fdt>
 			.filter_map( |(n, val)| if n == name { if let Item::Prop(p) = val { Some(p) } else { None } } else { None } )
 			.next()
 	}
+
+	/// Check if any entry in the `compatible` string list matches
+	pub fn is_compatible(&self, compat: &str) -> bool {
+		match self.get_prop("compatible")
+		{
+		Some(v) => v.split(|b| *b == 0).any(|s| s == compat.as_bytes()),
+		None => false,
+		}
+	}
+	/// Returns false if the `status` property marks this node as unavailable
+	pub fn is_enabled(&self) -> bool {
+		match self.get_prop("status")
+		{
+		Some(v) => v == b"okay\0" || v == b"ok\0",
+		None => true,
+		}
+	}
+	/// Decode entry `idx` of the `reg` property, `cells` is the parent's (address, size) cell counts
+	pub fn get_reg(&self, idx: usize, cells: (u32, u32)) -> Option<(u64, u64)> {
+		let (acells, scells) = cells;
+		let ent_size = (acells + scells) as usize * 4;
+		match self.get_prop("reg")
+		{
+		Some(data) if ent_size > 0 && (idx + 1) * ent_size <= data.len() => {
+			let ent = &data[idx * ent_size ..][.. ent_size];
+			let (a, s) = ent.split_at(acells as usize * 4);
+			Some( (read_cells(a), read_cells(s)) )
+			},
+		_ => None,
+		}
+	}
+}
+
+/// Decode a big-endian multi-cell value (values wider than 64 bits are truncated)
+fn read_cells(data: &[u8]) -> u64 {
+	data.chunks(4).fold(0, |acc, c| (acc << 32) | BigEndian::read_u32(c) as u64)
 }
 
 pub enum Item<'a, 'fdt: 'a> {
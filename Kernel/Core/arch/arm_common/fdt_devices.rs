@@ -12,28 +12,38 @@ struct BusDev
 {
 	node: fdt::Node<'static, 'static>,
 	compat: &'static str,
-	mmio: Option< (u64, u32) >,
+	/// Parent's (address, size) cell counts, used to decode `reg`
+	reg_cells: (u32, u32),
 	irq_gsi: Option<u32>,
 }
 
 fn init() {
 	if let Some(fdt) = super::boot::get_fdt()
 	{
-		let root_node = fdt.get_nodes(&[]).next().unwrap();
-		let (scells,) = decode_value(&root_node, "#size-cells", (1,)).unwrap_or( (0,) );
-		let (acells,) = decode_value(&root_node, "#address-cells", (1,)).unwrap_or( (0,) );
+		let reg_cells = fdt.root_cells();
 
 		let mut devices: Vec<Box<::device_manager::BusDevice>> = Vec::new();
 		for dev in fdt.get_nodes(&[""])
 		{
+			if !dev.is_enabled() {
+				log_debug!("dev '{}' disabled", dev.name());
+				continue ;
+			}
 			if let Some(compat) = dev.items().filter_map(|r| match r { ("compatible", fdt::Item::Prop(v)) => Some(v), _ => None }).next()
 			{
 				let compat = ::core::str::from_utf8(compat).unwrap_or("");
 				
 				log_debug!("dev '{}' compat = '{}'", dev.name(), compat);
-				let mmio = if let Some( (io_base, io_size) ) = decode_value(&dev, "reg", (acells, scells)) {
-						log_debug!("- IO {:#x}+{:#x}", io_base, io_size);
-						Some( (io_base, io_size as u32) )
+				let mut i = 0;
+				while let Some( (io_base, io_size) ) = dev.get_reg(i, reg_cells) {
+					log_debug!("- IO{} {:#x}+{:#x}", i, io_base, io_size);
+					i += 1;
+				}
+				// GIC interrupt specifier: (type, number, flags) - type 0 = SPI (ID 32+n), 1 = PPI (ID 16+n)
+				let irq_gsi = if let Some( (kind, num, _flags) ) = decode_value(&dev, "interrupts", (1, 1, 1)) {
+						let gsi = if kind == 0 { 32 + num } else { 16 + num };
+						log_debug!("- IRQ {}", gsi);
+						Some(gsi as u32)
 					}
 					else {
 						None
@@ -42,8 +52,8 @@ fn init() {
 				devices.push( Box::new(BusDev {
 					node: dev,
 					compat: compat,
-					mmio: mmio,
-					irq_gsi: None,
+					reg_cells: reg_cells,
+					irq_gsi: irq_gsi,
 					}) );
 			}
 		}
@@ -85,16 +95,16 @@ impl ::device_manager::BusDevice for BusDev
 	fn bind_io(&mut self, block_id: usize) -> ::device_manager::IOBinding {
 		match block_id
 		{
-		0 => if let Some((base, size)) = self.mmio {
+		// Each entry in `reg` is an IO block
+		_ => if let Some((base, size)) = self.node.get_reg(block_id, self.reg_cells) {
 				// TODO: Ensure safety
 				// SAFE: Can't easily prove
 				let ah = unsafe { ::memory::virt::map_mmio(base as ::memory::PAddr, size as usize).unwrap() };
 				::device_manager::IOBinding::Memory( ah )
 			}
 			else {
-				panic!("No MMIO block");
+				panic!("No MMIO block {} for fdt_devices::BusDev::bind_io", block_id);
 			},
-		_ => panic!("Unknown block_id {} for fdt_devices::BusDev::bind_io", block_id),
 		}
 	}
 	fn get_irq(&mut self, idx: usize) -> u32 {
@@ -139,4 +149,9 @@ impl<T> Tuple<T> for (T,T,) {
 		(f(self.0),f(self.1),)
 	}
 }
+impl<T> Tuple<T> for (T,T,T,) {
+	fn map<F>(self, mut f: F) -> Self where F: FnMut(T)->T {
+		(f(self.0),f(self.1),f(self.2),)
+	}
+}
 
@@ -35,8 +35,8 @@ pub fn mark_free(frame_idx: u64) -> bool {
 	// HACK: Assume it was used
 	true
 }
-pub fn mark_used(frame_idx: u64) {
-	log_warning!("TODO: mark_used - frame_idx={:#x} ({:#x})", frame_idx, frame_idx*::PAGE_SIZE as u64);
+pub fn mark_used(_frame_idx: u64) {
+	// TODO: Allocation bitmap (`mark_free` currently assumes that all frames are used)
 }
 
 
@@ -658,7 +658,12 @@ impl AddressSpace
 			match flags_to_prot_mode(mode_flags)
 			{
 			ProtectionMode::Unmapped => {},
-			ProtectionMode::UserCOW => todo!("Clone when COW"),
+			ProtectionMode::UserCOW => {
+				// Share the frame, the first write in either address space will copy it
+				::memory::phys::ref_frame( (src_slot_0_val & !PAGE_MASK_U32) as PAddr );
+				dst_slots[0] = src_slot_0_val;
+				dst_slots[1] = user_last_table()[ofs+1].load(Ordering::Relaxed);
+				},
 			ProtectionMode::UserRW | ProtectionMode::UserRO | ProtectionMode::UserRX => {
 				let src_ptr = (page * ::PAGE_SIZE) as *const u8;
 				// SAFE: Memory is valid (TODO: What if this changes? Shouldn't cause errors, just inconsistent user data)
@@ -695,6 +700,37 @@ pub struct AbortRegs
 	ret_pc: u32,	// SRSFD/RFEFD state
 	spsr: u32,
 }
+/// Give the current address space its own copy of a copy-on-write page (does nothing if the page isn't COW)
+///
+/// Returns `Err` if a frame couldn't be allocated for the copy.
+pub fn unshare_cow(addr: usize) -> Result<(), ()>
+{
+	let mut rv = Ok( () );
+	// 1. Lock (relevant) address space
+	// SAFE: Changes to address space are transparent
+	::memory::virt::with_lock(addr, || unsafe {
+		// - Checked under the lock, as another CPU may have already handled a fault on this page
+		let mut ent = PageEntry::get(addr as *const ());
+		if ent.mode() != ProtectionMode::UserCOW {
+			return ;
+		}
+		let frame = ent.phys_addr();
+		// 2. Get the PMM to provide us with a unique copy of that frame (can return the same addr)
+		match ::memory::phys::make_unique( frame, &*((addr & !PAGE_MASK) as *const [u8; PAGE_SIZE]) )
+		{
+		Ok(newframe) => {
+			// 3. Remap to this page as UserRW (because COW is user-only atm)
+			ent.set(newframe, ProtectionMode::UserRW);
+			log_debug!("- COW frame copied");
+			},
+		Err(_) => {
+			rv = Err( () );
+			},
+		}
+		});
+	rv
+}
+
 #[no_mangle]
 pub fn data_abort_handler(pc: u32, reg_state: &AbortRegs, dfar: u32, dfsr: u32) {
 
@@ -703,20 +739,18 @@ pub fn data_abort_handler(pc: u32, reg_state: &AbortRegs, dfar: u32, dfsr: u32)
 	//log_debug!("Registers:");
 	//log_debug!("R 0 {:08x}  R 1 {:08x}  R 2 {:08x}  R 3 {:08x}  R 4 {:08x}  R 5 {:08x}}  R 6 {:08x}", reg_state.gprs[0]);
 	
-	let mut ent = PageEntry::get(dfar as usize as *const ());
+	let ent = PageEntry::get(dfar as usize as *const ());
 	if ent.mode() == ProtectionMode::UserCOW {
-		// 1. Lock (relevant) address space
-		// SAFE: Changes to address space are transparent
-		::memory::virt::with_lock(dfar as usize, || unsafe {
-			let frame = ent.phys_addr();
-			// 2. Get the PMM to provide us with a unique copy of that frame (can return the same addr)
-			let newframe = ::memory::phys::make_unique( frame, &*(((dfar as usize) & !PAGE_MASK) as *const [u8; PAGE_SIZE]) );
-			// 3. Remap to this page as UserRW (because COW is user-only atm)
-			ent.set(newframe, ProtectionMode::UserRW);
-			log_debug!("- COW frame copied");
-			});
-
-		return ;
+		if unshare_cow(dfar as usize).is_ok() {
+			return ;
+		}
+		log_warning!("Out of memory handling COW write to {:#x}", dfar);
+		// Last resort: a user process that can't obtain memory is killed
+		// - Kernel-mode aborts can't exit in place (locks may be held), but syscalls unshare user buffers up-front
+		//   (see `FreezeMut`) so any left are a kernel bug, reported below.
+		if pc < 0x8000_0000 {
+			::memory::pressure::kill_current_process(dfar as usize);
+		}
 	}
 	
 	if pc < 0x8000_0000 {
@@ -16,7 +16,11 @@ pub mod pci;
 
 pub mod threads;
 
+mod timer;
+
+#[path="../arm_common/fdt.rs"]
 mod fdt;
+#[path="../arm_common/fdt_devices.rs"]
 mod fdt_devices;
 
 mod aeabi_unwind;
@@ -40,6 +44,7 @@ extern "C" {
 fn init()
 {
 	interrupts::init();
+	timer::init();
 }
 
 #[no_mangle]
@@ -107,7 +112,10 @@ pub fn puth(v: u64) {
 }
 
 pub fn cur_timestamp() -> u64 {
-	0
+	timer::cur_timestamp()
+}
+pub fn request_tick(_deadline: u64) {
+	// No timer interrupt yet, `threads::idle` arms the timer for the next deadline before waiting
 }
 
 pub fn print_backtrace() {
@@ -4,6 +4,8 @@
 pub struct State {
 	sp: usize,
 	ttbr0: u32,
+	/// Userland TLS base (loaded into TPIDRURO on switch)
+	user_tls: usize,
 	stack_handle: Option< ::memory::virt::ArrayHandle<u8> >,
 }
 
@@ -13,9 +15,15 @@ impl State
 		State {
 			sp: 0,
 			ttbr0: address_space.get_ttbr0(),
+			user_tls: 0,
 			stack_handle: None,
 		}
 	}
+
+	/// Set the userland TLS base (TPIDRURO) used when this thread is running
+	pub fn set_user_tls(&mut self, base: usize) {
+		self.user_tls = base;
+	}
 }
 
 pub fn init_tid0_state() -> State {
@@ -32,6 +40,20 @@ pub fn get_idle_thread() -> ::threads::ThreadPtr {
 	todo!("get_idle_thread");
 }
 
+/// Only a single CPU is supported
+pub const MAX_CPUS: usize = 1;
+pub fn cpu_index() -> usize {
+	0
+}
+pub fn cpu_count() -> usize {
+	1
+}
+pub fn send_reschedule(_cpu: usize) {
+}
+pub fn set_slice_timer(_enabled: bool) {
+	// TODO: No scheduler timer on this platform yet
+}
+
 pub fn set_thread_ptr(thread: ::threads::ThreadPtr) {
 	let real = borrow_thread_mut();
 	if real.is_null() {
@@ -82,15 +104,26 @@ pub fn switch_to(thread: ::threads::ThreadPtr) {
 		let new_sp = thread.cpu_state.sp;
 		let new_ttbr0 = thread.cpu_state.ttbr0;
 		log_trace!("Switching to SP={:#x},TTBR0={:#x}", new_sp, new_ttbr0);
+		asm!("mcr p15,0, $0, c13,c0,3" : : "r" (thread.cpu_state.user_tls) : : "volatile");
 		task_switch(&mut outstate.sp, new_sp, new_ttbr0, thread.into_usize());
 	}
 }
 pub fn idle() {
 	log_trace!("idle");
-	// SAFE: Calls 'wait for interrupt'
-	unsafe {
-		asm!("wfi" : : : : "volatile");
+	// There's no timer interrupt handling yet (see `super::timer`), so arm the timer for the next deadline and wait
+	// with IRQs masked. The pending interrupt wakes `wfi` without being taken, and is cleared before unmasking.
+	{
+		let _irq = super::sync::hold_interrupts();
+		if let Some(deadline) = ::time::next_deadline() {
+			super::timer::set_compare(deadline);
+		}
+		// SAFE: Calls 'wait for interrupt'
+		unsafe {
+			asm!("wfi" : : : : "volatile");
+		}
+		super::timer::clear_compare();
 	}
+	::time::time_tick();
 }
 
 pub fn start_thread<F: FnOnce()+Send+'static>(thread: &mut ::threads::Thread, code: F) {
@@ -129,8 +162,8 @@ pub fn start_thread<F: FnOnce()+Send+'static>(thread: &mut ::threads::Thread, co
 		// 1. Run closure
 		// SAFE: Functionally owns that pointer
 		(unsafe { ::core::ptr::read(code_ptr) })();
-		// 2. terminate thread
-		panic!("TODO: Terminate thread at end of thread_root");
+		// 2. terminate thread (waking anything joining it)
+		::threads::exit_thread(0);
 	}
 }
 
@@ -0,0 +1,71 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/arch/armv7/timer.rs
+//! ARM Generic Timer
+//!
+//! Timer interrupts need the GIC, which isn't supported yet. Instead `threads::idle` arms the virtual timer's compare
+//! for the next deadline and waits with IRQs masked (the pending timer interrupt still wakes `wfi`).
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counter frequency (Hz), read from CNTFRQ at init
+static S_FREQUENCY: AtomicUsize = AtomicUsize::new(0);
+
+pub fn init()
+{
+	let pfr1: u32;
+	// SAFE: Read-only register access
+	unsafe { asm!("mrc p15,0, $0, c0,c1,1" : "=r"(pfr1)); }
+	// - ID_PFR1[19:16] is non-zero if the generic timer is implemented
+	// NOTE: Without a time source every timed wait would hang, so refuse to continue
+	assert!((pfr1 >> 16) & 0xF != 0, "ARM generic timer not implemented, armv7 requires it for timekeeping");
+
+	let freq: u32;
+	// SAFE: Read-only register access
+	unsafe { asm!("mrc p15,0, $0, c14,c0,0" : "=r"(freq)); }
+	log_debug!("Generic timer: {} Hz", freq);
+	assert!(freq != 0, "CNTFRQ not set by firmware");
+	S_FREQUENCY.store(freq as usize, Ordering::Relaxed);
+}
+
+/// Current counter value in milliseconds
+pub fn cur_timestamp() -> u64
+{
+	let freq = S_FREQUENCY.load(Ordering::Relaxed) as u64;
+	if freq == 0 {
+		return 0;
+	}
+	let count = read_counter();
+	// Split to avoid overflowing the multiplication
+	(count / freq) * 1000 + (count % freq) * 1000 / freq
+}
+
+/// Arm the virtual timer to assert its interrupt once the timestamp reaches `deadline` (milliseconds)
+pub fn set_compare(deadline: u64)
+{
+	let freq = S_FREQUENCY.load(Ordering::Relaxed) as u64;
+	// Split to avoid overflowing the multiplication
+	let cval = (deadline / 1000) * freq + (deadline % 1000) * freq / 1000;
+	let (lo, hi) = (cval as u32, (cval >> 32) as u32);
+	// SAFE: Only affects the virtual timer, which nothing else uses
+	unsafe {
+		// CNTV_CVAL, then CNTV_CTL = ENABLE (IMASK clear)
+		asm!("mcrr p15,3, $0, $1, c14" : : "r"(lo), "r"(hi) : : "volatile");
+		asm!("mcr p15,0, $0, c14,c3,1; isb" : : "r"(1u32) : : "volatile");
+	}
+}
+/// Disable the virtual timer (de-asserting its interrupt)
+pub fn clear_compare()
+{
+	// SAFE: Only affects the virtual timer, which nothing else uses
+	unsafe { asm!("mcr p15,0, $0, c14,c3,1; isb" : : "r"(0u32) : : "volatile"); }
+}
+
+fn read_counter() -> u64
+{
+	let lo: u32;
+	let hi: u32;
+	// SAFE: Read-only register access (CNTVCT)
+	unsafe { asm!("isb; mrrc p15,1, $0, $1, c14" : "=r"(lo), "=r"(hi) : : : "volatile"); }
+	(hi as u64) << 32 | lo as u64
+}
@@ -62,8 +62,22 @@ impl BootInfo
 			// SAFE: In practice, this is run in a single-thread. Any possible race would be benign
 			unsafe {
 				const FLAGS: u64 = 0x403;
+				const PAGE_SIZE: usize = 0x4000;
+				// Limit on the mapped size of the tree (QEMU pads its tree to 1MB)
+				const MAX_PAGES: usize = 128;
+				let page_base = dt_phys_base & !(PAGE_SIZE as u64 - 1);
+				let page_ofs = (dt_phys_base & (PAGE_SIZE as u64 - 1)) as usize;
 				assert_eq!(kernel_hwmap_level3[1], 0);
-				kernel_hwmap_level3[1] = (dt_phys_base & !0x3FFF) + FLAGS;
+				kernel_hwmap_level3[1] = page_base + FLAGS;
+				// - Map the remainder of the tree (length is the header's `totalsize` field)
+				let header = (super::memory::addresses::HARDWARE_BASE + PAGE_SIZE + page_ofs) as *const u32;
+				let len = u32::from_be( ::core::intrinsics::volatile_load(header.offset(1)) ) as usize;
+				let n_pages = (page_ofs + len + PAGE_SIZE - 1) / PAGE_SIZE;
+				assert!(n_pages <= MAX_PAGES, "FDT too large ({:#x} bytes)", len);
+				for i in 1 .. n_pages {
+					assert_eq!(kernel_hwmap_level3[1+i], 0);
+					kernel_hwmap_level3[1+i] = page_base + (i * PAGE_SIZE) as u64 + FLAGS;
+				}
 			}
 
 			// SAFE: Address range checked
@@ -0,0 +1,125 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/arch/armv8/cpu_faults.rs
+//! Synchronous exception (fault) handlers
+use super::{puts,puth};
+
+#[repr(C)]
+/// Register state as saved by the exception entry code (`SAVE_REGS` in start.S)
+pub struct Regs
+{
+	gprs: [u64; 31],
+	sp_el0: u64,
+	elr: u64,
+	spsr: u64,
+}
+impl Regs
+{
+	/// True if the exception was taken from EL0
+	pub fn is_user(&self) -> bool {
+		self.spsr & 0xF == 0
+	}
+	/// Address the exception returns to
+	pub fn ip(&self) -> u64 {
+		self.elr
+	}
+	/// Interrupted frame pointer (X29)
+	pub fn fp(&self) -> u64 {
+		self.gprs[29]
+	}
+	fn dump(&self) {
+		puts("ELR  = "); puth(self.elr); puts("  SPSR = "); puth(self.spsr); puts("\n");
+		puts("SP_EL0 = "); puth(self.sp_el0); puts("\n");
+		for (i,v) in self.gprs.iter().enumerate() {
+			puts("X"); puth(i as u64); puts(" = "); puth(*v);
+			puts(if i % 4 == 3 { "\n" } else { "  " });
+		}
+		puts("\n");
+	}
+}
+
+/// Exception classes (ESR_EL1.EC) handled here
+mod ec {
+	pub const INSTR_ABORT_LOWER: u64 = 0x20;
+	pub const INSTR_ABORT_CUR: u64 = 0x21;
+	pub const DATA_ABORT_LOWER: u64 = 0x24;
+	pub const DATA_ABORT_CUR: u64 = 0x25;
+}
+
+#[no_mangle]
+#[doc(hidden)]
+/// Synchronous exception handler called by assembly (system calls are handled before this)
+pub extern "C" fn sync_exception_handler(regs: &mut Regs, esr: u64, far: u64)
+{
+	let class = esr >> 26;
+	match class
+	{
+	ec::INSTR_ABORT_LOWER | ec::INSTR_ABORT_CUR | ec::DATA_ABORT_LOWER | ec::DATA_ABORT_CUR => {
+		// ISS.WnR (bit 6) is only valid for data aborts
+		let is_write = (class == ec::DATA_ABORT_LOWER || class == ec::DATA_ABORT_CUR) && esr & (1 << 6) != 0;
+		if super::memory::virt::handle_page_fault(far as usize, is_write, class == ec::INSTR_ABORT_LOWER || class == ec::DATA_ABORT_LOWER) {
+			return ;
+		}
+		puts("Abort ("); puth(esr); puts(") at "); puth(far); puts(" by "); puth(regs.elr); puts("\n");
+		},
+	_ => {
+		puts("Exception ESR="); puth(esr); puts(" (EC "); puth(class); puts(") FAR="); puth(far); puts("\n");
+		},
+	}
+	regs.dump();
+
+	if regs.is_user() {
+		// It's a user fault, terminate the process
+		// SAFE: Exception state has been consumed, the thread is about to exit
+		unsafe { super::sync::start_interrupts(); }
+		// EXITSTATUS_KILLED in the syscall ABI
+		::threads::exit_process(0xFFFF_FFFE);
+	}
+	else {
+		panic!("Kernel fault: ESR={:#x} FAR={:#x} ELR={:#x}", esr, far, regs.elr);
+	}
+}
+
+#[no_mangle]
+#[doc(hidden)]
+/// Handler for exception vectors that should never be taken (IRQs on SP_EL0, FIQ, SError, AArch32)
+pub extern "C" fn unexpected_exception(vector: u64, regs: &Regs, esr: u64)
+{
+	puts("Unexpected exception vector "); puth(vector); puts(" ESR="); puth(esr); puts("\n");
+	regs.dump();
+	panic!("Unexpected exception (vector {})", vector);
+}
+
+/// Walk one frame of a kernel stack, returning the caller's frame pointer and the return address
+///
+/// The returned frame pointer is zero if the chain can't be followed further.
+pub fn backtrace(fp: u64) -> Option<(u64,u64)>
+{
+	if fp == 0 || fp % 16 != 0 {
+		return None;
+	}
+	if ! ::memory::buf_valid(fp as *const (), 16) {
+		return None;
+	}
+	// [fp] = old fp, [fp+8] = LR
+	// SAFE: Pointer access checked, any alias is benign
+	unsafe
+	{
+		let ptr: *const [u64; 2] = fp as usize as *const _;
+		if ! super::memory::virt::is_reserved(ptr) {
+			None
+		}
+		else {
+			let newfp = (*ptr)[0];
+			let lr = (*ptr)[1];
+			// Frames must move upwards on the stack, otherwise stop here
+			if newfp <= fp {
+				Some( (0, lr) )
+			}
+			else {
+				Some( (newfp, lr) )
+			}
+		}
+	}
+}
@@ -1,10 +1,249 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/arch/armv8/interrupts.rs
+//! ARMv8 interrupt handling (GICv2)
+use lib::Vec;
+use lib::LazyStatic;
+use sync::Spinlock;
 
-#[derive(Default)]
-pub struct IRQHandle;
-#[derive(Debug)]
-pub struct BindError;
+pub type BindError = ();
 
-pub fn bind_gsi(idx: usize, handler: fn(*const ()), info: *const ()) -> Result<IRQHandle,BindError> {
-	Ok(IRQHandle)
+/// Handle to a bound interrupt, the binding is removed (and the interrupt disabled) when dropped
+pub struct IRQHandle(u32);
+impl Default for IRQHandle {
+	fn default() -> IRQHandle { IRQHandle(!0) }
+}
+impl Drop for IRQHandle {
+	fn drop(&mut self) {
+		if self.0 != !0 {
+			S_GIC.set_enabled(self.0, false);
+			*S_IRQS[self.0 as usize].lock() = None;
+		}
+	}
+}
+
+struct Binding {
+	handler: fn ( *const() ),
+	info: *const (),
+}
+unsafe impl Send for Binding {}
+
+/// Device tree `compatible` values for GICv2-compatible interrupt controllers
+const GIC_COMPATIBLE: &'static [&'static str] = &["arm,cortex-a15-gic", "arm,gic-400", "arm,cortex-a9-gic"];
+
+struct Gic
+{
+	dist: ::memory::virt::AllocHandle,
+	cpu: ::memory::virt::AllocHandle,
+}
+
+#[allow(dead_code)]
+mod regs {
+	// Distributor registers (u32 indexes)
+	pub const GICD_CTLR: usize = 0x000 / 4;
+	pub const GICD_TYPER: usize = 0x004 / 4;
+	pub const GICD_ISENABLER: usize = 0x100 / 4;
+	pub const GICD_ICENABLER: usize = 0x180 / 4;
+	pub const GICD_IPRIORITYR: usize = 0x400 / 4;
+	pub const GICD_ITARGETSR: usize = 0x800 / 4;
+	pub const GICD_ICFGR: usize = 0xC00 / 4;
+	// CPU interface registers (u32 indexes)
+	pub const GICC_CTLR: usize = 0x000 / 4;
+	pub const GICC_PMR: usize = 0x004 / 4;
+	pub const GICC_IAR: usize = 0x00C / 4;
+	pub const GICC_EOIR: usize = 0x010 / 4;
+}
+
+/// Interrupt ID returned by IAR when there is no pending interrupt
+const SPURIOUS_IRQ: u32 = 1023;
+
+/// Per-CPU register state saved by the IRQ currently being handled (for the profiler)
+static mut S_INTERRUPTED: [*const super::cpu_faults::Regs; super::threads::MAX_CPUS] = [0 as *const _; super::threads::MAX_CPUS];
+
+static S_GIC: LazyStatic<Gic> = lazystatic_init!();
+static S_IRQS: LazyStatic<Vec< Spinlock<Option<Binding>> >> = lazystatic_init!();
+
+pub fn init() {
+	// Locate the GIC in the device tree, `reg` is the distributor followed by the CPU interface
+	let fdt = super::boot::get_fdt().expect("armv8 requires a device tree");
+	let cells = fdt.root_cells();
+	let (gicd_base, gicc_base) = match GIC_COMPATIBLE.iter().filter_map(|c| fdt.find_compatible(c)).next()
+		{
+		Some(node) => match (node.get_reg(0, cells), node.get_reg(1, cells))
+			{
+			(Some((d,_)), Some((c,_))) => (d, c),
+			_ => panic!("GIC node '{}' is missing register blocks", node.name()),
+			},
+		None => panic!("No supported interrupt controller in the device tree"),
+		};
+	log_debug!("GICv2: GICD={:#x} GICC={:#x}", gicd_base, gicc_base);
+	// SAFE: Called in a single-threaded context, and nothing else maps the GIC
+	unsafe {
+		S_GIC.prep(|| Gic {
+			dist: ::memory::virt::map_hw_rw(gicd_base, 1, "GIC").unwrap(),
+			cpu: ::memory::virt::map_hw_rw(gicc_base, 1, "GIC").unwrap(),
+			});
+	}
+	// GICD_TYPER.ITLinesNumber = number of 32-interrupt blocks minus one
+	let n_irqs = ((S_GIC.dist_read(regs::GICD_TYPER) & 0x1F) as usize + 1) * 32;
+	log_debug!("GICv2: {} interrupt lines", n_irqs);
+	// SAFE: Called in a single-threaded context
+	unsafe {
+		S_IRQS.prep(|| Vec::from_fn(n_irqs, |_| Default::default()));
+	}
+
+	// Disable everything, then enable the distributor and this CPU's interface (accepting all priorities)
+	for i in 0 .. n_irqs / 32 {
+		S_GIC.dist_write(regs::GICD_ICENABLER + i, !0);
+	}
+	S_GIC.dist_write(regs::GICD_CTLR, 1);
+	S_GIC.cpu_write(regs::GICC_PMR, 0xFF);
+	S_GIC.cpu_write(regs::GICC_CTLR, 1);
+}
+
+impl Gic
+{
+	fn dist_read(&self, idx: usize) -> u32 {
+		// SAFE: Aligned hardware access within the mapped page
+		unsafe { ::core::intrinsics::volatile_load( &self.dist.as_ref::<[u32; 0x1000/4]>(0)[idx] ) }
+	}
+	fn dist_write(&self, idx: usize, val: u32) {
+		// SAFE: Aligned hardware access within the mapped page
+		unsafe { ::core::intrinsics::volatile_store( &mut self.dist.as_int_mut::<[u32; 0x1000/4]>(0)[idx], val ) }
+	}
+	fn cpu_read(&self, idx: usize) -> u32 {
+		// SAFE: Aligned hardware access within the mapped page
+		unsafe { ::core::intrinsics::volatile_load( &self.cpu.as_ref::<[u32; 0x1000/4]>(0)[idx] ) }
+	}
+	fn cpu_write(&self, idx: usize, val: u32) {
+		// SAFE: Aligned hardware access within the mapped page
+		unsafe { ::core::intrinsics::volatile_store( &mut self.cpu.as_int_mut::<[u32; 0x1000/4]>(0)[idx], val ) }
+	}
+
+	fn set_enabled(&self, irq: u32, enabled: bool) {
+		let reg = if enabled { regs::GICD_ISENABLER } else { regs::GICD_ICENABLER };
+		self.dist_write(reg + irq as usize / 32, 1 << (irq % 32));
+	}
+	/// Route a shared interrupt to CPU 0 at the default priority
+	fn configure(&self, irq: u32) {
+		let shift = (irq % 4) * 8;
+		let idx = irq as usize / 4;
+		let v = self.dist_read(regs::GICD_IPRIORITYR + idx);
+		self.dist_write(regs::GICD_IPRIORITYR + idx, (v & !(0xFF << shift)) | (0xA0 << shift));
+		// - ITARGETSR is read-only for SGIs/PPIs
+		if irq >= 32 {
+			let v = self.dist_read(regs::GICD_ITARGETSR + idx);
+			self.dist_write(regs::GICD_ITARGETSR + idx, (v & !(0xFF << shift)) | (0x01 << shift));
+		}
+	}
+}
+
+#[linkage="external"]
+#[no_mangle]
+/// IRQ handler called by assembly
+///
+/// `frame` is the interrupted register state (saved on the stack by `SAVE_REGS`)
+pub extern "C" fn interrupt_handler(frame: &super::cpu_faults::Regs)
+{
+	let iar = S_GIC.cpu_read(regs::GICC_IAR);
+	let irq = iar & 0x3FF;
+	if irq == SPURIOUS_IRQ {
+		return ;
+	}
+	let cpu = super::threads::cpu_index();
+	// SAFE: Per-CPU, interrupts are disabled while handling an IRQ
+	unsafe {
+		S_INTERRUPTED[cpu] = frame;
+	}
+
+	if irq as usize >= S_IRQS.len() {
+		// ... No idea!
+	}
+	else {
+		match S_IRQS[irq as usize].try_lock_cpu()
+		{
+		None => {
+			// Lock is already held by this CPU, just drop the IRQ
+			},
+		Some(v) =>
+			match *v
+			{
+			None => {},
+			Some(ref v) => (v.handler)( v.info ),
+			},
+		}
+	}
+	// SAFE: Per-CPU, interrupts disabled
+	unsafe {
+		S_INTERRUPTED[cpu] = 0 as *const _;
+	}
+	S_GIC.cpu_write(regs::GICC_EOIR, iar);
+}
+
+/// Record a profiler sample of the code interrupted by the current IRQ (called from the timer tick)
+pub fn profile_sample()
+{
+	let depth = match ::profiler::sample_depth() { Some(v) => v, None => return };
+	// SAFE: Per-CPU, set by `interrupt_handler` on this CPU (which is the caller), and valid until it returns
+	let regs = match unsafe { S_INTERRUPTED[super::threads::cpu_index()].as_ref() } { Some(v) => v, None => return };
+
+	let mut frames = [0usize; ::profiler::MAX_DEPTH];
+	frames[0] = regs.ip() as usize;
+	let mut n = 1;
+	// - Only walk kernel stacks, userland frame pointers can't be trusted
+	if !regs.is_user() {
+		let mut fp = regs.fp();
+		while n < depth
+		{
+			match super::cpu_faults::backtrace(fp)
+			{
+			Some((newfp, lr)) => {
+				frames[n] = lr as usize;
+				n += 1;
+				fp = newfp;
+				},
+			None => break,
+			}
+		}
+	}
+	::profiler::record(&frames[..n], regs.is_user());
+}
+
+#[no_mangle]
+#[doc(hidden)]
+/// Called by assembly before an IRQ returns to userland, switches away from the current thread if it was preempted
+pub extern "C" fn irq_user_return()
+{
+	if ::threads::preempt_pending()
+	{
+		// SAFE: The IRQ has been handled (and acknowledged), so further interrupts can be taken while switching
+		unsafe { super::sync::start_interrupts(); }
+		::threads::yield_time();
+		// SAFE: The return path expects interrupts to be disabled
+		unsafe { super::sync::stop_interrupts(); }
+	}
+}
+
+pub fn bind_gsi(gsi: usize, handler: fn(*const()), info: *const ()) -> Result<IRQHandle,()> {
+
+	if gsi >= S_IRQS.len() {
+		Err( () )
+	}
+	else {
+		let mut lh = S_IRQS[gsi].lock();
+		if lh.is_some() {
+			Err( () )
+		}
+		else {
+			*lh = Some(Binding {
+				handler: handler,
+				info: info,
+				});
+			S_GIC.configure(gsi as u32);
+			S_GIC.set_enabled(gsi as u32, true);
+			Ok( IRQHandle(gsi as u32) )
+		}
+	}
 }
 
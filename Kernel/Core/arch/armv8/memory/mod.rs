@@ -7,7 +7,8 @@ pub type VAddr = usize;
 pub type PAddr = u64;
 
 pub mod addresses {
-	pub const USER_END: usize = 0x0000_FFF0_0000_0000;
+	/// End of user-controlled memory (the final 64GB of TTBR0's range holds the user table fractal)
+	pub const USER_END: usize = 0x0000_7FF0_0000_0000;
 
 	pub const IDENT_START: usize = 0xFFFF_8000_0000_0000;
 	pub const IDENT_SIZE : usize = 0x0000_0000_0200_0000;
@@ -21,6 +22,13 @@ pub mod addresses {
 	pub const STACKS_END : usize = 0xFFFF_FFC0_0000_0000;
 	pub const HARDWARE_BASE: usize = 0xFFFF_FFC0_0000_0000;
 	pub const HARDWARE_END : usize = 0xFFFF_FFD0_0000_0000;
+	/// Physical memory reference counts (4 bytes per frame)
+	pub const PMEMREF_BASE: usize = 0xFFFF_FFD0_0000_0000;
+	pub const PMEMREF_END : usize = 0xFFFF_FFE0_0000_0000;
+	// 0xFFFF_FFE0_0000_0000 - Kernel table fractal
+	/// Temporary mappings (single statically allocated table)
+	pub const TEMP_BASE: usize = 0xFFFF_FFF0_0000_0000;
+	pub const TEMP_END : usize = TEMP_BASE + 2048 * super::PAGE_SIZE;
 
 	pub const STACK_SIZE: usize = 0x8000;
 
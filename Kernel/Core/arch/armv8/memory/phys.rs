@@ -6,12 +6,12 @@
 //!
 //! Handles reference counting and allocation bitmaps
 //use prelude::*;
-//use arch::imp::memory::addresses::{PMEMREF_BASE,PMEMREF_END/*,PMEMBM_BASE,PMEMBM_END*/};
+use arch::imp::memory::addresses::{PMEMREF_BASE,PMEMREF_END/*,PMEMBM_BASE,PMEMBM_END*/};
 use sync::{RwLock,AtomicU32};
 use core::sync::atomic::Ordering;
 use memory::page_array::PageArray;
 
-//static S_REFCOUNT_ARRAY: RwLock<PageArray<AtomicU32>> = RwLock::new( PageArray::new(PMEMREF_BASE, PMEMREF_END) );
+static S_REFCOUNT_ARRAY: RwLock<PageArray<AtomicU32>> = RwLock::new( PageArray::new(PMEMREF_BASE, PMEMREF_END) );
 
 pub fn ref_frame(frame_idx: u64) {
 	with_ref_alloc( frame_idx, |r| r.fetch_add(1, Ordering::Acquire) );
@@ -35,20 +35,18 @@ pub fn mark_free(frame_idx: u64) -> bool {
 	// HACK: Assume it was used
 	true
 }
-pub fn mark_used(frame_idx: u64) {
-	log_warning!("TODO: mark_used - frame_idx={:#x} ({:#x})", frame_idx, frame_idx*::PAGE_SIZE as u64);
+pub fn mark_used(_frame_idx: u64) {
+	// TODO: Allocation bitmap (`mark_free` currently assumes that all frames are used)
 }
 
 
 fn with_ref<U, F: FnOnce(&AtomicU32)->U>(frame_idx: u64, fcn: F) -> Option<U>
 {
-	todo!("");
-	//S_REFCOUNT_ARRAY.read().get(frame_idx as usize).map(fcn)
+	S_REFCOUNT_ARRAY.read().get(frame_idx as usize).map(fcn)
 }
 fn with_ref_alloc<U, F: FnOnce(&AtomicU32)->U>(frame_idx: u64, fcn: F) -> U
 {
-	todo!("");
-	//let mut lh = S_REFCOUNT_ARRAY.write();
-	//fcn( lh.get_alloc(frame_idx as usize) )
+	let mut lh = S_REFCOUNT_ARRAY.write();
+	fcn( lh.get_alloc(frame_idx as usize) )
 }
 
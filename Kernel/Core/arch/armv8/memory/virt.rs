@@ -5,46 +5,85 @@
 use memory::virt::ProtectionMode;
 use PAGE_SIZE;
 use core::sync::atomic::{Ordering,AtomicU64};
+use arch::memory::virt::TempHandle;
+use super::addresses::{IDENT_START,IDENT_SIZE,TEMP_BASE,TEMP_END,USER_END};
 
-const KERNEL_FRACTAL_BASE: usize = 0xFFFF_FFE0_0000_0000;
-const LEVEL2_FRACTAL_OFS: usize = (2048-2)*2048*2048;	// Offset in KERNEL_FRACTAL for level2 tables
-const LEVEL1_FRACTAL_OFS: usize = (2048-2)*2048*2048 + (2048-2)*2048;	// Offset in KERNEL_FRACTAL for level1 table (root)
+extern "C" {
+	static user0_root: [AtomicU64; 2048];
+	static kernel_temp_level3: [AtomicU64; 2048];
+	static kernel_phys_start: u64;
+}
+
+/// A self-referencing slot in a root table, exposing all levels of the paging structures
+struct Fractal
+{
+	base: usize,
+	slot: usize,
+}
+/// Kernel (TTBR1) tables, slot 2046 of `kernel_root`
+static KERNEL_FRACTAL: Fractal = Fractal { base: 0xFFFF_FFE0_0000_0000, slot: 2048-2 };
+/// User (TTBR0) tables, final slot of each address space's root (above `USER_END`)
+static USER_FRACTAL: Fractal = Fractal { base: 0x0000_7FF0_0000_0000, slot: 2048-1 };
+
+const TEMP_COUNT: usize = (TEMP_END - TEMP_BASE) / PAGE_SIZE;
+static S_TEMP_MAP_SEMAPHORE: ::sync::Semaphore = ::sync::Semaphore::new(TEMP_COUNT as isize, TEMP_COUNT as isize);
+
+/// Page descriptor: Valid + Page/Table + Access Flag
+const DESC_PAGE: u64 = 0x403;
+/// Output address bits (bits 47:14)
+const ADDR_MASK: u64 = 0x0000_FFFF_FFFF_C000;
+/// AP[1] - Accessible from EL0
+const ATTR_USER: u64 = 1 << 6;
+/// AP[2] - Read-only
+const ATTR_RO: u64 = 1 << 7;
+/// Privileged execute never
+const ATTR_PXN: u64 = 1 << 53;
+/// Unprivileged execute never
+const ATTR_UXN: u64 = 1 << 54;
+/// Software bit - Copy-on-write (mapped read-only)
+const ATTR_COW: u64 = 1 << 55;
+const ATTR_MASK: u64 = ATTR_USER | ATTR_RO | ATTR_PXN | ATTR_UXN | ATTR_COW;
 
 pub struct AddressSpace(u64);
 
 pub fn post_init()
 {
+	// Remove the boot-time identity mapping from PID0's user table (leaving the fractal)
+	// SAFE: Atomic accesses, the identity mapping is only used before the jump to the kernel's real address
+	unsafe {
+		for ent in user0_root[.. USER_FRACTAL.slot].iter() {
+			ent.store(0, Ordering::SeqCst);
+		}
+		asm!("dsb ishst; tlbi vmalle1; dsb ish; isb" : : : "memory" : "volatile");
+	}
 }
 
 fn prot_mode_to_attrs(prot: ProtectionMode) -> u64
 {
 	match prot
 	{
-	//ProtectionMode::KernelRWX=> (0x00<<56 | 0x00<<2),
-	//ProtectionMode::UserRWX  => (0x00<<56 | 0x40<<2),
-	ProtectionMode::KernelRX => (0x00<<56 | 0x80<<2),
-	ProtectionMode::UserRX   => (0x00<<56 | 0xC0<<2),
-	ProtectionMode::KernelRW => (0x20<<56 | 0x00<<2),
-	ProtectionMode::UserRW   => (0x20<<56 | 0x40<<2),
-	ProtectionMode::KernelRO => (0x20<<56 | 0x80<<2),
-	ProtectionMode::UserRO   => (0x20<<56 | 0xC0<<2),
-	_ => 0,
+	ProtectionMode::Unmapped => 0,
+	ProtectionMode::KernelRO => ATTR_RO | ATTR_PXN | ATTR_UXN,
+	ProtectionMode::KernelRW => ATTR_PXN | ATTR_UXN,
+	ProtectionMode::KernelRX => ATTR_RO | ATTR_UXN,
+	ProtectionMode::UserRO   => ATTR_USER | ATTR_RO | ATTR_PXN | ATTR_UXN,
+	ProtectionMode::UserRW   => ATTR_USER | ATTR_PXN | ATTR_UXN,
+	ProtectionMode::UserRX   => ATTR_USER | ATTR_RO | ATTR_PXN,
+	ProtectionMode::UserCOW  => ATTR_USER | ATTR_RO | ATTR_PXN | ATTR_UXN | ATTR_COW,
+	ProtectionMode::UserRWX  => ATTR_USER | ATTR_PXN,
 	}
 }
 fn attrs_to_prot_mode(attrs: u64) -> ProtectionMode
 {
-	let v = (((attrs >> 56) & 0xFF) << 8) | ((attrs >> 2) & 0xFF);
-	match v
+	if attrs & ATTR_COW != 0 {
+		return ProtectionMode::UserCOW;
+	}
+	match (attrs & ATTR_USER != 0, attrs & ATTR_RO != 0)
 	{
-	0x00_00 => ProtectionMode::KernelRW,	// RWX
-	0x00_40 => ProtectionMode::UserRW,	// RWX
-	0x00_80 => ProtectionMode::KernelRX,
-	0x00_C0 => ProtectionMode::UserRX,
-	0x20_00 => ProtectionMode::KernelRW,
-	0x20_40 => ProtectionMode::UserRW,
-	0x20_80 => ProtectionMode::KernelRO,
-	0x20_C0 => ProtectionMode::UserRO,
-	_ => todo!("Unknown attributes - 0x{:04x}", v),
+	(false, false) => ProtectionMode::KernelRW,	// Includes the RWX kernel image
+	(false, true ) => if attrs & ATTR_PXN != 0 { ProtectionMode::KernelRO } else { ProtectionMode::KernelRX },
+	(true , false) => if attrs & ATTR_UXN != 0 { ProtectionMode::UserRW } else { ProtectionMode::UserRWX },
+	(true , true ) => if attrs & ATTR_UXN != 0 { ProtectionMode::UserRO } else { ProtectionMode::UserRX },
 	}
 }
 
@@ -61,14 +100,11 @@ pub fn get_info<T>(addr: *const T) -> Option<(u64, ProtectionMode)>
 {
 	if let Some(paddr) = get_phys_raw(addr)
 	{
-		let a = with_entry(Level::Middle, addr as usize >> (14+11), |e| {
-			let v = e.load(Ordering::Relaxed);
-			if v & 3 == 3 { None } else { Some(v) }
-			})
-			.unwrap_or_else(|| with_entry(Level::Bottom, addr as usize >> 14, |e| e.load(Ordering::Relaxed)))
-			;
-		let prot = attrs_to_prot_mode(a & 0xFF000000_000003FC);
-		Some( (paddr, prot) )
+		let (fractal, page) = get_fractal(addr as usize);
+		// Block mappings (e.g. the kernel image) only exist in the middle level
+		let a = fractal.with_entry(Level::Middle, page >> 11, |e| e.load(Ordering::Relaxed));
+		let a = if a & 3 == 1 { a } else { fractal.with_entry(Level::Bottom, page, |e| e.load(Ordering::Relaxed)) };
+		Some( (paddr, attrs_to_prot_mode(a & ATTR_MASK)) )
 	}
 	else
 	{
@@ -76,17 +112,19 @@ pub fn get_info<T>(addr: *const T) -> Option<(u64, ProtectionMode)>
 	}
 }
 fn get_phys_raw<T>(addr: *const T) -> Option<u64> {
+	// - Hold interrupts, so an IRQ can't clobber PAR_EL1
+	let _irq = ::arch::sync::hold_interrupts();
 	// SAFE: Queries an interface that cannot cause an exception (and won't induce memory unsafety)
 	let v = unsafe {
 		let ret: usize;
-		asm!("AT S1E1R, $1; mrs $0, PAR_EL1" : "=r"(ret) : "r"(addr));
+		asm!("AT S1E1R, $1; isb; mrs $0, PAR_EL1" : "=r"(ret) : "r"(addr) : "memory" : "volatile");
 		ret
 		};
 	if v & 1 != 0 {
 		None
 	}
 	else {
-		Some( ((v & 0x0000FFFF_FFFFF000) + (addr as usize % PAGE_SIZE)) as u64 )
+		Some( (v as u64 & ADDR_MASK) + (addr as usize % PAGE_SIZE) as u64 )
 	}
 }
 
@@ -98,113 +136,263 @@ enum Level
 	Bottom,
 }
 
-fn get_entry_addr(level: Level, index: usize) -> *const AtomicU64
+impl Fractal
 {
-	(match level
+	fn get_entry_addr(&self, level: Level, index: usize) -> *const AtomicU64
+	{
+		let ofs = match level
+			{
+			Level::Root => {
+				assert!(index < 2048);
+				(self.slot * 2048 + self.slot) * 2048 + index
+				},
+			Level::Middle => {
+				assert!(index < 2048*2048);
+				self.slot * 2048 * 2048 + index
+				},
+			Level::Bottom => {
+				assert!(index < 2048*2048*2048);
+				index
+				},
+			};
+		(self.base + ofs * 8) as *const _
+	}
+
+	fn with_entry<F, R>(&self, level: Level, index: usize, fcn: F) -> R
+	where
+		F: FnOnce(&AtomicU64)->R
 	{
-	Level::Root => {
-		assert!(index < 2048);
-		KERNEL_FRACTAL_BASE + (LEVEL1_FRACTAL_OFS + index) * 8
-		},
-	Level::Middle => {
-		assert!(index < 2048*2048);
-		KERNEL_FRACTAL_BASE + (LEVEL2_FRACTAL_OFS + index) * 8
-		},
-	Level::Bottom => {
-		assert!(index < 2048*2048*2048);
-		KERNEL_FRACTAL_BASE + index * 8
+		let ptr = self.get_entry_addr(level, index);
+		debug_assert!(is_reserved(ptr));
+		//log_trace!("with_entry({:?}, {}): ptr={:p}", level, index, ptr);
+
+		// SAFE: Pointer is asserted to be valid above
+		fcn( unsafe { &*ptr } )
+	}
+
+	/// Returns true if the entry at `level`/`index` points to a table, allocating one if `alloc` is set
+	fn ensure_table(&self, level: Level, index: usize, alloc: bool) -> bool
+	{
+		let v = self.with_entry(level, index, |e| e.load(Ordering::Relaxed));
+		if v & 3 == 3 {
+			true
+		}
+		else if v & 1 != 0 {
+			// Block mapping, there's no lower table
+			false
+		}
+		else if alloc {
+			let next = match level
+				{
+				Level::Root => Level::Middle,
+				Level::Middle => Level::Bottom,
+				Level::Bottom => unreachable!(),
+				};
+			// NOTE: Mapping the new table's fractal address writes the entry checked above
+			::memory::phys::allocate( self.get_entry_addr(next, index << 11) as *mut () )
+		}
+		else {
+			false
 		}
-	}) as *const _
+	}
 }
 
-fn with_entry<F, R>(level: Level, index: usize, fcn: F) -> R
-where
-	F: FnOnce(&AtomicU64)->R
+/// Obtain the fractal covering an address, and the page index within that fractal's (47-bit) range
+fn get_fractal(addr: usize) -> (&'static Fractal, usize)
 {
-	let ptr = get_entry_addr(level, index);
-	debug_assert!(is_reserved(ptr));
-	//log_trace!("with_entry({:?}, {}): ptr={:p}", level, index, ptr);
-	
-	// SAFE: Pointer is asserted to be valid above
-	fcn( unsafe { &*ptr } )
+	const PAGE_IDX_MASK: usize = (1 << (47-14)) - 1;
+	if addr >= IDENT_START {
+		(&KERNEL_FRACTAL, (addr >> 14) & PAGE_IDX_MASK)
+	}
+	else {
+		assert!(addr >> 47 == 0, "Non-canonical address {:#x}", addr);
+		(&USER_FRACTAL, addr >> 14)
+	}
+}
+
+/// Obtain the bottom-level entry for a page (if the tables above it are present, or if `alloc` is set)
+fn get_page_entry(addr: usize, alloc: bool) -> Option<&'static AtomicU64>
+{
+	let (fractal, page) = get_fractal(addr);
+	if fractal.ensure_table(Level::Root, page >> 22, alloc) && fractal.ensure_table(Level::Middle, page >> 11, alloc) {
+		// SAFE: Tables have been checked to be present, and the kernel half is never pruned (the VMM locks the user half)
+		Some( unsafe { &*fractal.get_entry_addr(Level::Bottom, page) } )
+	}
+	else {
+		None
+	}
+}
+
+/// TLB Invalidate by VA (all ASIDs)
+fn tlbi(addr: *const ()) {
+	// SAFE: TLB invalidation is not the unsafe part :)
+	unsafe {
+		asm!("dsb ishst; tlbi vaae1, $0; dsb ish; isb" : : "r"( (addr as usize >> 12) & ((1 << 44)-1) ) : "memory" : "volatile");
+	}
 }
 
 pub fn can_map_without_alloc(addr: *mut ()) -> bool
 {
-	false
+	get_page_entry(addr as usize, false).is_some()
 }
 pub unsafe fn map(addr: *const (), phys: u64, prot: ProtectionMode)
 {
 	log_debug!("map({:p} = {:#x}, {:?})", addr, phys, prot);
+	assert!(prot != ProtectionMode::Unmapped, "Invalid pass of ProtectionMode::Unmapped to map");
+	assert!(phys % PAGE_SIZE as u64 == 0, "Unaligned physical address {:#x} passed to map", phys);
 
-	let page = addr as usize / PAGE_SIZE;
-	if page >> (48-14) > 0
-	{
-		// Kernel AS doesn't need a deletion lock, as it's never pruned
-		// Mutation lock also not needed (but is provided in VMM)
-		
-		let mask = (1 << 33)-1;
-		let page = page & mask;
-		//log_trace!("page = {:#x}", page);
-		// 1. Ensure that top-level region is valid.
-		with_entry(Level::Root, page >> 22, |e| {
-			if e.load(Ordering::Relaxed) == 0 {
-				::memory::phys::allocate( get_entry_addr(Level::Middle, page >> 22 << 11) as *mut () );
-			}
-			});
-		// 2. Ensure that level2 is valid
-		with_entry(Level::Middle, page >> 11, |e| {
-			if e.load(Ordering::Relaxed) == 0 {
-				::memory::phys::allocate( get_entry_addr(Level::Bottom, page >> 11 << 11) as *mut () );
-			}
-			});
-		// 3. Set mapping in level3
-		let val = phys | prot_mode_to_attrs(prot) | 0x403;
-		with_entry(Level::Bottom, page, |e| {
-			if let Err(old) = e.compare_exchange(0, val, Ordering::SeqCst, Ordering::SeqCst) {
-				panic!("map() called over existing allocation: a={:p}, old={:#x}", addr, old);
-			}
-			});
-	}
-	else
-	{
-		// NOTE: Locking of address space not needed, as the VMM does that.
-		todo!("map - user");
+	// NOTE: Locking of the user half is handled by the VMM, and the kernel half is never pruned
+	let ent = get_page_entry(addr as usize, true).expect("map() - Unable to allocate paging structures");
+	let val = phys | prot_mode_to_attrs(prot) | DESC_PAGE;
+	if let Err(old) = ent.compare_exchange(0, val, Ordering::SeqCst, Ordering::SeqCst) {
+		panic!("map() called over existing allocation: a={:p}, old={:#x}", addr, old);
 	}
-	// Invalidate TLB for this address
-	// SAFE: Safe assembly
-	//unsafe {
-		let MASK: usize = ((1 << 43)-1) & !3;	// 43 bits of address (after shifting by 12)
-		asm!("TLBI ALLE1 $0" : : "r"( (addr as usize >> 12) & MASK ));
-	//}
+	tlbi(addr);
 }
 pub unsafe fn reprotect(addr: *const (), prot: ProtectionMode)
 {
-	todo!("reprotect");
+	log_debug!("reprotect({:p}, {:?})", addr, prot);
+	assert!(prot != ProtectionMode::Unmapped, "Invalid pass of ProtectionMode::Unmapped to reprotect");
+
+	let ent = get_page_entry(addr as usize, false).expect("Calling reprotect() on unmapped location");
+	let v = ent.load(Ordering::SeqCst);
+	assert!(v & 1 != 0, "reprotect() called on an unmapped location: a={:p}", addr);
+	let val = (v & ADDR_MASK) | prot_mode_to_attrs(prot) | DESC_PAGE;
+	if let Err(old) = ent.compare_exchange(v, val, Ordering::SeqCst, Ordering::SeqCst) {
+		panic!("reprotect() called in a racy manner: a={:p} old({:#x}) != v({:#x})", addr, old, v);
+	}
+	tlbi(addr);
 }
 pub unsafe fn unmap(addr: *const ()) -> Option<u64>
 {
-	None
+	log_debug!("unmap({:p})", addr);
+	let ent = match get_page_entry(addr as usize, false)
+		{
+		Some(e) => e,
+		None => return None,
+		};
+	let old = ent.swap(0, Ordering::SeqCst);
+	tlbi(addr);
+	if old & 1 == 0 {
+		None
+	}
+	else {
+		Some( old & ADDR_MASK )
+	}
 }
 
 
+/// Allocations within the kernel image's identity mapping
 pub unsafe fn fixed_alloc(phys: u64, count: usize) -> Option<*mut ()>
 {
-	None
+	let base = kernel_phys_start;
+	if base <= phys && phys + (count * PAGE_SIZE) as u64 <= base + IDENT_SIZE as u64 {
+		Some( (IDENT_START + (phys - base) as usize) as *mut () )
+	}
+	else {
+		None
+	}
 }
 pub fn is_fixed_alloc(addr: *const (), count: usize) -> bool
 {
-	false
+	let addr = addr as usize;
+	IDENT_START <= addr && addr + count * PAGE_SIZE <= IDENT_START + IDENT_SIZE
 }
 
 
+/// Temporarily map a frame into memory
+/// UNSAFE: User to ensure that the passed address doesn't alias
 pub unsafe fn temp_map<T>(phys: u64) -> *mut T
 {
-	todo!("");
+	log_trace!("temp_map<{}>({:#x})", type_name!(T), phys);
+	assert!(phys % PAGE_SIZE as u64 == 0);
+	let val = phys | prot_mode_to_attrs(ProtectionMode::KernelRW) | DESC_PAGE;
+
+	S_TEMP_MAP_SEMAPHORE.acquire();
+	for (i, ent) in kernel_temp_level3.iter().enumerate()
+	{
+		if ent.compare_exchange(0, val, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+			let addr = (TEMP_BASE + i * PAGE_SIZE) as *mut T;
+			tlbi(addr as *const ());
+			return addr;
+		}
+	}
+	panic!("No free temp mappings");
 }
+// UNSAFE: Can cause use-after-free if address is invalid
 pub unsafe fn temp_unmap<T>(addr: *mut T)
 {
-	todo!("");
+	log_trace!("temp_unmap<{}>({:p})", type_name!(T), addr);
+	assert!(addr as usize >= TEMP_BASE);
+	let i = (addr as usize - TEMP_BASE) / PAGE_SIZE;
+	assert!(i < TEMP_COUNT);
+	kernel_temp_level3[i].store(0, Ordering::Release);
+	S_TEMP_MAP_SEMAPHORE.release();
+}
+
+
+/// Give the current address space its own copy of a copy-on-write page (does nothing if the page isn't COW)
+///
+/// Returns `Err` if a frame couldn't be allocated for the copy.
+pub fn unshare_cow(addr: usize) -> Result<(), ()>
+{
+	let ent = match get_page_entry(addr, false)
+		{
+		Some(v) => v,
+		None => return Ok( () ),
+		};
+	let mut rv = Ok( () );
+	// 1. Lock (relevant) address space
+	// SAFE: Changes to address space are transparent
+	::memory::virt::with_lock(addr, || unsafe {
+		let pgaddr = addr & !(PAGE_SIZE - 1);
+		// - Checked under the lock, as another CPU may have already handled a fault on this page
+		let cur = ent.load(Ordering::Relaxed);
+		if cur & (ATTR_COW|1) != (ATTR_COW|1) {
+			return ;
+		}
+		let frame = cur & ADDR_MASK;
+		// 2. Get the PMM to provide us with a unique copy of that frame (can return the same addr)
+		match ::memory::phys::make_unique( frame, &*(pgaddr as *const [u8; PAGE_SIZE]) )
+		{
+		Ok(newframe) => {
+			// 3. Remap to this page as UserRW (because COW is user-only atm)
+			ent.store(newframe | prot_mode_to_attrs(ProtectionMode::UserRW) | DESC_PAGE, Ordering::SeqCst);
+			tlbi(pgaddr as *const ());
+			},
+		Err(_) => {
+			rv = Err( () );
+			},
+		}
+		});
+	rv
+}
+
+/// Handle a data/instruction abort, returns true if the faulting access can be retried
+pub fn handle_page_fault(accessed_address: usize, is_write: bool, is_user: bool) -> bool
+{
+	//  > Copy-on-write pages
+	if is_write && accessed_address < USER_END
+	{
+		if let Some(ent) = get_page_entry(accessed_address, false)
+		{
+			if ent.load(Ordering::Relaxed) & (ATTR_COW|1) == (ATTR_COW|1)
+			{
+				if unshare_cow(accessed_address).is_ok() {
+					return true;
+				}
+				log_warning!("Out of memory handling COW write to {:#x}", accessed_address);
+				// Last resort: a user process that can't obtain memory is killed (instead of the fault being fatal)
+				// - Kernel-mode faults can't exit in place (locks may be held), but syscalls unshare user buffers
+				//   up-front (see `FreezeMut`) so any left are a kernel bug, reported by the caller.
+				if is_user {
+					::memory::pressure::kill_current_process(accessed_address);
+				}
+				return false;
+			}
+		}
+	}
+	false
 }
 
 
@@ -212,15 +400,96 @@ impl AddressSpace
 {
 	pub fn pid0() -> AddressSpace
 	{
-		extern "C" {
-			static kernel_root: [u64; 2048];
-		}
-		// SAFE: Constant value
-		AddressSpace(unsafe { kernel_root[2048-2] & !0x3FFF })
+		// SAFE: Only the address is taken
+		AddressSpace( get_phys(unsafe { &user0_root }) )
 	}
-	pub fn new(start: usize, end: usize) -> Result<AddressSpace,()>
+	pub fn new(clone_start: usize, clone_end: usize) -> Result<AddressSpace,::memory::virt::MapError>
 	{
-		todo!("");
+		use core::cmp::{min,max};
+
+		/// Allocate a zeroed table
+		fn new_table() -> Result<TempHandle<u64>, ::memory::virt::MapError> {
+			let mut rv: TempHandle<u64> = try!( ::memory::phys::allocate_bare() ).into();
+			for v in rv.iter_mut() {
+				*v = 0;
+			}
+			Ok(rv)
+		}
+		/// Obtain the entry for a page in the new address space (sharing or copying the frame)
+		fn clone_page(page: usize) -> Result<u64, ::memory::virt::MapError>
+		{
+			let v = USER_FRACTAL.with_entry(Level::Bottom, page, |e| e.load(Ordering::Relaxed));
+			if v & 1 == 0 {
+				return Ok(0);
+			}
+			let frame = match attrs_to_prot_mode(v & ATTR_MASK)
+				{
+				ProtectionMode::UserRX | ProtectionMode::UserCOW => {
+					// Share the frame, the first write in either address space will copy it (if COW)
+					::memory::phys::ref_frame(v & ADDR_MASK);
+					v & ADDR_MASK
+					},
+				ProtectionMode::UserRO | ProtectionMode::UserRW | ProtectionMode::UserRWX => {
+					let src_ptr = (page * PAGE_SIZE) as *const u8;
+					// SAFE: Memory is valid (TODO: What if this changes? Shouldn't cause errors, just inconsistent user data)
+					let src = unsafe { ::core::slice::from_raw_parts(src_ptr, PAGE_SIZE) };
+					let mut data = try!( ::memory::phys::allocate_bare() );
+					data.copy_from_slice(src);
+					log_trace!("- Clone @{:p} = {:#x}", src_ptr, data.phys_addr());
+					data.phys_addr()
+					},
+				mode @ _ => {
+					log_warning!("TODO: Other protection modes: {:?}", mode);
+					return Ok(0);
+					},
+				};
+			Ok( frame | (v & ATTR_MASK) | DESC_PAGE )
+		}
+
+		/// Clone pages into the new address space (creating tables only where the current address space has them)
+		///
+		/// Tables are linked in as soon as they're allocated, so on failure everything is released by `AddressSpace::drop`
+		fn clone_range(new_root: &mut [u64], start_pidx: usize, end_pidx: usize) -> Result<(), ::memory::virt::MapError>
+		{
+			for root_idx in start_pidx >> 22 .. (end_pidx + (1 << 22) - 1) >> 22
+			{
+				if !USER_FRACTAL.ensure_table(Level::Root, root_idx, false) {
+					continue ;
+				}
+				let mut mid_tab = try!(new_table());
+				new_root[root_idx] = mid_tab.phys_addr() | DESC_PAGE;
+				for mid_idx in max(start_pidx >> 11, root_idx << 11) .. min((end_pidx + 2047) >> 11, (root_idx + 1) << 11)
+				{
+					if !USER_FRACTAL.ensure_table(Level::Middle, mid_idx, false) {
+						continue ;
+					}
+					let mut bottom_tab = try!(new_table());
+					mid_tab[mid_idx % 2048] = bottom_tab.phys_addr() | DESC_PAGE;
+					for page in max(start_pidx, mid_idx << 11) .. min(end_pidx, (mid_idx + 1) << 11)
+					{
+						bottom_tab[page % 2048] = try!(clone_page(page));
+					}
+				}
+			}
+			Ok( () )
+		}
+
+		assert!( clone_start % PAGE_SIZE == 0 );
+		assert!( clone_end % PAGE_SIZE == 0 );
+		assert!( clone_start <= clone_end && clone_end <= USER_END );
+
+		// 1. Allocate a new root table (with the fractal pointing back at itself)
+		let mut new_root = try!(new_table());
+		let root_phys = new_root.phys_addr();
+		new_root[USER_FRACTAL.slot] = root_phys | DESC_PAGE;
+
+		// 2. Clone the requested region
+		let res = clone_range(&mut new_root, clone_start / PAGE_SIZE, clone_end / PAGE_SIZE);
+		drop(new_root);
+		let rv = AddressSpace(root_phys);
+		// - On failure, dropping the partially constructed address space releases everything allocated so far
+		try!(res);
+		Ok(rv)
 	}
 
 	pub fn as_phys(&self) -> u64 {
@@ -228,3 +497,55 @@ impl AddressSpace
 	}
 }
 
+
+impl ::core::ops::Drop for AddressSpace
+{
+	fn drop(&mut self)
+	{
+		/// Release a table (and everything below it), `level` is 1 for a bottom-level table
+		fn drop_table(phys: u64, level: u8)
+		{
+			// SAFE: Tables are uniquely owned by this address space, which is no longer in use
+			unsafe {
+				::memory::virt::with_temp(phys, |pg| {
+					let tab: &mut [u64; 2048] = ::core::mem::transmute(pg);
+					for e in tab.iter_mut() {
+						drop_entry(e, level);
+					}
+					});
+			}
+			::memory::phys::deref_frame(phys);
+		}
+		fn drop_entry(ent: &mut u64, level: u8)
+		{
+			let v = *ent;
+			if v & 1 == 0 {
+				return ;
+			}
+			if level == 1 {
+				// Leaf page, release this reference to the frame (shared/COW frames are reference counted)
+				::memory::phys::deref_frame(v & ADDR_MASK);
+			}
+			else if v & 3 == 3 {
+				drop_table(v & ADDR_MASK, level - 1);
+			}
+			else {
+				log_warning!("AddressSpace::drop - Unexpected block mapping {:#x} at level {}", v, level);
+			}
+			*ent = 0;
+		}
+
+		assert!(self.0 != AddressSpace::pid0().0, "Dropping PID0's address space");
+		// SAFE: Root table is uniquely owned, and the address space is no longer in use
+		unsafe {
+			::memory::virt::with_temp(self.0, |pg| {
+				let root: &mut [u64; 2048] = ::core::mem::transmute(pg);
+				// - Skip the fractal slot (it points back at the root)
+				for e in root[.. USER_FRACTAL.slot].iter_mut() {
+					drop_entry(e, 3);
+				}
+				});
+		}
+		::memory::phys::deref_frame(self.0);
+	}
+}
@@ -9,13 +9,23 @@ pub mod sync;
 pub mod threads;
 pub mod boot;
 pub mod interrupts;
+mod cpu_faults;
+mod timer;
 
-#[path="../armv7/fdt.rs"]
+#[path="../arm_common/fdt.rs"]
 mod fdt;
+#[path="../arm_common/fdt_devices.rs"]
+mod fdt_devices;
 
 module_define!{arch, [], init}
 fn init()
 {
+	interrupts::init();
+	timer::init();
+	// SAFE: Interrupt controller and handlers are now initialised
+	unsafe {
+		sync::start_interrupts();
+	}
 }
 
 pub fn print_backtrace() {
@@ -46,7 +56,10 @@ pub fn print_backtrace() {
 }
 
 pub fn cur_timestamp() -> u64 {
-	0
+	timer::cur_timestamp()
+}
+pub fn request_tick(_deadline: u64) {
+	// The generic timer ticks periodically, so deadlines are picked up by the next tick
 }
 
 extern "C" {
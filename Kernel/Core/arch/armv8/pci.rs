@@ -1,8 +1,92 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/arch/armv8/pci.rs
+//! PCI configuration space access (PCIe ECAM window from the device tree)
+use prelude::*;
 
-pub fn write(addr: u32, value: u32) {
-	todo!("PCI write {:#x} v {:#x}", addr, value);
+/// Memory-mapped configuration space, each bus's 1MB window is mapped on first use
+struct Ecam
+{
+	base: ::memory::PAddr,
+	first_bus: u8,
+	buses: Vec< Option<::memory::virt::AllocHandle> >,
 }
+
+/// Size of each bus's window (32 devices * 8 functions * 4KB)
+const BUS_WINDOW_SIZE: usize = 1 << 20;
+
+static S_ECAM: ::sync::mutex::LazyMutex< Option<Ecam> > = lazymutex_init!();
+
+impl Ecam
+{
+	fn from_fdt() -> Option<Ecam>
+	{
+		use lib::byteorder::{ByteOrder,BigEndian};
+		let fdt = match super::boot::get_fdt() { Some(v) => v, None => return None };
+		let node = match fdt.find_compatible("pci-host-ecam-generic") { Some(v) => v, None => return None };
+		let (base, size) = match node.get_reg(0, fdt.root_cells()) { Some(v) => v, None => return None };
+		// - `bus-range` is (first, last), if absent the window starts at bus 0
+		let first_bus = match node.get_prop("bus-range")
+			{
+			Some(v) if v.len() >= 8 => BigEndian::read_u32(v) as u8,
+			_ => 0,
+			};
+		let n_buses = ::core::cmp::min(size as usize / BUS_WINDOW_SIZE, 256 - first_bus as usize);
+		log_debug!("PCIe ECAM {:#x}+{:#x}, buses {}..{}", base, size, first_bus, first_bus as usize + n_buses);
+		Some(Ecam {
+			base: base,
+			first_bus: first_bus,
+			buses: (0 .. n_buses).map(|_| None).collect(),
+			})
+	}
+
+	/// Obtain a pointer to the configuration word for the (pre-calculated) address
+	fn get_word(&mut self, addr: u32) -> Option<*mut u32>
+	{
+		// Address is bus:8, dev:5, fcn:3, reg:8 - ECAM offset is bus:8, dev:5, fcn:3, reg:12
+		let bus = (addr >> 16) as u8;
+		let ofs = (((addr >> 8) & 0xFF) << 12 | (addr & 0xFC)) as usize;
+		if bus < self.first_bus || (bus - self.first_bus) as usize >= self.buses.len() {
+			return None;
+		}
+		let idx = (bus - self.first_bus) as usize;
+		if self.buses[idx].is_none() {
+			let paddr = self.base + (idx * BUS_WINDOW_SIZE) as ::memory::PAddr;
+			// SAFE: Configuration space is only accessed through this module
+			match unsafe { ::memory::virt::map_hw_rw(paddr, BUS_WINDOW_SIZE / ::PAGE_SIZE, "PCIe ECAM") }
+			{
+			Ok(v) => self.buses[idx] = Some(v),
+			Err(e) => {
+				log_error!("Unable to map ECAM window for bus {}: {:?}", bus, e);
+				return None;
+				},
+			}
+		}
+		let h = self.buses[idx].as_ref().unwrap();
+		// SAFE: Offset is within the mapped window, and raw pointer is used for volatile access
+		Some( unsafe { h.as_int_mut::<u32>(ofs) as *mut u32 } )
+	}
+}
+
+/// Read a word from a pre-calculated PCI address
 pub fn read(addr: u32) -> u32 {
-	log_trace!("TODO: PCI read {:#x}", addr);
-	!0
+	let mut lh = S_ECAM.lock_init(|| Ecam::from_fdt());
+	match lh.as_mut().and_then(|e| e.get_word(addr))
+	{
+	// SAFE: Pointer is to a mapped configuration space word
+	Some(p) => unsafe { ::core::intrinsics::volatile_load(p) },
+	// No host bridge, or outside the bus range - reads as no device present
+	None => !0,
+	}
+}
+/// Write a word to a pre-calculated PCI address
+pub fn write(addr: u32, value: u32) {
+	let mut lh = S_ECAM.lock_init(|| Ecam::from_fdt());
+	match lh.as_mut().and_then(|e| e.get_word(addr))
+	{
+	// SAFE: Pointer is to a mapped configuration space word
+	Some(p) => unsafe { ::core::intrinsics::volatile_store(p, value) },
+	None => log_trace!("PCI write {:#x} v {:#x} outside ECAM", addr, value),
+	}
 }
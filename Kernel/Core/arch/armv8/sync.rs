@@ -78,25 +78,32 @@ impl<'a, T: 'a> ops::Drop for HeldSpinlock<'a, T>
 }
 
 
-pub struct HeldInterrupts;
+/// Handle that keeps IRQs masked until dropped (restoring the previous state)
+pub struct HeldInterrupts(bool);
 impl ops::Drop for HeldInterrupts {
 	fn drop(&mut self) {
-		// SAFE: TODO
-		unsafe {
-			start_interrupts();
+		if self.0 {
+			// SAFE: IRQs were enabled when this handle was created
+			unsafe {
+				start_interrupts();
+			}
 		}
 	}
 }
 
 pub fn hold_interrupts()->HeldInterrupts {
-	// SAFE: TODO
+	let daif: u64;
+	// SAFE: Reads DAIF and masks IRQs, no memory side-effects
 	unsafe {
-		stop_interrupts();
+		asm!("mrs $0, DAIF; msr DAIFSet, #2" : "=r"(daif) : : "memory" : "volatile");
 	}
-	HeldInterrupts
+	// DAIF.I (bit 7) set = IRQs masked
+	HeldInterrupts(daif & (1 << 7) == 0)
 }
 pub unsafe fn stop_interrupts() {
+	asm!("msr DAIFSet, #2" : : : "memory" : "volatile");
 }
 pub unsafe fn start_interrupts() {
+	asm!("msr DAIFClr, #2" : : : "memory" : "volatile");
 }
 
@@ -8,14 +8,24 @@ pub struct State
 {
 	sp: usize,
 	ttbr0: u64,
+	/// Userland TLS base (loaded into TPIDR_EL0 on switch)
+	user_tls: usize,
 	stack_handle: Option< ::memory::virt::ArrayHandle<u8> >,
 }
 
+/// Idle thread for each CPU, created by `init_tid0_state`
+static mut S_IDLE_THREADS: [*mut ::threads::Thread; MAX_CPUS] = [0 as *mut _; MAX_CPUS];
+
 pub fn init_tid0_state() -> State
 {
+	// SAFE: Called in a single-threaded context
+	unsafe {
+		S_IDLE_THREADS[0] = ::core::mem::transmute( ::threads::new_idle_thread(0) );
+	}
 	State {
 		sp: 0,
 		ttbr0: super::memory::virt::AddressSpace::pid0().as_phys(),
+		user_tls: 0,
 		stack_handle: None,
 		}
 }
@@ -26,13 +36,37 @@ impl State
 		State {
 			sp: 0,
 			ttbr0: addr_space.as_phys(),
+			user_tls: 0,
 			stack_handle: None,
 			}
 	}
+
+	/// Set the userland TLS base (TPIDR_EL0) used when this thread is running
+	pub fn set_user_tls(&mut self, base: usize) {
+		self.user_tls = base;
+	}
 }
 
 pub fn get_idle_thread() -> ::threads::ThreadPtr {
-	todo!("get_idle_thread");
+	// SAFE: Passes a static pointer. `static mut` is initialised by `init_tid0_state`
+	unsafe {
+		assert!(S_IDLE_THREADS[cpu_index()] != 0 as *mut _);
+		::threads::ThreadPtr::new_static( &mut *S_IDLE_THREADS[cpu_index()] )
+	}
+}
+
+/// Only a single CPU is supported
+pub const MAX_CPUS: usize = 1;
+pub fn cpu_index() -> usize {
+	0
+}
+pub fn cpu_count() -> usize {
+	1
+}
+pub fn send_reschedule(_cpu: usize) {
+}
+pub fn set_slice_timer(_enabled: bool) {
+	// The scheduler tick is periodic
 }
 
 pub fn set_thread_ptr(thread: ::threads::ThreadPtr) {
@@ -80,6 +114,7 @@ pub fn switch_to(thread: ::threads::ThreadPtr) {
 		let new_sp = thread.cpu_state.sp;
 		let new_ttbr0 = thread.cpu_state.ttbr0;
 		log_trace!("Switching to SP={:#x},TTBR0={:#x}", new_sp, new_ttbr0);
+		// NOTE: TPIDR_EL0 is saved/restored by task_switch (the initial value is pushed by `start_thread`)
 		task_switch(&mut outstate.sp, new_sp, new_ttbr0, thread.into_usize());
 	}
 }
@@ -103,6 +138,10 @@ pub fn start_thread<F: FnOnce()+Send+'static>(thread: &mut ::threads::Thread, co
 	stack.push( thread_root::<F> as usize );
 	
 	// 3. Populate with task_switch state
+	// - FPCR/FPSR and Q0-Q31 (2+32*2 words)
+	for _ in 0 .. 2+64 {
+		stack.push(0_usize);
+	}
 	// - R19-R28 saved by task_switch
 	for _ in 19 .. 28+1 {
 		stack.push(0_usize);
@@ -111,9 +150,9 @@ pub fn start_thread<F: FnOnce()+Send+'static>(thread: &mut ::threads::Thread, co
 	stack.push( thread_trampoline as usize );	// R30 - aka LR
 	stack.push(0_usize);	// R29
 
-	stack.push(0_usize);	// TPIDR_EL0 - User Thread Pointer
+	stack.push(thread.cpu_state.user_tls);	// TPIDR_EL0 - User Thread Pointer
 	stack.push(0_usize);	// SP_EL0    - User SP
-	stack.push(0_usize);	// pad
+	stack.push(0_usize);	// DAIF    - Interrupt mask (IRQs enabled)
 	stack.push(0_usize);	// ELR_EL1 - Exception return
 	
 	// 4. Apply newly updated state
@@ -130,8 +169,8 @@ pub fn start_thread<F: FnOnce()+Send+'static>(thread: &mut ::threads::Thread, co
 		// 1. Run closure
 		// SAFE: Functionally owns that pointer
 		(unsafe { ::core::ptr::read(code_ptr) })();
-		// 2. terminate thread
-		panic!("TODO: Terminate thread at end of thread_root");
+		// 2. terminate thread (waking anything joining it)
+		::threads::exit_thread(0);
 	}
 }
 
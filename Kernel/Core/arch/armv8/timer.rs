@@ -0,0 +1,90 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/arch/armv8/timer.rs
+//! ARM Generic Timer (virtual timer)
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Interrupt ID of the EL1 virtual timer (PPI 11), used if the device tree doesn't list it
+const VTIMER_IRQ_DEFAULT: usize = 27;
+/// Scheduler tick period
+const TICK_MS: u64 = 10;
+
+/// Counter frequency (Hz), read from CNTFRQ_EL0 at init
+static S_FREQUENCY: AtomicUsize = AtomicUsize::new(0);
+static mut S_IRQ_HANDLE: Option<super::interrupts::IRQHandle> = None;
+
+pub fn init()
+{
+	let freq: u64;
+	// SAFE: Read-only register access
+	unsafe { asm!("mrs $0, CNTFRQ_EL0" : "=r"(freq)); }
+	log_debug!("Generic timer: {} Hz", freq);
+	assert!(freq != 0, "CNTFRQ_EL0 not set by firmware");
+	S_FREQUENCY.store(freq as usize, Ordering::Relaxed);
+
+	let irq = get_vtimer_irq().unwrap_or(VTIMER_IRQ_DEFAULT);
+	log_debug!("Virtual timer IRQ {}", irq);
+	// SAFE: Called in a single-threaded context
+	unsafe {
+		S_IRQ_HANDLE = Some( super::interrupts::bind_gsi(irq, timer_irq, 0 as *const ()).expect("Unable to bind virtual timer IRQ") );
+	}
+	arm_timer();
+}
+
+/// Look up the virtual timer's interrupt in the device tree
+///
+/// The `interrupts` property lists the secure physical, non-secure physical, virtual, and hypervisor timers (in that order)
+fn get_vtimer_irq() -> Option<usize>
+{
+	use lib::byteorder::{ByteOrder,BigEndian};
+	let fdt = match super::boot::get_fdt() { Some(v) => v, None => return None };
+	let node = match fdt.find_compatible("arm,armv8-timer") { Some(v) => v, None => return None };
+	match node.get_prop("interrupts")
+	{
+	// - Each entry is (type, number, flags), all timers are PPIs
+	Some(v) if v.len() >= 3*12 => Some( 16 + BigEndian::read_u32(&v[2*12 + 4..]) as usize ),
+	_ => None,
+	}
+}
+
+/// Current counter value in milliseconds
+pub fn cur_timestamp() -> u64
+{
+	let freq = S_FREQUENCY.load(Ordering::Relaxed) as u64;
+	if freq == 0 {
+		return 0;
+	}
+	let count = read_counter();
+	// Split to avoid overflowing the multiplication
+	(count / freq) * 1000 + (count % freq) * 1000 / freq
+}
+
+fn read_counter() -> u64
+{
+	let rv: u64;
+	// SAFE: Read-only register access
+	unsafe { asm!("isb; mrs $0, CNTVCT_EL0" : "=r"(rv) : : : "volatile"); }
+	rv
+}
+
+/// Schedule the next tick and enable the timer (with its interrupt unmasked)
+fn arm_timer()
+{
+	let ticks = S_FREQUENCY.load(Ordering::Relaxed) as u64 * TICK_MS / 1000;
+	// SAFE: Timer register access, no memory side-effects
+	unsafe {
+		asm!("msr CNTV_TVAL_EL0, $0" : : "r"(ticks) : : "volatile");
+		asm!("msr CNTV_CTL_EL0, $0" : : "r"(1u64) : : "volatile");
+	}
+}
+
+fn timer_irq(_: *const ())
+{
+	// Writing TVAL clears the interrupt condition
+	arm_timer();
+
+	::time::time_tick();
+	::threads::preempt_tick();
+	super::interrupts::profile_sample();
+}
@@ -74,6 +74,9 @@ pub mod memory {
 		pub unsafe fn unmap(_a: *mut ()) -> Option<::memory::PAddr> {
 			None
 		}
+		pub fn unshare_cow(_addr: usize) -> Result<(), ()> {
+			Ok( () )
+		}
 	}
 	pub mod phys {
 		pub fn ref_frame(_frame_idx: u64) {
@@ -190,6 +193,17 @@ pub mod threads {
 	pub fn get_idle_thread() -> ::threads::ThreadPtr {
 		todo!("get_idle_thread");
 	}
+	pub const MAX_CPUS: usize = 1;
+	pub fn cpu_index() -> usize {
+		0
+	}
+	pub fn cpu_count() -> usize {
+		1
+	}
+	pub fn send_reschedule(_cpu: usize) {
+	}
+	pub fn set_slice_timer(_enabled: bool) {
+	}
 	pub fn switch_to(_t: ::threads::ThreadPtr) {
 	}
 
@@ -215,6 +229,8 @@ pub fn puth(_v: u64) {
 pub fn cur_timestamp() -> u64 {
 	0
 }
+pub fn request_tick(_deadline: u64) {
+}
 pub fn print_backtrace() {
 }
 
@@ -154,6 +154,11 @@ pub mod memory {
 		pub unsafe fn unmap(a: *mut ()) -> Option<::memory::PAddr> {
 			imp::unmap(a)
 		}
+		#[inline]
+		/// Give the current address space its own copy of a copy-on-write page (does nothing for other pages)
+		pub fn unshare_cow(addr: usize) -> Result<(), ()> {
+			imp::unshare_cow(addr)
+		}
 	}
 	/// Physical memory state tracking
 	pub mod phys {
@@ -256,6 +261,9 @@ pub mod threads {
 
 	pub type State = imp::State;
 
+	/// Maximum number of CPUs (size of per-CPU tables)
+	pub const MAX_CPUS: usize = imp::MAX_CPUS;
+
 	#[inline]
 	pub fn init_tid0_state() -> State {
 		imp::init_tid0_state()
@@ -287,6 +295,29 @@ pub mod threads {
 		imp::switch_to(t)
 	}
 
+	#[inline]
+	/// Index of the current CPU (less than `cpu_count`)
+	pub fn cpu_index() -> usize {
+		imp::cpu_index()
+	}
+	#[inline]
+	/// Number of running CPUs
+	pub fn cpu_count() -> usize {
+		imp::cpu_count()
+	}
+	#[inline]
+	/// Wake the given CPU so it re-checks for runnable threads
+	pub fn send_reschedule(cpu: usize) {
+		imp::send_reschedule(cpu)
+	}
+	#[inline]
+	/// Arm (or stop) this CPU's one-shot time-slice timer, which calls `::threads::preempt_tick` when it expires
+	///
+	/// Architectures with a periodic scheduler tick can ignore this.
+	pub fn set_slice_timer(enabled: bool) {
+		imp::set_slice_timer(enabled)
+	}
+
 	#[inline]
 	pub fn start_thread<F: FnOnce()+Send+'static>(thread: &mut ::threads::Thread, code: F) {
 		imp::start_thread(thread, code)
@@ -326,6 +357,11 @@ pub fn cur_timestamp() -> u64 {
 	imp::cur_timestamp()
 }
 #[inline]
+/// Request a call to `::time::time_tick` once the timestamp reaches `deadline` (used to program one-shot timers)
+pub fn request_tick(deadline: u64) {
+	imp::request_tick(deadline)
+}
+#[inline]
 pub fn print_backtrace() {
 	imp::print_backtrace()
 }
@@ -189,9 +189,8 @@ pub fn wait_on_list(waiters: &mut [&mut Waiter], timeout: Option<u64>) -> Option
 		panic!("wait_on_list - Nothing to wait on");
 	}
 	
-	if timeout.is_some() {
-		todo!("Support timeouts in wait_on_list");
-	}
+	// Timeout is in milliseconds, converted to an absolute deadline
+	let deadline = timeout.map(|ms| ::time::ticks() + ms);
 	
 	// Wait on primitives from the waiters, returning the indexes of those that need a state advance
 	
@@ -207,6 +206,8 @@ pub fn wait_on_list(waiters: &mut [&mut Waiter], timeout: Option<u64>) -> Option
 		.fold(false, |v,x| v | !x.get_waiter().bind_signal( &mut obj) )
 		// ^ doesn't use .any() becuase of unbind_signal below
 		;
+	// - Wake when the timeout expires (cancelled when dropped)
+	let timer = deadline.map(|d| ::time::bind_signal(&obj, d));
 	
 	if force_poll
 	{
@@ -225,8 +226,15 @@ pub fn wait_on_list(waiters: &mut [&mut Waiter], timeout: Option<u64>) -> Option
 				else {
 				}
 			}
+			if let Some(d) = deadline {
+				if ::time::ticks() >= d {
+					log_trace!("- Timeout");
+					break 'outer;
+				}
+			}
 			n_passes += 1;
-			// TODO: Take a short nap
+			// Give other threads a chance to run before polling again
+			::threads::yield_time();
 		}
 		log_trace!("- Fire ({} passes)", n_passes);
 	}
@@ -240,6 +248,7 @@ pub fn wait_on_list(waiters: &mut [&mut Waiter], timeout: Option<u64>) -> Option
 	for ent in waiters.iter_mut().filter(|x| !x.is_complete()) {
 		ent.get_waiter().unbind_signal();
 	}
+	::core::mem::drop(timer);
 	::core::mem::drop(obj);
 	
 	// Run completion handlers (via .is_ready and .complete), counting the number of changed waiters
@@ -5,20 +5,26 @@
 //! Asynchronous Timer.
 //! 
 //! An async timer type, firing after the specified duration has elapsed
-//!
-//! TODO: Fix to actually be usable
 
 pub struct Waiter
 {
 	expiry_ticks: u64,
+	/// Timer bound to a sleep object by `bind_signal` (cancelled by `unbind_signal`)
+	timer: Option<::time::Timer>,
 }
 
 impl Waiter
 {
 	pub fn new(duration_ms: u64) -> Waiter
+	{
+		Waiter::new_deadline( ::time::ticks() + duration_ms )
+	}
+	/// Create a timer that fires once the tick count reaches `expiry_ticks`
+	pub fn new_deadline(expiry_ticks: u64) -> Waiter
 	{
 		Waiter {
-			expiry_ticks: ::time::ticks() + duration_ms,
+			expiry_ticks: expiry_ticks,
+			timer: None,
 		}
 	}
 }
@@ -34,12 +40,13 @@ impl super::PrimitiveWaiter for Waiter {
 	fn run_completion(&mut self) {
 		// no action
 	}
-	fn bind_signal(&mut self, _sleeper: &mut ::threads::SleepObject) -> bool {
-		todo!("timer::Waiter::bind_signal()")
-		//::time::bind_signal(_sleeper, self.expiry_ticks)
+	fn bind_signal(&mut self, sleeper: &mut ::threads::SleepObject) -> bool {
+		// NOTE: If already expired, the sleeper is signalled immediately
+		self.timer = Some( ::time::bind_signal(sleeper, self.expiry_ticks) );
+		true
 	}
 	fn unbind_signal(&mut self) {
-		todo!("timer::Waiter::unbind_signal()")
+		self.timer = None;
 	}
 }
 
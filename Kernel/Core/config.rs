@@ -88,6 +88,8 @@ def_config_set! {
 		Loader @ "LOADER" = "/sysroot/bin/loader",
 //		/// Startup - Init executable (first userland process)
 		Init @ "INIT" = "/sysroot/bin/init",
+//		/// Storage - Multi-volume (RAID) arrays to assemble, see metadevs::storage
+		StorageArrays @ "STORAGE_ARRAYS" = "",
 	}
 }
 
@@ -39,7 +39,7 @@ impl storage::Mapper for Mapper
 		
 		// SAFE: Plain old data
 		let mut block: [u8; 512] = unsafe { ::core::mem::zeroed() };
-		try!(pv.read(0, 0, 1, &mut block).wait());
+		try!(pv.read_blocks(0, &mut block));
 		
 		log_debug!("PV '{}' boot sig {:02x} {:02x}", pv.name(), block[0x1FE], block[0x1FF]);
 		if block[0x1FE] == 0x55 && block[0x1FE+1] == 0xAA {
@@ -57,7 +57,7 @@ impl storage::Mapper for Mapper
 		
 		// SAFE: Plain old data
 		let mut block: [u8; 512] = unsafe { ::core::mem::zeroed() };
-		try!( pv.read(0, 0, 1, &mut block).wait() );
+		try!( pv.read_blocks(0, &mut block) );
 		if !(block[510] == 0x55 && block[511] == 0xAA) {
 			return Err( storage::IoError::InvalidParameter );
 		}
@@ -83,6 +83,27 @@ impl<K: Ord, V> VecMap<K,V>
 		}
 	}
 	
+	/// Remove all items for which the predicate returns false
+	pub fn retain<F>(&mut self, mut f: F)
+	where
+		F: FnMut(&K, &mut V) -> bool
+	{
+		let mut i = 0;
+		while i < self.ents.len()
+		{
+			let keep = {
+				let e = &mut self.ents[i];
+				f(&e.0, &mut e.1)
+				};
+			if keep {
+				i += 1;
+			}
+			else {
+				self.ents.remove(i);
+			}
+		}
+	}
+	
 	/// Return an 'entry' in the map, allowing cheap handling of insertion/lookup
 	pub fn entry(&mut self, key: K) -> Entry<K, V>
 	{
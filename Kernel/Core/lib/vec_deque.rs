@@ -12,6 +12,13 @@ pub struct VecDeque<T>
 	len: usize,
 }
 
+impl<T> Default for VecDeque<T>
+{
+	fn default() -> VecDeque<T> {
+		VecDeque::new_const()
+	}
+}
+
 impl<T> VecDeque<T>
 {
 	pub const fn new_const() -> VecDeque<T> {
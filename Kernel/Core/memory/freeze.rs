@@ -23,6 +23,8 @@ pub enum FreezeError {
 	///
 	/// TODO: What happens if two threads collide? Should the kernel let them hold each other?
 	Locked,
+	/// Memory couldn't be allocated to give the process its own copy of a copy-on-write page
+	OutOfMemory,
 }
 
 /// Type that holds an object in memory, ensuring that it's unmodified and kept valid
@@ -55,6 +57,17 @@ impl<T: ?Sized> FreezeMut<T> {
 	// UNSAFE: Requires the passed pointer to never alias pointers not protected via the API
 	pub unsafe fn new(ptr: *mut T) -> Result<FreezeMut<T>,FreezeError> {
 		// TODO: Freeze page as mutable (using a per-process freeze list to handle overlaps)
+		// Unshare copy-on-write pages now, so running out of memory is reported to the caller instead of faulting
+		// part-way through a write by the kernel (which can't be failed)
+		let start = ptr as *mut u8 as usize;
+		let end = start + ::core::mem::size_of_val(&*ptr);
+		let mut page = start & !::arch::memory::PAGE_MASK;
+		while page < end && page < ::arch::memory::addresses::USER_END {
+			if let Err(_) = ::arch::memory::virt::unshare_cow(page) {
+				return Err( FreezeError::OutOfMemory );
+			}
+			page += ::PAGE_SIZE;
+		}
 		Ok( FreezeMut(ptr) )
 	}
 }
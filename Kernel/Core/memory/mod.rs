@@ -22,6 +22,7 @@ pub mod memorymap;
 
 pub mod bump_region;
 pub mod page_cache;
+pub mod pressure;
 pub mod page_array;
 
 pub use arch::memory::PAddr;
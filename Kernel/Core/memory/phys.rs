@@ -22,7 +22,11 @@ static S_MEM_MAP: ::lib::LazyStatic<&'static [::memory::MemoryMapEnt]> = lazysta
 static S_MAPALLOC : ::sync::Mutex<(usize,PAddr)> = mutex_init!( (0,0) );
 // TODO: Multiple stacks based on page colouring
 static S_FREE_STACK : ::sync::Mutex<PAddr> = mutex_init!( NOPAGE );
-// TODO: Reference counts (maybe require arch to expose that)
+/// Shared all-zero frame, used to back demand-zero memory (never freed)
+static S_ZERO_FRAME: ::lib::LazyStatic<PAddr> = lazystatic_init!();
+
+/// Number of frames requested from reclaimable caches when physical memory runs out
+const RECLAIM_BATCH: usize = 16;
 
 /// A handle to a physical page (maintaining a reference to it, even when not mapped)
 pub struct FrameHandle(PAddr);
@@ -52,6 +56,18 @@ pub fn init()
 		panic!("No free memory in map.");
 	}
 	*S_MAPALLOC.lock() = (i, map[i].start as PAddr);
+
+	// 2. Allocate the shared zero frame (the initial reference is held forever)
+	// SAFE: Called in a true single-threaded context
+	unsafe {
+		S_ZERO_FRAME.prep(|| {
+			let mut page = allocate_bare().expect("Unable to allocate zero frame");
+			for b in page.iter_mut() {
+				*b = 0;
+			}
+			page.phys_addr()
+			});
+	}
 }
 
 impl FrameHandle
@@ -66,6 +82,10 @@ impl FrameHandle
 	pub unsafe fn from_addr_noref(addr: PAddr) -> FrameHandle {
 		FrameHandle(addr)
 	}
+	/// Physical address of the frame (the handle retains its reference)
+	pub fn addr(&self) -> PAddr {
+		self.0
+	}
 	pub fn into_addr(self) -> PAddr {
 		let rv = self.0;
 		::core::mem::forget(self);
@@ -109,20 +129,52 @@ fn is_ram(phys: PAddr) -> bool
 	false
 }
 
-pub fn make_unique(page: PAddr, virt_addr: &[u8; ::PAGE_SIZE]) -> PAddr
+/// Returns a new reference to the shared zero frame
+///
+/// The frame must only be mapped read-only (or COW), as it backs all demand-zero memory.
+pub fn zero_frame() -> FrameHandle
+{
+	// SAFE: The zero frame is valid forever
+	unsafe { FrameHandle::from_addr(*S_ZERO_FRAME) }
+}
+
+/// Returns true if the frame is referenced more than once (excluding the shared zero frame)
+///
+/// Used to tell intentionally shared memory (e.g. shared memory objects) from a process's private pages, once any
+/// copy-on-write has been resolved.
+pub fn is_shared(page: PAddr) -> bool
+{
+	is_ram(page) && page != *S_ZERO_FRAME && ::arch::memory::phys::get_multiref_count(page as u64 / ::PAGE_SIZE as u64) > 0
+}
+
+/// Obtain a frame that is only referenced by the caller, copying `virt_addr` if `page` is shared
+///
+/// The caller's reference to `page` is transferred to the returned frame (i.e. if a copy is made, `page` is dereferenced)
+pub fn make_unique(page: PAddr, virt_addr: &[u8; ::PAGE_SIZE]) -> Result<PAddr, Error>
 {
 	if !is_ram(page) {
 		panic!("Calling 'make_unique' on non-RAM page");
 	}
-	else if ::arch::memory::phys::get_multiref_count(page as u64 / ::PAGE_SIZE as u64) == 0 {
-		page
+	else if page != *S_ZERO_FRAME && ::arch::memory::phys::get_multiref_count(page as u64 / ::PAGE_SIZE as u64) == 0 {
+		Ok( page )
 	}
 	else {
 		// 1. Allocate a new frame in temp region
-		let mut new_frame = ::memory::virt::alloc_free().expect("TODO: handle OOM in make_unique");
-		// 2. Copy in content of old frame
-		new_frame.clone_from_slice( virt_addr );
-		new_frame.into_frame().into_addr()
+		let mut new_frame = try!( allocate_bare() );
+		// 2. Copy in content of old frame (or just clear if the source is the zero frame)
+		if page == *S_ZERO_FRAME {
+			for b in new_frame.iter_mut() {
+				*b = 0;
+			}
+		}
+		else {
+			new_frame.clone_from_slice( virt_addr );
+		}
+		let rv = new_frame.phys_addr();
+		drop(new_frame);
+		// 3. Release the caller's reference to the shared frame
+		deref_frame(page);
+		Ok( rv )
 	}
 }
 
@@ -133,12 +185,20 @@ pub fn allocate_range_bits(bits: u8, count: usize) -> PAddr
 	{
 		return allocate_range(count);
 	}
-	// 1. Locate the last block of a suitable bitness
-	// - Take care to correctly handle blocks that straddle bitness boundaries
-	// NOTE: Memory map constructor _can_ break blocks up at common bitness boundaries (16, 24, 32 bits) to make this more efficient
-	// 2. Obtain `count` pages from either the end (if possible) or the start of this block
-	// TODO: If the block is not large enough, return an error (NOPAGE)
-	panic!("TODO: allocate_range_bits(bits={}, count={})", bits, count);
+	// TODO: Locate the last block of a suitable bitness and allocate from its end (leaving low memory for later requests)
+	// - For now, use the linear allocator and fail if the result is above the limit
+	let limit: u64 = 1 << bits;
+	let size = count * ::PAGE_SIZE;
+	let rv = allocate_range(count);
+	if rv == NOPAGE {
+		return NOPAGE;
+	}
+	if rv as u64 + size as u64 > limit {
+		log_warning!("allocate_range_bits(bits={}, count={}): No memory below limit (got {:#x})", bits, count, rv);
+		release_range(rv, rv + size as PAddr);
+		return NOPAGE;
+	}
+	rv
 }
 
 pub fn allocate_range(count: usize) -> PAddr
@@ -166,12 +226,23 @@ pub fn allocate_range(count: usize) -> PAddr
 		}
 		addr = map[i].start as PAddr;
 	}
-	let rv = addr;
 	let shift = (count * ::PAGE_SIZE) as PAddr;
-	if addr + shift > map[i].end() as PAddr {
-		todo!("Handle allocating from ahead in map ({:#x} + {:#x} > {:#x}, start={:#x})", addr, shift, map[i].end(), map[i].start);
-		// TODO: If the shift pushes this allocation over the edge of a map entry, stick the remaining entries onto the free stack and move to the next free block
+	while addr + shift > map[i].end() as PAddr
+	{
+		// Not enough space left in this entry, put the remaining pages onto the free stack and move to the next free block
+		release_range(addr, map[i].end() as PAddr);
+		i += 1;
+		while i != map.len() && map[i].state != ::memory::memorymap::MemoryState::Free {
+			i += 1;
+		}
+		if i == map.len() {
+			log_error!("Out of physical memory (allocating {} contiguous pages)", count);
+			*h = (i, 0);
+			return NOPAGE;
+		}
+		addr = map[i].start as PAddr;
 	}
+	let rv = addr;
 	addr += shift;
 	//log_trace!("allocate_range: rv={:#x}, i={}, addr={:#x}", rv, i, addr);
 	*h = (i, addr);
@@ -179,6 +250,22 @@ pub fn allocate_range(count: usize) -> PAddr
 	return rv;
 }
 
+/// Push a range of never-allocated frames onto the free stack
+fn release_range(start: PAddr, end: PAddr)
+{
+	let mut h = S_FREE_STACK.lock();
+	let mut addr = start;
+	while addr + ::PAGE_SIZE as PAddr <= end
+	{
+		// SAFE: Frame is unallocated (and thus unaliased)
+		unsafe {
+			::memory::virt::with_temp(addr, |page| *(&mut page[0] as *mut u8 as *mut PAddr) = *h);
+		}
+		*h = addr;
+		addr += ::PAGE_SIZE as PAddr;
+	}
+}
+
 /// Allocate a page with no fixed alocation, returns a temporary handle to it
 pub fn allocate_bare() -> Result<TempHandle<u8>, Error> {
 	allocate_int(None).map(|x| x.expect("Ok(None) from allocate_int when None passed"))
@@ -189,10 +276,18 @@ pub fn allocate(address: *mut ()) -> bool {
 	allocate_int(Some(address)).is_ok()
 }
 
-/// Allocate a page at the given (optional) address
+/// Allocate a page at the given (optional) address, asking caches to release memory if none is free
 /// 
 /// If no address is provided, a temporary handle is returned
 fn allocate_int( address: Option<*mut ()> ) -> Result<Option<TempHandle<u8>>, Error>
+{
+	match allocate_int_noreclaim(address)
+	{
+	Err(_) if ::memory::pressure::reclaim(RECLAIM_BATCH) > 0 => allocate_int_noreclaim(address),
+	rv @ _ => rv,
+	}
+}
+fn allocate_int_noreclaim( address: Option<*mut ()> ) -> Result<Option<TempHandle<u8>>, Error>
 {
 	log_trace!("allocate(address={:?})", address);
 	// 1. Pop a page from the free stack
@@ -211,6 +306,7 @@ fn allocate_int( address: Option<*mut ()> ) -> Result<Option<TempHandle<u8>>, Er
 					// Map and obtain the next page
 					::memory::virt::map(address, paddr, super::virt::ProtectionMode::KernelRW);
 					*h = *(address as *const PAddr);
+					drop(h);
 				}
 				else {
 					// Otherwise, do a temp mapping, extract the next page, then drop the lock and map
@@ -228,6 +324,7 @@ fn allocate_int( address: Option<*mut ()> ) -> Result<Option<TempHandle<u8>>, Er
 			None => {
 				let handle = ::arch::memory::virt::TempHandle::new(paddr);
 				*h = *(&handle[0] as *const u8 as *const PAddr);
+				drop(h);
 				log_trace!("- None (stack) paddr = {:#x}", paddr);
 				mark_used(paddr);
 				return Ok( Some(handle) );
@@ -298,9 +395,12 @@ pub fn deref_frame(paddr: PAddr)
 	}
 }
 
+/// Flag a frame as allocated (so `deref_frame` will release it)
+///
+/// NOTE: Can allocate (to expand the bitmap), so must not be called with `S_FREE_STACK` held
 fn mark_used(paddr: PAddr)
 {
-	//::arch::memory::phys::mark_used(paddr / ::PAGE_SIZE as PAddr)
+	::arch::memory::phys::mark_used(paddr as u64 / ::PAGE_SIZE as u64)
 }
 
 // vim: ft=rust
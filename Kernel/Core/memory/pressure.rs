@@ -0,0 +1,71 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/memory/pressure.rs
+//! Memory pressure handling (reclaim from caches, and the out-of-memory policy)
+//!
+//! Subsystems holding memory that can be discarded (e.g. the block cache) register a `Reclaimable`, which is asked to
+//! release frames when the physical memory manager runs out.
+#[allow(unused_imports)]
+use prelude::*;
+
+/// Exit status of a process killed by the out-of-memory policy (matches `EXITSTATUS_OOM` in the syscall ABI)
+pub const EXITSTATUS_OOM: u32 = 0xFFFF_FFFD;
+
+/// A source of memory that can be released on demand
+pub trait Reclaimable: Send + Sync
+{
+	/// Name of the source (for logging)
+	fn name(&self) -> &str;
+	/// Release up to `target` frames, returning the number actually released
+	///
+	/// NOTE: This is called from within the allocator, so must not block on locks that may be held while allocating.
+	fn reclaim(&self, target: usize) -> usize;
+}
+
+static S_RECLAIMERS: ::sync::Mutex< Vec<&'static Reclaimable> > = ::sync::Mutex::new(Vec::new_const());
+
+/// Register a source of reclaimable memory
+pub fn register(source: &'static Reclaimable)
+{
+	log_debug!("Registered reclaimable memory source '{}'", source.name());
+	S_RECLAIMERS.lock().push(source);
+}
+
+/// Ask registered sources to release at least `target` frames, returns the number released
+///
+/// Returns zero immediately if a reclaim is already in progress (e.g. a reclaimer allocated)
+pub fn reclaim(target: usize) -> usize
+{
+	let lh = match S_RECLAIMERS.try_lock()
+		{
+		Some(v) => v,
+		None => return 0,
+		};
+	let mut released = 0;
+	for source in lh.iter()
+	{
+		if released >= target {
+			break;
+		}
+		let n = source.reclaim(target - released);
+		log_debug!("reclaim: '{}' released {} frames", source.name(), n);
+		released += n;
+	}
+	if released < target {
+		log_warning!("Memory pressure: only reclaimed {}/{} frames", released, target);
+	}
+	released
+}
+
+/// Last-resort out-of-memory policy: terminate the current (user) process
+///
+/// Called when a user-mode fault can't be satisfied, instead of panicking the kernel. Kernel-mode accesses must not
+/// use this (the faulting code may hold locks), syscalls instead fail when the buffer is frozen (see `FreezeMut`).
+pub fn kill_current_process(address: usize) -> !
+{
+	log_error!("Out of memory handling user access to {:#x}, killing process", address);
+	::threads::exit_process(EXITSTATUS_OOM)
+}
+
+// vim: ft=rust
@@ -118,12 +118,16 @@ where
 }
 
 /// Run the provided closure with no changes possible to the address space
+///
+/// NOTE: Anything read before the lock was taken (e.g. a faulting page's entry) must be re-checked within `fcn`, as
+/// another CPU may have changed it while this one waited.
 pub fn with_lock<F>(addr: usize, fcn: F)
 where
 	F: FnOnce()
 {
-	// TODO: Lock
-	log_notice!("TODO: with_lock(addr={:#x})", addr);
+	use arch::memory::addresses::is_global;
+	
+	let _lh = if is_global(addr) { s_kernelspace_lock.lock() } else { s_userspace_lock.lock() };
 	fcn();
 }
 
@@ -132,6 +136,8 @@ pub fn allocate(addr: *mut (), page_count: usize) -> Result<(), MapError> {
 	allocate_int(addr, page_count, false)
 }
 /// Allocate memory for user access
+///
+/// Pages are initially mapped to the shared zero frame (as copy-on-write), and only get a frame of their own when written
 pub fn allocate_user(addr: *mut (), page_count: usize) -> Result<(), MapError> {
 	allocate_int(addr, page_count, true)
 }
@@ -151,7 +157,17 @@ fn allocate_int(addr: *mut (), page_count: usize, is_user: bool) -> Result<(), M
 			return Err(MapError::RangeInUse);
 		}
 	}
-	// 3. do `page_count` single arbitary allocations
+	// 3. User allocations are demand-zero, just map the zero frame (COW) and let the fault handler populate
+	if is_user {
+		for pgptr in Pages(addr, page_count) {
+			// SAFE: Range is unused, and the zero frame reference is transferred to the mapping
+			unsafe {
+				::arch::memory::virt::map(pgptr, ::memory::phys::zero_frame().into_addr(), ProtectionMode::UserCOW);
+			}
+		}
+		return Ok( () );
+	}
+	// 4. do `page_count` single arbitary allocations
 	for pgptr in Pages(addr, page_count) {
 		if ! ::memory::phys::allocate( pgptr ) {
 			// Allocation error!
@@ -168,14 +184,6 @@ fn allocate_int(addr: *mut (), page_count: usize, is_user: bool) -> Result<(), M
 			return Err( MapError::OutOfMemory );
 		}
 	}
-	if is_user {
-		for pgptr in Pages(addr, page_count) {
-			// SAFE: This region has just been allocated, and is KernelRW, upgrading to allow user access
-			unsafe {
-				::arch::memory::virt::reprotect(pgptr, ProtectionMode::UserRW);
-			}
-		}
-	}
 
 	Ok( () )
 }
@@ -219,13 +227,18 @@ impl Reservation
 		// SAFE: Unique, and owned
 		unsafe { ::core::slice::from_raw_parts_mut( (self.0 as usize + idx * ::PAGE_SIZE) as *mut u8, ::PAGE_SIZE) }
 	}
-	//pub fn map_at(&mut self, idx: usize, frame: FrameHandle) -> Result<(),FrameHandle> {
-	//	assert!(idx < self.1);
-	//	let addr = (self.0 as usize + idx * ::PAGE_SIZE) as *mut ();
-	//	::arch::memory::virt::unmap(addr);
-	//	::arch::memory::virt::map(addr, frame.into_addr(), ProtectionMode::KernelRW);
-	//	Ok( () )
-	//}
+	/// Replace the page at `idx` with the provided frame (releasing the placeholder)
+	pub fn map_at(&mut self, idx: usize, frame: ::memory::phys::FrameHandle) {
+		assert!(idx < self.1);
+		let addr = (self.0 as usize + idx * ::PAGE_SIZE) as *mut ();
+		// SAFE: 'self' owns this region of memory, and the frame reference is transferred to the mapping
+		unsafe {
+			if let Some(pa) = ::arch::memory::virt::unmap(addr) {
+				::memory::phys::deref_frame(pa);
+			}
+			::arch::memory::virt::map(addr, frame.into_addr(), ProtectionMode::KernelRW);
+		}
+	}
 	pub fn finalise(self, final_mode: ProtectionMode) -> Result<(),()> {
 		log_trace!("Reservation::finalise(final_mode={:?})", final_mode);
 		for addr in Pages(self.0, self.1) {
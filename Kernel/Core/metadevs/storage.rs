@@ -4,14 +4,17 @@
 // Core/metadevs/storage.rs
 // - Storage (block device) subsystem
 use prelude::*;
-use core::sync::atomic::{AtomicUsize,ATOMIC_USIZE_INIT};
+use core::sync::atomic::{AtomicBool,AtomicUsize,ATOMIC_USIZE_INIT};
 use sync::mutex::LazyMutex;
+use sync::{Mutex,Queue};
 use lib::{VecMap};
 use lib::mem::Arc;
+use _async3 as async;
 
 module_define!{Storage, [], init}
 
-pub type AsyncIoResult<'a, T> = ::async::BoxAsyncResult<'a, T, IoError>;
+/// Maximum number of requests kept in flight on a physical volume for a single access
+const MAX_REQUESTS_IN_FLIGHT: usize = 4;
 
 /// A unique handle to a storage volume (logical)
 pub struct VolumeHandle
@@ -29,6 +32,19 @@ pub struct PhysicalVolumeReg
 	idx: usize,
 }
 
+/// Logical volume hotplug event, delivered to queues registered with `subscribe`
+#[derive(Debug,Clone)]
+pub enum VolumeEvent
+{
+	/// A new logical volume is available (LV index, name)
+	Added(usize, String),
+	/// A logical volume has been removed (LV index, name)
+	Removed(usize, String),
+}
+
+/// Volume event subscription (unsubscribes when dropped)
+pub struct VolumeSubscription(&'static Queue<VolumeEvent>);
+
 /// Helper to print out the size of a volume/size as a pretty SI base 2 number
 pub struct SizePrinter(pub u64);
 
@@ -45,6 +61,59 @@ pub enum IoError
 	Unknown(&'static str),
 }
 
+/// Completion values at or above this are encoded `IoError`s (see `IoError::encode`)
+const IO_ERROR_BASE: usize = !0 - 0xFF;
+impl IoError
+{
+	fn code(&self) -> usize {
+		match *self
+		{
+		IoError::BadAddr => 0,
+		IoError::InvalidParameter => 1,
+		IoError::Timeout => 2,
+		IoError::BadBlock => 3,
+		IoError::ReadOnly => 4,
+		IoError::NoMedium => 5,
+		IoError::Unknown(_) => 6,
+		}
+	}
+	/// Encode the result of an async IO operation as a completion value
+	pub fn encode(r: Result<usize,IoError>) -> usize {
+		match r
+		{
+		Ok(v) => {
+			assert!(v < IO_ERROR_BASE, "IO result {:#x} collides with error codes", v);
+			v
+			},
+		Err(e) => {
+			// The message for `Unknown` doesn't survive the encoding, so log it here
+			if let IoError::Unknown(msg) = e {
+				log_notice!("Async IO error: {}", msg);
+			}
+			!0 - e.code()
+			},
+		}
+	}
+	/// Decode a completion value created by `encode`
+	pub fn decode(v: usize) -> Result<usize,IoError> {
+		if v < IO_ERROR_BASE {
+			Ok(v)
+		}
+		else {
+			Err(match !0 - v
+				{
+				0 => IoError::BadAddr,
+				1 => IoError::InvalidParameter,
+				2 => IoError::Timeout,
+				3 => IoError::BadBlock,
+				4 => IoError::ReadOnly,
+				5 => IoError::NoMedium,
+				_ => IoError::Unknown("Device error"),
+				})
+		}
+	}
+}
+
 /// Mutable/Immutable data pointer, encoded as host-relative (Send = immutable data)
 pub enum DataPtr<'a>
 {
@@ -82,7 +151,12 @@ impl<'a> ::core::fmt::Debug for DataPtr<'a> {
 
 /// Physical volume instance provided by driver
 ///
-/// Provides the low-level methods to manipulate the underlying storage
+/// Provides the low-level methods to manipulate the underlying storage.
+///
+/// IO methods are async-v3 operations: the request is queued on the device, and `async` is signalled
+/// (with a value encoded by `IoError::encode`) once it completes. The caller must have pushed a layer
+/// onto `stack` to receive this value, and must keep the buffer and the async object alive until then.
+/// Drivers may push their own layers (e.g. to acquire a controller lock before starting the IO).
 pub trait PhysicalVolume: Send + 'static
 {
 	/// Returns the volume name (must be unique to the system)
@@ -100,14 +174,14 @@ pub trait PhysicalVolume: Send + 'static
 	///
 	/// The yeilded return value is the number of blocks that were written in this request (which
 	/// can be less than `count`, if the underlying medium has a maximum transfer size).
-	fn read<'a>(&'a self, prio: u8, blockidx: u64, count: usize, dst: &'a mut [u8]) -> AsyncIoResult<'a, usize>;
+	fn read<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, prio: u8, blockidx: u64, count: usize, dst: &'s mut [u8]);
 	/// Writer a number of blocks to the volume
-	fn write<'a>(&'a self, prio: u8, blockidx: u64, count: usize, src: &'a [u8]) -> AsyncIoResult<'a, usize>;
+	fn write<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, prio: u8, blockidx: u64, count: usize, src: &'s [u8]);
 	/// Erases a number of blocks from the volume
 	///
 	/// Erases (requests the underlying storage forget about) `count` blocks starting at `blockidx`.
-	/// This is functionally equivalent to the SSD "TRIM" command.
-	fn wipe<'a>(&'a self, blockidx: u64, count: usize) -> AsyncIoResult<'a,()>;
+	/// This is functionally equivalent to the SSD "TRIM" command. Yields zero on success.
+	fn wipe<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, blockidx: u64, count: usize);
 }
 
 /// Registration for a physical volume handling driver
@@ -129,11 +203,23 @@ pub trait Mapper: Send + Sync
 /// A single physical volume
 struct PhysicalVolumeInfo
 {
-	dev: Box<PhysicalVolume>,
+	dev: Box<PvShared>,
 	mapper: Option<(usize,&'static Mapper)>,
 }
+/// A physical volume, shared with in-progress accesses (see `PvAccess`)
+struct PvShared
+{
+	dev: Box<PhysicalVolume>,
+	/// Number of `PvAccess` handles, only changed with `drain_waiter` locked once the PV has been removed
+	accesses: AtomicUsize,
+	/// Signalled when the last access finishes (set by `PhysicalVolumeReg::drop` while it waits)
+	drain_waiter: ::sync::Spinlock<Option<::threads::SleepObjectRef>>,
+}
+/// Handle to a physical volume held by an in-progress access, so the PV list doesn't need to be locked during IO
+///
+/// The PV is kept until every access has finished (see `PhysicalVolumeReg::drop`).
+struct PvAccess(*const PvShared);
 /// A single logical volume, composed of 1 or more physical blocks
-#[derive(Default)]
 struct LogicalVolume
 {
 	/// LV Index, should be equal to the index in the VecMap
@@ -144,10 +230,23 @@ struct LogicalVolume
 	is_opened: bool,
 	/// Logical block size (max physical block size)
 	block_size: usize,
-	/// Stripe size (number of blocks), None = JBOD
-	chunk_size: Option<usize>,
+	/// Arrangement of data across the regions
+	layout: Layout,
 	/// Physical regions that compose this logical volume
 	regions: Vec<PhysicalRegion>,
+	/// Next mirror to service a read (used to balance reads across mirrors)
+	next_mirror: AtomicUsize,
+}
+/// Arrangement of a logical volume's data across its physical regions
+#[derive(Debug,Copy,Clone,PartialEq)]
+enum Layout
+{
+	/// Regions are concatenated end-to-end (JBOD)
+	Concat,
+	/// Data is striped across regions in chunks of the given number of blocks (RAID0)
+	Stripe(usize),
+	/// Every region holds a complete copy of the data (RAID1)
+	Mirror,
 }
 /// Physical region used by a logical volume
 struct PhysicalRegion
@@ -155,6 +254,21 @@ struct PhysicalRegion
 	volume: usize,
 	block_count: usize,	// usize to save space in average case
 	first_block: u64,
+	/// Set when a write to this mirror member fails, after which it's excluded from reads and writes
+	/// - There's no resync, so the member stays out until the array is re-assembled
+	degraded: AtomicBool,
+}
+
+/// Description of a multi-volume logical volume (from the `STORAGE_ARRAYS` boot option)
+///
+/// The array is assembled once all member LVs have been registered, at which point the
+/// members are consumed (removed from the LV list) and replaced by the array.
+struct ArrayDesc
+{
+	name: String,
+	layout: Layout,
+	members: Vec<String>,
+	assembled: bool,
 }
 
 static S_NEXT_PV_IDX: AtomicUsize = ATOMIC_USIZE_INIT;
@@ -162,6 +276,9 @@ static S_PHYSICAL_VOLUMES: LazyMutex<VecMap<usize,PhysicalVolumeInfo>> = lazymut
 static S_NEXT_LV_IDX: AtomicUsize = ATOMIC_USIZE_INIT;
 static S_LOGICAL_VOLUMES: LazyMutex<VecMap<usize,Arc<LogicalVolume>>> = lazymutex_init!();
 static S_MAPPERS: LazyMutex<Vec<&'static Mapper>> = lazymutex_init!();
+static S_ARRAYS: LazyMutex<Vec<ArrayDesc>> = lazymutex_init!();
+// NOTE: Lock ordering is S_SUBSCRIBERS, S_ARRAYS, then S_LOGICAL_VOLUMES, events must be sent with the LV list unlocked
+static S_SUBSCRIBERS: Mutex<Vec<&'static Queue<VolumeEvent>>> = Mutex::new(Vec::new_const());
 
 // NOTE: Should unbinding of LVs be allowed? (Yes, for volume removal)
 
@@ -170,6 +287,7 @@ fn init()
 	S_PHYSICAL_VOLUMES.init( || VecMap::new() );
 	S_LOGICAL_VOLUMES.init( || VecMap::new() );
 	S_MAPPERS.init( || Vec::new() );
+	S_ARRAYS.init( || parse_array_descs(::config::get_string(::config::Value::StorageArrays)) );
 	
 	// Default mapper just exposes the PV as a single LV
 	//S_MAPPERS.lock().push_back(&default_mapper::Mapper);
@@ -216,7 +334,11 @@ pub fn register_pv(dev: Box<PhysicalVolume>) -> PhysicalVolumeReg
 	
 	// Wait until after checking for a handler before we add the PV to the list
 	S_PHYSICAL_VOLUMES.lock().insert(pv_id, PhysicalVolumeInfo {
-		dev: dev,
+		dev: Box::new(PvShared {
+			dev: dev,
+			accesses: AtomicUsize::new(0),
+			drain_waiter: ::sync::Spinlock::new(None),
+			}),
 		mapper: None,
 		});
 	
@@ -247,7 +369,7 @@ pub fn register_mapper(mapper: &'static Mapper)
 			// No media, skip
 			continue ;
 		}
-		match mapper.handles_pv(&*pv.dev)
+		match mapper.handles_pv(&**pv.dev)
 		{
 		Err(e) => log_error!("Error checking PV{}: {:?}", pv.dev.name(), e),
 		Ok(0) => {},	// Ignore
@@ -304,8 +426,13 @@ fn apply_mapper_to_pv(mapper: &'static Mapper, level: usize, pv_id: usize, pvi:
 				.collect()
 			};
 		log_debug!("Removing {} LVs", keys.len());
-		for k in keys {
-			lh.remove(&k);
+		let removed: Vec<_> = keys.into_iter()
+			.filter_map(|k| lh.remove(&k))
+			.map(|lv| (lv.index, lv.name.clone()))
+			.collect();
+		drop(lh);
+		for (idx, name) in removed {
+			notify(VolumeEvent::Removed(idx, name));
 		}
 		pvi.mapper = None;
 	}
@@ -314,7 +441,7 @@ fn apply_mapper_to_pv(mapper: &'static Mapper, level: usize, pv_id: usize, pvi:
 	pvi.mapper = Some( (level, mapper) );
 	// - Enumerate volumes
 	//  TODO: Support more complex volume types
-	match mapper.enum_volumes(&*pvi.dev, &mut |name, base, len| {
+	match mapper.enum_volumes(&**pvi.dev, &mut |name, base, len| {
 		new_simple_lv(name, pv_id, pvi.dev.blocksize(), base, len);
 		})
 	{
@@ -332,18 +459,209 @@ fn new_simple_lv(name: String, pv_id: usize, block_size: usize, base: u64, size:
 		name: name,
 		is_opened: false,
 		block_size: block_size,
-		chunk_size: None,
-		regions: vec![ PhysicalRegion{ volume: pv_id, block_count: size as usize, first_block: base } ],
+		layout: Layout::Concat,
+		regions: vec![ PhysicalRegion{ volume: pv_id, block_count: size as usize, first_block: base, degraded: AtomicBool::new(false) } ],
+		next_mirror: Default::default(),
 		} );
 	
 	log_log!("Logical Volume: {} {}", lv.name, SizePrinter(size*block_size as u64));
+	let name = lv.name.clone();
 	
 	// Add to global list
 	{
 		let mut lh = S_LOGICAL_VOLUMES.lock();
 		lh.insert(lvidx, lv);
 	}
-	// TODO: Inform something of the new LV
+
+	// Check if this LV completes a configured array before announcing it (so array members are never automounted)
+	try_assemble_arrays();
+	if S_LOGICAL_VOLUMES.lock().get(&lvidx).is_some() {
+		notify(VolumeEvent::Added(lvidx, name));
+	}
+}
+/// Returns true if the named LV is reserved as a member of a configured array that hasn't been assembled yet
+fn is_pending_member(name: &str) -> bool
+{
+	S_ARRAYS.lock().iter().any(|a| !a.assembled && a.members.iter().any(|m| *m == name))
+}
+
+/// Parse the array descriptions from the boot-time configuration
+///
+/// Format: `<name>:<layout>:<member>+<member>...` with multiple arrays separated by `;`.
+/// Layouts are `jbod`, `raid0` (optionally `raid0/<chunk blocks>`, default 16) and `raid1`
+fn parse_array_descs(cfg: &str) -> Vec<ArrayDesc>
+{
+	let mut rv = Vec::new();
+	for desc in cfg.split(';').filter(|v| !v.is_empty())
+	{
+		let mut it = desc.splitn(3, ':');
+		let name = it.next().unwrap();
+		let (layout, members) = match (it.next(), it.next())
+			{
+			(Some(l), Some(m)) => (l, m),
+			_ => {
+				log_warning!("Malformed storage array '{}', expected <name>:<layout>:<members>", desc);
+				continue ;
+				},
+			};
+		let layout = match layout
+			{
+			"jbod" => Layout::Concat,
+			"raid0" => Layout::Stripe(16),
+			"raid1" => Layout::Mirror,
+			l if l.starts_with("raid0/") => match l[6..].parse::<usize>()
+				{
+				Ok(0) | Err(_) => {
+					log_warning!("Invalid stripe size in storage array '{}'", desc);
+					continue ;
+					},
+				Ok(v) => Layout::Stripe(v),
+				},
+			l => {
+				log_warning!("Unknown layout '{}' for storage array '{}'", l, name);
+				continue ;
+				},
+			};
+		let members: Vec<String> = members.split('+').filter(|v| !v.is_empty()).map(|v| String::from_str(v)).collect();
+		if members.len() < 2 {
+			log_warning!("Storage array '{}' needs at least two members", name);
+			continue ;
+		}
+		log_debug!("Storage array '{}': {:?} of {:?}", name, layout, members);
+		rv.push(ArrayDesc {
+			name: String::from_str(name),
+			layout: layout,
+			members: members,
+			assembled: false,
+			});
+	}
+	rv
+}
+
+/// Assemble any configured arrays that have all their members present
+fn try_assemble_arrays()
+{
+	let mut events = Vec::new();
+	let mut arrays = S_ARRAYS.lock();
+	let mut lh = S_LOGICAL_VOLUMES.lock();
+	for array in arrays.iter_mut().filter(|a| !a.assembled)
+	{
+		// 1. Locate all members (in order), bailing if any are still missing
+		let mut member_ids = Vec::with_capacity(array.members.len());
+		for m in array.members.iter()
+		{
+			match lh.iter().find(|&(_, ref v)| v.name == *m)
+			{
+			Some((&i,_)) => member_ids.push(i),
+			None => break,
+			}
+		}
+		if member_ids.len() != array.members.len() {
+			continue ;
+		}
+
+		// 2. Check that the members can be combined
+		// - Members must be simple volumes that aren't open, with matching block sizes
+		let block_size = lh.get(&member_ids[0]).unwrap().block_size;
+		let mut regions = Vec::with_capacity(member_ids.len());
+		let mut valid = true;
+		for &i in member_ids.iter()
+		{
+			let lv = lh.get(&i).unwrap();
+			if Arc::strong_count(lv) != 1 || lv.layout != Layout::Concat || lv.regions.len() != 1 {
+				log_warning!("Storage array '{}': member '{}' is in use or not a simple volume", array.name, lv.name);
+				valid = false;
+				break ;
+			}
+			if lv.block_size != block_size {
+				log_warning!("Storage array '{}': member '{}' block size mismatch ({} != {})",
+					array.name, lv.name, lv.block_size, block_size);
+				valid = false;
+				break ;
+			}
+			let r = &lv.regions[0];
+			regions.push(PhysicalRegion { volume: r.volume, block_count: r.block_count, first_block: r.first_block, degraded: AtomicBool::new(false) });
+		}
+		// - Left pending if invalid, so it's retried when another LV is registered (e.g. once a member is closed)
+		if !valid {
+			continue ;
+		}
+		array.assembled = true;
+
+		// 3. Consume the members and register the array
+		// - Members are never announced (see `notify`), so there's no need for removal events
+		for i in member_ids {
+			lh.remove(&i);
+		}
+		let lvidx = S_NEXT_LV_IDX.fetch_add(1, ::core::sync::atomic::Ordering::Relaxed);
+		let lv = LogicalVolume {
+			index: lvidx,
+			name: array.name.clone(),
+			is_opened: false,
+			block_size: block_size,
+			layout: array.layout,
+			regions: regions,
+			next_mirror: Default::default(),
+			};
+		log_log!("Logical Volume: {} {} ({:?} over {} volumes)", lv.name, SizePrinter(lv.capacity() * block_size as u64),
+			lv.layout, lv.regions.len());
+		events.push( VolumeEvent::Added(lvidx, lv.name.clone()) );
+		lh.insert(lvidx, Arc::new(lv));
+	}
+	drop(lh);
+	drop(arrays);
+
+	for ev in events {
+		notify(ev);
+	}
+}
+
+/// Send an event to all subscribers
+///
+/// Events for members of pending arrays are suppressed, as the volume is only used by the array.
+fn notify(ev: VolumeEvent)
+{
+	log_debug!("notify({:?})", ev);
+	match ev
+	{
+	VolumeEvent::Added(_, ref name) | VolumeEvent::Removed(_, ref name) => if is_pending_member(name) {
+		log_debug!("- '{}' is a member of a pending array, not announcing", name);
+		return ;
+		},
+	}
+	let lh = S_SUBSCRIBERS.lock();
+	for q in lh.iter()
+	{
+		q.push( ev.clone() );
+	}
+}
+
+/// Subscribe to logical volume addition/removal events
+///
+/// An `Added` event is queued immediately for every currently-registered logical volume.
+pub fn subscribe(queue: &'static Queue<VolumeEvent>) -> VolumeSubscription
+{
+	let mut lh = S_SUBSCRIBERS.lock();
+	lh.push(queue);
+	// - Same filtering as `notify` (with the array list locked first, to match `try_assemble_arrays`)
+	let arrays = S_ARRAYS.lock();
+	for (&idx, lv) in S_LOGICAL_VOLUMES.lock().iter()
+	{
+		if !arrays.iter().any(|a| !a.assembled && a.members.iter().any(|m| *m == lv.name)) {
+			queue.push( VolumeEvent::Added(idx, lv.name.clone()) );
+		}
+	}
+	VolumeSubscription(queue)
+}
+impl ::core::ops::Drop for VolumeSubscription
+{
+	fn drop(&mut self)
+	{
+		let mut lh = S_SUBSCRIBERS.lock();
+		if let Some(i) = lh.iter().position(|&q| q as *const _ == self.0 as *const _) {
+			lh.remove(i);
+		}
+	}
 }
 
 /// Enumerate present physical volumes (returning both the identifier and name)
@@ -380,15 +698,30 @@ impl VolumeHandle
 {
 	pub fn new_ramdisk(_count: usize) -> VolumeHandle {
 		VolumeHandle {
-			handle: Arc::new(LogicalVolume::default())
+			handle: Arc::new(LogicalVolume {
+				index: 0,
+				name: String::new(),
+				is_opened: false,
+				block_size: 0,
+				layout: Layout::Concat,
+				regions: Vec::new(),
+				next_mirror: Default::default(),
+				})
 		}
 	}
 	/// Acquire an unique handle to a logical volume
 	pub fn open_idx(idx: usize) -> Result<VolumeHandle,VolOpenError>
 	{
-		match S_LOGICAL_VOLUMES.lock().get(&idx)
+		match S_LOGICAL_VOLUMES.lock().get_mut(&idx)
 		{
-		Some(v) => todo!("open_lv '{}'", v.name),
+		Some(v) => {
+			if Arc::get_mut(v).is_some() {
+				Ok( VolumeHandle { handle: v.clone() } )
+			}
+			else {
+				Err( VolOpenError::Locked )
+			}
+			},
 		None => Err( VolOpenError::NotFound ),
 		}
 	}
@@ -419,31 +752,8 @@ impl VolumeHandle
 		&self.handle.name
 	}
 	
-	// TODO: Return a more complex type that can be incremented
-	// Returns: VolIdx, Block, Count
 	fn get_phys_block(&self, idx: u64, count: usize) -> Option<(usize,u64,usize)> {
-		if let Some(size) = self.handle.chunk_size
-		{
-			todo!("Non JBOD logocal volumes ({} block stripe)", size);
-		}
-		else
-		{
-			let mut idx_rem = idx;
-			for v in self.handle.regions.iter()
-			{
-				if idx_rem < v.block_count as u64 {
-					let ret_count = ::core::cmp::min(
-						v.block_count as u64 - idx_rem,
-						count as u64
-						) as usize;
-					return Some( (v.volume, v.first_block + idx_rem, ret_count) );
-				}
-				else {
-					idx_rem -= v.block_count as u64;
-				}
-			}
-		}
-		None
+		self.handle.get_phys_block(idx, count)
 	}
 	
 	/// Read a series of blocks from the volume into the provided buffer.
@@ -455,6 +765,9 @@ impl VolumeHandle
 			log_warning!("Read size {} not a multiple of {} bytes", dst.len(), self.block_size());
 			return Err( IoError::InvalidParameter );
 		}
+		if self.handle.layout == Layout::Mirror {
+			return self.read_blocks_mirror(idx, dst);
+		}
 		
 		let mut rem = dst.len() / self.block_size();
 		let mut blk = 0;
@@ -471,19 +784,54 @@ impl VolumeHandle
 			assert!(count <= rem);
 			let bofs = blk as usize * self.block_size();
 			let dst = &mut dst[bofs .. bofs + count * self.block_size()];
-			try!( S_PHYSICAL_VOLUMES.lock().get(&pv).expect("Volume missing").read(ofs, dst) );
+			try!( read_pv(pv, ofs, dst) );
 			blk += count;
 			rem -= count;
 		}
 		Ok( () )
 	}
 
+	/// Read from a mirrored volume, rotating between mirrors and falling back to other mirrors on error
+	///
+	/// Degraded mirrors are skipped, as they may have missed writes.
+	fn read_blocks_mirror(&self, idx: u64, dst: &mut [u8]) -> Result<(),IoError> {
+		let count = (dst.len() / self.block_size()) as u64;
+		if idx + count > self.handle.member_blocks() {
+			log_warning!("VolumeHandle::read_blocks - Block range {}+{} is invalid", idx, count);
+			return Err( IoError::BadAddr );
+		}
+		
+		let regions = &self.handle.regions;
+		let first = self.handle.next_mirror.fetch_add(1, ::core::sync::atomic::Ordering::Relaxed);
+		let mut err = IoError::NoMedium;
+		for i in 0 .. regions.len()
+		{
+			let r = &regions[(first + i) % regions.len()];
+			if r.degraded.load(::core::sync::atomic::Ordering::Acquire) {
+				continue ;
+			}
+			log_trace!("- PV{} {} + {}", r.volume, r.first_block + idx, count);
+			match read_pv(r.volume, r.first_block + idx, dst)
+			{
+			Ok(_) => return Ok( () ),
+			Err(e) => {
+				log_warning!("{}: Mirror PV{} failed read ({:?}), trying another", self.name(), r.volume, e);
+				err = e;
+				},
+			}
+		}
+		Err(err)
+	}
+
 	pub fn write_blocks(&self, idx: u64, dst: &[u8]) -> Result<(),IoError> {
 		log_trace!("VolumeHandle::write_blocks(idx={}, dst={{len={}}})", idx, dst.len());
 		if dst.len() % self.block_size() != 0 {
 			log_warning!("Write size {} not a multiple of {} bytes", dst.len(), self.block_size());
 			return Err( IoError::InvalidParameter );
 		}
+		if self.handle.layout == Layout::Mirror {
+			return self.write_blocks_mirror(idx, dst);
+		}
 		
 		let mut rem = dst.len() / self.block_size();
 		let mut blk = 0;
@@ -500,15 +848,171 @@ impl VolumeHandle
 			assert!(count <= rem);
 			let bofs = blk as usize * self.block_size();
 			let dst = &dst[bofs .. bofs + count * self.block_size()];
-			try!( S_PHYSICAL_VOLUMES.lock().get(&pv).unwrap().write(ofs, dst) );
+			try!( write_pv(pv, ofs, dst) );
 			blk += count;
 			rem -= count;
 		}
 		Ok( () )
 	}
+
+	/// Write to all mirrors, succeeding if at least one mirror accepted the data
+	///
+	/// Mirrors that fail the write are marked as degraded, so later reads can't return stale data from them.
+	fn write_blocks_mirror(&self, idx: u64, src: &[u8]) -> Result<(),IoError> {
+		let count = (src.len() / self.block_size()) as u64;
+		if idx + count > self.handle.member_blocks() {
+			log_warning!("VolumeHandle::write_blocks - Block range {}+{} is invalid", idx, count);
+			return Err( IoError::BadAddr );
+		}
+
+		let mut err = IoError::NoMedium;
+		let mut n_written = 0;
+		for r in self.handle.regions.iter()
+		{
+			if r.degraded.load(::core::sync::atomic::Ordering::Acquire) {
+				continue ;
+			}
+			log_trace!("- PV{} {} + {}", r.volume, r.first_block + idx, count);
+			match write_pv(r.volume, r.first_block + idx, src)
+			{
+			Ok(_) => n_written += 1,
+			Err(e) => {
+				log_warning!("{}: Mirror PV{} failed write ({:?}), running degraded", self.name(), r.volume, e);
+				r.degraded.store(true, ::core::sync::atomic::Ordering::Release);
+				err = e;
+				},
+			}
+		}
+		if n_written == 0 {
+			Err(err)
+		}
+		else {
+			Ok( () )
+		}
+	}
+}
+
+/// Run a single async IO operation to completion, blocking the current thread
+///
+/// `f` is passed the handle and stack of a fresh async object, and should start the operation.
+pub fn wait_io<'s, F>(f: F) -> Result<usize,IoError>
+where
+	F: for<'a> FnOnce(async::ObjectHandle, async::StackPush<'a, 's>)
+{
+	let mut obj = async::Object::default();
+	{
+		let handle = obj.get_handle();
+		let mut stack = obj.get_stack();
+		stack.push_closure(|_async, _stack, res| Some(res)).expect("Insufficient space when pushing closure");
+		f(handle, stack);
+	}
+	let handles = [&obj];
+	match async::Waiter::new(&handles).wait_one()
+	{
+	Some(res) => IoError::decode(res.result),
+	None => unreachable!(),
+	}
+}
+
+/// Obtain a handle to a physical volume, returning `NoMedium` if the volume has been removed
+fn get_pv(pv: usize) -> Result<PvAccess,IoError>
+{
+	match S_PHYSICAL_VOLUMES.lock().get(&pv)
+	{
+	Some(pvi) => {
+		// - Counted with the PV list locked, so removal (which happens with it locked) sees every access
+		pvi.dev.accesses.fetch_add(1, ::core::sync::atomic::Ordering::Acquire);
+		Ok( PvAccess(&*pvi.dev) )
+		},
+	None => Err( IoError::NoMedium ),
+	}
+}
+/// Read from a physical volume, returning `NoMedium` if the volume has been removed
+fn read_pv(pv: usize, first: u64, dst: &mut [u8]) -> Result<(),IoError>
+{
+	try!(get_pv(pv)).read_blocks(first, dst).map(|_| ())
+}
+/// Write to a physical volume, returning `NoMedium` if the volume has been removed
+fn write_pv(pv: usize, first: u64, src: &[u8]) -> Result<(),IoError>
+{
+	try!(get_pv(pv)).write_blocks(first, src).map(|_| ())
+}
+
+impl LogicalVolume
+{
+	/// Number of blocks usable from each region (for striped/mirrored volumes)
+	fn member_blocks(&self) -> u64 {
+		let min = self.regions.iter().map(|r| r.block_count as u64).min().unwrap_or(0);
+		match self.layout
+		{
+		Layout::Stripe(chunk_size) => min / chunk_size as u64 * chunk_size as u64,
+		_ => min,
+		}
+	}
+	/// Total number of logical blocks in this volume
+	fn capacity(&self) -> u64 {
+		match self.layout
+		{
+		Layout::Concat => self.regions.iter().map(|r| r.block_count as u64).sum(),
+		Layout::Stripe(_) => self.member_blocks() * self.regions.len() as u64,
+		Layout::Mirror => self.member_blocks(),
+		}
+	}
+
+	// TODO: Return a more complex type that can be incremented
+	// Returns: VolIdx, Block, Count
+	fn get_phys_block(&self, idx: u64, count: usize) -> Option<(usize,u64,usize)> {
+		match self.layout
+		{
+		Layout::Concat => {
+			let mut idx_rem = idx;
+			for v in self.regions.iter()
+			{
+				if idx_rem < v.block_count as u64 {
+					let ret_count = ::core::cmp::min(
+						v.block_count as u64 - idx_rem,
+						count as u64
+						) as usize;
+					return Some( (v.volume, v.first_block + idx_rem, ret_count) );
+				}
+				else {
+					idx_rem -= v.block_count as u64;
+				}
+			}
+			None
+			},
+		Layout::Stripe(chunk_size) => {
+			let chunk_size = chunk_size as u64;
+			let n_regions = self.regions.len() as u64;
+			let chunk_idx = idx / chunk_size;
+			let chunk_ofs = idx % chunk_size;
+			let region = &self.regions[(chunk_idx % n_regions) as usize];
+			let region_blk = (chunk_idx / n_regions) * chunk_size + chunk_ofs;
+			if region_blk >= self.member_blocks() {
+				None
+			}
+			else {
+				let ret_count = ::core::cmp::min(chunk_size - chunk_ofs, count as u64) as usize;
+				Some( (region.volume, region.first_block + region_blk, ret_count) )
+			}
+			},
+		// Mirrored volumes have multiple physical locations for each block, handled by the caller
+		Layout::Mirror => unreachable!(),
+		}
+	}
 }
 
-impl PhysicalVolumeInfo
+/// A single in-progress request on a physical volume
+#[derive(Copy,Clone)]
+struct PvRequest
+{
+	blk_id: u64,
+	/// Byte offset into the caller's buffer
+	ofs: usize,
+	blocks: usize,
+}
+
+impl PhysicalVolume
 {
 	fn max_blocks_per_read(&self) -> usize {
 		// 32 blocks per read op, = 0x4000 (16KB) for 512 byte sectors
@@ -516,65 +1020,142 @@ impl PhysicalVolumeInfo
 		32
 	}
 	
-	/// Read blocks from the device
-	pub fn read(&self, first: u64, dst: &mut [u8]) -> Result<usize,IoError>
+	/// Read blocks from the device (blocking until the read is complete)
+	pub fn read_blocks(&self, first: u64, dst: &mut [u8]) -> Result<usize,IoError>
+	{
+		log_trace!("PhysicalVolume::read_blocks(first={},{} bytes)", first, dst.len());
+		self.do_io(first, DataPtr::Recv(dst))
+	}
+	
+	/// Write blocks to the device (blocking until the write is complete)
+	pub fn write_blocks(&self, first: u64, src: &[u8]) -> Result<usize,IoError>
+	{
+		log_trace!("PhysicalVolume::write_blocks(first={},{} bytes)", first, src.len());
+		self.do_io(first, DataPtr::Send(src))
+	}
+
+	/// Split an access into requests of at most `max_blocks_per_read` blocks, with up to
+	/// `MAX_REQUESTS_IN_FLIGHT` of them queued on the device at once
+	fn do_io(&self, first: u64, data: DataPtr) -> Result<usize,IoError>
 	{
-		log_trace!("PhysicalVolumeInfo::read(first={},{} bytes)", first, dst.len());
-		let block_size = self.dev.blocksize();
-		let total_blocks = dst.len() / block_size;
-		// Read up to 'block_step' blocks in each read call
-		// - TODO: Request a read of as much as possible, and be told by the device how many were serviced
+		let block_size = self.blocksize();
+		assert!(data.len() % block_size == 0);
+		let total_blocks = data.len() / block_size;
+		let block_step = self.max_blocks_per_read();
+
+		let mut objects: [async::Object; MAX_REQUESTS_IN_FLIGHT] = Default::default();
+		let mut active: [Option<PvRequest>; MAX_REQUESTS_IN_FLIGHT] = [None; MAX_REQUESTS_IN_FLIGHT];
+		let mut next_block = 0;
+		let mut error = None;
+		loop
 		{
-			let mut buf = dst;
-			let mut blk_id = first;
-			while buf.len() > 0
+			// 1. Hand idle slots a new request (unless an error has been seen, then just wait for the others)
+			for (obj, slot) in Iterator::zip(objects.iter_mut(), active.iter_mut())
 			{
-				assert!(buf.len() % block_size == 0);
-				let prio = 0;
-				let blocks = buf.len() / block_size;
-				
-				// TODO: Async! (maybe return a composite read handle?)
-				let real_count = match self.dev.read(prio, blk_id, blocks, buf).wait()
-					{
-					Ok(v) => v,
-					Err(e) => todo!("Error when PV fails to read: {:?}", e),
+				if slot.is_some() || next_block == total_blocks || error.is_some() {
+					continue ;
+				}
+				let req = PvRequest {
+					blk_id: first + next_block as u64,
+					ofs: next_block * block_size,
+					blocks: ::core::cmp::min(block_step, total_blocks - next_block),
 					};
-				assert!(real_count <= blocks);
-				blk_id += real_count as u64;
+				next_block += req.blocks;
+				self.start_request(obj, &data, req);
+				*slot = Some(req);
+			}
 
-				// SAFE: Evil stuff to advance the buffer
-				buf = unsafe { &mut *(&mut buf[real_count * block_size..] as *mut _) };
-//				split_at_mut_inplace(&mut buf, real_count * block_size);
+			// 2. Wait for any of the active requests to complete
+			let res = {
+				let handles: Vec<&async::Object> = objects.iter().collect();
+				match async::Waiter::new(&handles).wait_one()
+				{
+				Some(v) => v,
+				// Nothing left in flight
+				None => break,
+				}
+				};
+			let req = active[res.slot].take().expect("Completion signalled on an idle request slot");
+			match IoError::decode(res.result)
+			{
+			Ok(count) if count > 0 => {
+				assert!(count <= req.blocks);
+				// The device can service less than requested (e.g. a maximum transfer size), queue the rest
+				if count < req.blocks && error.is_none() {
+					let rem = PvRequest {
+						blk_id: req.blk_id + count as u64,
+						ofs: req.ofs + count * block_size,
+						blocks: req.blocks - count,
+						};
+					self.start_request(&mut objects[res.slot], &data, rem);
+					active[res.slot] = Some(rem);
+				}
+				},
+			rv => {
+				let e = match rv { Err(e) => e, Ok(_) => IoError::Unknown("No progress"), };
+				log_warning!("{}: {} of block {} failed - {:?}", self.name(), if data.is_send() { "Write" } else { "Read" }, req.blk_id, e);
+				if error.is_none() {
+					error = Some(e);
+				}
+				},
 			}
 		}
 
-		Ok(total_blocks)
+		match error
+		{
+		Some(e) => Err(e),
+		None => Ok(total_blocks),
+		}
 	}
-	
-	/// Write blocks from the device
-	pub fn write(&self, first: u64, dst: &[u8]) -> Result<usize,IoError>
+
+	fn start_request<'s>(&'s self, obj: &mut async::Object<'s>, data: &DataPtr<'s>, req: PvRequest)
 	{
-		log_trace!("PhysicalVolumeInfo::write(first={},{} bytes)", first, dst.len());
-		let block_step = self.max_blocks_per_read();
-		let block_size = self.dev.blocksize();
-		// Read up to 'block_step' blocks in each read call
+		let len = req.blocks * self.blocksize();
+		let handle = obj.get_handle();
+		let mut stack = obj.get_stack();
+		// Keep the object active until the device signals completion, and pass its result up to `do_io`
+		stack.push_closure(|_async, _stack, res| Some(res)).expect("Insufficient space when pushing closure");
+		let prio = 0;
+		match *data
 		{
-			let iter_ids  = (first .. ).step_by(block_step);
-			let iter_bufs = dst.chunks( block_step * block_size );
-			for (blk_id,buf) in iter_ids.zip( iter_bufs )
-			{
-				let prio = 0;
-				let blocks = buf.len() / block_size;
-				
-				// TODO: Async! (maybe return a composite read handle?)
-				match self.dev.write(prio, blk_id, blocks, buf).wait()
-				{
-				Ok(real_count) => { assert!(real_count == blocks, "TODO: Handle incomplete writes"); },
-				Err(e) => todo!("Error when PV fails to write: {:?}", e),
-				}
+		DataPtr::Send(src) => self.write(handle, stack, prio, req.blk_id, req.blocks, &src[req.ofs ..][.. len]),
+		DataPtr::Recv(ref dst) => {
+			assert!(req.ofs + len <= dst.len());
+			// SAFE: Active requests cover disjoint ranges of the buffer, and all complete before `do_io` returns
+			let dst = unsafe { ::core::slice::from_raw_parts_mut(dst.as_ptr().offset(req.ofs as isize) as *mut u8, len) };
+			self.read(handle, stack, prio, req.blk_id, req.blocks, dst)
+			},
+		}
+	}
+}
+
+impl ::core::ops::Deref for PvShared
+{
+	type Target = PhysicalVolume;
+	fn deref(&self) -> &PhysicalVolume {
+		&*self.dev
+	}
+}
+impl ::core::ops::Deref for PvAccess
+{
+	type Target = PhysicalVolume;
+	fn deref(&self) -> &PhysicalVolume {
+		// SAFE: The PV isn't released until all accesses have been dropped
+		unsafe { &*(*self.0).dev }
+	}
+}
+impl ::core::ops::Drop for PvAccess
+{
+	fn drop(&mut self)
+	{
+		// SAFE: As above. Once the count is decremented the PV can be released, so the lock is held until the last use
+		let pv = unsafe { &*self.0 };
+		let lh = pv.drain_waiter.lock();
+		if pv.accesses.fetch_sub(1, ::core::sync::atomic::Ordering::Release) == 1 {
+			if let Some(ref w) = *lh {
+				w.signal();
 			}
 		}
-		Ok(dst.len()/block_size)
 	}
 }
 
@@ -582,7 +1163,48 @@ impl ::core::ops::Drop for PhysicalVolumeReg
 {
 	fn drop(&mut self)
 	{
-		todo!("PhysicalVolumeReg::drop idx={}", self.idx);
+		log_trace!("PhysicalVolumeReg::drop idx={}", self.idx);
+		// 1. Remove the PV itself, and logical volumes that depend on it
+		// - Open handles keep the LV alive, but accesses to this PV will fail with `NoMedium`
+		// - Mirrors are kept while any member is present
+		// - The PV list stays locked until the LVs are removed, so concurrently removed mirror members see each other gone
+		let (pvi, removed) = {
+			let mut pv_lh = S_PHYSICAL_VOLUMES.lock();
+			let pvi = pv_lh.remove(&self.idx);
+			let mut lh = S_LOGICAL_VOLUMES.lock();
+			let pv_idx = self.idx;
+			let keys: Vec<usize> = lh.iter()
+				.filter(|&(_,lv)| lv.regions.iter().any(|r| r.volume == pv_idx))
+				.filter(|&(_,lv)| lv.layout != Layout::Mirror || lv.regions.iter().all(|r| pv_lh.get(&r.volume).is_none()))
+				.map(|(&i,_)| i)
+				.collect();
+			let removed: Vec<_> = keys.into_iter()
+				.filter_map(|k| lh.remove(&k))
+				.map(|lv| (lv.index, lv.name.clone()))
+				.collect();
+			(pvi, removed)
+			};
+		for (idx, name) in removed {
+			log_log!("Logical Volume {} removed", name);
+			notify(VolumeEvent::Removed(idx, name));
+		}
+		// 2. Wait for in-flight accesses to drain, as the driver is about to release the hardware
+		// - The driver must have completed any requests the device won't (e.g. with `NoMedium` if it was unplugged)
+		if let Some(pvi) = pvi {
+			let obj = ::threads::SleepObject::new("PhysicalVolumeReg::drop");
+			*pvi.dev.drain_waiter.lock() = Some(obj.get_ref());
+			loop
+			{
+				{
+					let mut lh = pvi.dev.drain_waiter.lock();
+					if pvi.dev.accesses.load(::core::sync::atomic::Ordering::Acquire) == 0 {
+						*lh = None;
+						break ;
+					}
+				}
+				obj.wait();
+			}
+		}
 	}
 }
 
@@ -637,4 +1259,106 @@ mod default_mapper
 	}
 }
 
+#[cfg(test)]
+mod tests
+{
+	#[allow(unused_imports)]
+	use prelude::*;
+	use super::{LogicalVolume, PhysicalRegion, Layout, parse_array_descs};
+	use core::sync::atomic::{AtomicBool,AtomicUsize};
+	use super::{IoError, IO_ERROR_BASE};
+
+	fn lv(layout: Layout, regions: &[(usize, u64, usize)]) -> LogicalVolume {
+		LogicalVolume {
+			index: 0,
+			name: String::new(),
+			is_opened: false,
+			block_size: 512,
+			layout: layout,
+			regions: regions.iter().map(|&(volume, first_block, block_count)| PhysicalRegion { volume: volume, block_count: block_count, first_block: first_block, degraded: AtomicBool::new(false) }).collect(),
+			next_mirror: AtomicUsize::new(0),
+			}
+	}
+
+	#[test]
+	fn io_error_encoding()
+	{
+		// Counts pass through unchanged
+		for &v in [0, 1, 512, IO_ERROR_BASE - 1].iter()
+		{
+			assert_eq!(IoError::encode(Ok(v)), v);
+			assert_eq!(IoError::decode(v).ok(), Some(v));
+		}
+		// Every error survives a round trip (except `Unknown`'s message)
+		let rt = |e| IoError::decode(IoError::encode(Err(e)));
+		assert!( is!(rt(IoError::BadAddr), Err(IoError::BadAddr)) );
+		assert!( is!(rt(IoError::InvalidParameter), Err(IoError::InvalidParameter)) );
+		assert!( is!(rt(IoError::Timeout), Err(IoError::Timeout)) );
+		assert!( is!(rt(IoError::BadBlock), Err(IoError::BadBlock)) );
+		assert!( is!(rt(IoError::ReadOnly), Err(IoError::ReadOnly)) );
+		assert!( is!(rt(IoError::NoMedium), Err(IoError::NoMedium)) );
+		assert!( is!(rt(IoError::Unknown("test")), Err(IoError::Unknown(_))) );
+		// Codes without a variant decode as `Unknown`
+		assert!( is!(IoError::decode(!0 - 0x80), Err(IoError::Unknown(_))) );
+		assert!( IoError::encode(Err(IoError::BadAddr)) >= IO_ERROR_BASE );
+	}
+
+	#[test]
+	fn array_descs()
+	{
+		let descs = parse_array_descs("md0:raid0:sda+sdb;md1:raid1:sdc+sdd+sde;bad:raid5:x+y;md2:raid0/4:a+b;one:jbod:a;zero:raid0/0:a+b;;j:jbod:p+q");
+		let summary: Vec<(&str, Layout, Vec<&str>)> = descs.iter()
+			.map(|d| (&d.name[..], d.layout, d.members.iter().map(|m| &m[..]).collect()))
+			.collect();
+		assert_eq!(summary, vec![
+			("md0", Layout::Stripe(16), vec!["sda", "sdb"]),
+			("md1", Layout::Mirror, vec!["sdc", "sdd", "sde"]),
+			("md2", Layout::Stripe(4), vec!["a", "b"]),
+			("j", Layout::Concat, vec!["p", "q"]),
+			]);
+		assert!( descs.iter().all(|d| !d.assembled) );
+
+		// Missing members
+		assert_eq!(parse_array_descs("md0:raid1").len(), 0);
+		assert_eq!(parse_array_descs("").len(), 0);
+	}
+
+	#[test]
+	fn stripe_mapping()
+	{
+		// Two members, 4 block chunks. The shorter member limits each to 16 blocks (four whole chunks)
+		let v = lv(Layout::Stripe(4), &[(10, 100, 20), (11, 0, 18)]);
+		assert_eq!(v.member_blocks(), 16);
+		assert_eq!(v.capacity(), 32);
+
+		// (logical block, count) => (member, member block, blocks until the chunk ends)
+		assert_eq!(v.get_phys_block(0, 8), Some( (10, 100, 4) ));
+		assert_eq!(v.get_phys_block(4, 8), Some( (11, 0, 4) ));
+		assert_eq!(v.get_phys_block(6, 1), Some( (11, 2, 1) ));
+		assert_eq!(v.get_phys_block(9, 10), Some( (10, 105, 3) ));
+		assert_eq!(v.get_phys_block(31, 1), Some( (11, 15, 1) ));
+		assert_eq!(v.get_phys_block(32, 1), None);
+	}
+
+	#[test]
+	fn concat_mapping()
+	{
+		let v = lv(Layout::Concat, &[(10, 100, 20), (11, 0, 18)]);
+		assert_eq!(v.capacity(), 38);
+		assert_eq!(v.get_phys_block(0, 50), Some( (10, 100, 20) ));
+		assert_eq!(v.get_phys_block(19, 5), Some( (10, 119, 1) ));
+		assert_eq!(v.get_phys_block(20, 5), Some( (11, 0, 5) ));
+		assert_eq!(v.get_phys_block(37, 5), Some( (11, 17, 1) ));
+		assert_eq!(v.get_phys_block(38, 1), None);
+	}
+
+	#[test]
+	fn mirror_capacity()
+	{
+		let v = lv(Layout::Mirror, &[(10, 100, 20), (11, 0, 18)]);
+		assert_eq!(v.member_blocks(), 18);
+		assert_eq!(v.capacity(), 18);
+	}
+}
+
 // vim: ft=rust
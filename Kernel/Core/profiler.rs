@@ -0,0 +1,326 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/profiler.rs
+//! Sampling CPU profiler
+//!
+//! While running, each CPU's scheduler tick records the interrupted address (and optionally a short backtrace)
+//! into a per-CPU buffer. The results are dumped as folded stacks (the input format for flamegraph tools).
+//!
+//! Samples are taken by the amd64 and armv8 timer ticks, armv7 doesn't sample (its timer is polled).
+#[allow(unused_imports)]
+use prelude::*;
+use core::sync::atomic::{AtomicUsize,Ordering};
+use sync::Spinlock;
+use lib::LazyStatic;
+
+/// Maximum number of frames recorded per sample (including the interrupted address)
+pub const MAX_DEPTH: usize = 8;
+/// Samples stored per CPU, any more are counted but not recorded
+const SAMPLES_PER_CPU: usize = 4096;
+
+#[derive(Copy,Clone,PartialEq,Eq,PartialOrd,Ord)]
+struct Sample
+{
+	/// Interrupted address, followed by return addresses
+	frames: [usize; MAX_DEPTH],
+	depth: u8,
+	/// Interrupted code was in userland (only the address is recorded)
+	user: bool,
+}
+const EMPTY_SAMPLE: Sample = Sample { frames: [0; MAX_DEPTH], depth: 0, user: false };
+
+struct CpuBuffer
+{
+	/// Pre-allocated storage (so recording doesn't allocate in interrupt context)
+	samples: Vec<Sample>,
+	count: usize,
+	dropped: usize,
+}
+
+/// Number of frames to record per sample, zero when the profiler is stopped
+static S_DEPTH: AtomicUsize = AtomicUsize::new(0);
+static S_BUFFERS: LazyStatic< Vec<Spinlock<CpuBuffer>> > = lazystatic_init!();
+
+pub fn init()
+{
+	// SAFE: Called in a single-threaded context
+	unsafe {
+		S_BUFFERS.prep(|| Vec::from_fn(::arch::threads::MAX_CPUS, |_| Spinlock::new(CpuBuffer { samples: Vec::new(), count: 0, dropped: 0 })));
+	}
+}
+
+/// Start sampling, recording up to `depth` frames per sample (one for just the interrupted address)
+///
+/// Any samples from a previous run are discarded. On amd64 a CPU with its time-slice timer stopped (idle, or with
+/// nothing else to run) starts sampling at its next context switch.
+///
+/// Each CPU's buffer is allocated by the first start and then kept, so repeated runs don't grow the heap.
+pub fn start(depth: usize)
+{
+	let depth = ::core::cmp::max(1, ::core::cmp::min(depth, MAX_DEPTH));
+	for buf in S_BUFFERS[.. ::arch::threads::cpu_count()].iter()
+	{
+		let allocated = {
+			let _irq = ::sync::hold_interrupts();
+			buf.lock().samples.len() != 0
+			};
+		// Allocate outside the lock (interrupts are held while it's locked)
+		let mut samples = if allocated { Vec::new() } else { Vec::from_elem(SAMPLES_PER_CPU, EMPTY_SAMPLE) };
+		let _irq = ::sync::hold_interrupts();
+		let mut lh = buf.lock();
+		if lh.samples.len() == 0 {
+			::core::mem::swap(&mut lh.samples, &mut samples);
+		}
+		lh.count = 0;
+		lh.dropped = 0;
+	}
+	log_notice!("Profiler started (depth={})", depth);
+	S_DEPTH.store(depth, Ordering::SeqCst);
+}
+/// Stop sampling (the recorded samples are kept until dumped or the next `start`)
+pub fn stop()
+{
+	if S_DEPTH.swap(0, Ordering::SeqCst) != 0 {
+		log_notice!("Profiler stopped");
+	}
+}
+
+/// Number of frames the architecture should collect for a sample, `None` if the profiler isn't running
+#[is_safe(irq)]
+pub fn sample_depth() -> Option<usize>
+{
+	match S_DEPTH.load(Ordering::Relaxed)
+	{
+	0 => None,
+	v => Some(v),
+	}
+}
+#[doc(hidden)]
+/// Record a sample for the current CPU (called by the architecture's scheduler tick)
+///
+/// `frames` is the interrupted address, followed by any return addresses
+#[is_safe(irq)]
+pub fn record(frames: &[usize], user: bool)
+{
+	if frames.len() == 0 || !S_BUFFERS.ls_is_valid() {
+		return ;
+	}
+	let mut sample = EMPTY_SAMPLE;
+	let depth = ::core::cmp::min(frames.len(), MAX_DEPTH);
+	sample.frames[..depth].copy_from_slice(&frames[..depth]);
+	sample.depth = depth as u8;
+	sample.user = user;
+
+	// - If the buffer is locked (being reset or dumped), drop the sample
+	if let Some(mut lh) = S_BUFFERS[::arch::threads::cpu_index()].try_lock_cpu()
+	{
+		let idx = lh.count;
+		if idx < lh.samples.len() {
+			lh.samples[idx] = sample;
+			lh.count += 1;
+		}
+		else {
+			lh.dropped += 1;
+		}
+	}
+}
+
+/// Write the recorded samples to the serial log as folded stacks (one `frame;frame;... count` line per stack)
+///
+/// The samples are consumed, if the profiler is still running it continues recording into the emptied buffers.
+pub fn dump()
+{
+	// Collect unique stacks (and their counts) from all CPUs
+	let mut stacks: Vec<(Sample, usize)> = Vec::new();
+	let mut total = 0;
+	let mut dropped = 0;
+	for buf in S_BUFFERS[.. ::arch::threads::cpu_count()].iter()
+	{
+		let (mut samples, count) = {
+			let _irq = ::sync::hold_interrupts();
+			let mut lh = buf.lock();
+			dropped += lh.dropped;
+			let count = lh.count;
+			// - Take the buffer so the (slow) aggregation isn't done with interrupts held
+			let samples = ::core::mem::replace(&mut lh.samples, Vec::new());
+			lh.count = 0;
+			lh.dropped = 0;
+			(samples, count)
+			};
+		total += count;
+		count_stacks(&mut samples[..count], &mut stacks);
+
+		// Hand the buffer back for reuse (unless a `start` has already replaced it)
+		let _irq = ::sync::hold_interrupts();
+		let mut lh = buf.lock();
+		if lh.samples.len() == 0 {
+			::core::mem::swap(&mut lh.samples, &mut samples);
+		}
+	}
+	let stacks = merge_stacks(stacks);
+	log_notice!("Profiler dump: {} samples ({} dropped), {} unique stacks", total, dropped, stacks.len());
+
+	::arch::puts("--- BEGIN FOLDED STACKS ---\n");
+	for &(ref s, count) in stacks.iter()
+	{
+		::arch::puts(&format!("{} {}\n", FoldedStack(s), count));
+	}
+	::arch::puts("--- END FOLDED STACKS ---\n");
+}
+
+/// Sort `samples` and append each distinct stack (with its count) to `stacks`
+fn count_stacks(samples: &mut [Sample], stacks: &mut Vec<(Sample, usize)>)
+{
+	sort_by(samples, |a, b| a.cmp(b));
+	for s in samples.iter()
+	{
+		if let Some(last) = stacks.last_mut() {
+			if last.0 == *s {
+				last.1 += 1;
+				continue ;
+			}
+		}
+		stacks.push( (*s, 1) );
+	}
+}
+/// Combine the per-CPU stack counts, summing the counts of identical stacks
+fn merge_stacks(mut stacks: Vec<(Sample, usize)>) -> Vec<(Sample, usize)>
+{
+	sort_by(&mut stacks, |a, b| a.0.cmp(&b.0));
+	let mut rv: Vec<(Sample, usize)> = Vec::new();
+	for &(ref s, count) in stacks.iter()
+	{
+		if let Some(last) = rv.last_mut() {
+			if last.0 == *s {
+				last.1 += count;
+				continue ;
+			}
+		}
+		rv.push( (*s, count) );
+	}
+	rv
+}
+
+/// In-place heap sort (doesn't allocate, and is O(n log n) in the worst case)
+fn sort_by<T, F: Fn(&T, &T) -> ::core::cmp::Ordering>(v: &mut [T], cmp: F)
+{
+	let len = v.len();
+	for i in (0 .. len / 2).rev()
+	{
+		sift_down(v, &cmp, i, len);
+	}
+	for end in (1 .. len).rev()
+	{
+		v.swap(0, end);
+		sift_down(v, &cmp, 0, end);
+	}
+}
+/// Move the element at `root` down until the (max-)heap formed by the first `end` elements is valid
+fn sift_down<T, F: Fn(&T, &T) -> ::core::cmp::Ordering>(v: &mut [T], cmp: &F, mut root: usize, end: usize)
+{
+	use core::cmp::Ordering;
+	loop
+	{
+		let mut child = root * 2 + 1;
+		if child >= end {
+			break ;
+		}
+		if child + 1 < end && cmp(&v[child], &v[child+1]) == Ordering::Less {
+			child += 1;
+		}
+		if cmp(&v[root], &v[child]) != Ordering::Less {
+			break ;
+		}
+		v.swap(root, child);
+		root = child;
+	}
+}
+
+/// Formats a sample as a folded stack (outermost frame first)
+struct FoldedStack<'a>(&'a Sample);
+impl<'a> ::core::fmt::Display for FoldedStack<'a>
+{
+	fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result
+	{
+		if self.0.user {
+			return write!(f, "[user]");
+		}
+		let depth = self.0.depth as usize;
+		for i in (0 .. depth).rev()
+		{
+			let addr = self.0.frames[i];
+			// - Return addresses point after the call, so look up the call itself
+			let lookup = if i == 0 { addr } else { addr - 1 };
+			match ::symbols::get_symbol_for_addr(lookup)
+			{
+			Some( (name, _) ) => try!(write!(f, "{}", ::symbols::Demangle(name))),
+			None => try!(write!(f, "{:#x}", addr)),
+			}
+			if i != 0 {
+				try!(write!(f, ";"));
+			}
+		}
+		Ok( () )
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	#[allow(unused_imports)]
+	use prelude::*;
+	use super::{Sample, EMPTY_SAMPLE, FoldedStack};
+	use super::{count_stacks, merge_stacks, sort_by};
+
+	fn kernel_sample(frames: &[usize]) -> Sample {
+		let mut rv = EMPTY_SAMPLE;
+		rv.frames[..frames.len()].copy_from_slice(frames);
+		rv.depth = frames.len() as u8;
+		rv
+	}
+	fn user_sample(addr: usize) -> Sample {
+		let mut rv = kernel_sample(&[addr]);
+		rv.user = true;
+		rv
+	}
+
+	#[test]
+	fn sort()
+	{
+		let mut v = [5, 3, 9, 1, 1, 7, 0, 8, 2];
+		sort_by(&mut v, |a, b| a.cmp(b));
+		assert_eq!(v, [0, 1, 1, 2, 3, 5, 7, 8, 9]);
+
+		let mut e: [u32; 0] = [];
+		sort_by(&mut e, |a, b| a.cmp(b));
+	}
+
+	#[test]
+	fn stack_counts()
+	{
+		let a = kernel_sample(&[0x1000, 0x2000]);
+		let b = kernel_sample(&[0x1000, 0x3000]);
+		let u = user_sample(0x40_0000);
+
+		let mut stacks = Vec::new();
+		count_stacks(&mut [a, b, a, u, a], &mut stacks);
+		count_stacks(&mut [u, b], &mut stacks);
+		let stacks = merge_stacks(stacks);
+
+		assert_eq!(stacks.len(), 3);
+		let count_of = |s: &Sample| stacks.iter().find(|e| e.0 == *s).map(|e| e.1);
+		assert_eq!(count_of(&a), Some(3));
+		assert_eq!(count_of(&b), Some(2));
+		assert_eq!(count_of(&u), Some(2));
+	}
+
+	#[test]
+	fn folded_format()
+	{
+		// Userland samples are collapsed to a single frame
+		assert_eq!(format!("{}", FoldedStack(&user_sample(0x40_1234))), "[user]");
+		// Unresolved frames are printed as addresses, outermost (last recorded) first
+		assert_eq!(format!("{}", FoldedStack(&kernel_sample(&[0x10, 0x20, 0x30]))), "0x30;0x20;0x10");
+	}
+}
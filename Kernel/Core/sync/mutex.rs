@@ -18,8 +18,14 @@ pub struct Mutex<T: Send>
 pub struct MutexInner
 {
 	held: bool,
+	/// Thread holding the mutex (for priority inheritance), NULL if not known
+	owner: *const ::threads::Thread,
+	/// Priority lent to the owner by threads waiting on this mutex
+	lent: Option<::threads::Priority>,
 	queue: ::threads::WaitQueue,
 }
+// The owner pointer is only dereferenced while the mutex is held (and the inner lock is locked)
+unsafe impl Send for MutexInner { }
 
 // Mutexes are inherently sync
 unsafe impl<T: Send> Sync for Mutex<T> { }
@@ -44,6 +50,8 @@ impl<T: Send> Mutex<T>
 		Mutex {
 			inner: ::sync::Spinlock::new(MutexInner {
 				held: false,
+				owner: 0 as *const _,
+				lent: None,
 				queue: ::threads::WaitQueue::new(),
 				}),
 			val: ::core::cell::UnsafeCell::new(val),
@@ -59,23 +67,52 @@ impl<T: Send> Mutex<T>
 			let mut lh = self.inner.lock();
 			if lh.held != false
 			{
+				// Lend this thread's priority to the owner, so it can't be starved while we wait on it
+				if !lh.owner.is_null() {
+					// SAFE: The owner can't release the mutex (and exit) while the inner lock is held
+					if let Some(p) = unsafe { ::threads::lend_priority(lh.owner, lh.lent) } {
+						lh.lent = Some(p);
+					}
+				}
 				// If mutex is locked, then wait for it to be unlocked
 				// - ThreadList::wait will release the passed spinlock
 				waitqueue_wait_ext!(lh, .queue);
 				// lh.queue.wait(lh);	// << Trips borrowck
+				// - Ownership was passed to this thread by `unlock`
+				self.inner.lock().owner = ::threads::cur_thread_ptr();
 			}
 			else
 			{
 				lh.held = true;
+				lh.owner = ::threads::cur_thread_ptr();
 			}
 		}
 		::core::sync::atomic::fence(::core::sync::atomic::Ordering::Acquire);
 		return HeldMutex { lock: self };
 	}
+	/// Lock the mutex only if it's not already held (never blocks)
+	pub fn try_lock(&self) -> Option<HeldMutex<T>> {
+		{
+			let mut lh = self.inner.lock();
+			if lh.held != false {
+				return None;
+			}
+			lh.held = true;
+			lh.owner = ::threads::cur_thread_ptr();
+		}
+		::core::sync::atomic::fence(::core::sync::atomic::Ordering::Acquire);
+		Some( HeldMutex { lock: self } )
+	}
 	/// Release the mutex
 	fn unlock(&self) {
 		::core::sync::atomic::fence(::core::sync::atomic::Ordering::Release);
 		let mut lh = self.inner.lock();
+		lh.owner = 0 as *const _;
+		// Waiters may have lent their priority to this thread, drop it now that they're no longer blocked on it
+		// - Priority lent through other (still held) mutexes is kept
+		if let Some(p) = lh.lent.take() {
+			::threads::return_lent_priority(p);
+		}
 		if lh.queue.has_waiter()
 		{
 			lh.queue.wake_one();
@@ -128,6 +165,15 @@ impl<T: Send> LazyMutex<T>
 		assert!(lh.is_some(), "Locking an uninitialised LazyMutex<{}>", type_name!(T));
 		HeldLazyMutex( lh )
 	}
+	/// Lock the lazy mutex if it's initialised and not already held (never blocks)
+	pub fn try_lock(&self) -> Option<HeldLazyMutex<T>>
+	{
+		match self.0.try_lock()
+		{
+		Some(lh) => if lh.is_some() { Some(HeldLazyMutex(lh)) } else { None },
+		None => None,
+		}
+	}
 }
 
 impl<'lock,T:Send> ops::Drop for HeldMutex<'lock,T>
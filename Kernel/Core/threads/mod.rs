@@ -10,9 +10,15 @@ mod thread_list;
 mod wait_queue;
 
 mod worker_thread;
+pub mod work_queue;
 
 mod sleep_object;
 
+mod resources;
+
+mod priority;
+mod run_queue;
+
 pub use self::thread::{Thread,ThreadPtr};
 pub use self::thread::{ThreadHandle,ProcessHandle};
 pub use self::thread::new_idle_thread;
@@ -22,8 +28,14 @@ pub use self::worker_thread::WorkerThread;
 pub use self::thread_list::{ThreadList,THREADLIST_INIT};
 pub use self::sleep_object::{SleepObject,SleepObjectRef};
 pub use self::wait_queue::WaitQueue;
+pub use self::resources::{Accounting,Resource,LimitExceeded,Usage};
+pub use self::priority::Priority;
+
+use self::priority::PRIORITIES_DESCENDING;
+use self::run_queue::RunQueue;
 
 use lib::mem::aref::{Aref,ArefBorrow};
+use core::sync::atomic::{AtomicUsize,Ordering};
 
 /// A bitset of wait events
 pub type EventMask = u32;
@@ -31,8 +43,10 @@ pub type EventMask = u32;
 // ----------------------------------------------
 // Statics
 //static s_all_threads:	::sync::Mutex<Map<uint,*const Thread>> = mutex_init!(Map{});
-#[allow(non_upper_case_globals)]
-static s_runnable_threads: ::sync::Spinlock<ThreadList> = ::sync::Spinlock::new(THREADLIST_INIT);
+/// Per-CPU run queues (indexed by `::arch::threads::cpu_index`)
+static S_RUN_QUEUES: ::lib::LazyStatic<Vec<::sync::Spinlock<RunQueue>>> = ::lib::LazyStatic::new();
+/// Bitmask of CPUs halted in the idle loop
+static S_IDLE_CPUS: AtomicUsize = AtomicUsize::new(0);
 static S_PID0: ::lib::LazyStatic<::lib::mem::Arc<thread::Process>> = ::lib::LazyStatic::new();
 // Spinlocked due to low contention, and because the current thread is pushed to it
 static S_TO_REAP_THREADS: ::sync::Spinlock<ThreadList> = ::sync::Spinlock::new(THREADLIST_INIT);
@@ -44,7 +58,8 @@ pub fn init()
 {
 	// SAFE: Runs before any form of multi-threading starts
 	unsafe {
-		S_PID0.prep( || thread::Process::new_pid0() )
+		S_PID0.prep( || thread::Process::new_pid0() );
+		S_RUN_QUEUES.prep( || Vec::from_fn(::arch::threads::MAX_CPUS, |_| ::sync::Spinlock::new(RunQueue::new())) );
 	}
 	let mut tid0 = Thread::new_boxed(0, "ThreadZero", S_PID0.clone());
 	tid0.cpu_state = ::arch::threads::init_tid0_state();
@@ -59,7 +74,7 @@ fn reap_threads() -> bool
 		assert!(&*thread as *const Thread != ::arch::threads::borrow_thread() as *const _, "Reaping thread from itself");
 		match thread.into_boxed()
 		{
-		Ok(thread) => drop(thread),
+		Ok(thread) => Thread::reap(thread),
 		Err(thread) => log_warning!("Attempting reap 'static thread {:?}", thread),
 		}
 		rv = true;
@@ -75,7 +90,11 @@ pub fn idle_thread()
 		{
 			// SAFE: I know what I'm doing, and we trust idle() to re-enable them
 			unsafe { ::arch::sync::stop_interrupts(); }
+			// - Flag this CPU as idle before checking for work, so a thread queued after the check sends a wakeup
+			let cpu_mask = 1 << ::arch::threads::cpu_index();
+			S_IDLE_CPUS.fetch_or(cpu_mask, Ordering::SeqCst);
 			if let Some(thread) = get_thread_to_run() {
+				S_IDLE_CPUS.fetch_and(!cpu_mask, Ordering::SeqCst);
 				// SAFE: We turned them off, we turn them back on
 				unsafe { ::arch::sync::start_interrupts(); }
 				log_debug!("Idle task switch to {:?}", thread);
@@ -84,6 +103,7 @@ pub fn idle_thread()
 			else {
 				// NOTE: Idle _must_ re-enable interrupts
 				::arch::threads::idle();
+				S_IDLE_CPUS.fetch_and(!cpu_mask, Ordering::SeqCst);
 			}
 		}
 		else
@@ -100,30 +120,134 @@ pub fn yield_time()
 	reap_threads();
 
 	// Add current thread to active queue, then reschedule
-	s_runnable_threads.lock().push( get_cur_thread() );
+	push_local( get_cur_thread() );
 	reschedule();
 }
 
 pub fn yield_to(thread: ThreadPtr)
 {
 	log_debug!("Yielding CPU to {:?}", thread);
-	s_runnable_threads.lock().push( get_cur_thread() );
+	push_local( get_cur_thread() );
 	::arch::threads::switch_to( thread );
 }
 
 pub fn terminate_thread() -> !
+{
+	exit_thread(0);
+}
+
+/// Count a scheduler tick against the current thread (called when this CPU's time-slice timer fires)
+///
+/// Once the thread has used its time slice, and another thread of the same or higher priority is waiting,
+/// the thread is preempted at the next preemption point (see `preempt_point`).
+pub fn preempt_tick()
+{
+	let p = ::arch::threads::borrow_thread();
+	// SAFE: Checks for NULL, and the thread should be vaild while executing
+	let prio = unsafe {
+		if p.is_null() || !S_RUN_QUEUES.ls_is_valid() {
+			return ;
+		}
+		(*p).priority()
+		};
+	let _irq_lock = ::arch::sync::hold_interrupts();
+	let mut lh = S_RUN_QUEUES[::arch::threads::cpu_index()].lock();
+	account_running(&mut lh);
+	lh.tick(prio);
+	// - The timer is one-shot, keep it going only while the slice can still be cut short
+	let want_timer = lh.needs_slice_timer(prio);
+	lh.slice_timer = want_timer;
+	::arch::threads::set_slice_timer(want_timer);
+}
+/// Check (and clear) this CPU's pending preemption request
+pub fn preempt_pending() -> bool
+{
+	if !S_RUN_QUEUES.ls_is_valid() {
+		return false;
+	}
+	let _irq_lock = ::arch::sync::hold_interrupts();
+	S_RUN_QUEUES[::arch::threads::cpu_index()].lock().take_resched()
+}
+/// Yield the CPU if the current thread has been preempted
+///
+/// Called when returning to userland (from syscalls and interrupts)
+pub fn preempt_point()
+{
+	if preempt_pending() {
+		yield_time();
+	}
+}
+
+/// Get the current thread's priority
+pub fn get_priority() -> Priority {
+	with_cur_thread(|cur| cur.priority())
+}
+/// Set the current thread's priority
+pub fn set_priority(prio: Priority)
+{
+	with_cur_thread(|cur| cur.set_priority(prio));
+	// - Lowering priority may mean another thread should be running
+	yield_time();
+}
+
+/// Lend the current thread's priority to `thread` (priority inheritance, used when blocking on a lock it holds)
+///
+/// `lent` is the priority already lent through this lock (if any). Returns the new priority lent through the lock,
+/// or `None` if it's unchanged (the current thread's priority is no higher).
+///
+/// UNSAFE: `thread` must remain valid for the duration of the call (e.g. the caller holds a lock that prevents
+/// it from releasing the resource)
+pub unsafe fn lend_priority(thread: *const Thread, lent: Option<Priority>) -> Option<Priority>
+{
+	let prio = get_priority();
+	if lent.map_or(false, |p| p >= prio) {
+		return None;
+	}
+	if let Some(old_prio) = (*thread).inherit_priority(prio, lent)
+	{
+		// If the thread is queued, move it to the list for its new priority
+		let _irq_lock = ::arch::sync::hold_interrupts();
+		for q in S_RUN_QUEUES[.. ::arch::threads::cpu_count()].iter()
+		{
+			let mut lh = q.lock();
+			if let Some(t) = lh.remove(thread, old_prio) {
+				lh.push(t);
+				break ;
+			}
+		}
+	}
+	Some(prio)
+}
+/// Drop a priority lent to the current thread with `lend_priority` (called when it releases the lock)
+pub fn return_lent_priority(prio: Priority) {
+	with_cur_thread(|cur| cur.release_inherited_priority(prio))
+}
+/// Returns a pointer to the current thread (NULL before threading is initialised), for use as an owner tag
+pub fn cur_thread_ptr() -> *const Thread {
+	::arch::threads::borrow_thread()
+}
+
+/// Terminate the current thread, passing `status` to anything waiting on it
+pub fn exit_thread(status: u32) -> !
 {
 	// NOTE: If TID0 (aka init's main thread) terminates, panic the kernel
 	if with_cur_thread(|cur| cur.get_tid() == 0) {
 		panic!("TID 0 terminated");
 	}
 
+	with_cur_thread(|cur| cur.mark_exit(status));
+
 	// NOTE: Can this just obtain a handle to the current thread then drop it?
 	// - No... kinda needs to be properly reaped. (so that no outstanding pointers exist)
 	//
 	// Set state to "Dead"
+	// - Charge the final part of the slice now, the thread can't be touched once it's queued for reaping
+	{
+		let _irq_lock = ::arch::sync::hold_interrupts();
+		account_running(&mut S_RUN_QUEUES[::arch::threads::cpu_index()].lock());
+	}
 	let mut this_thread = get_cur_thread();
-	this_thread.set_state( thread::RunState::Dead(0) );
+	this_thread.set_state( thread::RunState::Dead(status) );
 	S_TO_REAP_THREADS.lock().push( this_thread );
 	// Reschedule
 	// - The idle thread will handle reaping?
@@ -132,21 +256,62 @@ pub fn terminate_thread() -> !
 }
 
 pub fn exit_process(status: u32) -> ! {
-	// Requirements:
-	// - Save exit status somewhere
+	// - Save exit status (this also wakes any of this process's threads that are sleeping on it)
 	match with_cur_thread( |cur| cur.get_process_info().mark_exit(status) )
 	{
-	Ok(_) => {},
-	Err(_) => todo!("Two threads raced to exit"),
+	Ok(_) => log_notice!("Terminating process with status={:#x}", status),
+	// Another thread (or a kill request) got there first, just exit this thread
+	Err(_) => log_debug!("Process already exiting"),
 	}
-	log_notice!("Terminating process with status={:#x}", status);
 
-	// - Request all other threads terminate
-	// TODO: How would this be done cleanly? Need to wake all and terminate on syscall boundary?
-	
+	// - Other threads terminate when they next cross a syscall boundary (see `exit_if_process_exiting`)
 	// - Terminate this thread
-	//  > Process reaping is handled by the PCB dropping when refcount reaches zero
-	terminate_thread();
+	//  > The last thread to exit releases process-local data, and the address space is released once it's reaped
+	exit_thread(status);
+}
+
+/// Terminate the current thread if its process is exiting (called on syscall boundaries)
+pub fn exit_if_process_exiting()
+{
+	if let Some(status) = with_cur_thread(|cur| cur.get_process_info().get_exit_status()) {
+		exit_thread(status);
+	}
+}
+
+/// Charge `count` units of a resource to the current process (failing if it would exceed the process's limit)
+pub fn charge_resource(res: Resource, count: usize) -> Result<(), LimitExceeded> {
+	with_cur_thread(|cur| cur.get_process_info().resources().charge(res, count))
+}
+/// Release resources charged with `charge_resource`
+pub fn release_resource(res: Resource, count: usize) {
+	with_cur_thread(|cur| { cur.get_process_info().resources().release(res, count); })
+}
+/// Inspect the resource usage/limits of the current process
+pub fn with_process_resources<R, F: FnOnce(&Accounting)->R>(fcn: F) -> R {
+	with_cur_thread(|cur| fcn(cur.get_process_info().resources()))
+}
+/// Charge the time since this CPU's last accounting point to the running thread's process
+///
+/// Called on each scheduler tick and when switching threads, with interrupts held.
+fn account_running(rq: &mut RunQueue)
+{
+	let elapsed = rq.take_elapsed( ::time::ticks() );
+	let p = ::arch::threads::borrow_thread();
+	// SAFE: Checks for NULL, and the thread is vaild while executing (dead threads are charged before being queued for reaping)
+	unsafe {
+		if !p.is_null() && !is!((*p).run_state, thread::RunState::Dead(_)) {
+			(*p).get_process_info().resources().add_cpu_ticks(elapsed as usize);
+		}
+	}
+}
+
+/// Bind a sleep object to the current process's exit (so blocking calls can be interrupted by process termination)
+pub fn bind_wait_process_exit(obj: &mut SleepObject) {
+	with_cur_thread(|cur| cur.get_process_info().bind_wait_terminate(obj))
+}
+/// Unbind a sleep object bound with `bind_wait_process_exit`, returning true if the process is exiting
+pub fn clear_wait_process_exit(obj: &mut SleepObject) -> bool {
+	with_cur_thread(|cur| cur.get_process_info().clear_wait_terminate(obj))
 }
 
 pub fn get_thread_id() -> thread::ThreadID
@@ -276,20 +441,84 @@ fn rel_cur_thread(t: ThreadPtr)
 //	BorrowedThread( Some(get_cur_thread()) )
 //}
 
+/// Push a thread onto the current CPU's run queue
+fn push_local(thread: ThreadPtr)
+{
+	let _irq_lock = ::arch::sync::hold_interrupts();
+	S_RUN_QUEUES[::arch::threads::cpu_index()].lock().push(thread);
+}
+
+/// Queue a woken thread, and wake an idle CPU to run it
+///
+/// If the thread has a higher priority than the current thread, the current thread is preempted.
+fn make_runnable(thread: ThreadPtr)
+{
+	let _irq_lock = ::arch::sync::hold_interrupts();
+	let cpu = ::arch::threads::cpu_index();
+	let cur = ::arch::threads::borrow_thread();
+	// SAFE: Checks for NULL, and the thread should be vaild while executing
+	let preempt = !cur.is_null() && thread.priority() > unsafe { (*cur).priority() };
+	let prio = thread.priority();
+	let mut lh = S_RUN_QUEUES[cpu].lock();
+	lh.push(thread);
+	if preempt {
+		lh.request_resched();
+	}
+	else if !cur.is_null() && !lh.slice_timer {
+		// SAFE: (as above)
+		let cur_prio = unsafe { (*cur).priority() };
+		// - An equal-priority thread is now waiting, so the current thread's time slice needs to be enforced
+		if prio == cur_prio {
+			lh.slice_timer = true;
+			::arch::threads::set_slice_timer(true);
+		}
+	}
+	drop(lh);
+
+	// Any idle CPU will take the thread from this CPU's queue
+	let idle = S_IDLE_CPUS.load(Ordering::SeqCst) & !(1 << cpu);
+	if idle != 0 {
+		::arch::threads::send_reschedule( idle.trailing_zeros() as usize );
+	}
+}
+
 fn get_thread_to_run() -> Option<ThreadPtr>
 {
 	let _irq_lock = ::arch::sync::hold_interrupts();
-	let mut handle = s_runnable_threads.lock();
-	if handle.empty()
+	let cpu = ::arch::threads::cpu_index();
+	// Charge the outgoing thread, then whichever thread is picked (even the current one) gets a fresh time slice
 	{
-		// WTF? At least an idle thread should be ready
-		None
+		let mut lh = S_RUN_QUEUES[cpu].lock();
+		account_running(&mut lh);
+		lh.start_slice();
 	}
-	else
+	let rv = pick_thread(cpu);
+	// Only arm the time-slice timer if there's a waiting thread that could use the rest of the slice
+	let mut lh = S_RUN_QUEUES[cpu].lock();
+	let want_timer = match rv
+		{
+		Some(ref t) => lh.needs_slice_timer(t.priority()),
+		None => false,
+		};
+	lh.slice_timer = want_timer;
+	::arch::threads::set_slice_timer(want_timer);
+	rv
+}
+/// Take the next thread to run, highest priority first (preferring this CPU's queue then taking threads queued on other CPUs)
+fn pick_thread(cpu: usize) -> Option<ThreadPtr>
+{
+	let count = ::arch::threads::cpu_count();
+	for &prio in PRIORITIES_DESCENDING.iter()
 	{
-		// 2. Pop off a new thread
-		handle.pop()
+		for i in 0 .. count
+		{
+			let rv = S_RUN_QUEUES[(cpu + i) % count].lock().pop(prio);
+			if rv.is_some() {
+				return rv;
+			}
+		}
 	}
+	None
 }
 
 // vim: ft=rust
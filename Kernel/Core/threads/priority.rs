@@ -0,0 +1,106 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/threads/priority.rs
+//! Thread scheduling priorities
+use core::sync::atomic::{AtomicUsize,Ordering};
+
+/// Number of priority classes (and hence run queue lists)
+pub const N_PRIORITIES: usize = 3;
+
+/// Thread priority class
+///
+/// A runnable thread is always chosen over any runnable thread of a lower class, threads within a class share
+/// the CPU in time slices.
+#[derive(Copy,Clone,Debug,PartialEq,Eq,PartialOrd,Ord)]
+pub enum Priority
+{
+	/// Only runs when nothing else is runnable (background work)
+	Idle = 0,
+	/// Default for all threads
+	Normal = 1,
+	/// Latency sensitive (e.g. the GUI compositor), preempts all normal threads
+	Realtime = 2,
+}
+impl Default for Priority { fn default() -> Priority { Priority::Normal } }
+
+/// All priority classes, highest first (order used when selecting a thread)
+pub const PRIORITIES_DESCENDING: [Priority; N_PRIORITIES] = [Priority::Realtime, Priority::Normal, Priority::Idle];
+
+impl Priority
+{
+	fn from_index(v: usize) -> Priority {
+		match v
+		{
+		0 => Priority::Idle,
+		1 => Priority::Normal,
+		_ => Priority::Realtime,
+		}
+	}
+}
+
+/// Per-thread priority, split into the requested (base) priority and the priorities lent by threads blocked on
+/// mutexes this thread holds
+pub struct PriorityState
+{
+	base: AtomicUsize,
+	/// Number of held mutexes lending each priority (so releasing one mutex keeps the boost from the others)
+	lent: [AtomicUsize; N_PRIORITIES],
+}
+
+impl PriorityState
+{
+	pub fn new(base: Priority) -> PriorityState {
+		PriorityState {
+			base: AtomicUsize::new(base as usize),
+			lent: [AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0)],
+		}
+	}
+
+	/// Priority requested for this thread
+	pub fn base(&self) -> Priority {
+		Priority::from_index( self.base.load(Ordering::Relaxed) )
+	}
+	pub fn set_base(&self, prio: Priority) {
+		self.base.store(prio as usize, Ordering::Relaxed);
+	}
+
+	/// Priority used for scheduling (the higher of the base priority and the highest lent priority)
+	pub fn effective(&self) -> Priority {
+		let base = self.base();
+		for &p in PRIORITIES_DESCENDING.iter()
+		{
+			if p <= base {
+				break ;
+			}
+			if self.lent[p as usize].load(Ordering::Relaxed) > 0 {
+				return p;
+			}
+		}
+		base
+	}
+
+	/// Lend a priority to this thread through a mutex, replacing the priority previously lent through it (if any)
+	///
+	/// Returns the previous effective priority if it was raised
+	pub fn inherit(&self, prio: Priority, replaces: Option<Priority>) -> Option<Priority> {
+		let old = self.effective();
+		self.lent[prio as usize].fetch_add(1, Ordering::Relaxed);
+		if let Some(p) = replaces {
+			self.release_inherited(p);
+		}
+		if prio > old {
+			Some(old)
+		}
+		else {
+			None
+		}
+	}
+	/// Drop a priority lent with `inherit` (the mutex it was lent through has been released)
+	pub fn release_inherited(&self, prio: Priority) {
+		let old = self.lent[prio as usize].fetch_sub(1, Ordering::Relaxed);
+		assert!(old > 0, "Releasing {:?} priority that wasn't lent", prio);
+	}
+}
+
+// vim: ft=rust
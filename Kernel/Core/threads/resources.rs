@@ -0,0 +1,184 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/threads/resources.rs
+//! Per-process resource accounting and limits
+use core::sync::atomic::{AtomicUsize,Ordering};
+
+/// Value used internally for "no limit"
+const UNLIMITED: usize = !0;
+
+/// A resource with a per-process limit
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum Resource
+{
+	/// Committed userland memory (in pages)
+	Pages,
+	/// Open userland objects
+	Objects,
+	/// Threads that haven't exited
+	Threads,
+}
+const N_RESOURCES: usize = 3;
+
+/// Error returned when a charge would exceed the process's limit
+#[derive(Debug)]
+pub struct LimitExceeded(pub Resource);
+
+/// Snapshot of a process's resource usage
+#[derive(Copy,Clone,Debug,Default)]
+pub struct Usage
+{
+	pub pages: usize,
+	pub objects: usize,
+	pub threads: usize,
+	/// CPU time (in timer ticks)
+	pub cpu_ticks: usize,
+}
+
+/// Resource counters for a single process
+pub struct Accounting
+{
+	usage: [AtomicUsize; N_RESOURCES],
+	limits: [AtomicUsize; N_RESOURCES],
+	cpu_ticks: AtomicUsize,
+}
+
+impl Accounting
+{
+	/// Construct with all limits set to `UNLIMITED`
+	pub const fn new() -> Accounting {
+		Accounting {
+			usage: [AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0)],
+			limits: [AtomicUsize::new(UNLIMITED), AtomicUsize::new(UNLIMITED), AtomicUsize::new(UNLIMITED)],
+			cpu_ticks: AtomicUsize::new(0),
+		}
+	}
+
+	/// Charge `count` units, failing (and charging nothing) if that would exceed the limit
+	pub fn charge(&self, res: Resource, count: usize) -> Result<(), LimitExceeded> {
+		let limit = self.limits[res as usize].load(Ordering::Relaxed);
+		let ctr = &self.usage[res as usize];
+		let mut cur = ctr.load(Ordering::Relaxed);
+		loop
+		{
+			let new = match cur.checked_add(count)
+				{
+				Some(v) if v <= limit => v,
+				_ => return Err( LimitExceeded(res) ),
+				};
+			let prev = ctr.compare_and_swap(cur, new, Ordering::Acquire);
+			if prev == cur {
+				return Ok( () );
+			}
+			cur = prev;
+		}
+	}
+	/// Charge `count` units without checking the limit (for kernel-initiated allocations)
+	pub fn charge_unchecked(&self, res: Resource, count: usize) {
+		self.usage[res as usize].fetch_add(count, Ordering::Acquire);
+	}
+	/// Release `count` units, returning the remaining usage
+	///
+	/// Everything released must have been charged, releasing more is a (debug-checked) accounting bug.
+	pub fn release(&self, res: Resource, count: usize) -> usize {
+		let ctr = &self.usage[res as usize];
+		let mut cur = ctr.load(Ordering::Relaxed);
+		loop
+		{
+			debug_assert!(cur >= count, "Releasing {} {:?} with only {} charged", count, res, cur);
+			// - Saturate in release builds, so a missed charge doesn't wrap the counter
+			let new = cur.saturating_sub(count);
+			let prev = ctr.compare_and_swap(cur, new, Ordering::Release);
+			if prev == cur {
+				return new;
+			}
+			cur = prev;
+		}
+	}
+
+	/// Set the limit for a resource (`None` to remove it)
+	///
+	/// NOTE: Doesn't affect existing usage, only later charges
+	pub fn set_limit(&self, res: Resource, limit: Option<usize>) {
+		self.limits[res as usize].store(limit.unwrap_or(UNLIMITED), Ordering::Relaxed);
+	}
+	pub fn get_limit(&self, res: Resource) -> Option<usize> {
+		match self.limits[res as usize].load(Ordering::Relaxed)
+		{
+		UNLIMITED => None,
+		v => Some(v),
+		}
+	}
+
+	/// Add CPU time (called from the timer tick)
+	pub fn add_cpu_ticks(&self, ticks: usize) {
+		self.cpu_ticks.fetch_add(ticks, Ordering::Relaxed);
+	}
+
+	pub fn usage(&self) -> Usage {
+		Usage {
+			pages: self.usage[Resource::Pages as usize].load(Ordering::Relaxed),
+			objects: self.usage[Resource::Objects as usize].load(Ordering::Relaxed),
+			threads: self.usage[Resource::Threads as usize].load(Ordering::Relaxed),
+			cpu_ticks: self.cpu_ticks.load(Ordering::Relaxed),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::{Accounting, Resource, LimitExceeded};
+
+	#[test]
+	fn charge_and_release()
+	{
+		let a = Accounting::new();
+		assert_eq!(a.get_limit(Resource::Pages), None);
+		assert!( a.charge(Resource::Pages, 10).is_ok() );
+		a.charge_unchecked(Resource::Pages, 2);
+		assert!( a.charge(Resource::Objects, 1).is_ok() );
+		assert_eq!(a.usage().pages, 12);
+		assert_eq!(a.usage().objects, 1);
+		assert_eq!(a.usage().threads, 0);
+
+		assert_eq!(a.release(Resource::Pages, 5), 7);
+		assert_eq!(a.release(Resource::Pages, 7), 0);
+		assert_eq!(a.usage().pages, 0);
+	}
+
+	#[test]
+	fn limits()
+	{
+		let a = Accounting::new();
+		a.set_limit(Resource::Threads, Some(2));
+		assert_eq!(a.get_limit(Resource::Threads), Some(2));
+		assert!( a.charge(Resource::Threads, 2).is_ok() );
+		// A failed charge doesn't change the usage
+		assert!( is!(a.charge(Resource::Threads, 1), Err(LimitExceeded(Resource::Threads))) );
+		assert_eq!(a.usage().threads, 2);
+		// Unchecked charges can exceed the limit, and then block checked charges
+		a.charge_unchecked(Resource::Threads, 1);
+		assert_eq!(a.usage().threads, 3);
+		assert!( a.charge(Resource::Threads, 0).is_err() );
+		a.release(Resource::Threads, 2);
+		assert!( a.charge(Resource::Threads, 1).is_ok() );
+
+		// Overflowing charges fail instead of wrapping
+		assert!( a.charge(Resource::Pages, !0).is_ok() );
+		assert!( a.charge(Resource::Pages, 1).is_err() );
+
+		a.set_limit(Resource::Threads, None);
+		assert_eq!(a.get_limit(Resource::Threads), None);
+	}
+
+	#[test]
+	fn cpu_ticks()
+	{
+		let a = Accounting::new();
+		a.add_cpu_ticks(3);
+		a.add_cpu_ticks(4);
+		assert_eq!(a.usage().cpu_ticks, 7);
+	}
+}
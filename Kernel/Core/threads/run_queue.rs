@@ -0,0 +1,88 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/threads/run_queue.rs
+//! Per-CPU queue of runnable threads (one list per priority class)
+use super::{Thread,ThreadPtr,ThreadList,THREADLIST_INIT};
+use super::priority::{Priority,N_PRIORITIES};
+
+/// Number of scheduler ticks a thread runs for before it's preempted by a thread of equal priority
+const TIME_SLICE_TICKS: u32 = 2;
+
+pub struct RunQueue
+{
+	lists: [ThreadList; N_PRIORITIES],
+	/// Scheduler ticks the current thread has been running for
+	slice_ticks: u32,
+	/// Set when the current thread should give up the CPU at the next preemption point
+	need_resched: bool,
+	/// The time-slice timer is armed (see `::arch::threads::set_slice_timer`)
+	pub slice_timer: bool,
+	/// Timestamp up to which this CPU's time has been charged to a thread
+	last_account: u64,
+}
+
+impl RunQueue
+{
+	pub const fn new() -> RunQueue {
+		RunQueue {
+			lists: [THREADLIST_INIT, THREADLIST_INIT, THREADLIST_INIT],
+			slice_ticks: 0,
+			need_resched: false,
+			slice_timer: false,
+			last_account: 0,
+		}
+	}
+
+	/// Queue a thread (at the back of the list for its current priority)
+	pub fn push(&mut self, thread: ThreadPtr) {
+		let prio = thread.priority();
+		self.lists[prio as usize].push(thread);
+	}
+	/// Take the next thread from the given priority class
+	pub fn pop(&mut self, prio: Priority) -> Option<ThreadPtr> {
+		self.lists[prio as usize].pop()
+	}
+	/// Remove a specific thread (queued at `prio`)
+	pub fn remove(&mut self, thread: *const Thread, prio: Priority) -> Option<ThreadPtr> {
+		self.lists[prio as usize].remove(thread)
+	}
+	/// Returns true if a thread of at least the given priority is queued
+	pub fn has_runnable(&self, min: Priority) -> bool {
+		self.lists[min as usize ..].iter().any(|l| !l.empty())
+	}
+
+	/// Start a new time slice (called when a thread is selected to run)
+	pub fn start_slice(&mut self) {
+		self.slice_ticks = 0;
+		self.need_resched = false;
+	}
+	/// Account a scheduler tick to the running thread, requesting preemption if another thread should run
+	pub fn tick(&mut self, cur_prio: Priority) {
+		self.slice_ticks += 1;
+		if self.slice_ticks >= TIME_SLICE_TICKS && self.has_runnable(cur_prio) {
+			self.need_resched = true;
+		}
+	}
+	/// Returns true if the time-slice timer is needed (another thread is waiting to take over from the current one)
+	pub fn needs_slice_timer(&self, cur_prio: Priority) -> bool {
+		!self.need_resched && self.has_runnable(cur_prio)
+	}
+	/// Request that the running thread is preempted
+	pub fn request_resched(&mut self) {
+		self.need_resched = true;
+	}
+	/// Check (and clear) the pending preemption flag
+	pub fn take_resched(&mut self) -> bool {
+		::core::mem::replace(&mut self.need_resched, false)
+	}
+
+	/// Returns the time since the previous call (the time to charge to the running thread)
+	pub fn take_elapsed(&mut self, now: u64) -> u64 {
+		let prev = ::core::mem::replace(&mut self.last_account, now);
+		// - The first call has nothing to compare against
+		if prev == 0 { 0 } else { now - prev }
+	}
+}
+
+// vim: ft=rust
@@ -5,7 +5,7 @@
 //! Sleep object
 use core::ops;
 use super::thread::{ThreadPtr, RunState};
-use super::s_runnable_threads;
+use super::make_runnable;
 
 /// An object on which a thread can sleep, woken by various event sources
 ///
@@ -88,6 +88,22 @@ impl<'a> SleepObject<'a>
 		}
 	}
 	
+	/// Wait the current thread on this object, with a deadline (in timer ticks)
+	///
+	/// Returns false if the deadline passed before the object was signalled.
+	/// NOTE: As with `wait`, callers should re-check their wake condition either way.
+	pub fn wait_until(&self, deadline: ::time::TickCount) -> bool
+	{
+		let timer = ::time::bind_signal(self, deadline);
+		self.wait();
+		!timer.has_fired()
+	}
+	/// Wait the current thread on this object for at most `timeout_ms` milliseconds
+	pub fn wait_timeout(&self, timeout_ms: u64) -> bool
+	{
+		self.wait_until( ::time::ticks() + timeout_ms )
+	}
+	
 	/// Signal this sleep object (waking threads)
 	#[is_safe(irq)]	// Holds interrupts before locking
 	pub fn signal(&self)
@@ -100,7 +116,7 @@ impl<'a> SleepObject<'a>
 		if let Some(mut t) = lh.thread.take()
 		{
 			t.set_state( RunState::Runnable );
-			s_runnable_threads.lock().push(t);
+			make_runnable(t);
 		}
 		else
 		{
@@ -13,6 +13,8 @@
  */
 use prelude::*;
 use lib::mem::Arc;
+use super::resources::{Accounting,Resource,LimitExceeded};
+use super::priority::{Priority,PriorityState};
 
 /// Thread identifier (unique)
 pub type ThreadID = u32;
@@ -39,9 +41,14 @@ pub struct Process
 {
 	name: String,
 	pid: ProcessID,
-	address_space: ::memory::virt::AddressSpace,
-	// TODO: use of a tuple here looks a little crufty
-	exit_status: ::sync::Mutex< (Option<u32>, Option<::threads::sleep_object::SleepObjectRef>) >,
+	/// Address space, released once the last thread has been reaped
+	address_space: ::sync::Mutex< Option<::memory::virt::AddressSpace> >,
+	/// Exit status (when set, all threads exit at the next syscall boundary)
+	exit_status: ::sync::Mutex<ExitStatus>,
+	/// Resource usage and limits (the thread count is the number of threads that haven't yet exited)
+	resources: Accounting,
+	/// Number of threads that have yet to be reaped (the address space is released when this reaches zero)
+	unreaped_threads: ::core::sync::atomic::AtomicUsize,
 	pub proc_local_data: ::sync::RwLock<Vec< ::lib::mem::aref::Aref<::core::any::Any+Sync+Send> >>,
 }
 /// Handle to a process, used for spawning and communicating
@@ -57,6 +64,17 @@ struct SharedBlock
 	name: String,
 	tid: ThreadID,
 	process: Arc<Process>,
+	exit_status: ::sync::Mutex<ExitStatus>,
+	/// Scheduling priority (read by the scheduler, so not behind a lock)
+	priority: PriorityState,
+}
+
+/// Thread/process exit status, and the objects waiting for it to be set
+#[derive(Default)]
+struct ExitStatus
+{
+	status: Option<u32>,
+	waiters: Vec<::threads::sleep_object::SleepObjectRef>,
 }
 
 /// An owning thread handle
@@ -127,7 +145,9 @@ impl Process
 			name: String::from("PID0"),
 			pid: 0,
 			exit_status: Default::default(),
-			address_space: ::memory::virt::AddressSpace::pid0(),
+			resources: Accounting::new(),
+			unreaped_threads: ::core::sync::atomic::AtomicUsize::new(0),
+			address_space: ::sync::Mutex::new( Some(::memory::virt::AddressSpace::pid0()) ),
 			proc_local_data: ::sync::RwLock::new( Vec::new() ),
 		})
 	}
@@ -137,38 +157,105 @@ impl Process
 			pid: allocate_pid(),
 			name: name.into(),
 			exit_status: Default::default(),
-			address_space: addr_space,
+			resources: Accounting::new(),
+			unreaped_threads: ::core::sync::atomic::AtomicUsize::new(0),
+			address_space: ::sync::Mutex::new( Some(addr_space) ),
 			proc_local_data: ::sync::RwLock::new( Vec::new() ),
 		})
 	}
 	
 	fn empty_cpu_state(&self) -> ::arch::threads::State {
-		::arch::threads::State::new( &self.address_space )
+		match *self.address_space.lock()
+		{
+		Some(ref a) => ::arch::threads::State::new(a),
+		None => panic!("Creating a thread in {} after its address space was released", self),
+		}
 	}
 
 	pub fn get_pid(&self) -> ProcessID { self.pid }
+	pub fn resources(&self) -> &Accounting { &self.resources }
 
+	/// Set the exit status, returns Err if the process is already exiting
+	///
+	/// Wakes all waiters (including this process's own sleeping threads, so they can exit)
 	pub fn mark_exit(&self, status: u32) -> Result<(),()> {
-		let mut lh = self.exit_status.lock();
-		if lh.0.is_some() {
+		self.exit_status.lock().set(status)
+	}
+	pub fn get_exit_status(&self) -> Option<u32> {
+		self.exit_status.lock().status
+	}
+
+	pub fn bind_wait_terminate(&self, obj: &mut ::threads::SleepObject) {
+		self.exit_status.lock().bind(obj)
+	}
+	pub fn clear_wait_terminate(&self, obj: &mut ::threads::SleepObject) -> bool {
+		self.exit_status.lock().clear(obj)
+	}
+
+	/// Called when a thread in this process exits, returns true if it was the last thread
+	fn thread_exited(&self) -> bool {
+		self.resources.release(Resource::Threads, 1) == 0
+	}
+	/// Release per-process state (e.g. userland objects), called by the last thread to exit
+	fn release_local_data(&self) {
+		// NOTE: Dropped outside the lock, as destructors may access process-local data
+		let data = ::core::mem::replace(&mut *self.proc_local_data.write(), Vec::new());
+		log_debug!("{} - Releasing {} process-local items", self, data.len());
+		drop(data);
+	}
+	/// Release the address space, called once the final thread has been reaped
+	fn release_address_space(&self) {
+		let asp = self.address_space.lock().take();
+		log_debug!("{} - Releasing address space {:?}", self, asp);
+		drop(asp);
+	}
+}
+
+impl ExitStatus
+{
+	fn set(&mut self, status: u32) -> Result<(),()> {
+		if self.status.is_some() {
 			Err( () )
 		}
 		else {
-			
-			if let Some(ref sleep_ref) = lh.1 {
-				sleep_ref.signal();
+			self.status = Some(status);
+			for r in self.waiters.iter() {
+				r.signal();
 			}
-
-			lh.0 = Some(status);
 			Ok( () )
 		}
 	}
+	fn bind(&mut self, obj: &mut ::threads::SleepObject) {
+		if self.status.is_some() {
+			obj.signal();
+		}
+		else {
+			self.waiters.push( obj.get_ref() );
+		}
+	}
+	fn clear(&mut self, obj: &mut ::threads::SleepObject) -> bool {
+		if let Some(i) = self.waiters.iter().position(|r| r.is_from(obj)) {
+			self.waiters.remove(i);
+		}
+		self.status.is_some()
+	}
 }
 
 impl ProcessHandle
 {
 	pub fn new<S: Into<String>+::core::fmt::Debug>(name: S, clone_start: usize, clone_end: usize) -> ProcessHandle {
-		ProcessHandle( Process::new(name, ::memory::virt::AddressSpace::new(clone_start, clone_end).expect("ProcessHandle::new - OOM")) )
+		// - Pages in the cloned range become part of the new process, so are charged to it
+		let mut cloned_pages = 0;
+		let mut addr = clone_start & !(::PAGE_SIZE - 1);
+		while addr < clone_end {
+			if ::memory::virt::is_reserved(addr as *const ()) {
+				cloned_pages += 1;
+			}
+			addr += ::PAGE_SIZE;
+		}
+		let rv = Process::new(name, ::memory::virt::AddressSpace::new(clone_start, clone_end).expect("ProcessHandle::new - OOM"));
+		rv.resources.charge_unchecked(Resource::Pages, cloned_pages);
+		ProcessHandle( rv )
 	}
 	
 	pub fn start_root_thread(&mut self, ip: usize, sp: usize) {
@@ -235,34 +322,31 @@ impl ProcessHandle
 
 	pub fn bind_wait_terminate(&self, obj: &mut ::threads::SleepObject) {
 		log_trace!("bind_wait_terminate({:p}, obj={:p})", self, obj);
-		let mut lh = self.0.exit_status.lock();
-		if let Some(_status) = lh.0 {
-			obj.signal();
-		}
-		else if let Some(_) = lh.1 {
-			todo!("Multiple threads sleeping on this process");
-		}
-		else {
-			lh.1 = Some( obj.get_ref() );
-		}
+		self.0.bind_wait_terminate(obj)
 	}
 	pub fn clear_wait_terminate(&self, obj: &mut ::threads::SleepObject) -> bool {
 		log_trace!("clear_wait_terminate({:p}, obj={:p})", self, obj);
-		let mut lh = self.0.exit_status.lock();
-
-		if let Some(ref v) = lh.1 {
-			assert!(v.is_from(obj), "clear_wait_terminate from different object");
-		}
-		else {
-			log_trace!("- Wasn't registered");
-		}
-		lh.1 = None;
-		
-		lh.0.is_some()
+		self.0.clear_wait_terminate(obj)
 	}
 
 	pub fn get_exit_status(&self) -> Option<u32> {
-		self.0.exit_status.lock().0
+		self.0.get_exit_status()
+	}
+
+	/// Resource usage and limits of the process
+	pub fn resources(&self) -> &Accounting {
+		&self.0.resources
+	}
+
+	/// Request that the process terminate (with the passed exit status)
+	///
+	/// All threads exit when they next cross a syscall boundary, sleeping threads are woken to do so.
+	pub fn kill(&self, status: u32) {
+		match self.0.mark_exit(status)
+		{
+		Ok(_) => log_notice!("Killing {:?} with status={:#x}", self, status),
+		Err(_) => log_debug!("{:?} already exiting", self),
+		}
 	}
 }
 impl ::core::ops::Drop for ProcessHandle {
@@ -286,6 +370,63 @@ impl ThreadHandle
 		
 		handle
 	}
+
+	/// Start a new userland thread in the current process
+	///
+	/// `arg` is passed to the entrypoint as its first argument, `tls_base` is loaded into the architecture's user TLS register.
+	/// Fails if the process's thread limit has been reached.
+	pub fn new_user(ip: usize, sp: usize, tls_base: usize, arg: usize) -> Result<ThreadHandle, LimitExceeded>
+	{
+		let process = super::with_cur_thread(|cur| cur.block.process.clone());
+		try!( process.resources.charge(Resource::Threads, 1) );
+		let tid = allocate_tid();
+		let mut thread = Thread::new_boxed_charged(tid, format!("{}#{}", process.name, tid), process);
+		log_trace!("new_user(ip={:#x}, sp={:#x}, tls_base={:#x}, arg={:#x}) - {:?}", ip, sp, tls_base, arg, thread);
+		let handle = ThreadHandle {
+			block: thread.block.clone(),
+			};
+		thread.cpu_state.set_user_tls(tls_base);
+		::arch::threads::start_thread( &mut thread,
+			// SAFE: Addresses are only used in userland, so any fault is the user's problem
+			move || unsafe { ::arch::drop_to_user(ip, sp, arg) }
+			);
+		super::yield_to(thread);
+		Ok( handle )
+	}
+
+	pub fn get_tid(&self) -> ThreadID { self.block.tid }
+
+	pub fn bind_wait_terminate(&self, obj: &mut ::threads::SleepObject) {
+		self.block.exit_status.lock().bind(obj)
+	}
+	pub fn clear_wait_terminate(&self, obj: &mut ::threads::SleepObject) -> bool {
+		self.block.exit_status.lock().clear(obj)
+	}
+
+	pub fn get_exit_status(&self) -> Option<u32> {
+		self.block.exit_status.lock().status
+	}
+	/// Block until the thread terminates, returning its exit status
+	pub fn wait(&self) -> u32 {
+		assert!( super::with_cur_thread(|cur| cur.get_tid()) != self.block.tid, "Thread {} waiting on itself", self.block );
+		let mut obj = ::threads::SleepObject::new("ThreadHandle::wait");
+		self.bind_wait_terminate(&mut obj);
+		while self.get_exit_status().is_none() {
+			obj.wait();
+		}
+		self.clear_wait_terminate(&mut obj);
+		self.get_exit_status().unwrap()
+	}
+
+	pub fn get_priority(&self) -> Priority {
+		self.block.priority.base()
+	}
+	/// Change the thread's priority
+	///
+	/// NOTE: If the thread is currently queued, the change takes effect the next time it's scheduled
+	pub fn set_priority(&self, prio: Priority) {
+		self.block.priority.set_base(prio)
+	}
 }
 impl ::core::fmt::Debug for ThreadHandle
 {
@@ -297,7 +438,10 @@ impl ::core::fmt::Debug for ThreadHandle
 impl ::core::ops::Drop for ThreadHandle
 {
 	fn drop(&mut self) {
-		panic!("TODO: Wait for thread to terminate when handle is dropped");
+		// NOTE: The thread holds its own reference to the shared block, so dropping the handle just detaches it
+		if self.block.exit_status.lock().status.is_none() {
+			log_debug!("Detaching running thread {}", self.block);
+		}
 	}
 }
 
@@ -371,9 +515,21 @@ impl Thread
 	/// Create a new thread
 	pub fn new_boxed<S: Into<String>>(tid: ThreadID, name: S, process: Arc<Process>) -> ThreadPtr
 	{
+		// NOTE: Not limit-checked, as kernel threads must always succeed (see `ThreadHandle::new_user`)
+		process.resources.charge_unchecked(Resource::Threads, 1);
+		Thread::new_boxed_charged(tid, name, process)
+	}
+	/// Create a new thread, with the process already charged for it
+	fn new_boxed_charged<S: Into<String>>(tid: ThreadID, name: S, process: Arc<Process>) -> ThreadPtr
+	{
+		process.unreaped_threads.fetch_add(1, ::core::sync::atomic::Ordering::SeqCst);
 		let rv = box Thread {
 			cpu_state: process.empty_cpu_state(),
-			block: Arc::new( SharedBlock { tid: tid, name: name.into(), process: process } ),
+			block: Arc::new( SharedBlock {
+				tid: tid, name: name.into(), process: process,
+				exit_status: Default::default(),
+				priority: PriorityState::new(Priority::Normal),
+				} ),
 			run_state: RunState::Runnable,
 			next: None,
 			};
@@ -384,6 +540,19 @@ impl Thread
 		ThreadPtr::new( rv )
 	}
 	
+	/// Destroy an exited thread (called by the reaper)
+	///
+	/// Releases the process's address space if this was its last thread. This is done after the thread is dropped, as
+	/// dropping the CPU state waits for the exiting CPU to switch away (and off the address space).
+	pub fn reap(thread: Box<Thread>)
+	{
+		let process = thread.block.process.clone();
+		drop(thread);
+		if process.unreaped_threads.fetch_sub(1, ::core::sync::atomic::Ordering::SeqCst) == 1 {
+			process.release_address_space();
+		}
+	}
+
 	pub fn get_tid(&self) -> ThreadID { self.block.tid }
 	
 	/// Set the execution state of this thread
@@ -403,10 +572,42 @@ impl Thread
 	pub fn get_process_info(&self) -> &Process {
 		&*self.block.process
 	}
+
+	/// Effective scheduling priority (including any priority inherited through mutexes)
+	pub fn priority(&self) -> Priority {
+		self.block.priority.effective()
+	}
+	pub fn set_priority(&self, prio: Priority) {
+		self.block.priority.set_base(prio)
+	}
+	/// Lend a priority to this thread through a mutex, replacing any previously lent through it (returns the previous priority if it was raised)
+	pub fn inherit_priority(&self, prio: Priority, replaces: Option<Priority>) -> Option<Priority> {
+		self.block.priority.inherit(prio, replaces)
+	}
+	/// Drop a priority lent with `inherit_priority`
+	pub fn release_inherited_priority(&self, prio: Priority) {
+		self.block.priority.release_inherited(prio)
+	}
+
+	/// Record the thread's exit status and wake anything waiting on it
+	///
+	/// If this is the last thread in the process, the process is marked as exited (if it wasn't already)
+	/// and its process-local data (e.g. userland objects) is released.
+	pub fn mark_exit(&self, status: u32) {
+		if let Err(_) = self.block.exit_status.lock().set(status) {
+			panic!("Thread {:?} exited twice", self);
+		}
+		let process = &self.block.process;
+		if process.thread_exited() {
+			let _ = process.mark_exit(status);
+			process.release_local_data();
+		}
+	}
 }
 
 pub fn new_idle_thread(cpu: usize) -> ThreadPtr {
 	let mut thread = Thread::new_boxed(allocate_tid(), format!("Idle#{}", cpu), super::S_PID0.clone());
+	thread.set_priority(Priority::Idle);
 	::arch::threads::start_thread(&mut thread, super::idle_thread);
 	thread
 }
@@ -439,6 +640,7 @@ impl ::core::ops::Drop for Thread
 	{
 		// TODO: Remove self from the global thread map
 		log_debug!("Destroying thread {:?} - {} handles to block, {} to process", self, Arc::strong_count(&self.block), Arc::strong_count(&self.block.process));
+		// NOTE: The address space is released by `Thread::reap`, once the CPU state has been torn down
 	}
 }
 
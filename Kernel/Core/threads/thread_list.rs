@@ -65,5 +65,34 @@ impl ThreadList
 		// SAFE: ptr is non-zero
 		self.last = Some(unsafe { NonNull::new_unchecked(ptr) });
 	}
+	/// Remove a specific thread from the list (returns None if it's not on this list)
+	pub fn remove(&mut self, thread: *const Thread) -> Option<ThreadPtr>
+	{
+		let mut prev: Option< NonNull<Thread> > = None;
+		let mut link: *mut Option<ThreadPtr> = &mut self.first;
+		// SAFE: List is locked, and the pointers only reference entries (and links) within it
+		unsafe {
+			loop
+			{
+				match *link
+				{
+				None => return None,
+				Some(ref mut t) => {
+					if &**t as *const Thread == thread {
+						break ;
+					}
+					prev = Some( NonNull::new_unchecked(&mut **t as *mut Thread) );
+					link = &mut t.next;
+					},
+				}
+			}
+			let mut rv = (*link).take().unwrap();
+			*link = rv.next.take();
+			if (*link).is_none() {
+				self.last = prev;
+			}
+			Some(rv)
+		}
+	}
 }
 
@@ -0,0 +1,48 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/threads/work_queue.rs
+//! Shared pool of kernel threads for deferred work
+//!
+//! Used for short jobs (IRQ bottom halves, cache flushes, USB hub events) that would otherwise each need a permanent thread.
+#[allow(unused_imports)]
+use prelude::*;
+use lib::LazyStatic;
+use sync::Queue;
+
+/// Number of threads servicing the queue
+const NUM_WORKERS: usize = 2;
+
+static S_QUEUE: Queue< Box<FnMut() + Send> > = Queue::new_const();
+static S_WORKERS: LazyStatic< Vec<super::WorkerThread> > = lazystatic_init!();
+
+/// Start the worker threads
+pub fn init()
+{
+	// SAFE: Called in a single-threaded context
+	unsafe {
+		S_WORKERS.prep(|| (0 .. NUM_WORKERS).map(|i| super::WorkerThread::new(&format!("Work Queue #{}", i), worker)).collect());
+	}
+}
+
+/// Queue a closure to be run on one of the shared worker threads
+///
+/// Items can run concurrently with each other, so must do their own locking. Long-running (or forever blocking) jobs
+/// should use a dedicated `WorkerThread` instead, as they starve the rest of the queue.
+///
+/// NOTE: This can sleep, so cannot be called from a raw interrupt handler (but can from `irqs::bind_object` handlers)
+pub fn queue<F: FnOnce() + Send + 'static>(fcn: F)
+{
+	// Box<FnOnce> can't be called, so wrap in an Option and take on the first (only) call
+	let mut fcn = Some(fcn);
+	S_QUEUE.push( Box::new(move || (fcn.take().expect("Work item run twice"))()) );
+}
+
+fn worker()
+{
+	loop
+	{
+		let mut item = S_QUEUE.wait_pop();
+		item();
+	}
+}
@@ -5,25 +5,37 @@
 //! Management of kernel worker threads (short or long)
 #[allow(unused_imports)]
 use prelude::*;
+use sync::Spinlock;
 
+/// Handle to a kernel worker thread, the thread is detached if this is dropped
+pub struct WorkerThread<R: Send + 'static = ()>
+{
+	handle: super::thread::ThreadHandle,
+	/// Return value of the closure, populated just before the thread exits
+	result: Arc<Spinlock<Option<R>>>,
+}
 
-pub struct WorkerThread(super::thread::ThreadHandle);
-
-impl WorkerThread
+impl<R: Send + 'static> WorkerThread<R>
 {
 	#[allow(dead_code)]
 	/// Construct a new worker thread
-	pub fn new<F: FnOnce()+Send+'static>(name: &str, fcn: F) -> WorkerThread
+	pub fn new<F: FnOnce()->R + Send + 'static>(name: &str, fcn: F) -> WorkerThread<R>
 	{
-		let handle = super::thread::ThreadHandle::new(name, fcn, super::S_PID0.clone());
-		WorkerThread(handle)
+		let result = Arc::new( Spinlock::new(None) );
+		let result_w = result.clone();
+		let handle = super::thread::ThreadHandle::new(name, move || { let v = fcn(); *result_w.lock() = Some(v); }, super::S_PID0.clone());
+		WorkerThread {
+			handle: handle,
+			result: result,
+			}
 	}
 
-	// TODO: Allow the worker to return a value?
-	pub fn wait(&self) -> Result<(),()>
+	/// Block until the worker terminates, and obtain its return value
+	///
+	/// Returns `Err` if the value has already been taken by a previous call
+	pub fn wait(&self) -> Result<R,()>
 	{
-		todo!("Wait for worker thread to terminate");
+		self.handle.wait();
+		self.result.lock().take().ok_or( () )
 	}
 }
-
-
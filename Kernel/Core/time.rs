@@ -3,9 +3,13 @@
 //
 // Core/time.rs
 //! Kernel timing and timers
+#[allow(unused_imports)]
+use prelude::*;
 
 /// Timer ticks (ms)
 pub type TickCount = u64;
+/// Wall-clock time (milliseconds since 1970-01-01 00:00 UTC)
+pub type Timestamp = i64;
 
 /// Obtain the number of timer ticks since an arbitary point (system startup)
 pub fn ticks() -> u64
@@ -13,6 +17,367 @@ pub fn ticks() -> u64
 	::arch::cur_timestamp()
 }
 
+/// Handle to a pending timer wakeup, the timer is cancelled when this is dropped
+pub struct Timer(Box<TimerEnt>);
+struct TimerEnt
+{
+	expiry: TickCount,
+	next: *mut TimerEnt,
+	/// Set (with the list locked) once the sleeper has been signalled
+	fired: bool,
+	sleeper: ::threads::SleepObjectRef,
+}
+/// Pending timers, sorted by expiry time
+struct TimerList
+{
+	head: *mut TimerEnt,
+}
+unsafe impl Send for TimerList {}
+impl TimerList
+{
+	/// Insert an entry (after any with the same expiry), returning true if it's now the earliest
+	///
+	/// UNSAFE: `ent` must stay valid until it's removed (by `remove` or `pop_expired`)
+	unsafe fn insert(&mut self, ent: *mut TimerEnt) -> bool {
+		let mut pp: *mut *mut TimerEnt = &mut self.head;
+		while !(*pp).is_null() && (**pp).expiry <= (*ent).expiry {
+			pp = &mut (**pp).next;
+		}
+		(*ent).next = *pp;
+		*pp = ent;
+		self.head == ent
+	}
+	/// Remove an entry, if it's still in the list
+	fn remove(&mut self, ent: *mut TimerEnt) {
+		// SAFE: All list entries are valid (see `insert`)
+		unsafe {
+			let mut pp: *mut *mut TimerEnt = &mut self.head;
+			while !(*pp).is_null() {
+				if *pp == ent {
+					*pp = (*ent).next;
+					(*ent).next = 0 as *mut _;
+					break ;
+				}
+				pp = &mut (**pp).next;
+			}
+		}
+	}
+	/// Expiry of the earliest entry
+	fn first_expiry(&self) -> Option<TickCount> {
+		// SAFE: All list entries are valid (see `insert`)
+		unsafe { self.head.as_ref().map(|e| e.expiry) }
+	}
+	/// Remove and return the earliest entry, if it has expired by `now`
+	fn pop_expired(&mut self, now: TickCount) -> Option<*mut TimerEnt> {
+		match self.first_expiry()
+		{
+		Some(e) if e <= now => {
+			let ent = self.head;
+			// SAFE: All list entries are valid (see `insert`)
+			unsafe {
+				self.head = (*ent).next;
+				(*ent).next = 0 as *mut _;
+			}
+			Some(ent)
+			},
+		_ => None,
+		}
+	}
+}
+// SAFE: The entry is only accessed with the list locked (or once removed from it)
+unsafe impl Send for Timer {}
+
+static S_TIMERS: ::sync::Spinlock<TimerList> = ::sync::Spinlock::new(TimerList { head: 0 as *mut _ });
+
+/// Signal `sleeper` once the tick count reaches `expiry`
+pub fn bind_signal(sleeper: &::threads::SleepObject, expiry: TickCount) -> Timer
+{
+	let mut ent = Box::new(TimerEnt {
+		expiry: expiry,
+		next: 0 as *mut _,
+		fired: false,
+		sleeper: sleeper.get_ref(),
+		});
+	if expiry <= ticks() {
+		// Already expired, don't bother adding to the list
+		ent.fired = true;
+		ent.sleeper.signal();
+	}
+	else {
+		let _irq = ::sync::hold_interrupts();
+		let is_first = {
+			let mut lh = S_TIMERS.lock();
+			// SAFE: All list entries are owned by a `Timer`, which removes the entry before freeing it
+			unsafe { lh.insert(&mut *ent) }
+			};
+		// - New earliest deadline, make sure the timer hardware fires in time for it
+		// NOTE: Called without the list locked, as the architecture calls `next_deadline` when reprogramming
+		if is_first {
+			::arch::request_tick(expiry);
+		}
+	}
+	Timer(ent)
+}
+impl Timer
+{
+	/// Tick count at which the timer fires
+	pub fn expiry(&self) -> TickCount {
+		self.0.expiry
+	}
+	/// Returns true if the timer has fired (signalling its sleep object)
+	pub fn has_fired(&self) -> bool {
+		let _irq = ::sync::hold_interrupts();
+		let _lh = S_TIMERS.lock();
+		self.0.fired
+	}
+}
+impl ::core::ops::Drop for Timer
+{
+	fn drop(&mut self)
+	{
+		let _irq = ::sync::hold_interrupts();
+		S_TIMERS.lock().remove(&mut *self.0);
+	}
+}
+
+/// Tick count of the earliest pending timer (if any)
+///
+/// Used by the architecture to program the next timer interrupt, ticks between deadlines can be skipped.
+pub fn next_deadline() -> Option<TickCount>
+{
+	let _irq = ::sync::hold_interrupts();
+	S_TIMERS.lock().first_expiry()
+}
+
+#[doc(hidden)]
+/// Called by the architecture's timer interrupt, fires any expired timers
+pub fn time_tick()
+{
+	let now = ticks();
+	let mut lh = S_TIMERS.lock();
+	while let Some(ent) = lh.pop_expired(now)
+	{
+		// SAFE: All list entries are owned by a `Timer`, which removes the entry (with the list locked) before freeing it
+		unsafe {
+			(*ent).fired = true;
+			(*ent).sleeper.signal();
+		}
+	}
+}
+
+/// Hardware real-time clock
+pub trait RtcDevice: Send + Sync
+{
+	fn name(&self) -> &str;
+	/// Read the current time (`None` if the clock isn't running or reports an invalid date)
+	fn read(&self) -> Option<Timestamp>;
+	/// Set the clock (the sub-second part is discarded by most hardware)
+	fn write(&self, time: Timestamp) -> bool;
+}
+
+/// Wall-clock state, the wall time is extrapolated from the monotonic tick count between RTC reads
+struct WallClock
+{
+	/// Wall-clock time at `base_tick`
+	base_time: Timestamp,
+	base_tick: TickCount,
+	/// Correction applied to elapsed ticks (parts per million, positive if the tick runs slow)
+	drift_ppm: i64,
+	/// Tick count when the clock was last compared against the RTC
+	last_sync: TickCount,
+	/// Tick count at the start of the current drift measurement (spans resyncs until the error is measurable)
+	drift_start: TickCount,
+	/// RTC error at `drift_start` (ms), only the change from this is counted as drift
+	drift_offset: i64,
+}
+static S_WALLCLOCK: ::sync::Spinlock<WallClock> = ::sync::Spinlock::new(WallClock { base_time: 0, base_tick: 0, drift_ppm: 0, last_sync: 0, drift_start: 0, drift_offset: 0 });
+static S_RTC: ::sync::Mutex<Option<Box<RtcDevice>>> = ::sync::Mutex::new(None);
+
+/// Interval between RTC re-reads (ms)
+const RTC_RESYNC_INTERVAL: TickCount = 10 * 60 * 1000;
+/// Resolution of the RTC (ms), errors smaller than this are indistinguishable from the RTC's quantisation
+const RTC_RESOLUTION: i64 = 1000;
+/// Error beyond which the clock is stepped instead of slewed (ms)
+const RTC_STEP_THRESHOLD: i64 = 2 * RTC_RESOLUTION;
+/// Limit on the drift correction (parts per million)
+const MAX_DRIFT_PPM: i64 = 1000;
+
+impl WallClock
+{
+	fn at(&self, tick: TickCount) -> Timestamp {
+		// NOTE: Signed, as `tick` may have been read just before another CPU re-based the clock
+		let elapsed = tick as i64 - self.base_tick as i64;
+		self.base_time + elapsed + elapsed * self.drift_ppm / 1_000_000
+	}
+	fn set(&mut self, time: Timestamp, tick: TickCount) {
+		self.base_time = time;
+		self.base_tick = tick;
+		self.last_sync = tick;
+		self.drift_start = tick;
+		self.drift_offset = 0;
+	}
+}
+
+/// Register the system's hardware real-time clock, and set the wall clock from it
+pub fn register_rtc(dev: Box<RtcDevice>)
+{
+	let mut lh = S_RTC.lock();
+	if let Some(ref cur) = *lh {
+		log_notice!("Replacing RTC '{}' with '{}'", cur.name(), dev.name());
+	}
+	match dev.read()
+	{
+	Some(t) => {
+		log_notice!("Wall clock set from RTC '{}' ({:?})", dev.name(), date_from_timestamp(t));
+		let _irq = ::sync::hold_interrupts();
+		S_WALLCLOCK.lock().set(t, ticks());
+		},
+	None => log_warning!("RTC '{}' doesn't hold a valid time", dev.name()),
+	}
+	*lh = Some(dev);
+}
+
+/// Obtain the current wall-clock time (milliseconds since 1970-01-01 00:00 UTC)
+///
+/// If no RTC is present this counts from the epoch at startup.
+pub fn realtime() -> Timestamp
+{
+	let now = ticks();
+	let (rv, last_sync) = {
+		let _irq = ::sync::hold_interrupts();
+		let lh = S_WALLCLOCK.lock();
+		(lh.at(now), lh.last_sync)
+		};
+	if now.saturating_sub(last_sync) >= RTC_RESYNC_INTERVAL {
+		resync_rtc();
+	}
+	rv
+}
+
+/// Set the wall-clock time (and the RTC, if present)
+///
+/// NOTE: Callers are responsible for checking that the caller is permitted to do this
+pub fn set_realtime(time: Timestamp)
+{
+	log_notice!("Wall clock set to {:?}", date_from_timestamp(time));
+	{
+		let _irq = ::sync::hold_interrupts();
+		let mut lh = S_WALLCLOCK.lock();
+		lh.set(time, ticks());
+		// - Any measured drift was relative to the old time, start again
+		lh.drift_ppm = 0;
+	}
+	if let Some(ref dev) = *S_RTC.lock() {
+		if !dev.write(time) {
+			log_warning!("Unable to update RTC '{}'", dev.name());
+		}
+	}
+}
+
+/// Compare the wall clock against the RTC, updating the drift correction
+fn resync_rtc()
+{
+	// - Don't block if another thread is already doing this
+	let lh_rtc = match S_RTC.try_lock()
+		{
+		Some(v) => v,
+		None => return,
+		};
+	let rtc_time = match *lh_rtc
+		{
+		Some(ref dev) => dev.read(),
+		None => None,
+		};
+	let now = ticks();
+
+	let _irq = ::sync::hold_interrupts();
+	let mut lh = S_WALLCLOCK.lock();
+	let elapsed = now as i64 - lh.last_sync as i64;
+	match rtc_time
+	{
+	// - Raced with another update
+	_ if elapsed <= 0 => {},
+	Some(t) => {
+		let predicted = lh.at(now);
+		let error = t - predicted;
+		if error.abs() >= RTC_STEP_THRESHOLD {
+			log_notice!("Wall clock stepped by {}ms to match RTC", error);
+			lh.set(t, now);
+		}
+		else if (error - lh.drift_offset).abs() < RTC_RESOLUTION {
+			// - Within the RTC's quantisation, keep accumulating over further intervals until the error is measurable
+			lh.last_sync = now;
+		}
+		else {
+			// Re-base at the predicted time (so time doesn't jump), and correct the rate
+			// - The error has built up since `drift_start` (possibly several intervals), so average over that whole span
+			let span = now as i64 - lh.drift_start as i64;
+			let ppm = lh.drift_ppm + (error - lh.drift_offset) * 1_000_000 / span;
+			lh.drift_ppm = ::core::cmp::max(-MAX_DRIFT_PPM, ::core::cmp::min(MAX_DRIFT_PPM, ppm));
+			lh.set(predicted, now);
+			lh.drift_offset = error;
+		}
+		},
+	None => lh.last_sync = now,
+	}
+}
+
+/// Convert a calendar date and time (UTC) into a timestamp
+///
+/// `month` and `day` are one-based
+pub fn timestamp_from_date(year: u32, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Timestamp
+{
+	// Count days using a year starting in March (moves the leap day to the end)
+	let (y, m) = if month <= 2 { (year as i64 - 1, month as i64 + 9) } else { (year as i64, month as i64 - 3) };
+	let era = (if y >= 0 { y } else { y - 399 }) / 400;
+	let year_of_era = y - era * 400;
+	let day_of_year = (153 * m + 2) / 5 + day as i64 - 1;
+	let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+	// 719468 = Days between 0000-03-01 and 1970-01-01
+	let days = era * 146097 + day_of_era - 719468;
+
+	(((days * 24 + hour as i64) * 60 + minute as i64) * 60 + second as i64) * 1000
+}
+
+/// Calendar date and time (UTC), as returned by `date_from_timestamp`
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct Date
+{
+	pub year: u32,
+	/// One-based month
+	pub month: u8,
+	/// One-based day of the month
+	pub day: u8,
+	pub hour: u8,
+	pub minute: u8,
+	pub second: u8,
+}
+
+/// Convert a timestamp into a calendar date and time (UTC), the inverse of `timestamp_from_date`
+pub fn date_from_timestamp(ts: Timestamp) -> Date
+{
+	let secs = if ts >= 0 { ts / 1000 } else { (ts - 999) / 1000 };
+	let (days, day_secs) = if secs >= 0 { (secs / 86400, secs % 86400) } else { ((secs - 86399) / 86400, ((secs % 86400) + 86400) % 86400) };
+	// Same March-based year as `timestamp_from_date`
+	let z = days + 719468;
+	let era = (if z >= 0 { z } else { z - 146096 }) / 146097;
+	let day_of_era = z - era * 146097;
+	let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let m = (5 * day_of_year + 2) / 153;
+	let day = day_of_year - (153 * m + 2) / 5 + 1;
+	let month = if m < 10 { m + 3 } else { m - 9 };
+	let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+	Date {
+		year: year as u32,
+		month: month as u8,
+		day: day as u8,
+		hour: (day_secs / 3600) as u8,
+		minute: (day_secs / 60 % 60) as u8,
+		second: (day_secs % 60) as u8,
+	}
+}
 
 /// Records the current time on construction, and prints the elapsed time with {:?} / {}
 pub struct ElapsedLogger(TickCount);
@@ -52,5 +417,53 @@ impl CacheTimer
 	}
 }
 
-// vim: ft=rust
+#[cfg(test)]
+mod tests
+{
+	#[allow(unused_imports)]
+	use prelude::*;
+	use super::{TimerEnt, TimerList, TickCount};
 
+	fn ent(sleeper: &::threads::SleepObject, expiry: TickCount) -> Box<TimerEnt> {
+		Box::new(TimerEnt { expiry: expiry, next: 0 as *mut _, fired: false, sleeper: sleeper.get_ref() })
+	}
+
+	#[test]
+	fn timer_list()
+	{
+		let sleeper = ::threads::SleepObject::new("timer_list");
+		let mut list = TimerList { head: 0 as *mut _ };
+		assert_eq!(list.first_expiry(), None);
+
+		let mut e30 = ent(&sleeper, 30);
+		let mut e10a = ent(&sleeper, 10);
+		let mut e20 = ent(&sleeper, 20);
+		let mut e10b = ent(&sleeper, 10);
+		// SAFE: Entries outlive the list's use of them
+		unsafe {
+			assert!( list.insert(&mut *e30) );
+			assert!( list.insert(&mut *e10a) );
+			assert!( !list.insert(&mut *e20) );
+			// - Equal expiry goes after the existing entry
+			assert!( !list.insert(&mut *e10b) );
+		}
+		assert_eq!(list.first_expiry(), Some(10));
+
+		list.remove(&mut *e10a);
+		assert_eq!(list.first_expiry(), Some(10));
+		// Removing an entry that's not in the list is a no-op
+		list.remove(&mut *e10a);
+
+		assert!( list.pop_expired(9).is_none() );
+		assert_eq!(list.pop_expired(15), Some(&mut *e10b as *mut _));
+		assert!( list.pop_expired(15).is_none() );
+		assert_eq!(list.first_expiry(), Some(20));
+
+		assert_eq!(list.pop_expired(30), Some(&mut *e20 as *mut _));
+		assert_eq!(list.pop_expired(30), Some(&mut *e30 as *mut _));
+		assert_eq!(list.first_expiry(), None);
+		assert!( list.pop_expired(!0).is_none() );
+	}
+}
+
+// vim: ft=rust
@@ -14,7 +14,7 @@ use super::Path;
 pub struct Any {
 	node: CacheHandle,
 }
-#[derive(Debug,Clone)]
+#[derive(Debug)]
 /// Normal file
 pub struct File {
 	node: CacheHandle,
@@ -90,6 +90,10 @@ impl Any
 	pub fn get_class(&self) -> super::node::NodeClass {
 		self.node.get_class()
 	}
+	/// Obtain the node's metadata (size, permissions, timestamps, ...)
+	pub fn get_metadata(&self) -> super::node::Metadata {
+		self.node.get_metadata()
+	}
 	
 	/// Upgrade the handle to a directory handle
 	pub fn to_dir(self) -> super::Result<Dir> {
@@ -141,13 +145,20 @@ impl File
 		}
 		match mode
 		{
-		// TODO: Mark file as shared
 		// TODO: Check permissions (must be readable in current context)
 		FileOpenMode::SharedRO => {},
-		// TODO: Mark file as shared
 		// TODO: Check permissions (must be executable in current context)
 		FileOpenMode::Execute => {},
-		_ => todo!("Acquire lock depending on mode({:?})", mode),
+		// TODO: Check permissions (must be writable in current context)
+		FileOpenMode::ExclRW => {},
+		FileOpenMode::Append => {},
+		FileOpenMode::Unsynch => {},
+		// TODO: Needs copy-on-write support in the node cache
+		FileOpenMode::UniqueRW => return Err( super::Error::Unknown("TODO: UniqueRW file handles") ),
+		}
+		if !node.file_lock(&mode) {
+			log_debug!("File::from_node - {:?} conflicts with existing handles", mode);
+			return Err( super::Error::Locked );
 		}
 		Ok(File { node: node, mode: mode })
 	}
@@ -164,9 +175,20 @@ impl File
 		assert!(self.node.is_file());
 		self.node.read(ofs, dst)
 	}
+	/// Write data to the file at the specified offset
+	///
+	/// Writing past the end of the file extends it (zero-filling any gap). For `Append`
+	/// handles the offset is ignored, and data is always written to the end of the file.
 	pub fn write(&self, ofs: u64, src: &[u8]) -> super::Result<usize> {
 		assert!(self.node.is_file());
-		todo!("Handle::write({:#x}, {:p}+{}", ofs, src.as_ptr(), src.len());
+		match self.mode
+		{
+		FileOpenMode::SharedRO | FileOpenMode::Execute => Err(super::Error::PermissionDenied),
+		// NOTE: `from_node` refuses to create these (no copy-on-write support yet), but fail cleanly regardless
+		FileOpenMode::UniqueRW => Err( super::Error::Unknown("TODO: UniqueRW file handles") ),
+		FileOpenMode::Append => self.node.append(src),
+		FileOpenMode::ExclRW | FileOpenMode::Unsynch => self.node.write(ofs, src),
+		}
 	}
 
 	
@@ -233,6 +255,12 @@ impl File
 		// - Obtain handles to each cached page, and map into the reservation
 		for i in 0 .. page_count {
 			let page = ofs / ::PAGE_SIZE as u64 + i as u64;
+			// 0. Memory-resident files can share their pages directly
+			// - Writes through a COW mapping will copy the page (as the frame is multiply referenced)
+			if let Some(frame) = self.node.get_page(page) {
+				resv.map_at(i, frame);
+				continue ;
+			}
 			// 1. Search the node for this particular page
 			//let lh = self.page_cache.read();
 			//  - If found, map over region
@@ -265,16 +293,21 @@ impl File
 			})
 	}
 }
+impl Clone for File
+{
+	fn clone(&self) -> File {
+		// The new handle shares the lock held by this one
+		self.node.file_lock_ref(&self.mode);
+		File {
+			node: self.node.clone(),
+			mode: self.mode.clone(),
+		}
+	}
+}
 impl ::core::ops::Drop for File
 {
 	fn drop(&mut self) {
-		match self.mode
-		{
-		FileOpenMode::SharedRO => {},
-		FileOpenMode::Execute => {},
-		_ => todo!("File::drop() - mode={:?}", self.mode),
-		}
-		// TODO: For files, we need to release the lock
+		self.node.file_unlock(&self.mode);
 	}
 }
 
@@ -323,6 +356,19 @@ impl Dir
 		try!(self.node.create(name.as_ref(), NodeType::Symlink(target)));
 		Ok( () )
 	}
+	/// Create a new (empty) file, and open it with the specified mode
+	pub fn create_file(&self, name: &ByteStr, mode: FileOpenMode) -> super::Result<File> {
+		let node = try!(self.node.create(name, NodeType::File));
+		File::from_node(node, mode)
+	}
+	/// Remove a name from this directory
+	pub fn unlink(&self, name: &ByteStr) -> super::Result<()> {
+		self.node.unlink(name)
+	}
+	/// Move an entry to `new_name` in `dest` (atomic, both directories must be on the same mount)
+	pub fn rename(&self, name: &ByteStr, dest: &Dir, new_name: &ByteStr) -> super::Result<()> {
+		self.node.rename(name, &dest.node, new_name)
+	}
 
 	/// Open a child of this node
 	pub fn open_child(&self, name: &ByteStr) -> super::Result<Any> {
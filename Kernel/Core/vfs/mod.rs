@@ -33,6 +33,8 @@ pub enum Error
 	NonDirComponent,
 	/// Symbolic link recursion limit reached
 	RecursionDepthExceeded,
+	/// Directory could not be removed as it still has entries
+	NotEmpty,
 
 
 	/// Block-level IO Error
@@ -92,6 +94,7 @@ fn init()
 		};
 	root.mkdir("system").unwrap();
 	root.mkdir("volumes").unwrap();
-	root.mkdir("temp").unwrap();
+	// - Scratch space (ramfs backed, so available in every configuration)
+	root.mkdir("tmp").unwrap();
 }
 
@@ -9,7 +9,7 @@ use super::node::{InodeId,Node,CacheHandle};
 use sync::RwLock;
 use lib::{LazyStatic,SparseVec,VecMap};
 
-use metadevs::storage::VolumeHandle;
+use metadevs::storage::{self,VolumeHandle,VolumeEvent};
 
 /// A handle to a mounted filesystem
 /// 
@@ -25,6 +25,8 @@ pub struct SelfHandle(usize);
 struct MountedVolume
 {
 	mountpoint_node: CacheHandle,
+	/// Index of the logical volume the filesystem is on
+	volume: usize,
 	fs: Box<Filesystem>,
 }
 
@@ -35,6 +37,9 @@ pub trait Filesystem:
 {
 	fn root_inode(&self) -> InodeId;
 	fn get_node_by_inode(&self, InodeId) -> Option<Node>;
+	/// Called once an unlinked node (link count of zero) is no longer open, so its storage can be freed
+	fn release_inode(&self, _id: InodeId) {
+	}
 }
 
 struct NullFs;
@@ -66,6 +71,8 @@ static S_DRIVERS: LazyStatic<RwLock< VecMap<&'static str, &'static Driver> >> =
 static S_VOLUMES: LazyStatic<RwLock< SparseVec<MountedVolume> >> = lazystatic_init!();
 /// Root mount
 static S_ROOT_VOLUME: RwLock<Option<Box<Filesystem>>> = RwLock::new(None);
+/// Volume hotplug events for the automounter
+static S_AUTOMOUNT_QUEUE: ::sync::Queue<VolumeEvent> = ::sync::Queue::new_const();
 
 pub fn init()
 {
@@ -83,12 +90,9 @@ pub fn mount(location: &Path, vol: VolumeHandle, fs: &str, _options: &[&str]) ->
 	let drivers = S_DRIVERS.read();
 	// 1. (maybe) detect filesystem
 	let driver = if fs == "" {
-			match drivers.iter()
-				.filter_map(|(n,fs)| fs.detect(&vol).ok().map(|r| (r, n, fs)))
-				.max_by_key(|&(l,_,_)| l)
+			match detect_driver(&drivers, &vol)
 			{
-			Some((0,_,_)) => return Err(MountError::NoHandler),
-			Some((_,_name,fs)) => fs,
+			Some((_name,fs)) => fs,
 			None => return Err(MountError::NoHandler),
 			}
 		}
@@ -134,7 +138,7 @@ pub fn mount(location: &Path, vol: VolumeHandle, fs: &str, _options: &[&str]) ->
 		
 		// 3. Reserve the mountpoint ID (using a placeholder instance)
 		// NOTE: Nothing should know of this index until after mount is completed
-		let vidx = S_VOLUMES.write().insert(MountedVolume { mountpoint_node: nh, fs: Box::new(NullFs) });
+		let vidx = S_VOLUMES.write().insert(MountedVolume { mountpoint_node: nh, volume: vol.idx(), fs: Box::new(NullFs) });
 
 		// 4. Mount and register volume
 		let fs = match driver.mount(vol, SelfHandle(vidx))
@@ -148,7 +152,10 @@ pub fn mount(location: &Path, vol: VolumeHandle, fs: &str, _options: &[&str]) ->
 			let mut lh = S_VOLUMES.write();
 			lh[vidx].fs = fs;
 			if lh[vidx].mountpoint_node.mount(vidx + 1) == false {
-				lh.remove(vidx);
+				// - Released with the list unlocked, as dropping a node handle can call into the node cache
+				let mv = lh.remove(vidx);
+				drop(lh);
+				drop(mv);
 				return Err(MountError::MountpointUsed);
 			}
 		}
@@ -156,6 +163,114 @@ pub fn mount(location: &Path, vol: VolumeHandle, fs: &str, _options: &[&str]) ->
 
 	Ok( () )
 }
+/// Unmount the filesystem on the given logical volume, returning `Ok(false)` if it isn't mounted
+///
+/// Fails with `MountError::Busy` if any file or directory on it is still open.
+pub fn unmount_volume(lv_idx: usize) -> Result<bool,MountError>
+{
+	let (vidx, mountpoint_node) = {
+		let lh = S_VOLUMES.read();
+		match (0 .. lh.len()).find(|&i| lh.get(i).map_or(false, |v| v.volume == lv_idx))
+		{
+		Some(i) => (i, lh[i].mountpoint_node.clone()),
+		None => return Ok(false),
+		}
+		};
+	// NOTE: The node cache is locked by `unmount`, which must be done with the volume list unlocked (the cache calls
+	// into the volume list when loading nodes)
+	if ! mountpoint_node.unmount(vidx + 1) {
+		return Err(MountError::Busy);
+	}
+	// - Nothing can reach the filesystem now, so release it (dropping its handle to the volume)
+	let mv = S_VOLUMES.write().remove(vidx);
+	drop(mv);
+	Ok(true)
+}
+
+/// Locate the best registered driver for the provided volume
+fn detect_driver(drivers: &VecMap<&'static str, &'static Driver>, vol: &VolumeHandle) -> Option<(&'static str, &'static Driver)>
+{
+	match drivers.iter()
+		.filter_map(|(n,fs)| fs.detect(vol).ok().map(|r| (r, n, fs)))
+		.max_by_key(|&(l,_,_)| l)
+	{
+	Some((0,_,_)) => None,
+	Some((_,&name,&fs)) => Some( (name, fs) ),
+	None => None,
+	}
+}
+
+/// Start automatically mounting logical volumes under `/mount/<lvname>` as they appear
+///
+/// Volumes that are already open (e.g. the system disk) are skipped.
+pub fn start_automount()
+{
+	match super::handle::Dir::open(Path::new("/")).and_then(|h| h.mkdir("mount"))
+	{
+	Ok(_) => {},
+	Err(super::Error::AlreadyExists) => {},
+	Err(e) => {
+		log_error!("Unable to create /mount, automount disabled: {:?}", e);
+		return ;
+		},
+	}
+	// NOTE: The subscription replays already-registered volumes
+	let subscription = storage::subscribe(&S_AUTOMOUNT_QUEUE);
+	::core::mem::forget( ::threads::WorkerThread::new("VFS Automount", move || {
+		let _subscription = subscription;
+		loop
+		{
+			match S_AUTOMOUNT_QUEUE.wait_pop()
+			{
+			VolumeEvent::Added(idx, name) => automount_volume(idx, &name),
+			VolumeEvent::Removed(idx, name) => match unmount_volume(idx)
+				{
+				Ok(true) => log_log!("Volume '{}' removed, unmounted", name),
+				Ok(false) => {},
+				// - Left mounted (returning IO errors) until it's no longer in use
+				Err(e) => log_notice!("Volume '{}' removed, unable to unmount: {}", name, e),
+				},
+			}
+		}
+		}) );
+}
+
+fn automount_volume(idx: usize, name: &str)
+{
+	let vh = match VolumeHandle::open_idx(idx)
+		{
+		Ok(v) => v,
+		Err(e) => {
+			log_log!("Automount: Unable to open '{}': {}", name, e);
+			return ;
+			},
+		};
+	let fs_name = match detect_driver(&S_DRIVERS.read(), &vh)
+		{
+		Some((n,_)) => n,
+		None => {
+			log_log!("Automount: No filesystem detected on '{}'", name);
+			return ;
+			},
+		};
+	// - The mountpoint might already exist (if the volume has been seen before)
+	match super::handle::Dir::open(Path::new("/mount")).and_then(|h| h.mkdir(name))
+	{
+	Ok(_) => {},
+	Err(super::Error::AlreadyExists) => {},
+	Err(e) => {
+		log_notice!("Automount: Unable to create mountpoint for '{}': {:?}", name, e);
+		return ;
+		},
+	}
+	let mountpt = format!("/mount/{}", name);
+	match mount(mountpt.as_ref(), vh, fs_name, &[])
+	{
+	Ok(_) => log_log!("Auto-mounted '{}' ({}) to {}", name, fs_name, mountpt),
+	Err(e) => log_notice!("Unable to automount '{}': {}", name, e),
+	}
+}
+
 #[derive(Debug)]
 pub enum MountError
 {
@@ -164,6 +279,7 @@ pub enum MountError
 	InvalidMountpoint,
 	MountpointUsed,
 	CallFailed,
+	Busy,
 }
 impl_fmt! {
 	Display(self,f) for MountError {
@@ -174,6 +290,7 @@ impl_fmt! {
 			&MountError::InvalidMountpoint => "The specified mountpoint was invalid",
 			&MountError::MountpointUsed => "The specified mountpoint was already used",
 			&MountError::CallFailed => "Driver's mount call failed",
+			&MountError::Busy => "Files on the filesystem are still open",
 			})
 	}
 }
@@ -217,6 +334,9 @@ impl Handle
 	pub fn get_node(&self, id: InodeId) -> Option<Node> {
 		self.with_fs(|fs| fs.get_node_by_inode(id))
 	}
+	pub fn release_inode(&self, id: InodeId) {
+		self.with_fs(|fs| fs.release_inode(id))
+	}
 
 	fn with_fs<R, F: FnOnce(&Filesystem)->R>(&self, f: F) -> R {
 		if self.0 == 0 {
@@ -5,6 +5,7 @@
 //! VFS vode management
 use prelude::*;
 use super::Path;
+use super::handle::FileOpenMode;
 use sync::mutex::LazyMutex;
 use lib::byte_str::{ByteStr,ByteString};
 use core::sync::atomic::{self,AtomicUsize};
@@ -27,12 +28,36 @@ pub enum NodeClass {
 	Special,
 }
 
+/// Node metadata (size, permissions, ownership and timestamps)
+#[derive(Debug,Default,Clone)]
+pub struct Metadata
+{
+	/// Size in bytes (zero for directories, unless the filesystem reports otherwise)
+	pub size: u64,
+	/// UNIX-style permission bits (e.g. `0o755`), excluding the node type
+	pub mode: u16,
+	/// Owning user ID
+	pub uid: u32,
+	/// Owning group ID
+	pub gid: u32,
+	/// Number of directory entries referencing this node
+	pub link_count: u32,
+	/// Creation time (zero if not recorded)
+	pub created: ::time::Timestamp,
+	/// Last modification time (zero if not recorded)
+	pub modified: ::time::Timestamp,
+	/// Last access time (zero if not recorded)
+	pub accessed: ::time::Timestamp,
+}
+
 /// Base trait for a VFS node, defines common operation on nodes
 pub trait NodeBase: Send {
 	/// Return the volume's inode number
 	fn get_id(&self) -> InodeId;
 	/// Return an &Any associated with this node (not nessesarily same as `self`, up to the driver)
 	fn get_any(&self) -> &Any;
+	/// Obtain the node's metadata
+	fn get_metadata(&self) -> Metadata;
 }
 /// Trait for "File" nodes
 pub trait File: NodeBase {
@@ -46,6 +71,12 @@ pub trait File: NodeBase {
 	fn read(&self, ofs: u64, buf: &mut [u8]) -> Result<usize>;
 	/// Write data to the file, can only grow the file if ofs==size
 	fn write(&self, ofs: u64, buf: &[u8]) -> Result<usize>;
+	/// Obtain the frame backing a page of the file, if the file is memory-resident
+	///
+	/// Allows `memory_map` to share the page instead of copying the data
+	fn get_page(&self, _page: u64) -> Option<::memory::phys::FrameHandle> {
+		None
+	}
 }
 
 // TODO: Should this be &ByteStr instead of an iterator?
@@ -73,6 +104,13 @@ pub trait Dir: NodeBase {
 	fn link(&self, name: &ByteStr, inode: &NodeBase) -> Result<()>;
 	/// Remove the specified name
 	fn unlink(&self, name: &ByteStr) -> Result<()>;
+	/// Atomically move an entry to a new name, possibly in another directory
+	///
+	/// `dest` is always a directory on the same filesystem instance. Fails with `AlreadyExists` if
+	/// the new name is already in use.
+	fn rename(&self, _old_name: &ByteStr, _dest: &Dir, _new_name: &ByteStr) -> Result<()> {
+		Err( super::Error::Unknown("Rename not supported by filesystem") )
+	}
 }
 /// Trait for symbolic link nodes.
 pub trait Symlink: NodeBase {
@@ -101,7 +139,11 @@ pub enum Node
 enum CacheNodeInt
 {
 	File {
-		fsnode: Box<File>
+		fsnode: Box<File>,
+		/// Open-mode locking state (see `handle::FileOpenMode`)
+		locks: ::sync::Mutex<FileLocks>,
+		/// Serialises size-changing writes (so appends are atomic)
+		write_lock: ::sync::Mutex<()>,
 		
 		// File memory map data
 		//mapped_pages: HashMap<u64,FrameHandle>,
@@ -122,7 +164,7 @@ impl_from!{
 	From<Node>(v) for CacheNodeInt {
 		match v
 		{
-		Node::File(f) => CacheNodeInt::File { fsnode: f },
+		Node::File(f) => CacheNodeInt::File { fsnode: f, locks: Default::default(), write_lock: Default::default() },
 		Node::Dir(f) => CacheNodeInt::Dir { fsnode: f, mountpoint: AtomicUsize::new(0) },
 		Node::Symlink(f) => CacheNodeInt::Symlink { target: f.read(), fsnode: f },
 		Node::Special(f) => CacheNodeInt::Special { fsnode: f },
@@ -130,6 +172,41 @@ impl_from!{
 	}
 }
 
+/// Count of open handles to a file, by class of open mode
+#[derive(Default)]
+struct FileLocks
+{
+	/// SharedRO, Execute and UniqueRW handles
+	readers: usize,
+	appenders: usize,
+	exclusive: usize,
+	unsynch: usize,
+}
+impl FileLocks
+{
+	fn counter(&mut self, mode: &FileOpenMode) -> &mut usize {
+		match *mode
+		{
+		FileOpenMode::SharedRO | FileOpenMode::Execute | FileOpenMode::UniqueRW => &mut self.readers,
+		FileOpenMode::Append => &mut self.appenders,
+		FileOpenMode::ExclRW => &mut self.exclusive,
+		FileOpenMode::Unsynch => &mut self.unsynch,
+		}
+	}
+	/// Check if a new handle with the specified mode can coexist with the existing handles
+	fn compatible(&self, mode: &FileOpenMode) -> bool {
+		match *mode
+		{
+		// Readers can't see changes, so block anything that modifies existing data
+		FileOpenMode::SharedRO | FileOpenMode::Execute | FileOpenMode::UniqueRW => self.exclusive == 0 && self.unsynch == 0,
+		// Appends only extend the file, which readers are allowed to see
+		FileOpenMode::Append => self.unsynch == 0,
+		FileOpenMode::ExclRW => self.readers == 0 && self.exclusive == 0 && self.unsynch == 0,
+		FileOpenMode::Unsynch => self.readers == 0 && self.appenders == 0 && self.exclusive == 0,
+		}
+	}
+}
+
 struct CachedNode
 {
 	refcount: AtomicUsize,
@@ -146,10 +223,99 @@ unsafe impl Sync for CacheHandle {}
 unsafe impl Send for CacheHandle {}
 
 static S_NODE_CACHE: LazyMutex<::lib::VecMap<(usize,InodeId),Box<CachedNode>>> = lazymutex_init!();
+/// Name lookup cache: (mountpoint, directory inode, name) to child inode
+static S_DENTRY_CACHE: LazyMutex<::lib::VecMap<(usize,InodeId,ByteString),InodeId>> = lazymutex_init!();
+
+/// Maximum number of symbolic links followed in a single path lookup
+const MAX_SYMLINK_DEPTH: usize = 16;
+/// Maximum number of entries in the name lookup cache
+const MAX_DENTRY_CACHE: usize = 256;
+
+/// Memory pressure hook, releases unreferenced nodes and the name lookup cache
+struct NodeCacheReclaim;
+static S_RECLAIM: NodeCacheReclaim = NodeCacheReclaim;
 
 pub fn init()
 {
 	S_NODE_CACHE.init(|| Default::default());
+	S_DENTRY_CACHE.init(|| Default::default());
+	::memory::pressure::register(&S_RECLAIM);
+}
+
+impl ::memory::pressure::Reclaimable for NodeCacheReclaim
+{
+	fn name(&self) -> &str {
+		"vfs_node_cache"
+	}
+	fn reclaim(&self, target: usize) -> usize
+	{
+		// NOTE: Can't block, as the caches are locked while loading nodes (which allocates)
+		let mut released_bytes = 0;
+		if let Some(mut lh) = S_DENTRY_CACHE.try_lock() {
+			// - Lookups fall back to the filesystem, so the whole cache can go
+			let n_ents = lh.iter().count();
+			*lh = Default::default();
+			released_bytes += n_ents * ::core::mem::size_of::<((usize,InodeId,ByteString),InodeId)>();
+		}
+		if let Some(mut lh) = S_NODE_CACHE.try_lock() {
+			lh.retain(|_, e| {
+				// - Handles are only created with the cache locked, so a zero count can't change here
+				// - Orphaned nodes are left for `evict_if_orphaned` (which releases their storage)
+				if released_bytes >= target * ::PAGE_SIZE || e.refcount.load(atomic::Ordering::SeqCst) != 0 || e.node.get_metadata().link_count == 0 {
+					true
+				}
+				else {
+					released_bytes += ::core::mem::size_of::<CachedNode>();
+					false
+				}
+				});
+		}
+		// NOTE: The heap is counted in whole frames, so this is an estimate (and can be zero)
+		released_bytes / ::PAGE_SIZE
+	}
+}
+
+/// Add an entry to the name lookup cache
+fn dentry_insert(key: (usize,InodeId,ByteString), inode: InodeId)
+{
+	let mut lh = S_DENTRY_CACHE.lock();
+	if lh.get(&key).is_none() {
+		// TODO: Use LRU eviction instead of evicting an arbitary entry
+		let n_ents = lh.iter().count();
+		if n_ents >= MAX_DENTRY_CACHE {
+			let evict = lh.iter().next().map(|(k,_)| k.clone()).unwrap();
+			lh.remove(&evict);
+		}
+	}
+	lh.insert(key, inode);
+}
+/// Remove an entry from the name lookup cache (called when a name is removed)
+fn dentry_forget(mountpt: usize, dir: InodeId, name: &ByteStr)
+{
+	S_DENTRY_CACHE.lock().remove( &(mountpt, dir, ByteString::from(name)) );
+}
+
+/// Evict a node that has neither open handles nor directory entries (i.e. it has been unlinked), and let the
+/// filesystem release its storage
+fn evict_if_orphaned(mountpt: usize, inode: InodeId)
+{
+	let ent = {
+		let mut lh = S_NODE_CACHE.lock();
+		// NOTE: Checked with the cache locked, as `from_ids` takes new references with it held
+		let orphaned = match lh.get( &(mountpt, inode) )
+			{
+			Some(e) => e.refcount.load(atomic::Ordering::SeqCst) == 0 && e.node.get_metadata().link_count == 0,
+			None => false,
+			};
+		if !orphaned {
+			return ;
+		}
+		lh.remove( &(mountpt, inode) )
+		};
+	// - Drop the filesystem's node before telling it to release the inode
+	drop(ent);
+	log_debug!("Evicting orphaned node {}:{:#x}", mountpt, inode);
+	super::mount::Handle::from_id(mountpt).release_inode(inode);
 }
 
 impl_fmt! {
@@ -173,6 +339,18 @@ impl Clone for CacheHandle
 	}
 }
 
+impl Drop for CacheHandle
+{
+	fn drop(&mut self) {
+		// SAFE: self.ptr is valid until the cache entry is evicted, which requires the count to reach zero
+		let was_last = unsafe { (*self.ptr).refcount.fetch_sub(1, atomic::Ordering::SeqCst) == 1 };
+		// Unlinked nodes are kept in the cache only while open, other nodes stay cached
+		if was_last {
+			evict_if_orphaned(self.mountpt, self.inode);
+		}
+	}
+}
+
 impl CacheHandle
 {
 	/// Obtain a node handle using a mountpoint ID and inode number
@@ -223,78 +401,72 @@ impl CacheHandle
 	}
 	
 	
-	pub fn from_path_at_node(mut node_h: CacheHandle, path: &Path) -> super::Result<CacheHandle>
+	/// Obtain a node handle using a path relative to another node
+	///
+	/// Absolute paths are treated as relative to `node_h`, and `..` at `node_h` refers to `node_h`. A
+	/// symbolic link as the final component is not followed.
+	pub fn from_path_at_node(node_h: CacheHandle, path: &Path) -> super::Result<CacheHandle>
 	{
 		log_function!("CacheHandle::from_path_at_node(node_h={:?}, {:?})", node_h, path);
-		let path = if path.is_absolute() {
-				try!(path.split_off_first().ok_or(super::Error::MalformedPath)).1
-			}
-			else {
-				path
-			};
-		// Iterate path components
-		for seg in path
+		// Stack of traversed nodes, popped to handle `..` (which also handles mountpoints)
+		let mut stack = vec![node_h];
+		let mut link_depth = 0;
+		try!(CacheHandle::walk_path(&mut stack, path, &mut link_depth, false));
+		let rv = stack.pop().expect("CacheHandle::from_path_at_node - Empty stack");
+		log_trace!("CacheHandle::from_path_at_node() {:?}", rv);
+		Ok( rv )
+	}
+
+	/// Walk a path, pushing each resolved component onto `stack`
+	fn walk_path(stack: &mut Vec<CacheHandle>, path: &Path, link_depth: &mut usize, follow_final: bool) -> super::Result<()>
+	{
+		let mut segs = path.iter().filter(|s| s.len() > 0 && *s != ".").peekable();
+		while let Some(seg) = segs.next()
 		{
 			log_trace!("seg = {:?}", seg);
-			// Loop resolving symbolic links
-			loop
-			{
-				// TODO: Should symlinks be handled in this function? Or should the passed path be without symlinks?
-				node_h = if let CacheNodeInt::Symlink { ref target, .. } = *node_h.as_ref() {
-					//log_debug!("- seg={:?} : SYMLINK {:?}", seg, name);
-					let linkpath = Path::new(&target);
-					if linkpath.is_absolute() {
-						try!(CacheHandle::from_path(linkpath))
-					}
-					else {
-						//TODO: To make this work (or any path-relative symlink), the current position in
-						//      `path` needs to be known.
-						// It can be hacked up though...
-						let parent_segs_len = seg.as_ref().as_ptr() as usize - AsRef::<[u8]>::as_ref(path).as_ptr() as usize;
-						let parent = ByteStr::new( &AsRef::<[u8]>::as_ref(path)[..parent_segs_len] );
-
-						//let segs = [ &path[..pos], &link ];
-						//let p = PathChain::new(&segs);
-						//try!( CacheHandle::from_path(p) )
-						// Recurse with a special chained path type
-						// (that iterates but can't be sliced).
-						todo!("Relative symbolic links {:?} relative to {:?}", target, parent)
-					}
+			if seg == ".." {
+				if stack.len() > 1 {
+					stack.pop();
 				}
-				else {
-					break;
-				};
+				continue ;
 			}
+			let is_last = segs.peek().is_none();
 
-			// Look up this component in the current node
-			node_h = match *node_h.as_ref()
+			let child = try!(stack.last().unwrap().lookup_child(seg));
+			let link_target = match *child.as_ref()
 				{
-				CacheNodeInt::Dir { fsnode: ref dir, .. } => {
-					//log_debug!("- seg={:?} : DIR", seg);
-					let next_id = match dir.lookup(seg)
-						{
-						Ok(v) => v,
-						Err(_) => return Err(super::Error::NotFound),
-						};
-					try!(CacheHandle::from_ids( node_h.mountpt, next_id ))
-					},
-				_ => return Err(super::Error::NonDirComponent),
+				CacheNodeInt::Symlink { ref target, .. } if follow_final || !is_last => Some(target.clone()),
+				_ => None,
 				};
+			match link_target
+			{
+			Some(target) => {
+				*link_depth += 1;
+				if *link_depth > MAX_SYMLINK_DEPTH {
+					log_notice!("Symbolic link depth exceeded resolving {:?}", path);
+					return Err( super::Error::RecursionDepthExceeded );
+				}
+				let linkpath = Path::new(&target);
+				// Absolute targets are relative to the real root, relative targets to the directory holding the link
+				if linkpath.is_absolute() {
+					let mph = super::mount::Handle::from_id(0);
+					let root = try!(CacheHandle::from_ids( mph.id(), mph.root_inode() ));
+					stack.clear();
+					stack.push(root);
+				}
+				try!(CacheHandle::walk_path(stack, linkpath, link_depth, true));
+				},
+			None => stack.push(child),
+			}
 		}
-		log_trace!("CacheHandle::from_path_at_node() {:?}", node_h);
-		Ok( node_h )
+		Ok( () )
 	}
 
 	/// Obtain a node handle using a path
 	pub fn from_path(path: &Path) -> super::Result<CacheHandle>
 	{
 		log_function!("CacheHandle::from_path({:?})", path);
-		// TODO: Support path caching?
-		
-		// - Remove the leading / from the absolute path
-		//  > Also checks that it's actually abolsute
-		let (first_comp, path) = try!( path.split_off_first().ok_or(super::Error::MalformedPath) );
-		if first_comp.len() != 0 {
+		if !path.is_absolute() {
 			return Err(super::Error::MalformedPath);
 		}
 
@@ -324,6 +496,16 @@ impl CacheHandle
 		self.get_class() == NodeClass::Symlink
 	}
 
+	pub fn get_metadata(&self) -> Metadata {
+		match self.as_ref()
+		{
+		&CacheNodeInt::Dir { ref fsnode, .. } => fsnode.get_metadata(),
+		&CacheNodeInt::File { ref fsnode, .. } => fsnode.get_metadata(),
+		&CacheNodeInt::Special { ref fsnode, .. } => fsnode.get_metadata(),
+		&CacheNodeInt::Symlink { ref fsnode, .. } => fsnode.get_metadata(),
+		}
+	}
+
 	pub fn get_any(&self) -> &Any {
 		match self.as_ref()
 		{
@@ -354,16 +536,83 @@ impl CacheHandle
 		_ => Err( super::Error::Unknown("Calling read_dir on non-directory") ),
 		}
 	}
-	pub fn open_child(&self, name: &ByteStr) -> super::Result<CacheHandle> {
+	/// Remove a name from this directory
+	pub fn unlink(&self, name: &ByteStr) -> super::Result<()> {
 		match self.as_ref()
 		{
 		&CacheNodeInt::Dir { ref fsnode, .. } => {
 			let inode = try!(fsnode.lookup(name));
-			Ok( try!(CacheHandle::from_ids(self.mountpt, inode)) )
+			if self.is_child_mountpoint(inode) {
+				return Err( super::Error::Locked );
+			}
+			try!(fsnode.unlink(name));
+			dentry_forget(self.mountpt, self.inode, name);
+			// - If that was the last link and nothing has the node open, it can be released now
+			evict_if_orphaned(self.mountpt, inode);
+			Ok( () )
+			},
+		_ => Err( super::Error::Unknown("Calling unlink on non-directory") ),
+		}
+	}
+	/// Move an entry from this directory to `dest` (which must be on the same mount)
+	pub fn rename(&self, old_name: &ByteStr, dest: &CacheHandle, new_name: &ByteStr) -> super::Result<()> {
+		if self.mountpt != dest.mountpt {
+			return Err( super::Error::InvalidParameter );
+		}
+		match (self.as_ref(), dest.as_ref())
+		{
+		(&CacheNodeInt::Dir { ref fsnode, .. }, &CacheNodeInt::Dir { fsnode: ref dest_node, .. }) => {
+			let inode = try!(fsnode.lookup(old_name));
+			if self.is_child_mountpoint(inode) {
+				return Err( super::Error::Locked );
+			}
+			try!(fsnode.rename(old_name, &**dest_node, new_name));
+			dentry_forget(self.mountpt, self.inode, old_name);
+			Ok( () )
+			},
+		(&CacheNodeInt::Dir { .. }, _) => Err( super::Error::TypeMismatch ),
+		_ => Err( super::Error::Unknown("Calling rename on non-directory") ),
+		}
+	}
+	/// Check if the specified child inode has a volume mounted on it
+	fn is_child_mountpoint(&self, inode: InodeId) -> bool {
+		match S_NODE_CACHE.lock().get( &(self.mountpt, inode) )
+		{
+		Some(cn) => match cn.node
+			{
+			CacheNodeInt::Dir { ref mountpoint, .. } => mountpoint.load(atomic::Ordering::Relaxed) != 0,
+			_ => false,
 			},
-		_ => Err( super::Error::Unknown("Calling open_child on non-directory") ),
+		None => false,
 		}
 	}
+	pub fn open_child(&self, name: &ByteStr) -> super::Result<CacheHandle> {
+		if !self.is_dir() {
+			return Err( super::Error::Unknown("Calling open_child on non-directory") );
+		}
+		self.lookup_child(name)
+	}
+	/// Look up a child of this directory, using the name lookup cache
+	fn lookup_child(&self, name: &ByteStr) -> super::Result<CacheHandle> {
+		let fsnode = match self.as_ref()
+			{
+			&CacheNodeInt::Dir { ref fsnode, .. } => fsnode,
+			_ => return Err(super::Error::NonDirComponent),
+			};
+		let key = (self.mountpt, self.inode, ByteString::from(name));
+		let cached = S_DENTRY_CACHE.lock().get(&key).cloned();
+		if let Some(inode) = cached {
+			match CacheHandle::from_ids(self.mountpt, inode)
+			{
+			Ok(v) => return Ok(v),
+			// - Stale entry, drop it and ask the filesystem
+			Err(_) => dentry_forget(self.mountpt, self.inode, name),
+			}
+		}
+		let inode = try!(fsnode.lookup(name));
+		dentry_insert(key, inode);
+		CacheHandle::from_ids(self.mountpt, inode)
+	}
 }
 /// Directory methods (mountpoint)
 impl CacheHandle
@@ -387,6 +636,29 @@ impl CacheHandle
 		_ => false,
 		}
 	}
+	/// Remove the binding made by `mount`, dropping all cached nodes of the filesystem
+	///
+	/// Returns `false` (leaving the filesystem bound) if any of its nodes are still open.
+	pub fn unmount(&self, filesystem_id: usize) -> bool {
+		let mountpoint = match self.as_ref()
+			{
+			&CacheNodeInt::Dir { ref mountpoint, .. } => mountpoint,
+			_ => return false,
+			};
+		{
+			// NOTE: The cache is locked while unbinding, so no new handles to the filesystem's nodes can be created
+			let mut lh = S_NODE_CACHE.lock();
+			if lh.iter().any(|(k,v)| k.0 == filesystem_id && v.refcount.load(atomic::Ordering::SeqCst) != 0) {
+				return false;
+			}
+			if mountpoint.compare_and_swap(filesystem_id, 0, atomic::Ordering::Relaxed) != filesystem_id {
+				return false;
+			}
+			lh.retain(|k,_| k.0 != filesystem_id);
+		}
+		S_DENTRY_CACHE.lock().retain(|k,_| k.0 != filesystem_id);
+		true
+	}
 }
 /// Normal file methods
 impl CacheHandle
@@ -406,6 +678,119 @@ impl CacheHandle
 		_ => Err( super::Error::Unknown("Calling read on non-file") ),
 		}
 	}
+	/// Write data to the file, extending it if required
+	///
+	/// Writes past the end of the file zero-fill the gap.
+	pub fn write(&self, ofs: u64, src: &[u8]) -> super::Result<usize> {
+		match self.as_ref()
+		{
+		&CacheNodeInt::File { ref fsnode, ref write_lock, .. } => {
+			let _lh = write_lock.lock();
+			Self::write_locked(&**fsnode, ofs, src)
+			},
+		_ => Err( super::Error::Unknown("Calling write on non-file") ),
+		}
+	}
+	/// Atomically write data to the end of the file
+	pub fn append(&self, src: &[u8]) -> super::Result<usize> {
+		match self.as_ref()
+		{
+		&CacheNodeInt::File { ref fsnode, ref write_lock, .. } => {
+			let _lh = write_lock.lock();
+			Self::write_locked(&**fsnode, fsnode.size(), src)
+			},
+		_ => Err( super::Error::Unknown("Calling append on non-file") ),
+		}
+	}
+	/// Write with the file's write lock held
+	fn write_locked(fsnode: &File, ofs: u64, src: &[u8]) -> super::Result<usize> {
+		let mut size = fsnode.size();
+		// Zero-pad up to the write start
+		if ofs > size {
+			size = try!(fsnode.truncate(ofs));
+			if size < ofs {
+				return Err( super::Error::OutOfSpace );
+			}
+		}
+		
+		let end = ofs + src.len() as u64;
+		if end <= size {
+			return fsnode.write(ofs, src);
+		}
+		// - Write straddles EOF, overwrite the existing data then grow the file
+		//   (`node::File::write` can only grow the file when `ofs == size`)
+		let inner_len = (size - ofs) as usize;
+		let count = if inner_len > 0 {
+				let count = try!(fsnode.write(ofs, &src[..inner_len]));
+				if count < inner_len {
+					return Ok(count);
+				}
+				count
+			}
+			else {
+				0
+			};
+		match fsnode.write(size, &src[inner_len..])
+		{
+		Ok(v) => Ok(count + v),
+		Err(e) =>
+			if count == 0 {
+				Err(e)
+			}
+			else {
+				log_notice!("Error extending file after partial write - {:?}", e);
+				Ok(count)
+			},
+		}
+	}
+
+	/// Acquire an open-mode lock on the file
+	///
+	/// Returns `false` if the mode conflicts with existing handles
+	pub fn file_lock(&self, mode: &FileOpenMode) -> bool {
+		match self.as_ref()
+		{
+		&CacheNodeInt::File { ref locks, .. } => {
+			let mut lh = locks.lock();
+			if lh.compatible(mode) {
+				*lh.counter(mode) += 1;
+				true
+			}
+			else {
+				false
+			}
+			},
+		_ => false,
+		}
+	}
+	/// Add another reference to an already-held open-mode lock (used when cloning handles)
+	pub fn file_lock_ref(&self, mode: &FileOpenMode) {
+		match self.as_ref()
+		{
+		&CacheNodeInt::File { ref locks, .. } => *locks.lock().counter(mode) += 1,
+		_ => panic!("file_lock_ref on non-file"),
+		}
+	}
+	/// Release an open-mode lock acquired using `file_lock` or `file_lock_ref`
+	pub fn file_unlock(&self, mode: &FileOpenMode) {
+		match self.as_ref()
+		{
+		&CacheNodeInt::File { ref locks, .. } => {
+			let mut lh = locks.lock();
+			let c = lh.counter(mode);
+			assert!(*c > 0, "file_unlock({:?}) with no locks held", mode);
+			*c -= 1;
+			},
+		_ => panic!("file_unlock on non-file"),
+		}
+	}
+	pub fn get_page(&self, page: u64) -> Option<::memory::phys::FrameHandle> {
+		match self.as_ref()
+		{
+		&CacheNodeInt::File { ref fsnode, .. } => fsnode.get_page(page),
+		_ => None,
+		}
+	}
 }
 
 
@@ -10,13 +10,23 @@ use metadevs::storage::VolumeHandle;
 use lib::{VecMap,SparseVec};
 use lib::byte_str::{ByteStr,ByteString};
 use lib::mem::aref::{Aref,ArefInner,ArefBorrow};
+use memory::phys::FrameHandle;
+use core::sync::atomic::{AtomicUsize,Ordering};
 
 pub struct Driver;
 pub static S_DRIVER: Driver = Driver;
 
+struct RamNode
+{
+	/// Number of directory entries referencing this node
+	link_count: AtomicUsize,
+	created: ::time::Timestamp,
+	modified: ::sync::atomic::AtomicValue<::time::Timestamp>,
+	file: RamFile,
+}
 enum RamFile
 {
-	//File(RamFileFile),
+	File(RamFileFile),
 	Dir(RamFileDir),
 	Symlink(RamFileSymlink),
 }
@@ -30,13 +40,19 @@ struct RamFileSymlink
 {
 	target: super::PathBuf,
 }
-//#[derive(Default)]
-//struct RamFileFile
-//{
-//	ofs: usize,
-//	size: usize,
-//}
-struct FileRef(ArefBorrow<RamFSInner>,ArefBorrow<RamFile>);
+#[derive(Default)]
+struct RamFileFile
+{
+	data: ::sync::RwLock<RamFileData>,
+}
+#[derive(Default)]
+struct RamFileData
+{
+	size: u64,
+	/// Backing frames (from the physical allocator), `None` is a sparse page that reads as zero
+	pages: Vec<Option<FrameHandle>>,
+}
+struct FileRef(ArefBorrow<RamFSInner>,ArefBorrow<RamNode>,node::InodeId);
 
 struct RamFS
 {
@@ -47,7 +63,12 @@ struct RamFSInner
 	_vh: VolumeHandle,
 	// TODO: Store as much data (and metadata) as possible on the volume
 	// - Possibly by using an allocation pool backed onto the volume
-	nodes: ::sync::Mutex< SparseVec<Aref<RamFile>> >,
+	nodes: ::sync::Mutex< SparseVec<Aref<RamNode>> >,
+	/// Serialises operations that hold two directory locks at once (`rename` and `unlink`)
+	///
+	/// Lock order is `rename_lock`, then directory locks, then `nodes`. Holding this also ensures that a directory
+	/// can't be moved into its own subtree by a racing rename.
+	rename_lock: ::sync::Mutex<()>,
 }
 
 pub fn init()
@@ -68,9 +89,10 @@ impl mount::Driver for Driver
 			inner: unsafe { ArefInner::new( RamFSInner {
 				_vh: vol,
 				nodes: Default::default(),
+				rename_lock: Default::default(),
 				}) },
 			});
-		let root_inode = rv.inner.nodes.lock().insert( Aref::new(RamFile::Dir(Default::default())) );
+		let root_inode = rv.inner.nodes.lock().insert( Aref::new(RamNode::new(RamFile::Dir(Default::default()))) );
 		assert_eq!(root_inode, 0);
 		Ok(rv)
 	}
@@ -84,48 +106,139 @@ impl mount::Filesystem for RamFS
 	fn get_node_by_inode(&self, id: node::InodeId) -> Option<node::Node> {
 		log_trace!("RamFS::get_node_by_inode({})", id);
 		let nodes = self.inner.nodes.lock();
-		if id >= nodes.len() as node::InodeId {
+		match nodes.get(id as usize)
+		{
+		None => {
 			log_log!("RamFile::get_node_by_inode - Inode {} out of range", id);
 			None
-		}
-		else {
+			},
+		Some(n) => {
 			let fr = Box::new(FileRef(
 				self.inner.borrow(),
-				nodes[id as usize].borrow()
+				n.borrow(),
+				id
 				));
-			match *nodes[id as usize]
+			match n.file
 			{
 			RamFile::Dir(_) => Some(node::Node::Dir(fr)),
 			RamFile::Symlink(_) => Some(node::Node::Symlink(fr)),
-			//RamFile::File(_) => todo!("normal files"),
+			RamFile::File(_) => Some(node::Node::File(fr)),
 			}
+			},
+		}
+	}
+	fn release_inode(&self, id: node::InodeId) {
+		self.inner.release_orphan(id as usize);
+	}
+}
+
+impl RamFSInner {
+	/// Returns true if `node` is `ancestor`, or is within its subtree
+	fn is_ancestor(&self, ancestor: usize, node: usize) -> bool {
+		// Iterative walk of the subtree (a recursive one could overflow the kernel stack on a deep tree)
+		let mut to_visit = vec![ancestor];
+		while let Some(inode) = to_visit.pop()
+		{
+			if inode == node {
+				return true;
+			}
+			// NOTE: The node list lock is released before the directory is read (directory locks come first)
+			let n = match self.nodes.lock().get(inode)
+				{
+				Some(n) => n.borrow(),
+				None => continue,
+				};
+			if let RamFile::Dir(ref d) = n.file {
+				to_visit.extend( d.ents.read().iter().map(|(_,&i)| i) );
+			}
+		}
+		false
+	}
+	/// Free an unlinked node, if nothing else is referencing it
+	fn release_orphan(&self, inode: usize) {
+		let mut nodes = self.nodes.lock();
+		let unlinked = match nodes.get(inode)
+			{
+			Some(n) => n.link_count.load(Ordering::SeqCst) == 0,
+			None => false,
+			};
+		if !unlinked {
+			return ;
+		}
+		if Aref::get_mut(&mut nodes[inode]).is_some() {
+			// - Dropping the node frees its data pages
+			nodes.remove(inode);
+		}
+		else {
+			log_debug!("RamFS - Inode {} unlinked while open, released once closed", inode);
 		}
 	}
 }
 
+impl RamNode {
+	fn new(file: RamFile) -> RamNode {
+		let now = ::time::realtime();
+		RamNode {
+			link_count: AtomicUsize::new(1),
+			created: now,
+			modified: ::sync::atomic::AtomicValue::new(now),
+			file: file,
+		}
+	}
+	/// Update the modification time
+	fn touch(&self) {
+		self.modified.store(::time::realtime(), Ordering::Relaxed);
+	}
+}
+
 impl FileRef {
 	fn dir(&self) -> &RamFileDir {
-		match &*self.1
+		match self.1.file
 		{
-		&RamFile::Dir(ref e) => e,
+		RamFile::Dir(ref e) => e,
 		_ => panic!("Called FileRef::dir() on non-dir"),
 		}
 	}
 	fn symlink(&self) -> &RamFileSymlink {
-		match &*self.1
+		match self.1.file
 		{
-		&RamFile::Symlink(ref e) => e,
+		RamFile::Symlink(ref e) => e,
 		_ => panic!("Called FileRef::symlink() on non-symlink"),
 		}
 	}
+	fn file(&self) -> &RamFileFile {
+		match self.1.file
+		{
+		RamFile::File(ref e) => e,
+		_ => panic!("Called FileRef::file() on non-file"),
+		}
+	}
 }
 impl node::NodeBase for FileRef {
 	fn get_id(&self) -> node::InodeId {
-		unimplemented!()
+		self.2
 	}
 	fn get_any(&self) -> &::core::any::Any {
 		self
 	}
+	fn get_metadata(&self) -> node::Metadata {
+		let modified = self.1.modified.load(Ordering::Relaxed);
+		node::Metadata {
+			size: match self.1.file
+				{
+				RamFile::File(ref f) => f.data.read().size,
+				RamFile::Symlink(ref l) => AsRef::<[u8]>::as_ref(&*l.target).len() as u64,
+				RamFile::Dir(_) => 0,
+				},
+			mode: 0o777,
+			link_count: self.1.link_count.load(Ordering::Relaxed) as u32,
+			created: self.1.created,
+			modified: modified,
+			// NOTE: Access times aren't tracked
+			accessed: modified,
+			..Default::default()
+		}
+	}
 }
 impl node::Dir for FileRef {
 	fn lookup(&self, name: &ByteStr) -> vfs::Result<node::InodeId> {
@@ -161,23 +274,251 @@ impl node::Dir for FileRef {
 			let nn = match nodetype
 				{
 				node::NodeType::Dir  => RamFile::Dir (Default::default()),
-				//node::NodeType::File => RamFile::File(Default::default()),
-				node::NodeType::File => return Err(vfs::Error::Unknown("TODO: Files")),
+				node::NodeType::File => RamFile::File(Default::default()),
 				node::NodeType::Symlink(v) =>
 					RamFile::Symlink(RamFileSymlink{target: From::from(v)}),
 				};
-			let inode = self.0.nodes.lock().insert( Aref::new(nn) );
+			let inode = self.0.nodes.lock().insert( Aref::new(RamNode::new(nn)) );
 			e.insert(inode);
+			self.1.touch();
 			Ok(inode as node::InodeId)
 			},
 		}
 	}
 	fn link(&self, name: &ByteStr, node: &node::NodeBase) -> vfs::Result<()> {
-		todo!("<FileRef as Dir>::link({:?}, inode={})", name, node.get_id())
+		use lib::vec_map::Entry;
+		// - The target must be a node on this filesystem
+		let other = match node.get_any().downcast_ref::<FileRef>()
+			{
+			Some(v) if &*v.0 as *const _ == &*self.0 as *const _ => v,
+			_ => return Err(vfs::Error::InvalidParameter),
+			};
+		// - Directories can't be hard linked (would allow loops)
+		if let RamFile::Dir(_) = other.1.file {
+			return Err(vfs::Error::TypeMismatch);
+		}
+		let mut lh = self.dir().ents.write();
+		match lh.entry(From::from(name))
+		{
+		Entry::Occupied(_) => Err(vfs::Error::AlreadyExists),
+		Entry::Vacant(e) => {
+			other.1.link_count.fetch_add(1, Ordering::SeqCst);
+			e.insert(other.2 as usize);
+			self.1.touch();
+			Ok( () )
+			},
+		}
 	}
 	fn unlink(&self, name: &ByteStr) -> vfs::Result<()> {
-		todo!("<FileRef as Dir>::unlink({:?})", name)
+		// - Removing a directory locks both it and its parent, so is serialised with `rename`
+		let _rename_lh = self.0.rename_lock.lock();
+		let mut lh = self.dir().ents.write();
+		let inode = match lh.get(name)
+			{
+			Some(&v) => v,
+			None => return Err(vfs::Error::NotFound),
+			};
+		let is_last = {
+			let node = self.0.nodes.lock().get(inode).expect("RamFS - Directory entry for missing inode").borrow();
+			// - Hold the directory's lock until the entry is gone, so nothing can be created in it meanwhile
+			let _child_lh = match node.file
+				{
+				RamFile::Dir(ref d) => {
+					let child_lh = d.ents.read();
+					if child_lh.iter().next().is_some() {
+						return Err(vfs::Error::NotEmpty);
+					}
+					Some(child_lh)
+					},
+				_ => None,
+				};
+			lh.remove(&ByteString::from(name));
+			node.link_count.fetch_sub(1, Ordering::SeqCst) == 1
+			};
+		self.1.touch();
+		// Only release the node if nothing else has it open, otherwise it's released by `release_inode` once closed
+		if is_last {
+			self.0.release_orphan(inode);
+		}
+		Ok( () )
+	}
+	fn rename(&self, old_name: &ByteStr, dest: &node::Dir, new_name: &ByteStr) -> vfs::Result<()> {
+		let dest = match dest.get_any().downcast_ref::<FileRef>()
+			{
+			Some(v) if &*v.0 as *const _ == &*self.0 as *const _ => v,
+			_ => return Err(vfs::Error::InvalidParameter),
+			};
+		let _rename_lh = self.0.rename_lock.lock();
+		let inode = match self.dir().ents.read().get(old_name)
+			{
+			Some(&v) => v,
+			None => return Err(vfs::Error::NotFound),
+			};
+		// - Moving a directory into itself would disconnect it from the tree
+		if self.0.is_ancestor(inode, dest.2 as usize) {
+			return Err(vfs::Error::InvalidParameter);
+		}
+
+		if dest.2 == self.2 {
+			let mut lh = self.dir().ents.write();
+			if lh.get(new_name).is_some() {
+				return Err(vfs::Error::AlreadyExists);
+			}
+			lh.remove(&ByteString::from(old_name));
+			lh.insert(From::from(new_name), inode);
+		}
+		else {
+			// NOTE: Holding two directory locks is safe, as all such paths are serialised by `rename_lock`
+			let mut src_lh = self.dir().ents.write();
+			let mut dst_lh = dest.dir().ents.write();
+			if dst_lh.get(new_name).is_some() {
+				return Err(vfs::Error::AlreadyExists);
+			}
+			src_lh.remove(&ByteString::from(old_name));
+			dst_lh.insert(From::from(new_name), inode);
+			dest.1.touch();
+		}
+		self.1.touch();
+		Ok( () )
+	}
+}
+impl node::File for FileRef {
+	fn size(&self) -> u64 {
+		self.file().data.read().size
+	}
+	fn truncate(&self, newsize: u64) -> vfs::Result<u64> {
+		let mut lh = self.file().data.write();
+		if newsize < lh.size {
+			let n_pages = ((newsize + ::PAGE_SIZE as u64 - 1) / ::PAGE_SIZE as u64) as usize;
+			lh.pages.truncate(n_pages);
+			// Clear the tail of the final page, so re-extending reads zeroes
+			let tail = (newsize % ::PAGE_SIZE as u64) as usize;
+			if tail != 0 {
+				if let Some(&Some(ref frame)) = lh.pages.last() {
+					// SAFE: Frame is owned by this file, and the data lock is held
+					unsafe { ::memory::virt::with_temp(frame.addr(), |p| for b in p[tail..].iter_mut() { *b = 0 }) }
+				}
+			}
+		}
+		// NOTE: Extending just updates the size, new pages are sparse until written
+		lh.size = newsize;
+		self.1.touch();
+		Ok(newsize)
+	}
+	fn clear(&self, ofs: u64, size: u64) -> vfs::Result<()> {
+		let mut lh = self.file().data.write();
+		if ofs > lh.size || lh.size - ofs < size {
+			return Err(vfs::Error::InvalidParameter);
+		}
+		let mut pos = ofs;
+		while pos < ofs + size
+		{
+			let page = (pos / ::PAGE_SIZE as u64) as usize;
+			let pofs = (pos % ::PAGE_SIZE as u64) as usize;
+			let len = ::core::cmp::min(::PAGE_SIZE - pofs, (ofs + size - pos) as usize);
+			if page < lh.pages.len() {
+				if len == ::PAGE_SIZE {
+					// Full page, release the frame
+					lh.pages[page] = None;
+				}
+				else if let Some(ref frame) = lh.pages[page] {
+					// SAFE: Frame is owned by this file, and the data lock is held
+					unsafe { ::memory::virt::with_temp(frame.addr(), |p| for b in p[pofs .. pofs + len].iter_mut() { *b = 0 }) }
+				}
+			}
+			pos += len as u64;
+		}
+		self.1.touch();
+		Ok( () )
+	}
+	fn read(&self, ofs: u64, buf: &mut [u8]) -> vfs::Result<usize> {
+		let lh = self.file().data.read();
+		if ofs >= lh.size {
+			return Ok(0);
+		}
+		let len = ::core::cmp::min(buf.len() as u64, lh.size - ofs) as usize;
+		let mut pos = 0;
+		while pos < len
+		{
+			let cur = ofs + pos as u64;
+			let page = (cur / ::PAGE_SIZE as u64) as usize;
+			let pofs = (cur % ::PAGE_SIZE as u64) as usize;
+			let n = ::core::cmp::min(::PAGE_SIZE - pofs, len - pos);
+			let dst = &mut buf[pos .. pos + n];
+			match lh.pages.get(page)
+			{
+			// SAFE: Frame is owned by this file, and the data lock is held
+			Some(&Some(ref frame)) => unsafe { ::memory::virt::with_temp(frame.addr(), |p| dst.clone_from_slice(&p[pofs .. pofs + n])) },
+			_ => for b in dst.iter_mut() { *b = 0 },
+			}
+			pos += n;
+		}
+		Ok(len)
+	}
+	fn write(&self, ofs: u64, buf: &[u8]) -> vfs::Result<usize> {
+		let mut lh = self.file().data.write();
+		if ofs > lh.size {
+			return Err(vfs::Error::InvalidParameter);
+		}
+		let mut pos = 0;
+		while pos < buf.len()
+		{
+			let cur = ofs + pos as u64;
+			let page = (cur / ::PAGE_SIZE as u64) as usize;
+			let pofs = (cur % ::PAGE_SIZE as u64) as usize;
+			let n = ::core::cmp::min(::PAGE_SIZE - pofs, buf.len() - pos);
+			let src = &buf[pos .. pos + n];
+			let frame = match get_frame(&mut lh, page)
+				{
+				Ok(v) => v,
+				// Report a partial write if some data was written
+				Err(_) if pos > 0 => break,
+				Err(e) => return Err(e),
+				};
+			// SAFE: Frame is owned by this file, and the data lock is held
+			unsafe { ::memory::virt::with_temp(frame, |p| p[pofs .. pofs + n].clone_from_slice(src)) }
+			pos += n;
+			if cur + n as u64 > lh.size {
+				lh.size = cur + n as u64;
+			}
+		}
+		if pos > 0 {
+			self.1.touch();
+		}
+		Ok(pos)
+	}
+	fn get_page(&self, page: u64) -> Option<FrameHandle> {
+		let mut lh = self.file().data.write();
+		if page >= (lh.size + ::PAGE_SIZE as u64 - 1) / ::PAGE_SIZE as u64 {
+			return None;
+		}
+		match get_frame(&mut lh, page as usize)
+		{
+		// SAFE: Frame is valid and owned by the file, adding a new reference
+		Ok(addr) => Some( unsafe { FrameHandle::from_addr(addr) } ),
+		Err(_) => None,
+		}
+	}
+}
+
+/// Get the frame backing a page of file data, allocating a zeroed frame if the page is sparse
+fn get_frame(data: &mut RamFileData, page: usize) -> vfs::Result<::memory::PAddr>
+{
+	while data.pages.len() <= page {
+		data.pages.push(None);
+	}
+	if data.pages[page].is_none() {
+		let mut fp = match ::memory::virt::alloc_free()
+			{
+			Ok(v) => v,
+			Err(_) => return Err(vfs::Error::OutOfMemory),
+			};
+		for b in fp.iter_mut() {
+			*b = 0;
+		}
+		data.pages[page] = Some(fp.into_frame());
 	}
+	Ok( data.pages[page].as_ref().unwrap().addr() )
 }
 impl node::Symlink for FileRef {
 	fn read(&self) -> ByteString {
@@ -58,6 +58,7 @@ struct CachedBlock
 
 
 static S_BLOCK_CACHE: LazyMutex<Cache> = LazyMutex::new();
+static S_RECLAIM: CacheReclaim = CacheReclaim;
 //static S_BLOCK_CACHE: Mutex<Cache> = Mutex::new(Cache {
 //	map: ::kernel::lib::VecMap::new(),
 //	});
@@ -110,7 +111,10 @@ impl CacheHandle
 		let cache_block = block - block % self.blocks_per_page();
 		let handle = {
 			use kernel::lib::vec_map::Entry;
-			let mut lh = S_BLOCK_CACHE.lock_init(|| Default::default());
+			let mut lh = S_BLOCK_CACHE.lock_init(|| {
+				::kernel::memory::pressure::register(&S_RECLAIM);
+				Default::default()
+				});
 			let handle = match lh.map.entry( (self.vh.idx(), cache_block) )
 				{
 				Entry::Occupied(v) => v.into_mut().borrow(),
@@ -184,6 +188,37 @@ impl CacheHandle
 	}
 }
 
+/// Memory pressure hook, releases unreferenced (and clean) blocks
+struct CacheReclaim;
+impl ::kernel::memory::pressure::Reclaimable for CacheReclaim
+{
+	fn name(&self) -> &str {
+		"block_cache"
+	}
+	fn reclaim(&self, target: usize) -> usize
+	{
+		// NOTE: Can't block, as the cache is locked while creating new entries (which allocates)
+		let mut lh = match S_BLOCK_CACHE.try_lock()
+			{
+			Some(v) => v,
+			None => return 0,
+			};
+		// TODO: Release least-recently-used blocks first (using `last_access`)
+		let mut released = 0;
+		lh.map.retain(|_, block| {
+			// - Handles are only created with the cache locked, so a zero count can't change here
+			if released >= target || block.reference_count.load(Ordering::Acquire) != 0 || block.is_dirty.load(Ordering::Relaxed) {
+				true
+			}
+			else {
+				released += 1;
+				false
+			}
+			});
+		released
+	}
+}
+
 fn map_cached_frame(frame: &::kernel::memory::phys::FrameHandle) -> ::kernel::memory::page_cache::CachedPage
 {
 	// TODO: If this returns that there's no free mappings, go and steal one from within the cache
@@ -263,15 +298,10 @@ impl<'a> ::core::ops::Drop for MetaBlockHandle<'a>
 {
 	fn drop(&mut self)
 	{
-		// TODO: Clearing this when the refcount reaches zero will lead to a lot of mapping/unmapping
+		// NOTE: Unreferenced blocks are only released under memory pressure (see `CacheReclaim`)
+		// TODO: Clearing the mapping when the refcount reaches zero will lead to a lot of mapping/unmapping
 		// - Maybe keep a local queue or run a purge every now and then. Using a LRU list could work.
-		/*
-		if self.0.reference_count.fetch_sub(1, Ordering::Release) == 1
-		{
-			let mut wh = self.0.mapping.write();
-			*wh = None;
-		}
-		*/
+		self.0.reference_count.fetch_sub(1, Ordering::Release);
 	}
 }
 
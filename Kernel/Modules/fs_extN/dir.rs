@@ -114,6 +114,9 @@ impl vfs::node::NodeBase for Dir
 	fn get_any(&self) -> &::core::any::Any {
 		self
 	}
+	fn get_metadata(&self) -> vfs::node::Metadata {
+		self.inode.get_metadata()
+	}
 }
 impl vfs::node::Dir for Dir
 {
@@ -33,6 +33,9 @@ impl vfs::node::NodeBase for File
 	fn get_any(&self) -> &::core::any::Any {
 		self
 	}
+	fn get_metadata(&self) -> vfs::node::Metadata {
+		self.inode.get_metadata()
+	}
 }
 impl vfs::node::File for File
 {
@@ -66,6 +66,29 @@ impl Inode
 	pub fn i_size(&self) -> u64 {
 		self.ondisk.i_size as u64
 	}
+
+	/// Build VFS metadata from the on-disk inode
+	pub fn get_metadata(&self) -> vfs::node::Metadata {
+		// Timestamps are seconds since the UNIX epoch
+		fn ts(v: u32) -> ::kernel::time::Timestamp {
+			v as ::kernel::time::Timestamp * 1000
+		}
+		let od = &self.ondisk;
+		// Linux stores the high 16 bits of the UID/GID in the OS-dependent area
+		let uid_hi = od._osd2[1] & 0xFFFF;
+		let gid_hi = od._osd2[1] >> 16;
+		vfs::node::Metadata {
+			size: self.i_size(),
+			mode: od.i_mode & !::ondisk::S_IFMT,
+			uid: od.i_uid as u32 | uid_hi << 16,
+			gid: od.i_gid as u32 | gid_hi << 16,
+			link_count: od.i_links_count as u32,
+			// NOTE: i_ctime is the inode change time, but is the closest that the base inode has
+			created: ts(od.i_ctime),
+			modified: ts(od.i_mtime),
+			accessed: ts(od.i_atime),
+		}
+	}
 }
 
 impl Inode
@@ -17,6 +17,8 @@ pub struct DirNode
 	fs: ArefBorrow<::FilesystemInner>,
 	start_cluster: u32,
 	// - Uses the cluster chain
+	/// Metadata from the parent's directory entry
+	meta: node::Metadata,
 }
 impl_fmt! {
 	Debug(self, f) for DirNode {
@@ -29,11 +31,16 @@ impl DirNode {
 		DirNode {
 			fs: fs,
 			start_cluster: start_cluster,
+			// - The root has no directory entry to get metadata from
+			meta: node::Metadata { mode: 0o777, link_count: 1, ..Default::default() },
 		}
 	}
 	pub fn new_boxed(fs: ArefBorrow<FilesystemInner>, start_cluster: u32) -> Box<DirNode> {
 		Box::new(Self::new(fs, start_cluster))
 	}
+	fn new_boxed_meta(fs: ArefBorrow<FilesystemInner>, start_cluster: u32, meta: node::Metadata) -> Box<DirNode> {
+		Box::new(DirNode { meta: meta, ..Self::new(fs, start_cluster) })
+	}
 }
 
 impl node::NodeBase for DirNode {
@@ -43,6 +50,9 @@ impl node::NodeBase for DirNode {
 	fn get_any(&self) -> &::core::any::Any {
 		self
 	}
+	fn get_metadata(&self) -> node::Metadata {
+		self.meta.clone()
+	}
 }
 
 impl DirNode {
@@ -67,14 +77,14 @@ impl DirNode {
 		None => None,
 		Some(e) =>
 			if e.attributes & on_disk::ATTR_DIRECTORY != 0 {
-				Some(node::Node::Dir(DirNode::new_boxed(self.fs.reborrow(), ent_cluster)))
+				Some(node::Node::Dir(DirNode::new_boxed_meta(self.fs.reborrow(), ent_cluster, e.metadata())))
 			}
 			else if e.attributes & on_disk::ATTR_VOLUMEID != 0 {
 				None
 			}
 			else {
 				Some(node::Node::File(FileNode::new_boxed(
-					self.fs.reborrow(), self.start_cluster, ent_cluster, e.size
+					self.fs.reborrow(), self.start_cluster, ent_cluster, e.size, e.metadata()
 					)))
 			},
 		}
@@ -121,9 +131,9 @@ struct DirEntShort {
 	cluster: u32,
 	size: u32,
 	attributes: u8,
-	//creation_time: ::kernel::time::Timestamp,
-	//modified_time: ::kernel::time::Timestamp,
-	//accessed_time: ::kernel::time::Timestamp,
+	creation_time: ::kernel::time::Timestamp,
+	modified_time: ::kernel::time::Timestamp,
+	accessed_time: ::kernel::time::Timestamp,
 }
 impl_fmt! {
 	Debug(self,f) for DirEntShort {
@@ -231,12 +241,46 @@ impl<'a> ::core::iter::Iterator for DirEnts<'a> {
 					cluster: (ent.cluster as u32) | (ent.cluster_hi as u32) << 16,
 					size: ent.size,
 					attributes: ent.attribs,
+					creation_time: decode_time(ent.creation_date, ent.creation_time) + ent.creation_ds as i64 * 10,
+					modified_time: decode_time(ent.modified_date, ent.modified_time),
+					accessed_time: decode_time(ent.accessed_date, 0),
 					}) )
 			}
 		}
 	}
 }
+/// Decode a FAT date/time pair into a timestamp
+///
+/// NOTE: FAT stores local time, which is treated as UTC
+fn decode_time(date: u16, time: u16) -> ::kernel::time::Timestamp {
+	if date == 0 {
+		0
+	}
+	else {
+		::kernel::time::timestamp_from_date(
+			1980 + (date >> 9) as u32, ((date >> 5) & 0xF) as u8, (date & 0x1F) as u8,
+			(time >> 11) as u8, ((time >> 5) & 0x3F) as u8, ((time & 0x1F) * 2) as u8
+			)
+	}
+}
+
 impl DirEntShort {
+	/// Build VFS metadata from this entry
+	fn metadata(&self) -> node::Metadata {
+		// FAT has no ownership, just a read-only flag
+		let is_dir = self.attributes & on_disk::ATTR_DIRECTORY != 0;
+		let mode = if is_dir { 0o777 } else { 0o666 };
+		let mode = if self.attributes & on_disk::ATTR_READONLY != 0 { mode & !0o222 } else { mode };
+		node::Metadata {
+			size: if is_dir { 0 } else { self.size as u64 },
+			mode: mode,
+			link_count: 1,
+			created: self.creation_time,
+			modified: self.modified_time,
+			accessed: self.accessed_time,
+			..Default::default()
+		}
+	}
 	fn name(&self) -> &ByteStr {
 		ByteStr::new( (&self.name).split(|&e|e==0).next().unwrap() )
 	}
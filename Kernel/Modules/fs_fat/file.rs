@@ -15,16 +15,19 @@ pub struct FileNode
 	//parent_dir: u32,
 	first_cluster: u32,
 	size: u32,
+	/// Metadata from the directory entry
+	meta: node::Metadata,
 }
 
 impl FileNode
 {
-	pub fn new_boxed(fs: ArefBorrow<FilesystemInner>, _parent: u32, first_cluster: u32, size: u32) -> Box<FileNode> {	
+	pub fn new_boxed(fs: ArefBorrow<FilesystemInner>, _parent: u32, first_cluster: u32, size: u32, meta: node::Metadata) -> Box<FileNode> {	
 		Box::new(FileNode {
 			fs: fs,
 			//parent_dir: parent,
 			first_cluster: first_cluster,
 			size: size,
+			meta: meta,
 			})
 	}
 }
@@ -35,6 +38,9 @@ impl node::NodeBase for FileNode {
 	fn get_any(&self) -> &::core::any::Any {
 		self
 	}
+	fn get_metadata(&self) -> node::Metadata {
+		node::Metadata { size: self.size as u64, ..self.meta.clone() }
+	}
 }
 impl node::File for FileNode {
 	fn size(&self) -> u64 {
@@ -36,6 +36,7 @@ struct InstanceInner
 	lb_size: usize,
 	root_lba: u32,
 	root_size: u32,
+	root_meta: node::Metadata,
 
 	susp_len_skip: Option<u8>,
 }
@@ -107,6 +108,13 @@ impl mount::Driver for Driver
 			lb_size: lb_size as usize,
 			root_lba: root_lba,
 			root_size: root_size,
+			root_meta: node::Metadata {
+				mode: 0o555,
+				link_count: 1,
+				created: decode_datetime(&block[156+18..][..7]),
+				modified: decode_datetime(&block[156+18..][..7]),
+				..Default::default()
+				},
 			susp_len_skip: None,
 			};
 
@@ -138,7 +146,7 @@ impl mount::Filesystem for Instance
 	}
 	fn get_node_by_inode(&self, id: node::InodeId) -> Option<node::Node> {
 		if id == 0 {
-			Some(Dir::new_node(self.0.borrow(), self.root_lba, self.root_size) )
+			Some(Dir::new_node(self.0.borrow(), self.root_lba, self.root_size, self.root_meta.clone()) )
 		}
 		else {
 			// Look up (or read) parent directory to obtain the info
@@ -164,13 +172,13 @@ impl mount::Filesystem for Instance
 					None
 				}
 				else if ent.flags & (1 << 1) != 0 {
-					Some(Dir::new_node(self.0.borrow(), ent.start, ent.size))
+					Some(Dir::new_node(self.0.borrow(), ent.start, ent.size, ent.meta))
 				}
 				else if ent.flags & 0x64 != 0 {
 					None
 				}
 				else {
-					Some(File::new_node(self.0.borrow(), ent.start, ent.size))
+					Some(File::new_node(self.0.borrow(), ent.start, ent.size, ent.meta))
 				}
 			}
 		}
@@ -211,14 +219,16 @@ struct File
 	fs: ArefBorrow<InstanceInner>,
 	first_lba: u32,
 	size: u32,
+	meta: node::Metadata,
 }
 impl File
 {
-	fn new_node(fs: ArefBorrow<InstanceInner>, first_lba: u32, size: u32) -> node::Node {
+	fn new_node(fs: ArefBorrow<InstanceInner>, first_lba: u32, size: u32, meta: node::Metadata) -> node::Node {
 		node::Node::File( Box::new( File {
 			fs: fs,
 			first_lba: first_lba,
 			size: size,
+			meta: meta,
 			} ) )
 	}
 }
@@ -230,6 +240,9 @@ impl node::NodeBase for File
 	fn get_any(&self) -> &::core::any::Any {
 		self
 	}
+	fn get_metadata(&self) -> node::Metadata {
+		self.meta.clone()
+	}
 }
 impl node::File for File
 {
@@ -300,14 +313,16 @@ struct Dir
 	fs: ArefBorrow<InstanceInner>,
 	first_lba: u32,
 	size: u32,
+	meta: node::Metadata,
 }
 impl Dir
 {
-	fn new_node(fs: ArefBorrow<InstanceInner>, first_lba: u32, size: u32) -> node::Node {
+	fn new_node(fs: ArefBorrow<InstanceInner>, first_lba: u32, size: u32, meta: node::Metadata) -> node::Node {
 		node::Node::Dir( Box::new( Dir {
 			fs: fs,
 			first_lba: first_lba,
 			size: size,
+			meta: meta,
 			} ) )
 	}
 }
@@ -319,6 +334,9 @@ impl node::NodeBase for Dir
 	fn get_any(&self) -> &::core::any::Any {
 		self
 	}
+	fn get_metadata(&self) -> node::Metadata {
+		self.meta.clone()
+	}
 }
 impl node::Dir for Dir
 {
@@ -397,6 +415,8 @@ struct DirEnt<'a>
 	size: u32,
 	name: &'a [u8],
 	sys_use: &'a [u8],
+	/// Metadata from the record (and RockRidge extensions)
+	meta: node::Metadata,
 }
 impl<'a> ::core::fmt::Debug for DirEnt<'a> {
 	fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
@@ -410,6 +430,17 @@ impl<'a> DirEnt<'a>
 {
 }
 
+/// Decode a 7-byte directory record date/time into a timestamp
+fn decode_datetime(d: &[u8]) -> ::kernel::time::Timestamp {
+	if d[1] == 0 {
+		// - Month of zero, not recorded
+		return 0;
+	}
+	let local = ::kernel::time::timestamp_from_date(1900 + d[0] as u32, d[1], d[2], d[3], d[4], d[5]);
+	// - Offset from GMT is in 15 minute intervals
+	local - (d[6] as i8) as i64 * 15 * 60 * 1000
+}
+
 struct DirSector<'a> {
 	fs: &'a InstanceInner,
 	data: Sector<'a>,
@@ -462,6 +493,14 @@ impl<'a> DirSector<'a>
 				let su = &ent[33 + namelen ..];
 
 				let mut name = &ent[33..][..namelen];
+				let mut meta = node::Metadata {
+					size: LittleEndian::read_u32(&ent[10..]) as u64,
+					mode: 0o555,
+					link_count: 1,
+					created: decode_datetime(&ent[18..][..7]),
+					modified: decode_datetime(&ent[18..][..7]),
+					..Default::default()
+					};
 
 				if let Some(skip) = self.fs.susp_len_skip {
 					let skip = skip as usize;
@@ -474,8 +513,16 @@ impl<'a> DirSector<'a>
 					{
 						//log_trace!("ent={:?}", ent);
 						// TODO: Need to handle this _FAR_ better
-						if let SuspItem::AlternateName(0, new_name) = ent {
-							name = new_name;
+						match ent
+						{
+						SuspItem::AlternateName(0, new_name) => name = new_name,
+						SuspItem::PosixMode { mode, n_links, uid, gid, .. } => {
+							meta.mode = (mode & 0o7777) as u16;
+							meta.link_count = n_links;
+							meta.uid = uid;
+							meta.gid = gid;
+							},
+						_ => {},
 						}
 					}
 				}
@@ -488,6 +535,7 @@ impl<'a> DirSector<'a>
 					size: LittleEndian::read_u32(&ent[10..]),
 					name: name,
 					sys_use: su,
+					meta: meta,
 					}))
 			}
 		}
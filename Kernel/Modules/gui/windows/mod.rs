@@ -166,6 +166,8 @@ pub fn switch_active(new: usize)
 fn render_thread()
 {
 	log_debug!("GUI Render Thread started");
+	// Keep the display responsive while userland is busy
+	::kernel::threads::set_priority(::kernel::threads::Priority::Realtime);
 	loop
 	{
 		// Wait for a signal to start a render
@@ -162,7 +162,18 @@ impl<'a> PacketReader<'a> {
 pub trait Interface: 'static + Send + Sync
 {
 	/// Transmit a raw packet (blocking)
-	fn tx_raw(&self, pkt: SparsePacket);
+	fn tx_raw(&self, pkt: SparsePacket) {
+		let mut o: async::Object = Default::default();
+		o.get_stack().push_closure(|_, _, v| Some(v)).expect("Insufficient space when pushing closure");
+		match self.tx_async(o.get_handle(), o.get_stack(), pkt)
+		{
+		Ok(_) => {
+			let h = [&o];
+			async::Waiter::new(&h).wait_one();
+			},
+		Err(e) => log_error!("tx_raw: Transmit failed - {:?}", e),
+		}
+	}
 
 	/// The input buffer can be a mix of `> 'stack` and `< 'stack` buffers. This function should collapse shorter lifetime
 	/// buffers into an internal buffer that lives long enough.
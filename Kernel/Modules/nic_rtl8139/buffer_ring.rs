@@ -4,6 +4,7 @@
 //! Container for a ring buffer of pooled objects
 use core::ops;
 use kernel::_async3 as async;
+use kernel::lib::VecDeque;
 
 pub type BufferRing4<V> = BufferRing<[V; 4]>;
 
@@ -18,7 +19,8 @@ unsafe impl<S: Storage + Send> Sync for BufferRing<S> {}
 #[derive(Default)]
 struct Inner
 {
-	wait_queue: ::kernel::threads::WaitQueue,
+	/// Async waiters, signalled with the acquired index when an entry is released
+	async_waiters: VecDeque<async::ObjectHandle>,
 	// Index of next free entry
 	next_free: u16,
 	// Index of first used entry. If equal to next_free, all are free.
@@ -57,28 +59,12 @@ impl<S: Storage> BufferRing<S>
 				})
 		}
 	}
-	/// Aquire with a blocking wait
-	pub fn acquire_wait(&self) -> Handle<S> {
-		let mut lh = self.inner.lock();
-		while (lh.next_free + 1) % S::len() as u16 == lh.first_used {
-			waitqueue_wait_ext!(lh, .wait_queue);
-			lh = self.inner.lock();
-		}
-		
-		let idx = lh.next_free as usize;
-		lh.next_free = (lh.next_free + 1) % S::len() as u16;
-		
-		Handle {
-			bs: self,
-			idx: idx,
-			}
-	}
 	/// Acquire in an async manner
 	pub fn acquire_async(&self, async: async::ObjectHandle, _stack: async::StackPush) {
 		let mut lh = self.inner.lock();
 		if (lh.next_free + 1) % S::len() as u16 == lh.first_used {
-			// TODO: Figure out how to push `async` onto the wait queue
-			panic!("TODO: acquire_async");
+			// Full, the next `release` will allocate an entry for this waiter
+			lh.async_waiters.push_back(async);
 		}
 		else {
 			async.signal( lh.next_free as usize );
@@ -96,6 +82,11 @@ impl<S: Storage> BufferRing<S>
 		}
 	}
 
+	/// Obtain a mutable reference to an entry by index (e.g. to inspect a completed entry before `release`)
+	pub unsafe fn get_mut(&self, index: usize) -> &mut S::Inner {
+		&mut *(*self.data.get()).get(index)
+	}
+
 	/// Get a handle using the id returned by an async operation
 	pub unsafe fn handle_from_async(&self, index: usize) -> Handle<S> {
 		Handle {
@@ -109,8 +100,11 @@ impl<S: Storage> BufferRing<S>
 		assert_eq!(index, lh.first_used as usize);
 		lh.first_used = (lh.first_used + 1) % S::len() as u16;
 		
-		if lh.wait_queue.has_waiter() {
-			lh.wait_queue.wake_one();
+		// Hand the freed space directly to the first async waiter
+		if let Some(async) = lh.async_waiters.pop_front() {
+			let idx = lh.next_free as usize;
+			lh.next_free = (lh.next_free + 1) % S::len() as u16;
+			async.signal(idx);
 		}
 	}
 }
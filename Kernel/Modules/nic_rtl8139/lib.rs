@@ -215,9 +215,14 @@ impl Card
 				}
 				else {
 					// Activated and complete (and now marked as inactive), release it to the pool
+					// SAFE: The card has finished with this descriptor, and it's not yet released
+					let waiter = unsafe { self.tx_slots.get_mut(idx).async.take() };
 					// SAFE: This descriptor can only have been activated if ownership was passed to the card, so it's safe to release.
 					unsafe { self.tx_slots.release(idx); }
-					// TODO: signal waiter.
+					// Signal the async transmit (if any) that it's complete
+					if let Some(async) = waiter {
+						async.signal(0);
+					}
 				}
 				log_trace!("handle_irq: TOK {}", idx);
 			}
@@ -296,22 +301,6 @@ impl Card
 
 impl nic::Interface for Card
 {
-	fn tx_raw(&self, pkt: nic::SparsePacket) {
-		log_trace!("tx_raw()");
-		// 1. Pick a TX buffer (waiting until one is free)
-		let mut buf = self.tx_slots.acquire_wait();
-		// 2. Populate the buffer with the contents of the packet
-		let mut total_len = 0;
-		for span in &pkt {
-			buf.fill(span, total_len).expect("TODO: Error when packet TX overflows buffer");
-			total_len += span.len();
-		}
-		buf.async = None;
-
-		self.start_tx(buf, total_len);
-		// - No need to wait.
-	}
-
 	fn tx_async<'a, 's>(&'s self, async: async::ObjectHandle, mut stack: async::StackPush<'a, 's>, pkt: nic::SparsePacket) -> Result<(), nic::Error> {
 		log_trace!("tx_async()");
 		// If there's an immediately-avaliable slot, take it
@@ -324,6 +313,8 @@ impl nic::Interface for Card
 				total_len += span.len();
 			}
 
+			// Pause until the TX completes (signalled by the IRQ handler)
+			stack.push_closure(|_async, _stack, v| Some(v)).expect("Insufficient space when pushing closure");
 			buf.async = Some(async);
 			self.start_tx(buf, total_len);
 		}
@@ -0,0 +1,91 @@
+// "Tifflin" Kernel - PL031 RTC Driver
+// - By John Hodge (thePowersGang)
+//
+// Modules/rtc_pl031/lib.rs
+//! ARM PrimeCell PL031 real-time clock
+#![no_std]
+#![feature(linkage)]	// for module_define!
+use kernel::prelude::*;
+use kernel::device_manager;
+
+#[macro_use]
+extern crate kernel;
+
+module_define!{rtc_pl031, [DeviceManager], init}
+
+fn init()
+{
+	static FDT_DRIVER: FdtDriver = FdtDriver;
+	device_manager::register_driver(&FDT_DRIVER);
+}
+
+/// Data register (current time, seconds since the epoch)
+const REG_DR: usize = 0x00;
+/// Load register (written to set the time)
+const REG_LR: usize = 0x08;
+/// Control register (bit 0 starts the counter)
+const REG_CR: usize = 0x0C;
+
+struct FdtDriver;
+/// Device manager's handle (the clock itself is owned by the time subsystem)
+struct Instance;
+struct Pl031
+{
+	io: device_manager::IOBinding,
+}
+// SAFE: All register accesses are single 32-bit reads/writes
+unsafe impl Sync for Pl031 {}
+
+impl device_manager::Driver for FdtDriver
+{
+	fn name(&self) -> &str {
+		"pl031"
+	}
+	fn bus_type(&self) -> &str {
+		"fdt"
+	}
+	fn handles(&self, bus_dev: &device_manager::BusDevice) -> u32
+	{
+		// - The compatible list is NUL separated, and usually also lists "arm,primecell"
+		if bus_dev.get_attr("compatible").unwrap_str().starts_with("arm,pl031\0") {
+			1
+		}
+		else {
+			0
+		}
+	}
+	fn bind(&self, bus_dev: &mut device_manager::BusDevice) -> Box<device_manager::DriverInstance+'static>
+	{
+		let io = bus_dev.bind_io(0);
+		// SAFE: Starting the counter has no effect if already running
+		unsafe { io.write_32(REG_CR, 1); }
+		::kernel::time::register_rtc( Box::new(Pl031 { io: io }) );
+		Box::new(Instance)
+	}
+}
+impl device_manager::DriverInstance for Instance
+{
+}
+
+impl ::kernel::time::RtcDevice for Pl031
+{
+	fn name(&self) -> &str { "PL031" }
+	fn read(&self) -> Option<::kernel::time::Timestamp>
+	{
+		// SAFE: Read-only register
+		let secs = unsafe { self.io.read_32(REG_DR) };
+		Some( secs as ::kernel::time::Timestamp * 1000 )
+	}
+	fn write(&self, time: ::kernel::time::Timestamp) -> bool
+	{
+		let secs = time / 1000;
+		if secs < 0 || secs > 0xFFFF_FFFF {
+			return false;
+		}
+		// SAFE: Only changes the RTC's value
+		unsafe { self.io.write_32(REG_LR, secs as u32); }
+		true
+	}
+}
+
+// vim: ft=rust
@@ -3,13 +3,15 @@
 //
 //! 
 use kernel::prelude::*;
-use core::sync::atomic::Ordering;
+use core::sync::atomic::{Ordering,AtomicBool};
 use kernel::sync::atomic::AtomicU32;
-use kernel::sync::Mutex;
+use kernel::sync::{Mutex,Spinlock};
 use kernel::metadevs::storage::{self, DataPtr};
+use kernel::_async3 as async;
 use kernel::memory::virt::AllocHandle;
 use kernel::lib::mem::aref::ArefBorrow;
 use kernel::device_manager;
+use kernel::lib::VecDeque;
 use hw;
 
 enum Error
@@ -30,6 +32,22 @@ impl_fmt! {
 		}
 	}
 }
+impl From<Error> for storage::IoError
+{
+	fn from(v: Error) -> storage::IoError
+	{
+		use storage_scsi::proto::SenseKey;
+		match v
+		{
+		Error::Atapi { sense_key: SenseKey::NotReady, .. } => storage::IoError::NoMedium,
+		Error::Atapi { sense_key: SenseKey::IllegalRequest, .. } => storage::IoError::InvalidParameter,
+		Error::Atapi { sense_key: SenseKey::MediumError, .. } => storage::IoError::BadBlock,
+		Error::Atapi { .. } => storage::IoError::Unknown("ATAPI"),
+		Error::Ata { .. } => storage::IoError::Unknown("ATA"),
+		Error::Bus => storage::IoError::Unknown("AHCI Bus"),
+		}
+	}
+}
 
 pub struct Port
 {
@@ -47,11 +65,27 @@ pub struct Port
 	command_list_alloc: AllocHandle,
 	command_tables: [AllocHandle; 4],
 
-	command_events: Vec<::kernel::sync::EventChannel>,
+	/// Async objects waiting on each command slot (set while the command is issued to the device)
+	command_async: Spinlock<Vec<Option<async::ObjectHandle>>>,
+	/// Commands waiting for a free slot (started by the IRQ handler as slots are released)
+	pending_commands: Spinlock<VecDeque<PendingCommand>>,
+	/// Set when the port is being torn down, new commands fail with `NoMedium`
+	removed: AtomicBool,
 
-	used_commands_sem: ::kernel::sync::Semaphore,
 	used_commands: AtomicU32,
 }
+/// A FIS queued because all command slots were in use
+struct PendingCommand
+{
+	async: async::ObjectHandle,
+	cmd: Vec<u8>,
+	pkt: Vec<u8>,
+	data_ptr: *const u8,
+	data_len: usize,
+	is_send: bool,
+}
+// SAFE: The data pointer is only used to build the PRDT, and the caller keeps it valid until `async` is signalled
+unsafe impl Send for PendingCommand {}
 pub struct PortRegs<'a>
 {
 	idx: usize,
@@ -139,8 +173,9 @@ impl Port
 			command_list_alloc: cl_page,
 			command_tables: cmdtab_pages,
 
-			command_events: (0 .. max_commands).map(|_| ::kernel::sync::EventChannel::new()).collect(),
-			used_commands_sem: ::kernel::sync::Semaphore::new(max_commands as isize, max_commands as isize),
+			command_async: Spinlock::new( (0 .. max_commands).map(|_| None).collect() ),
+			pending_commands: Spinlock::new(VecDeque::new()),
+			removed: AtomicBool::new(false),
 			used_commands: AtomicU32::new(0),
 			})
 	}
@@ -231,23 +266,32 @@ impl Port
 		}
 
 		// Check commands
-		//if int_status & hw::PxIS_DPS != 0
-		//{
+		// - Locked so commands can't be started between reading the registers and checking the handles
+		let mut async_lh = self.command_async.lock();
 		let issued_commands = regs.read(hw::REG_PxCI);
 		let active_commands = regs.read(hw::REG_PxSACT);
-		let used_commands = self.used_commands.load(Ordering::Relaxed);
-		//log_trace!("{} - used_commands = {:#x}, issued_commands={:#x}, active_commands={:#x}",
-		//	self, used_commands, issued_commands, active_commands);
+		//log_trace!("{} - issued_commands={:#x}, active_commands={:#x}", self, issued_commands, active_commands);
 		for cmd in 0 .. self.ctrlr.max_commands as usize
 		{
 			let mask = 1 << cmd;
-			if used_commands & mask != 0
+			if async_lh[cmd].is_some()
 			{
-				if tfd & 0x01 != 0 {
-					self.command_events[cmd].post();
-				}
-				else if issued_commands & mask == 0 || active_commands & mask == 0 {
-					self.command_events[cmd].post();
+				if tfd & 0x01 != 0 || issued_commands & mask == 0 || active_commands & mask == 0 {
+					let h = async_lh[cmd].take().unwrap();
+					let res = {
+						// SAFE: The command has been issued, so this is the slot's owner (and it's released on drop)
+						let slot = unsafe { self.get_slot(cmd) };
+						slot.get_result()
+						};
+					let res = match res
+						{
+						Ok(bytes) => Ok(bytes),
+						Err(e) => {
+							log_warning!("{} - Command {} failed: {:?}", self, cmd, e);
+							Err( From::from(e) )
+							},
+						};
+					h.signal( storage::IoError::encode(res) );
 				}
 				else {
 					// Not yet complete
@@ -259,7 +303,12 @@ impl Port
 			else {
 			}
 		}
-		//}
+		drop(async_lh);
+
+		// Start any commands that were waiting for the slots released above
+		if !self.removed.load(Ordering::Acquire) {
+			self.start_pending();
+		}
 	
 		// SAFE: Exclusive range, only written here
 		unsafe {
@@ -374,10 +423,13 @@ impl Port
 		}
 	}
 
-	fn request_identify(&self, cmd: u8) -> Result<::storage_ata::AtaIdentifyData, Error>
+	fn request_identify(&self, cmd: u8) -> Result<::storage_ata::AtaIdentifyData, storage::IoError>
 	{
 		let mut ata_identify_data = ::storage_ata::AtaIdentifyData::default();
-		try!( self.request_ata_lba28(0, cmd, 0,0, DataPtr::Recv(::kernel::lib::as_byte_slice_mut(&mut ata_identify_data))) );
+		{
+			let data = ::kernel::lib::as_byte_slice_mut(&mut ata_identify_data);
+			try!( storage::wait_io(|async, stack| self.request_ata_lba28(async, stack, 0, cmd, 0,0, DataPtr::Recv(data))) );
+		}
 
 		fn flip_bytes(bytes: &mut [u8]) {
 			for pair in bytes.chunks_mut(2) {
@@ -391,7 +443,7 @@ impl Port
 		Ok( ata_identify_data )
 	}
 
-	fn request_ata_lba28(&self, disk: u8, cmd: u8,  n_sectors: u8, lba: u32, data: DataPtr) -> Result<usize, Error>
+	fn request_ata_lba28<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, disk: u8, cmd: u8,  n_sectors: u8, lba: u32, data: DataPtr<'s>)
 	{
 		log_trace!("request_ata_lba28(disk={}, cmd={:#02x}, n_sectors={}, lba={})", disk, cmd, n_sectors, lba);
 		assert!(lba < (1<<24));
@@ -408,9 +460,9 @@ impl Port
 			sector_count_exp: 0,
 			..Default::default()
 			};
-		self.do_fis(cmd_data.as_ref(), &[], data)
+		self.do_fis(async, stack, cmd_data.as_ref(), &[], data)
 	}
-	fn request_ata_lba48(&self, disk: u8, cmd: u8,  n_sectors: u16, lba: u64, data: DataPtr) -> Result<usize, Error>
+	fn request_ata_lba48<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, disk: u8, cmd: u8,  n_sectors: u16, lba: u64, data: DataPtr<'s>)
 	{
 		log_trace!("request_ata_lba48(disk={}, cmd={:#02x}, n_sectors={}, lba={})", disk, cmd, n_sectors, lba);
 		assert!(lba < (1<<48));
@@ -429,9 +481,9 @@ impl Port
 			sector_count_exp: (n_sectors >> 8) as u8,
 			..Default::default()
 			};
-		self.do_fis(cmd_data.as_ref(), &[], data)
+		self.do_fis(async, stack, cmd_data.as_ref(), &[], data)
 	}
-	fn request_atapi(&self, disk: u8, cmd: &[u8], data: DataPtr) -> Result<(), Error>
+	fn request_atapi<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, disk: u8, cmd: &[u8], data: DataPtr<'s>)
 	{
 		let fis = hw::sata::FisHost2DevReg {
 			ty: hw::sata::FisType::H2DRegister as u8,
@@ -442,29 +494,115 @@ impl Port
 			cyl_high: (data.len() >> 8) as u8,
 			..Default::default()
 			};
-		match self.do_fis(fis.as_ref(), cmd, data)
+		self.do_fis(async, stack, fis.as_ref(), cmd, data)
+	}
+
+	/// Create and dispatch a FIS, `async` is signalled with the number of bytes transferred
+	///
+	/// The command and packet are copied (into the command table, or the pending queue if all slots are in use)
+	/// before this returns.
+	fn do_fis<'a, 's>(&'s self, async: async::ObjectHandle, _stack: async::StackPush<'a, 's>, cmd: &[u8], pkt: &[u8], data: DataPtr<'s>)
+	{
+		//log_trace!("do_fis(self={}, cmd={:p}+{}, pkt={:p}+{}, data={:?})",
+		//	self, cmd.as_ptr(), cmd.len(), pkt.as_ptr(), pkt.len(), data);
+
+		if self.removed.load(Ordering::Acquire) {
+			async.signal( storage::IoError::encode(Err(storage::IoError::NoMedium)) );
+			return ;
+		}
+		match self.try_get_command_slot()
 		{
-		Ok(_) => Ok( () ),
-		Err(e) => Err(e),
+		Some(slot) => self.start_fis(slot, async, cmd, pkt, data.as_slice().as_ptr(), data.len(), data.is_send()),
+		None => {
+			log_trace!("{} - All command slots in use, queueing", self);
+			self.pending_commands.lock().push_back(PendingCommand {
+				async: async,
+				cmd: cmd.to_owned(),
+				pkt: pkt.to_owned(),
+				data_ptr: data.as_slice().as_ptr(),
+				data_len: data.len(),
+				is_send: data.is_send(),
+				});
+			// A slot may have been released between the check and the push, so try again
+			// - Or the port was removed, in which case the queue has to be emptied again
+			if self.removed.load(Ordering::Acquire) {
+				self.fail_pending();
+			}
+			else {
+				self.start_pending();
+			}
+			},
 		}
 	}
 
-	/// Create and dispatch a FIS, returns the number of bytes
-	fn do_fis(&self, cmd: &[u8], pkt: &[u8], data: DataPtr) -> Result<usize, Error>
+	/// Complete all queued commands with `NoMedium` (used when the port is removed)
+	fn fail_pending(&self)
 	{
-		use kernel::memory::virt::get_phys;
+		while let Some(p) = self.pending_commands.lock().pop_front() {
+			p.async.signal( storage::IoError::encode(Err(storage::IoError::NoMedium)) );
+		}
+	}
 
-		//log_trace!("do_fis(self={}, cmd={:p}+{}, pkt={:p}+{}, data={:?})",
-		//	self, cmd.as_ptr(), cmd.len(), pkt.as_ptr(), pkt.len(), data);
+	/// Stop the port and complete all outstanding commands with `NoMedium`, so accesses waiting on them can finish
+	fn fail_outstanding(&self)
+	{
+		self.removed.store(true, Ordering::Release);
+
+		// Stop the command list, so the device can't touch buffers after they're handed back
+		let regs = self.regs();
+		// SAFE: Only clears the start bit, nothing else is started once `removed` is set
+		unsafe {
+			regs.write(hw::REG_PxCMD, regs.read(hw::REG_PxCMD) & !hw::PxCMD_ST);
+		}
+		// - AHCI 1.3 §10.1.2: The HBA clears CR within 500ms
+		let deadline = ::kernel::time::ticks() + 500;
+		while regs.read(hw::REG_PxCMD) & hw::PxCMD_CR != 0 {
+			if ::kernel::time::ticks() > deadline {
+				log_warning!("{} - Command list didn't stop", self);
+				break ;
+			}
+			::kernel::threads::yield_time();
+		}
+
+		{
+			let mut async_lh = self.command_async.lock();
+			// - The slots are left allocated, as the port is going away (and the device may not have released them)
+			for h in async_lh.iter_mut()
+			{
+				if let Some(h) = h.take() {
+					h.signal( storage::IoError::encode(Err(storage::IoError::NoMedium)) );
+				}
+			}
+		}
+		self.fail_pending();
+	}
+
+	/// Start queued commands while there are free slots
+	fn start_pending(&self)
+	{
+		while let Some(slot) = self.try_get_command_slot()
+		{
+			// NOTE: If the queue is empty, the slot is released on drop
+			let p = match self.pending_commands.lock().pop_front()
+				{
+				Some(p) => p,
+				None => break,
+				};
+			self.start_fis(slot, p.async, &p.cmd, &p.pkt, p.data_ptr, p.data_len, p.is_send);
+		}
+	}
 
-		let slot = self.get_command_slot();
+	/// Fill a command slot and issue it to the device
+	fn start_fis(&self, slot: CommandSlot, async: async::ObjectHandle, cmd: &[u8], pkt: &[u8], data_ptr: *const u8, data_len: usize, is_send: bool)
+	{
+		use kernel::memory::virt::get_phys;
 
 		slot.data.cmd_fis[..cmd.len()].clone_from_slice(cmd);
 		slot.data.atapi_cmd[..pkt.len()].clone_from_slice(pkt);
 
 		// Generate the scatter-gather list
-		let mut va = data.as_slice().as_ptr() as usize;
-		let mut len = data.as_slice().len();
+		let mut va = data_ptr as usize;
+		let mut len = data_len;
 		let mut n_prdt_ents = 0;
 		while len > 0
 		{
@@ -495,25 +633,43 @@ impl Port
 		slot.hdr.prdbc = 0;
 		slot.hdr.flags = (cmd.len() / 4) as u16
 			//| (multiplier_port << 12)
-			| (if data.is_send() { 1 << 6 } else { 0 })	// Write
+			| (if is_send { 1 << 6 } else { 0 })	// Write
 			| (if pkt.len() > 0 { 1 << 5 } else { 0 })	// ATAPI
 			;
 
-		slot.event.clear();
-		// SAFE: Wait ensures that memory stays valid
+		// Register the completion handle and start the command (locked against the IRQ handler)
+		let mut async_lh = self.command_async.lock();
+		// - Checked with the handles locked, so a command can't be started after `fail_outstanding` has run
+		if self.removed.load(Ordering::Acquire) {
+			drop(async_lh);
+			drop(slot);
+			async.signal( storage::IoError::encode(Err(storage::IoError::NoMedium)) );
+			return ;
+		}
+		async_lh[slot.idx as usize] = Some(async);
+		// SAFE: Caller keeps the buffer valid until `async` is signalled
 		unsafe {
 			slot.start();
-			slot.wait()
 		}
+		// - Ownership of the slot now lies with the IRQ handler
+		::core::mem::forget(slot);
 	}
 
-	fn get_command_slot(&self) -> CommandSlot
+	/// UNSAFE: Caller must own the slot (i.e. have allocated it from `used_commands`)
+	unsafe fn get_slot(&self, idx: usize) -> CommandSlot
 	{
 		let max_commands = self.ctrlr.max_commands as usize;
+		CommandSlot {
+			idx: idx as u8,
+			port: self,
+			data: &mut *self.get_cmdtab_ptr(idx),
+			hdr: &mut self.command_list_alloc.as_int_mut_slice(0, max_commands)[idx],
+			}
+	}
 
-		// 0. Request slot from semaphore
-		self.used_commands_sem.acquire();
-		
+	/// Allocate a free command slot, returning `None` if all are in use
+	fn try_get_command_slot(&self) -> Option<CommandSlot>
+	{
 		// 1. Load
 		let mut cur_used_commands = self.used_commands.load(Ordering::Relaxed);
 		loop
@@ -527,7 +683,9 @@ impl Port
 					break ;
 				}
 			}
-			assert!(avail < self.ctrlr.max_commands as usize);
+			if avail == self.ctrlr.max_commands as usize {
+				return None;
+			}
 
 			// 3. Try and commit
 			let try_new_val = cur_used_commands | (1 << avail);
@@ -537,19 +695,7 @@ impl Port
 			{
 				// If successful, return
 				// SAFE: Exclusive access
-				let (tab, hdr) = unsafe {
-					(
-						&mut *self.get_cmdtab_ptr(avail),
-						&mut self.command_list_alloc.as_int_mut_slice(0, max_commands)[avail],
-						)
-					};
-				return CommandSlot {
-					idx: avail as u8,
-					port: self,
-					data: tab,
-					hdr: hdr,
-					event: &self.command_events[avail],
-					};
+				return Some( unsafe { self.get_slot(avail) } );
 			}
 
 			cur_used_commands = newval;
@@ -560,6 +706,8 @@ impl ::core::ops::Drop for Port
 {
 	fn drop(&mut self)
 	{
+		// Outstanding requests have to complete before the volume can be unregistered (which waits for them)
+		self.fail_outstanding();
 		*self.volume.lock() = None;
 		//assert!( self.interface_active == false );
 	}
@@ -570,7 +718,6 @@ struct CommandSlot<'a> {
 	port: &'a Port,
 	pub data: &'a mut hw::CmdTable,
 	pub hdr: &'a mut hw::CmdHeader,
-	pub event: &'a ::kernel::sync::EventChannel,
 }
 impl<'a> CommandSlot<'a>
 {
@@ -583,11 +730,9 @@ impl<'a> CommandSlot<'a>
 		self.port.regs().write(hw::REG_PxCI, mask);
 	}
 
-	/// Wait for a command to complete and returns the number of bytes transferred
-	pub fn wait(&self) -> Result<usize, Error>
+	/// Get the result of a completed command (the number of bytes transferred)
+	pub fn get_result(&self) -> Result<usize, Error>
 	{
-		self.event.sleep();
-
 		let regs = self.port.regs();
 		let active = regs.read(hw::REG_PxCI);
 		let tfd = regs.read(hw::REG_PxTFD);
@@ -645,7 +790,6 @@ impl<'a> ::core::ops::Drop for CommandSlot<'a>
 				break ;
 			}
 		}
-		self.port.used_commands_sem.release();
 	}
 }
 
@@ -664,6 +808,16 @@ impl Interface
 	}
 }
 
+/// Async layer converting the byte count yielded by `do_fis` into a sector count
+fn bytes_to_sectors(_async: async::ObjectHandle, _stack: async::StackPush, res: usize) -> Option<usize>
+{
+	match storage::IoError::decode(res)
+	{
+	Ok(bytes) => Some( storage::IoError::encode(Ok(bytes / 512)) ),
+	Err(_) => Some(res),
+	}
+}
+
 impl ::storage_ata::volume::Interface for Interface
 {
 	fn name(&self) -> &str { &self.port().name }
@@ -672,25 +826,16 @@ impl ::storage_ata::volume::Interface for Interface
 		match self.port().request_identify(0xEC)
 		{
 		Ok(v) => Ok(v),
-		Err(Error::Ata{err, ..}) => Err(From::from(err)),
 		Err(_) => Err(From::from(0)),
 		}
 	}
-	fn dma_lba_28(&self, cmd: u8, count: u8 , addr: u32, data: DataPtr) -> Result<usize,::storage_ata::volume::Error> {
-		match self.port().request_ata_lba28(0, cmd, count, addr, data)
-		{
-		Ok(bc) => Ok( bc / 512 ),
-		Err(Error::Ata{err, ..}) => Err(From::from(err)),
-		Err(_) => Err(From::from(0)),
-		}
+	fn dma_lba_28<'a, 's>(&'s self, async: async::ObjectHandle, mut stack: async::StackPush<'a, 's>, cmd: u8, count: u8 , addr: u32, data: DataPtr<'s>) {
+		stack.push_closure(bytes_to_sectors).expect("Insufficient space when pushing closure");
+		self.port().request_ata_lba28(async, stack, 0, cmd, count, addr, data)
 	}
-	fn dma_lba_48(&self, cmd: u8, count: u16, addr: u64, data: DataPtr) -> Result<usize,::storage_ata::volume::Error> {
-		match self.port().request_ata_lba48(0, cmd, count, addr, data)
-		{
-		Ok(bc) => Ok( bc / 512 ),
-		Err(Error::Ata{err, ..}) => Err(From::from(err)),
-		Err(_) => Err(From::from(0)),
-		}
+	fn dma_lba_48<'a, 's>(&'s self, async: async::ObjectHandle, mut stack: async::StackPush<'a, 's>, cmd: u8, count: u16, addr: u64, data: DataPtr<'s>) {
+		stack.push_closure(bytes_to_sectors).expect("Insufficient space when pushing closure");
+		self.port().request_ata_lba48(async, stack, 0, cmd, count, addr, data)
 	}
 }
 
@@ -699,26 +844,12 @@ impl ::storage_scsi::ScsiInterface for Interface
 	fn name(&self) -> &str {
 		&self.port().name
 	}
-	fn send<'a>(&'a self, command: &[u8], data: &'a [u8]) -> storage::AsyncIoResult<'a,()>
+	fn send<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, command: &[u8], data: &'s [u8])
 	{
-		use storage_scsi::proto::SenseKey;
-		use kernel::async::NullResultWaiter;
-		match self.port().request_atapi(0, command, DataPtr::Send(data))
-		{
-		Ok(_) => Box::new( NullResultWaiter::new(|| Ok( () )) ),
-		Err(Error::Atapi { sense_key: SenseKey::NotReady, .. }) => Box::new(NullResultWaiter::new(|| Err(storage::IoError::NoMedium))),
-		Err(_) => Box::new(NullResultWaiter::new(|| Err(storage::IoError::Unknown(""))))
-		}
+		self.port().request_atapi(async, stack, 0, command, DataPtr::Send(data))
 	}
-	fn recv<'a>(&'a self, command: &[u8], data: &'a mut [u8]) -> storage::AsyncIoResult<'a,()>
+	fn recv<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, command: &[u8], data: &'s mut [u8])
 	{
-		use storage_scsi::proto::SenseKey;
-		use kernel::async::NullResultWaiter;
-		match self.port().request_atapi(0, command, DataPtr::Recv(data))
-		{
-		Ok(_) => Box::new( NullResultWaiter::new(|| Ok( () )) ),
-		Err(Error::Atapi { sense_key: SenseKey::NotReady, .. }) => Box::new(NullResultWaiter::new(|| Err(storage::IoError::NoMedium))),
-		Err(_) => Box::new(NullResultWaiter::new(|| Err(storage::IoError::Unknown(""))))
-		}
+		self.port().request_atapi(async, stack, 0, command, DataPtr::Recv(data))
 	}
 }
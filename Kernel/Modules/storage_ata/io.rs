@@ -4,9 +4,11 @@
 //! ATA IO code, handling device multiplexing and IO operations
 use kernel::prelude::*;
 use kernel::memory::helpers::{DMABuffer};
-use kernel::async;
+use kernel::_async3 as async;
 use kernel::metadevs::storage;
 use kernel::device_manager::IOBinding;
+use kernel::lib::mem::Arc;
+use kernel::sync::Spinlock;
 
 pub const SECTOR_SIZE: usize = 512;
 //const MAX_DMA_SECTORS: usize = 0x2_0000 / SECTOR_SIZE;	// Limited by sector count (and PRDT entries)
@@ -38,7 +40,7 @@ struct DmaRegBorrow<'a>
 struct DmaStatusVal(u8);
 pub struct AtaController
 {
-	regs: ::kernel::async::Mutex<AtaRegs>,
+	regs: async::Mutex<AtaRegs>,
 	interrupt: AtaInterrupt,
 }
 struct AtaRegs
@@ -52,7 +54,9 @@ struct AtaErrorVal(u8);
 struct AtapiErrorVal(u8);
 struct AtaInterrupt
 {
-	handle: ::kernel::irqs::EventHandle,
+	/// Async object waiting for the active command to complete (signalled by the IRQ)
+	waiter: Arc<Spinlock<Option<async::ObjectHandle>>>,
+	_handle: ::kernel::irqs::ObjectHandle,
 }
 
 #[repr(C)]
@@ -77,19 +81,19 @@ impl DmaController
 		}
 	}
 
-	/// Read ATA DMA
-	pub fn do_dma_rd<'a>(&'a self, blockidx: u64, count: usize, dst: &'a mut [u8], disk: u8) -> storage::AsyncIoResult<'a,usize> {
+	/// Read ATA DMA (yields the number of sectors read)
+	pub fn do_dma_rd<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, blockidx: u64, count: usize, dst: &'s mut [u8], disk: u8) {
 		assert_eq!(dst.len(), count * SECTOR_SIZE);
 		let dst = if count > MAX_DMA_SECTORS { &mut dst[.. MAX_DMA_SECTORS * SECTOR_SIZE] } else { dst };
-		self.do_dma(blockidx, DMABuffer::new_mut(dst, 32), disk, false)
+		self.do_dma(async, stack, blockidx, DMABuffer::new_mut(dst, 32), disk, false)
 	}
-	/// Write ATA DMA
-	pub fn do_dma_wr<'a>(&'a self, blockidx: u64, count: usize, dst: &'a [u8], disk: u8) -> storage::AsyncIoResult<'a,usize> {
+	/// Write ATA DMA (yields the number of sectors written)
+	pub fn do_dma_wr<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, blockidx: u64, count: usize, dst: &'s [u8], disk: u8) {
 		assert_eq!(dst.len(), count * SECTOR_SIZE);
 		let dst = if count > MAX_DMA_SECTORS { &dst[.. MAX_DMA_SECTORS * SECTOR_SIZE] } else { dst };
-		self.do_dma(blockidx, DMABuffer::new(dst, 32), disk, true)
+		self.do_dma(async, stack, blockidx, DMABuffer::new(dst, 32), disk, true)
 	}
-	fn do_dma<'a>(&'a self, blockidx: u64, dst: DMABuffer<'a>, disk: u8, is_write: bool) -> storage::AsyncIoResult<'a,usize>
+	fn do_dma<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, blockidx: u64, dst: DMABuffer<'s>, disk: u8, is_write: bool)
 	{
 		log_trace!("do_dma(blockidx={}, dst={:?}, disk={})", blockidx, dst, disk);
 		assert!(disk < 4);
@@ -102,17 +106,18 @@ impl DmaController
 		let ctrlr = &self.ata_controllers[bus as usize];
 		let bm_regs = self.borrow_regs(bus == 1);
 		
-		let ub = ctrlr.do_dma(blockidx, dst, disk, is_write, bm_regs);
-		Box::new(ub)
+		ctrlr.do_dma(async, stack, blockidx, dst, disk, is_write, bm_regs);
 	}
 	
-	pub fn do_atapi_rd<'a>(&'a self, disk: u8, cmd: &[u8], dst: &'a mut [u8]) -> storage::AsyncIoResult<'a,()> {
-		self.do_atapi(disk, cmd, DMABuffer::new_mut(dst, 32), false)
+	/// Send an ATAPI command with a data read (yields the number of bytes transferred)
+	pub fn do_atapi_rd<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, disk: u8, cmd: &[u8], dst: &'s mut [u8]) {
+		self.do_atapi(async, stack, disk, cmd, DMABuffer::new_mut(dst, 32), false)
 	}
-	pub fn do_atapi_wr<'a>(&'a self, disk: u8, cmd: &[u8], dst: &'a [u8]) -> storage::AsyncIoResult<'a,()> {
-		self.do_atapi(disk, cmd, DMABuffer::new(dst, 32), true)
+	/// Send an ATAPI command with a data write (yields the number of bytes transferred)
+	pub fn do_atapi_wr<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, disk: u8, cmd: &[u8], dst: &'s [u8]) {
+		self.do_atapi(async, stack, disk, cmd, DMABuffer::new(dst, 32), true)
 	}
-	fn do_atapi<'a>(&'a self, disk: u8, cmd: &[u8], dst: DMABuffer<'a>, is_write: bool) -> storage::AsyncIoResult<'a,()>
+	fn do_atapi<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, disk: u8, cmd: &[u8], dst: DMABuffer<'s>, is_write: bool)
 	{
 		assert!(disk < 4);
 		
@@ -123,8 +128,7 @@ impl DmaController
 		let ctrlr = &self.ata_controllers[bus as usize];
 		let bm_regs = self.borrow_regs(bus == 1);
 		
-		let ub = ctrlr.do_atapi(disk, bm_regs, cmd, dst, is_write);
-		Box::new(ub)
+		ctrlr.do_atapi(async, stack, disk, bm_regs, cmd, dst, is_write);
 	}
 }
 
@@ -383,217 +387,59 @@ impl AtaRegs
 	}
 }
 
-enum WaitState<'dev>
-{
-	Acquire(async::mutex::Waiter<'dev,AtaRegs>),
-	IoActive(async::mutex::HeldMutex<'dev,AtaRegs>, async::event::Waiter<'dev>),
-	Done(Result<(),storage::IoError>),
-}
-struct AtaWaiter<'dev,'buf>
-{
-	dev: &'dev AtaController,
-	disk: u8,
-	blockidx: u64,
-	is_write: bool,
-	dma_regs: DmaRegBorrow<'dev>,
-	dma_buffer: DMABuffer<'buf>,
-	state: WaitState<'dev>,
-}
-impl<'a,'b> async::ResultWaiter for AtaWaiter<'a,'b>
-{
-	type Result = Result<usize, storage::IoError>;
-	
-	fn get_result(&mut self) -> Option<Self::Result> {
-		match self.state
-		{
-		WaitState::Done(r) => Some(r.map( |()| self.dma_buffer.len() / 512 )),
-		//WaitState::Done(r) => Some(r),
-		_ => None,
-		}
-	}
-	
-	fn as_waiter(&mut self) -> &mut async::Waiter { self }
-}
-
-impl<'a,'b> async::Waiter for AtaWaiter<'a,'b>
+impl AtaController
 {
-	fn is_complete(&self) -> bool {
-		if let WaitState::Done(..) = self.state { true } else { false }
-	}
-	
-	fn get_waiter(&mut self) -> &mut async::PrimitiveWaiter
+	pub fn new(ata_base: u16, sts_port: u16, irq: u32) -> AtaController
 	{
-		match self.state
-		{
-		// Initial state: Acquire the register lock
-		WaitState::Acquire(ref mut waiter) => waiter,
-		// Final state: Start IO and wait for it to complete
-		WaitState::IoActive(_, ref mut waiter) => waiter,
-		//
-		WaitState::Done(..) => unreachable!(),
-		}
+		let waiter = Arc::new( Spinlock::new(None) );
+		AtaController {
+			regs: async::Mutex::new( AtaRegs::new(ata_base, sts_port) ),
+			interrupt: AtaInterrupt {
+				waiter: waiter.clone(),
+				_handle: ::kernel::irqs::bind_object(irq, Box::new(move || {
+					if let Some(h) = waiter.lock().take() {
+						h.signal(0);
+					}
+					true
+					})),
+				},
+			}
 	}
 	
-	fn complete(&mut self) -> bool
+	fn do_dma<'a,'s>(&'s self, async: async::ObjectHandle, mut stack: async::StackPush<'a,'s>, blockidx: u64, dst: DMABuffer<'s>, disk: u8, is_write: bool, dma_regs: DmaRegBorrow<'s>)
 	{
-		// Update state if the match returns
-		self.state = match self.state
+		let mut held_lock = None;
+		stack.push_closure(move |async, _stack, res| {
+			match held_lock.take()
 			{
-			// If the Acquire wait completed, switch to IoActive state
-			WaitState::Acquire(ref mut waiter) => {
-				let mut lh = waiter.take_lock();
-				lh.start_dma( self.disk, self.blockidx, &self.dma_buffer, self.is_write, &self.dma_regs );
-				WaitState::IoActive(lh, self.dev.interrupt.handle.get_event().wait())
+			// Initial state: register lock acquired (`res` is the lock index), start the IO and wait for the IRQ
+			None => {
+				let mut lh = self.regs.ack_lock(res);
+				*self.interrupt.waiter.lock() = Some(async);
+				lh.start_dma( disk, blockidx, &dst, is_write, &dma_regs );
+				held_lock = Some(lh);
+				None
 				},
-			// And if IoActive completes, we're complete
-			WaitState::IoActive(ref mut lh, ref _waiter) => WaitState::Done(
+			// IRQ fired, IO is complete
+			Some(mut lh) => {
 				// SAFE: Holding the register lock
-				unsafe {
+				let res = unsafe {
 					log_trace!("Complete");
-					self.dma_regs.out_8(0, 0);	// Stop transfer
+					dma_regs.out_8(0, 0);	// Stop transfer
 					let ata_status = AtaStatusVal(lh.in_8(7));
-					let dma_status = DmaStatusVal(self.dma_regs.in_8(2));
+					let dma_status = DmaStatusVal(dma_regs.in_8(2));
 					log_trace!("BM Status = {:?}, ATA Status = {:?}", dma_status, ata_status);
 					lh.last_result(false)	// not ATAPI
-				}
-				),
-			//
-			WaitState::Done(..) => unreachable!(),
-			};
-		
-		self.is_complete()
-	}
-}
-impl<'a,'b> ::core::fmt::Debug for AtaWaiter<'a,'b> {
-	fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
-		try!( write!(f, "AtaWaiter") );
-		match self.state
-		{
-		WaitState::Acquire(..) => write!(f, "(Acquire)"),
-		WaitState::IoActive(..) => write!(f, "(IoActive)"),
-		WaitState::Done(..) => write!(f, "(Done)"),
-		}
-	}
-}
-struct AtapiWaiter<'dev,'buf>
-{
-	dev: &'dev AtaController,
-	disk: u8,
-	is_write: bool,
-	dma_regs: DmaRegBorrow<'dev>,
-	cmd_buffer: [u16; 6],
-	dma_buffer: DMABuffer<'buf>,
-	state: WaitState<'dev>,
-}
-impl<'a,'b> async::ResultWaiter for AtapiWaiter<'a,'b>
-{
-	type Result = Result<(), storage::IoError>;
-	
-	fn get_result(&mut self) -> Option<Self::Result> {
-		match self.state
-		{
-		WaitState::Done(r) => Some(r),
-		_ => None,
-		}
-	}
-	
-	fn as_waiter(&mut self) -> &mut async::Waiter { self }
-}
-
-impl<'a,'b> async::Waiter for AtapiWaiter<'a,'b>
-{
-	fn is_complete(&self) -> bool {
-		if let WaitState::Done(..) = self.state { true } else { false }
-	}
-	
-	fn get_waiter(&mut self) -> &mut async::PrimitiveWaiter
-	{
-		match self.state
-		{
-		// Initial state: Acquire the register lock
-		WaitState::Acquire(ref mut waiter) => waiter,
-		// Final state: Start IO and wait for it to complete
-		WaitState::IoActive(_, ref mut waiter) => waiter,
-		//
-		WaitState::Done(..) => unreachable!(),
-		}
-	}
-	
-	fn complete(&mut self) -> bool
-	{
-		// Update state if the match returns
-		self.state = match self.state
-			{
-			// If the Acquire wait completed, switch to IoActive state
-			WaitState::Acquire(ref mut waiter) => {
-				let mut lh = waiter.take_lock();
-				lh.start_atapi( &self.dma_regs, self.disk, self.is_write, &self.cmd_buffer, &self.dma_buffer );
-				WaitState::IoActive(lh, self.dev.interrupt.handle.get_event().wait())
-				},
-			// And if IoActive completes, we're complete
-			WaitState::IoActive(ref mut lh, ref _waiter) => {
-				// If the controller is still busy, keep going
-				if lh.in_sts() & AtaStatusVal::BSY != 0 {
-					log_warning!("Controller still busy when waiter woken");
-					return false;
-				}
-				// SAFE: Holding the register lock
-				let completion_res = unsafe {
-						//log_trace!("Complete");
-						self.dma_regs.out_8(0, 0);	// Stop transfer
-						let ata_status = AtaStatusVal( lh.in_8(7) );
-						let dma_status = DmaStatusVal(self.dma_regs.in_8(2));
-						log_trace!("BM Status = {:?}, ATA Status = {:?}", dma_status, ata_status);
-						lh.last_result(true)
 					};
-				WaitState::Done( completion_res )
-				},
-			//
-			WaitState::Done(..) => unreachable!(),
-			};
-		
-		self.is_complete()
-	}
-}
-impl<'a,'b> ::core::fmt::Debug for AtapiWaiter<'a,'b> {
-	fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
-		try!( write!(f, "AtapiWaiter") );
-		match self.state
-		{
-		WaitState::Acquire(..) => write!(f, "(Acquire)"),
-		WaitState::IoActive(..) => write!(f, "(IoActive)"),
-		WaitState::Done(..) => write!(f, "(Done)"),
-		}
-	}
-}
-
-impl AtaController
-{
-	pub fn new(ata_base: u16, sts_port: u16, irq: u32) -> AtaController
-	{
-		AtaController {
-			regs: async::Mutex::new( AtaRegs::new(ata_base, sts_port) ),
-			interrupt: AtaInterrupt {
-				handle: ::kernel::irqs::bind_event(irq),
+				Some( storage::IoError::encode( res.map(|()| dst.len() / SECTOR_SIZE) ) )
 				},
 			}
+			}).expect("Out of space on async stack for ATA DMA");
+		self.regs.lock_async(async, stack);
 	}
-	
-	fn do_dma<'a,'b>(&'a self, blockidx: u64, dst: DMABuffer<'b>, disk: u8, is_write: bool, dma_regs: DmaRegBorrow<'a>) -> AtaWaiter<'a,'b>
-	{
-		AtaWaiter {
-			dev: self,
-			disk: disk,
-			blockidx: blockidx,
-			is_write: is_write,
-			dma_regs: dma_regs,
-			dma_buffer: dst,
-			state: WaitState::Acquire( self.regs.async_lock() ),
-		}
-	}
-	fn do_atapi<'a,'b>(&'a self, disk: u8, dma_regs: DmaRegBorrow<'a>, cmd: &[u8], dst: DMABuffer<'b>, is_write: bool) -> AtapiWaiter<'a,'b>
+	fn do_atapi<'a,'s>(&'s self, async: async::ObjectHandle, mut stack: async::StackPush<'a,'s>, disk: u8, dma_regs: DmaRegBorrow<'s>, cmd: &[u8], dst: DMABuffer<'s>, is_write: bool)
 	{
-		let cmdbuf = {
+		let cmd_buffer = {
 			let mut buf = [0u16; 6];
 			for i in 0 .. 6 {
 				// Read zero-padded little endian words from stream
@@ -610,19 +456,44 @@ impl AtaController
 			}
 			buf
 			};
-		AtapiWaiter {
-			dev: self,
-			disk: disk,
-			dma_regs: dma_regs,
-			is_write: is_write,
-			cmd_buffer: cmdbuf,
-			dma_buffer: dst,
-			state: WaitState::Acquire( self.regs.async_lock() ),
-		}
+		let mut held_lock = None;
+		stack.push_closure(move |async, _stack, res| {
+			match held_lock.take()
+			{
+			// Initial state: register lock acquired (`res` is the lock index), start the IO and wait for the IRQ
+			None => {
+				let mut lh = self.regs.ack_lock(res);
+				*self.interrupt.waiter.lock() = Some(async);
+				lh.start_atapi( &dma_regs, disk, is_write, &cmd_buffer, &dst );
+				held_lock = Some(lh);
+				None
+				},
+			// IRQ fired, check that the command is actually complete
+			Some(mut lh) => {
+				// If the controller is still busy, keep waiting
+				if lh.in_sts() & AtaStatusVal::BSY != 0 {
+					log_warning!("Controller still busy when IRQ fired");
+					*self.interrupt.waiter.lock() = Some(async);
+					held_lock = Some(lh);
+					return None;
+				}
+				// SAFE: Holding the register lock
+				let res = unsafe {
+					dma_regs.out_8(0, 0);	// Stop transfer
+					let ata_status = AtaStatusVal( lh.in_8(7) );
+					let dma_status = DmaStatusVal(dma_regs.in_8(2));
+					log_trace!("BM Status = {:?}, ATA Status = {:?}", dma_status, ata_status);
+					lh.last_result(true)
+					};
+				Some( storage::IoError::encode( res.map(|()| dst.len()) ) )
+				},
+			}
+			}).expect("Out of space on async stack for ATAPI");
+		self.regs.lock_async(async, stack);
 	}
 	
 	/// Request an ATA IDENTIFY packet from the device
-	pub fn ata_identify<'a>(&'a self, disk: u8, data: &'a mut ::AtaIdentifyData, class: &'a mut ::AtaClass) -> async::poll::Waiter<'a>
+	pub fn ata_identify<'a>(&'a self, disk: u8, data: &'a mut ::AtaIdentifyData, class: &'a mut ::AtaClass) -> ::kernel::async::poll::Waiter<'a>
 	{
 		// - Cast 'data' to a u16 slice
 		// SAFE: AtaIdentifyData should be POD
@@ -649,7 +520,7 @@ impl AtaController
 				*class = ::AtaClass::None;
 				// SAFE: Plain old data
 				*data = unsafe { ::core::mem::zeroed() };
-				async::poll::Waiter::null()
+				::kernel::async::poll::Waiter::null()
 			}
 			else
 			{
@@ -658,7 +529,7 @@ impl AtaController
 				while buslock.in_sts() & AtaStatusVal::BSY != 0 { }
 				
 				// Return a poller
-				async::poll::Waiter::new(move |e| match e
+				::kernel::async::poll::Waiter::new(move |e| match e
 					{
 					// Being called as a completion function
 					Some(_event_ptr) => {
@@ -14,7 +14,7 @@ use kernel::lib::mem::Arc;
 
 use kernel::device_manager;
 use kernel::metadevs::storage;
-use kernel::async;
+use kernel::_async3 as async;
 
 module_define!{ATA, [DeviceManager, Storage], init}
 
@@ -138,22 +138,22 @@ impl ::kernel::metadevs::storage::PhysicalVolume for AtaVolume
 	fn blocksize(&self) -> usize { io::SECTOR_SIZE }
 	fn capacity(&self) -> Option<u64> { Some(self.size) }
 	
-	fn read<'a>(&'a self, _prio: u8, idx: u64, num: usize, dst: &'a mut [u8]) -> storage::AsyncIoResult<'a,usize>
+	fn read<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, _prio: u8, idx: u64, num: usize, dst: &'s mut [u8])
 	{
 		assert_eq!( dst.len(), num * io::SECTOR_SIZE );
-		self.controller.do_dma_rd(idx, num, dst, self.disk)
+		self.controller.do_dma_rd(async, stack, idx, num, dst, self.disk)
 	}
-	fn write<'a>(&'a self, _prio: u8, idx: u64, num: usize, src: &'a [u8]) -> storage::AsyncIoResult<'a,usize>
+	fn write<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, _prio: u8, idx: u64, num: usize, src: &'s [u8])
 	{
 		assert_eq!( src.len(), num * io::SECTOR_SIZE );
 		let ctrlr = &self.controller;
-		ctrlr.do_dma_wr(idx, num, src, self.disk)
+		ctrlr.do_dma_wr(async, stack, idx, num, src, self.disk)
 	}
 	
-	fn wipe<'a>(&'a self, _blockidx: u64, _count: usize) -> storage::AsyncIoResult<'a,()>
+	fn wipe<'a, 's>(&'s self, async: async::ObjectHandle, _stack: async::StackPush<'a, 's>, _blockidx: u64, _count: usize)
 	{
 		// Do nothing, no support for TRIM
-		Box::new(async::NullResultWaiter::new( || Ok( () ) ))
+		async.signal( storage::IoError::encode(Ok(0)) );
 	}
 	
 }
@@ -173,10 +173,10 @@ impl storage_scsi::ScsiInterface for AtapiVolume
 	fn name(&self) -> &str {
 		&self.name
 	}
-	fn send<'a>(&'a self, command: &[u8], data: &'a [u8]) -> storage::AsyncIoResult<'a,()> {
-		self.controller.do_atapi_wr(self.disk, command, data)
+	fn send<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, command: &[u8], data: &'s [u8]) {
+		self.controller.do_atapi_wr(async, stack, self.disk, command, data)
 	}
-	fn recv<'a>(&'a self, command: &[u8], data: &'a mut [u8]) -> storage::AsyncIoResult<'a,()>  {
+	fn recv<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, command: &[u8], data: &'s mut [u8]) {
 		log_debug!("- command=[{:?}]", command);
 		match command[0] & 0xE0
 		{
@@ -187,7 +187,7 @@ impl storage_scsi::ScsiInterface for AtapiVolume
 		0x80 => assert_eq!(command.len(), 16),
 		_ => {},
 		}
-		self.controller.do_atapi_rd(self.disk, command, data)
+		self.controller.do_atapi_rd(async, stack, self.disk, command, data)
 	}
 }
 
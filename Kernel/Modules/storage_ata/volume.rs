@@ -5,7 +5,7 @@
 //! Generic ATA volume support
 use kernel::prelude::*;
 use kernel::metadevs::storage::{self, DataPtr};
-use kernel::async;
+use kernel::_async3 as async;
 
 pub struct Error(u8);
 impl From<Error> for storage::IoError
@@ -33,8 +33,9 @@ pub trait Interface: 'static + Send
 	fn name(&self) -> &str;
 
 	fn ata_identify(&self) -> Result<super::AtaIdentifyData, Error>;
-	fn dma_lba_28(&self, cmd: u8, count: u8 , addr: u32, data: DataPtr) -> Result<usize,Error>;
-	fn dma_lba_48(&self, cmd: u8, count: u16, addr: u64, data: DataPtr) -> Result<usize,Error>;
+	/// Start a DMA command, `async` is signalled with the encoded result (the number of sectors transferred)
+	fn dma_lba_28<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, cmd: u8, count: u8 , addr: u32, data: DataPtr<'s>);
+	fn dma_lba_48<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, cmd: u8, count: u16, addr: u64, data: DataPtr<'s>);
 }
 
 pub struct AtaVolume<I: Interface>
@@ -70,43 +71,39 @@ impl<I: Interface + Send + 'static> storage::PhysicalVolume for AtaVolume<I>
 	fn blocksize(&self) -> usize { self.block_size as usize }
 	fn capacity(&self) -> Option<u64> { Some(self.block_count) }
 	
-	fn read<'a>(&'a self, _prio: u8, idx: u64, num: usize, dst: &'a mut [u8]) -> storage::AsyncIoResult<'a,usize>
+	fn read<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, _prio: u8, idx: u64, num: usize, dst: &'s mut [u8])
 	{
 		assert_eq!( dst.len(), num * self.block_size as usize );
-		let ret = if idx < (1 << 28) && num < 256 {
-				self.int.dma_lba_28(ATA_READ_DMA, num as u8, idx as u32, DataPtr::Recv(dst))
-			}
-			else if idx < (1 << 48) && num < (1 << 16) {
-				self.int.dma_lba_48(ATA_READ_DMA_EXT, num as u16, idx, DataPtr::Recv(dst))
-			}
-			else {
-				panic!("Count/address out of range for ATA");
-			};
-		let ret = ret.map_err(|e| e.into());
-
-		Box::new( ::kernel::async::NullResultWaiter::new( move || ret ) )
+		if idx < (1 << 28) && num < 256 {
+			self.int.dma_lba_28(async, stack, ATA_READ_DMA, num as u8, idx as u32, DataPtr::Recv(dst))
+		}
+		else if idx < (1 << 48) && num < (1 << 16) {
+			self.int.dma_lba_48(async, stack, ATA_READ_DMA_EXT, num as u16, idx, DataPtr::Recv(dst))
+		}
+		else {
+			log_notice!("{}: Read {}+{} out of range for ATA", self.int.name(), idx, num);
+			async.signal( storage::IoError::encode(Err(storage::IoError::InvalidParameter)) );
+		}
 	}
-	fn write<'a>(&'a self, _prio: u8, idx: u64, num: usize, src: &'a [u8]) -> storage::AsyncIoResult<'a,usize>
+	fn write<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, _prio: u8, idx: u64, num: usize, src: &'s [u8])
 	{
 		assert_eq!( src.len(), num * self.block_size as usize );
-		let ret = if idx < (1 << 28) && num < 256 {
-				self.int.dma_lba_28(ATA_WRITE_DMA, num as u8, idx as u32, DataPtr::Send(src))
-			}
-			else if idx < (1 << 48) && num < (1 << 16) {
-				self.int.dma_lba_48(ATA_WRITE_DMA_EXT, num as u16, idx, DataPtr::Send(src))
-			}
-			else {
-				panic!("Count/address out of range for ATA");
-			};
-		let ret = ret.map_err(|e| e.into());
-
-		Box::new( ::kernel::async::NullResultWaiter::new( move || ret ) )
+		if idx < (1 << 28) && num < 256 {
+			self.int.dma_lba_28(async, stack, ATA_WRITE_DMA, num as u8, idx as u32, DataPtr::Send(src))
+		}
+		else if idx < (1 << 48) && num < (1 << 16) {
+			self.int.dma_lba_48(async, stack, ATA_WRITE_DMA_EXT, num as u16, idx, DataPtr::Send(src))
+		}
+		else {
+			log_notice!("{}: Write {}+{} out of range for ATA", self.int.name(), idx, num);
+			async.signal( storage::IoError::encode(Err(storage::IoError::InvalidParameter)) );
+		}
 	}
 	
-	fn wipe<'a>(&'a self, _blockidx: u64, _count: usize) -> storage::AsyncIoResult<'a,()>
+	fn wipe<'a, 's>(&'s self, async: async::ObjectHandle, _stack: async::StackPush<'a, 's>, _blockidx: u64, _count: usize)
 	{
 		// Do nothing, no support for TRIM
-		Box::new(async::NullResultWaiter::new( || Ok( () ) ))
+		async.signal( storage::IoError::encode(Ok(0)) );
 	}
 	
 }
@@ -8,7 +8,7 @@
 #[allow(unused_imports)]
 use kernel::prelude::*;
 
-use kernel::async;
+use kernel::_async3 as async;
 use kernel::metadevs::storage;
 
 pub mod proto;
@@ -16,8 +16,12 @@ pub mod proto;
 pub trait ScsiInterface: Sync + Send + 'static
 {
 	fn name(&self) -> &str;
-	fn send<'a>(&'a self, command: &[u8], data: &'a [u8]) -> storage::AsyncIoResult<'a,()>;
-	fn recv<'a>(&'a self, command: &[u8], data: &'a mut [u8]) -> storage::AsyncIoResult<'a,()>;
+	/// Send a command with an outgoing data phase, signalling `async` with an encoded `IoError` result on completion
+	///
+	/// The command bytes are copied before this returns, only the data buffer needs to outlive the request.
+	fn send<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, command: &[u8], data: &'s [u8]);
+	/// Send a command with an incoming data phase (see `send`)
+	fn recv<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, command: &[u8], data: &'s mut [u8]);
 }
 
 #[derive(Debug)]
@@ -39,7 +43,7 @@ pub struct Volume<I: ScsiInterface>
 
 impl<I: ScsiInterface> Volume<I>
 {
-	fn recv_cmd<'a>(int: &I, cmd: &[u8], data: &'a mut [u8]) -> Result<(), storage::IoError> {
+	fn recv_cmd<'s>(int: &'s I, cmd: &[u8], data: &'s mut [u8]) -> Result<(), storage::IoError> {
 		log_debug!("- cmd=[{:?}]", cmd);
 		match cmd[0] & 0xE0
 		{
@@ -51,13 +55,7 @@ impl<I: ScsiInterface> Volume<I>
 		_ => {},
 		}
 
-		let _size = {
-			let mut v = int.recv(cmd, data);
-			while !v.is_complete() {
-				::kernel::async::wait_on_list(&mut [v.as_waiter()], None);
-			}
-			try!(v.get_result().unwrap())
-			};
+		let _size = try!( storage::wait_io(|async, stack| int.recv(async, stack, cmd, data)) );
 		Ok( () )
 	}
 	pub fn new_boxed(int: I) -> Result<Box<Self>,storage::IoError> {
@@ -125,53 +123,43 @@ impl<I: ScsiInterface> storage::PhysicalVolume for Volume<I>
 	fn blocksize(&self) -> usize { self.size.expect("Calling blocksize on no-media volume").0 }
 	fn capacity(&self) -> Option<u64> { self.size.map(|x| x.1) }
 	
-	fn read<'a>(&'a self, _prio: u8, idx: u64, num: usize, dst: &'a mut [u8]) -> storage::AsyncIoResult<'a,usize>
+	fn read<'a, 's>(&'s self, async: async::ObjectHandle, mut stack: async::StackPush<'a, 's>, _prio: u8, idx: u64, num: usize, dst: &'s mut [u8])
 	{
+		// - Read16 has the widest count (and a 64-bit address), so anything it can't encode can't be read
+		if !fits_in_bits(num, 32) {
+			log_notice!("{}: Read {}+{} out of range for SCSI", self.int.name(), idx, num);
+			async.signal( storage::IoError::encode(Err(storage::IoError::InvalidParameter)) );
+			return ;
+		}
+		// The interface yields a byte count (if anything), convert to the requested block count
+		stack.push_closure(move |_, _, res| Some( storage::IoError::encode( storage::IoError::decode(res).map(|_| num) ) ))
+			.expect("Out of space on async stack for SCSI read");
 		// NOTE: Read6 commented out, as qemu's CD code doesn't support it
-		let rv = /*if idx < (1<<24) && num < (1 << 8) {
-				log_trace!("SCSI Read6");
-				self.int.recv(proto::Read6::new(idx as u32, num as u8).as_ref(), dst)
-			}
-			else*/ if idx < (1<<32) && num < (1 << 16) {
-				log_trace!("SCSI Read10");
-				self.int.recv(proto::Read10::new(idx as u32, num as u16).as_ref(), dst)
-			}
-			else if /*idx < (1 << 64) &&*/ fits_in_bits(num, 32) {
-				log_trace!("SCSI Read16");
-				self.int.recv(proto::Read16::new(idx, num as u32).as_ref(), dst)
-			}
-			else {
-				todo!("SCSI read out of range");
-			};
-		
-		#[derive(Debug)]
-		struct Wrapper<'a>(storage::AsyncIoResult<'a,()>, usize);
-		impl<'a> ::kernel::async::Waiter for Wrapper<'a> {
-			fn is_complete(&self) -> bool { self.0.is_complete() }
-			fn get_waiter(&mut self) -> &mut ::kernel::async::PrimitiveWaiter { self.0.get_waiter() }
-			fn complete(&mut self) -> bool { self.0.complete() }
+		/*if idx < (1<<24) && num < (1 << 8) {
+			log_trace!("SCSI Read6");
+			self.int.recv(async, stack, proto::Read6::new(idx as u32, num as u8).as_ref(), dst)
 		}
-		impl<'a> ::kernel::async::ResultWaiter for Wrapper<'a> {
-			type Result = Result<usize, ::kernel::metadevs::storage::IoError>;
-			fn get_result(&mut self) -> Option<Self::Result> { self.0.get_result().map(|v| v.map(|_| self.1)) }
-			fn as_waiter(&mut self) -> &mut ::kernel::async::Waiter { self.0.as_waiter() }
+		else*/ if idx < (1<<32) && num < (1 << 16) {
+			log_trace!("SCSI Read10");
+			self.int.recv(async, stack, proto::Read10::new(idx as u32, num as u16).as_ref(), dst)
+		}
+		else {
+			log_trace!("SCSI Read16");
+			self.int.recv(async, stack, proto::Read16::new(idx, num as u32).as_ref(), dst)
 		}
-		Box::new( Wrapper(rv, num) )
-
-		//rv
 	}
-	fn write<'s>(&'s self, _prio: u8, idx: u64, num: usize, src: &'s [u8]) -> storage::AsyncIoResult<'s,usize> {
+	fn write<'a, 's>(&'s self, async: async::ObjectHandle, _stack: async::StackPush<'a, 's>, _prio: u8, idx: u64, num: usize, src: &'s [u8]) {
 		match self.class
 		{
-		VolumeClass::CdDvd => Box::new(async::NullResultWaiter::new( || Err(storage::IoError::ReadOnly) )),
+		VolumeClass::CdDvd => async.signal( storage::IoError::encode(Err(storage::IoError::ReadOnly)) ),
 		VolumeClass::DirectAccessBlock => {
 			todo!("Volume::write(idx={},num={},len={})", idx, num, src.len());
 			},
-		_ => Box::new(async::NullResultWaiter::new( || Err(storage::IoError::Unknown("TODO: Write support")) )),
+		_ => async.signal( storage::IoError::encode(Err(storage::IoError::Unknown("TODO: Write support"))) ),
 		}
 	}
 	
-	fn wipe<'a>(&'a self, _blockidx: u64, _count: usize) -> storage::AsyncIoResult<'a,()>
+	fn wipe<'a, 's>(&'s self, _async: async::ObjectHandle, _stack: async::StackPush<'a, 's>, _blockidx: u64, _count: usize)
 	{
 		todo!("Volume::wipe");
 	}
@@ -40,8 +40,16 @@ impl<T: Pod> SyscallArg for Freeze<T>
 			return Err( ::Error::TooManyArgs );
 		}
 		let ptr = args[0] as *const T;
-		*args = &args[2..];
-		todo!("Freeze {:p}", ptr);
+		*args = &args[1..];
+		// SAFE: Performs data validation, and only accepts user pointers (which are checkable)
+		unsafe {
+			let bs = if let Some(v) = ::kernel::memory::buf_to_slice(ptr, 1) {
+					v
+				} else {
+					return Err( ::Error::InvalidBuffer(ptr as *const (), ::core::mem::size_of::<T>()) );
+				};
+			Ok( try!(Freeze::new(&bs[0])) )
+		}
 	}
 }
 impl<T: Pod> SyscallArg for Freeze<[T]>
@@ -86,11 +94,18 @@ impl<T: Pod> SyscallArg for FreezeMut<T>
 			return Err( ::Error::TooManyArgs );
 		}
 		let ptr = args[0] as *mut T;
+		*args = &args[1..];
 
 		// SAFE: Performs data validation, and only accepts user pointers (which are checkable)
 		unsafe { 
+			// 2. Ensure that the pointer is valid
+			let bs = if let Some(v) = ::kernel::memory::buf_to_slice_mut(ptr, 1) {
+					v
+				} else {
+					return Err( ::Error::InvalidBuffer(ptr as *const (), ::core::mem::size_of::<T>()) );
+				};
 			// 3. Create a freeze on that memory (ensuring that it's not unmapped until the Freeze object drops)
-			Ok( try!(FreezeMut::new(&mut *ptr)) )
+			Ok( try!(FreezeMut::new(&mut bs[0])) )
 		}
 	}
 }
@@ -0,0 +1,131 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Modules/syscalls/futex.rs
+//! Userland futexes (sleep until a word in memory is changed)
+//!
+//! Futexes in private memory are keyed by process and virtual address. Ones in shared memory are keyed by the
+//! physical address of the futex word, so the same futex can be used from multiple processes.
+use kernel::prelude::*;
+use kernel::lib::VecMap;
+use kernel::lib::collections::vec_map::Entry;
+use kernel::memory::PAddr;
+use kernel::memory::virt::ProtectionMode;
+use kernel::threads::{SleepObject,SleepObjectRef};
+use core::sync::atomic::{AtomicUsize,Ordering};
+
+#[derive(Copy,Clone,PartialEq,Eq,PartialOrd,Ord,Debug)]
+enum FutexKey
+{
+	/// Process-private memory: process ID and virtual address
+	Private(u32, usize),
+	/// Memory shared between address spaces: physical address
+	Shared(PAddr),
+}
+
+/// Sleeping threads, keyed by the futex word's location
+static S_FUTEXES: ::kernel::sync::Mutex< VecMap<FutexKey, Vec<SleepObjectRef>> > = ::kernel::sync::Mutex::new(VecMap::new_const());
+
+/// Validate a user-provided futex address, returning the futex and its key
+fn get_futex<'a>(addr: usize) -> Result<(&'a AtomicUsize, FutexKey), ::Error>
+{
+	if addr >= ::kernel::arch::memory::addresses::USER_END {
+		return Err( ::Error::InvalidBuffer(addr as *const (), ::core::mem::size_of::<usize>()) );
+	}
+	// SAFE: Pointer is checked to be valid and aligned (and accesses are atomic)
+	let futex = match unsafe { ::kernel::memory::buf_to_slice(addr as *const AtomicUsize, 1) }
+		{
+		Some(v) => &v[0],
+		None => return Err( ::Error::InvalidBuffer(addr as *const (), ::core::mem::size_of::<usize>()) ),
+		};
+	let paddr = match ::kernel::memory::virt::get_info(futex)
+		{
+		// - Demand-zero and copy-on-write pages share frames until written, so force the copy now
+		//   (a no-op write, the fault handler resolves it). Otherwise the key would change on the first write.
+		Some( (_, ProtectionMode::UserCOW) ) => {
+			futex.fetch_add(0, Ordering::SeqCst);
+			::kernel::memory::virt::get_phys(futex)
+			},
+		Some( (paddr, _) ) => paddr,
+		None => return Err( ::Error::InvalidBuffer(addr as *const (), ::core::mem::size_of::<usize>()) ),
+		};
+	// - Any writable page that is still multiply referenced is intentionally shared
+	let key = if ::kernel::memory::phys::is_shared(paddr) {
+			FutexKey::Shared(paddr)
+		}
+		else {
+			FutexKey::Private(::kernel::threads::get_process_id(), addr)
+		};
+	Ok( (futex, key) )
+}
+
+/// Sleep the current thread on `addr`, if it still contains `val`
+///
+/// Returns 0 if the thread slept, and 1 if the value didn't match
+pub fn sleep(addr: usize, val: usize) -> Result<u64, ::Error>
+{
+	let (futex, key) = try!(get_futex(addr));
+	let mut waiter = SleepObject::new("futex");
+	{
+		let mut lh = S_FUTEXES.lock();
+		// Check the value while the table is locked, so a wake between the check and the sleep isn't lost
+		if futex.load(Ordering::SeqCst) != val {
+			return Ok(1);
+		}
+		let r = waiter.get_ref();
+		match lh.entry(key)
+		{
+		Entry::Occupied(mut e) => e.get_mut().push(r),
+		Entry::Vacant(e) => { e.insert( vec![r] ); },
+		}
+	}
+
+	// Process termination also wakes the sleeper (the thread then exits on return from the syscall)
+	::kernel::threads::bind_wait_process_exit(&mut waiter);
+	waiter.wait();
+	::kernel::threads::clear_wait_process_exit(&mut waiter);
+
+	// Remove this thread's entry (only present if the wakeup wasn't from `wake`)
+	let mut lh = S_FUTEXES.lock();
+	let is_empty = if let Some(list) = lh.get_mut(&key) {
+			if let Some(i) = list.iter().position(|r| r.is_from(&waiter)) {
+				list.remove(i);
+			}
+			list.len() == 0
+		}
+		else {
+			false
+		};
+	if is_empty {
+		lh.remove(&key);
+	}
+	Ok(0)
+}
+
+/// Wake at most `count` threads sleeping on `addr`
+///
+/// Returns the number of threads woken
+pub fn wake(addr: usize, count: usize) -> Result<u64, ::Error>
+{
+	let (_futex, key) = try!(get_futex(addr));
+	let mut lh = S_FUTEXES.lock();
+	let mut n_woken = 0;
+	let is_empty = if let Some(list) = lh.get_mut(&key) {
+			while n_woken < count {
+				match list.pop()
+				{
+				Some(r) => r.signal(),
+				None => break,
+				}
+				n_woken += 1;
+			}
+			list.len() == 0
+		}
+		else {
+			false
+		};
+	if is_empty {
+		lh.remove(&key);
+	}
+	Ok(n_woken as u64)
+}
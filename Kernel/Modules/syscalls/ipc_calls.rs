@@ -3,10 +3,15 @@
 //
 // Core/syscalls/ipc_calls.rs
 //! Userland interface to IPC channels
+use kernel::prelude::*;
 use args::Args;
 use kernel::memory::freeze::{Freeze,FreezeMut};
 use core::sync::atomic::{AtomicU8,Ordering};
-use values::RpcMessage;
+use values::{RpcMessage,IpcError};
+use objects::ObjectAlloc;
+
+/// Maximum number of messages waiting on one end of a channel
+const MAX_QUEUED_MESSAGES: usize = 16;
 
 struct SyncChannel {
 	// TODO: NonZero?
@@ -31,19 +36,11 @@ impl ::objects::Object for SyncChannel
 		::values::IPC_RPC_SEND => {
 			let data: Freeze<::values::RpcMessage> = try!(args.get());
 			let obj: u32 = try!(args.get());
-			todo!("IPC_RPC_SEND({:p}, {})", &*data, obj);
+			Ok( ::from_result(self.send(*data, obj).map(|_| 0u32)) )
 			},
 		::values::IPC_RPC_RECV => {
-			let _data: FreezeMut<::values::RpcMessage> = try!(args.get());
-
-			if let Some(msg) = self.take_message()
-			{
-				todo!("IPC_RPC_RECV - Message present - {:?}", msg);
-			}
-			else
-			{
-				Ok( 0x1000 )
-			}
+			let mut data: FreezeMut<::values::RpcMessage> = try!(args.get());
+			Ok( ::from_result(self.receive(&mut data)) )
 			},
 		_ => ::objects::object_has_no_such_method_ref("ipc_calls::SyncChannel", call),
 		}
@@ -57,7 +54,7 @@ impl ::objects::Object for SyncChannel
 		let mut ret = 0;
 		if flags & ::values::EV_IPC_RPC_RECV != 0 {
 			self.wait_upon(obj);
-			ret |= ::values::EV_IPC_RPC_RECV;
+			ret += 1;
 		}
 		ret
 	}
@@ -65,8 +62,8 @@ impl ::objects::Object for SyncChannel
 		let mut ret = 0;
 		if flags & ::values::EV_IPC_RPC_RECV != 0 {
 			self.clear_wait(obj);
-			if self.has_message() {
-				ret += 1;
+			if self.has_message() || self.is_peer_closed() {
+				ret |= ::values::EV_IPC_RPC_RECV;
 			}
 		}
 		ret
@@ -91,25 +88,41 @@ pub fn new_pair() -> Result< (u32,u32), () >
 	Ok( (a,b) )
 }
 
-#[derive(Default)]
+/// Shared state for both ends of a channel, freed once both are dropped
 struct SyncChannelBack
 {
+	/// Bitmask of sides that have started dropping (seen by the peer as closed)
 	dying_refs: AtomicU8,
+	/// Bitmask of sides that have finished with the shared state
 	dead_refs: AtomicU8,
 	sides: [ SyncChannelSide; 2 ],
 }
-#[derive(Default)]
 struct SyncChannelSide
 {
-	message: ::kernel::sync::Spinlock<Option<RpcMessage>>,
+	/// Messages waiting to be received by this side (with attached objects)
+	messages: ::kernel::sync::Mutex< Vec<(RpcMessage, Option<ObjectAlloc>)> >,
 	queue: ::kernel::async::queue::Source,
 }
 
+impl SyncChannelSide
+{
+	fn new() -> SyncChannelSide {
+		SyncChannelSide {
+			messages: ::kernel::sync::Mutex::new(Vec::new()),
+			queue: ::kernel::async::queue::Source::new(),
+		}
+	}
+}
+
 impl SyncChannel
 {
 	fn new_pair() -> (SyncChannel, SyncChannel) {
 		// SAFE: Allocation is safe?
-		let ptr = unsafe { ::kernel::memory::heap::alloc( SyncChannelBack::default() ) };
+		let ptr = unsafe { ::kernel::memory::heap::alloc( SyncChannelBack {
+			dying_refs: AtomicU8::new(0),
+			dead_refs: AtomicU8::new(0),
+			sides: [SyncChannelSide::new(), SyncChannelSide::new()],
+			} ) };
 
 		(SyncChannel { ptr: ptr, side_idx: 0 }, SyncChannel { ptr: ptr, side_idx: 1 })
 	}
@@ -120,37 +133,117 @@ impl SyncChannel
 			&(*self.ptr).sides[self.side_idx as usize]
 		}
 	}
+	fn get_peer(&self) -> &SyncChannelSide {
+		// SAFE: Destructor ensures that pointer is valid until both are dead
+		unsafe {
+			&(*self.ptr).sides[1 - self.side_idx as usize]
+		}
+	}
+	fn is_peer_closed(&self) -> bool {
+		// SAFE: Destructor ensures that pointer is valid until both are dead
+		let dead = unsafe { (*self.ptr).dying_refs.load(Ordering::SeqCst) };
+		dead & (1 << (1 - self.side_idx)) != 0
+	}
 
 	pub fn wait_upon(&self, waiter: &mut ::kernel::threads::SleepObject) {
 		self.get_side().queue.wait_upon(waiter);
+		// If there's already something to receive (or the peer is gone), wake immediately
+		if self.has_message() || self.is_peer_closed() {
+			waiter.signal();
+		}
 	}
 	pub fn clear_wait(&self, waiter: &mut ::kernel::threads::SleepObject) {
 		self.get_side().queue.clear_wait(waiter);
 	}
 
 	pub fn has_message(&self) -> bool {
-		self.get_side().message.lock().is_some()
+		self.get_side().messages.lock().len() > 0
 	}
-	pub fn take_message(&self) -> Option<RpcMessage> {
-		self.get_side().message.lock().take()
+
+	/// Queue a message (and optionally an object from this process) on the other end of the channel
+	fn send(&self, msg: RpcMessage, obj_handle: u32) -> Result<(), IpcError> {
+		let peer = self.get_peer();
+		{
+			let mut lh = peer.messages.lock();
+			// NOTE: Checked with the queue locked, so a message can't be queued after the peer drains its queue
+			if self.is_peer_closed() {
+				return Err( IpcError::ConnectionClosed );
+			}
+			if lh.len() >= MAX_QUEUED_MESSAGES {
+				return Err( IpcError::QueueFull );
+			}
+			// Only take the object once the send is known to succeed (so it's not lost on error)
+			let obj = if obj_handle == 0 {
+					None
+				}
+				else {
+					match ::objects::take_object_alloc(obj_handle)
+					{
+					Ok(v) => Some(v),
+					Err(e) => {
+						log_log!("IPC_RPC_SEND - Can't send object {}: {:?}", obj_handle, e);
+						return Err( IpcError::InvalidObject );
+						},
+					}
+				};
+			lh.push( (msg, obj) );
+		}
+		peer.queue.wake_one();
+		Ok( () )
+	}
+
+	/// Pop a message from this end's queue, returning the handle of the attached object (or 0)
+	fn receive(&self, dst: &mut RpcMessage) -> Result<u32, IpcError> {
+		let mut lh = self.get_side().messages.lock();
+		if lh.len() == 0 {
+			return Err( if self.is_peer_closed() { IpcError::ConnectionClosed } else { IpcError::NoMessage } );
+		}
+		let (msg, obj) = lh.remove(0);
+		let handle = match obj
+			{
+			None => 0,
+			Some(obj) => match ::objects::insert_object_alloc(obj)
+				{
+				Ok(h) => h,
+				Err(obj) => {
+					// Leave the message on the queue, so it can be received once a slot is free
+					lh.insert(0, (msg, Some(obj)));
+					return Err( IpcError::TooManyObjects );
+					},
+				},
+			};
+		*dst = msg;
+		Ok( handle )
 	}
 }
 
 impl ::core::ops::Drop for SyncChannel {
 	fn drop(&mut self) {
-		// SAFE: Pointer is valid
-		let should_free = unsafe {
-			if (*self.ptr).dying_refs.fetch_or(1 << self.side_idx, Ordering::SeqCst) != 0 {
-				// Other side is in shutdown or dead.
-			}
-			else {
-			}
+		// 1. Mark this side as closing, so the peer stops expecting messages
+		// SAFE: Pointer is valid until both sides are dead
+		unsafe { (*self.ptr).dying_refs.fetch_or(1 << self.side_idx, Ordering::SeqCst); }
+
+		// 2. Release any messages (and objects) that were never received
+		// - Swapped out before dropping, as dropping objects can be arbitarily complex
+		// - Anything the peer sends after this is released when the shared state is freed
+		let unreceived = ::core::mem::replace(&mut *self.get_side().messages.lock(), Vec::new());
+		drop(unreceived);
+
+		// 3. Wake anything waiting on the other end, so it sees the closure
+		let peer = self.get_peer();
+		while peer.queue.wake_one() {
+		}
 
-			(*self.ptr).dead_refs.fetch_or(1 << self.side_idx, Ordering::SeqCst) != 0
-			};
-		if should_free {
-			todo!("Deallocate SyncChannel");
+		// 4. Mark this side as dead, this must be the last access to the shared state.
+		// - If the peer was already dead, it has finished with the state too, so free it
+		// SAFE: Pointer is valid until both sides are dead
+		let prev_dead = unsafe { (*self.ptr).dead_refs.fetch_or(1 << self.side_idx, Ordering::SeqCst) };
+		if prev_dead != 0 {
+			// SAFE: Both sides are now dead, so no other references exist
+			unsafe {
+				::core::ptr::drop_in_place(self.ptr as *mut SyncChannelBack);
+				::kernel::memory::heap::dealloc(self.ptr as *mut SyncChannelBack);
+			}
 		}
 	}
 }
-
@@ -21,11 +21,13 @@ mod objects;
 mod args;
 
 mod threads;
+mod futex;
 #[path="gui.rs"]
 mod gui_calls;
 mod vfs;
 mod ipc_calls;
 mod network_calls;
+mod memory_calls;
 
 pub type ObjectHandle = u32;
 
@@ -41,12 +43,19 @@ pub enum Error
 	BorrowFailure,
 	MoveContention,
 	InvalidUnicode(::core::str::Utf8Error),
+	OutOfMemory,
 }
 impl From<::core::str::Utf8Error> for Error {
 	fn from(v: ::core::str::Utf8Error) -> Self { Error::InvalidUnicode(v) }
 }
 impl From<FreezeError> for Error {
-	fn from(_v: FreezeError) -> Self { Error::BorrowFailure }
+	fn from(v: FreezeError) -> Self {
+		match v
+		{
+		FreezeError::OutOfMemory => Error::OutOfMemory,
+		_ => Error::BorrowFailure,
+		}
+	}
 }
 impl ::core::fmt::Display for Error {
 	fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
@@ -61,10 +70,17 @@ impl ::core::fmt::Display for Error {
 		Error::BorrowFailure => f.write_str("Contention on memory accesses"),
 		Error::MoveContention => f.write_str("Contention on object transfer"),
 		Error::InvalidUnicode(_) => f.write_str("Passed string wasn't valid unicode"),
+		Error::OutOfMemory => f.write_str("Out of memory accessing a buffer"),
 		}
 	}
 }
 
+/// Returns true if the calling process may use privileged calls
+// TODO: Use a capability system instead of only allowing PID0 (init)
+fn is_privileged() -> bool {
+	::kernel::threads::get_process_id() == 0
+}
+
 /// Initialise PID0's handles
 pub fn init(loader_handle: ::kernel::vfs::handle::File, init_handle: ::kernel::vfs::handle::File) {
 	vfs::init_handles(loader_handle, init_handle);
@@ -75,7 +91,13 @@ pub fn init(loader_handle: ::kernel::vfs::handle::File, init_handle: ::kernel::v
 pub unsafe extern "C" fn syscalls_handler(id: u32, first_arg: *const usize, count: u32) -> u64
 {
 	//log_debug!("syscalls_handler({}, {:p}+{})", id, first_arg, count);
-	invoke(id, ::core::slice::from_raw_parts(first_arg, count as usize))
+	// If the process is being terminated, exit this thread instead of returning to userland
+	::kernel::threads::exit_if_process_exiting();
+	let rv = invoke(id, ::core::slice::from_raw_parts(first_arg, count as usize));
+	::kernel::threads::exit_if_process_exiting();
+	// Give up the CPU if this thread's time slice expired (or a higher priority thread woke) during the call
+	::kernel::threads::preempt_point();
+	rv
 }
 
 fn invoke(call_id: u32, args: &[usize]) -> u64 {
@@ -84,7 +106,11 @@ fn invoke(call_id: u32, args: &[usize]) -> u64 {
 	Ok(v) => v,
 	Err(e) => {
 		log_log!("Syscall formatting error in call {:#x} - {:?} {}", call_id, e, e);
-		::kernel::threads::exit_process(0x8000_0000);
+		match e
+		{
+		Error::OutOfMemory => ::kernel::threads::exit_process(::kernel::memory::pressure::EXITSTATUS_OOM),
+		_ => ::kernel::threads::exit_process(0x8000_0000),
+		}
 		// !0
 		},
 	}
@@ -151,7 +177,8 @@ fn invoke_int(call_id: u32, args: &mut Args) -> Result<u64,Error>
 			},
 		// - 0/2: Terminate current thread
 		CORE_EXITTHREAD => {
-			threads::terminate(); 0
+			let status: u32 = try!(args.get());
+			threads::exit_thread(status); 0
 			},
 		// - 0/3: Start process
 		CORE_STARTPROCESS => {
@@ -168,7 +195,9 @@ fn invoke_int(call_id: u32, args: &mut Args) -> Result<u64,Error>
 		CORE_STARTTHREAD => {
 			let ip: usize = try!(args.get());
 			let sp: usize = try!(args.get());
-			threads::newthread(sp, ip) as u64
+			let tlsbase: usize = try!(args.get());
+			let arg: usize = try!(args.get());
+			threads::newthread(ip, sp, tlsbase, arg) as u64
 			},
 		// - 0/5: Wait for event
 		CORE_WAIT => {
@@ -177,10 +206,49 @@ fn invoke_int(call_id: u32, args: &mut Args) -> Result<u64,Error>
 			try!(threads::wait(&mut events, timeout)) as u64
 			},
 		CORE_FUTEX_SLEEP => {
-			todo!("FUTEX_SLEEP");
+			let addr: usize = try!(args.get());
+			let val: usize = try!(args.get());
+			try!(futex::sleep(addr, val))
 			},
 		CORE_FUTEX_WAKE => {
-			todo!("FUTEX_SLEEP");
+			let addr: usize = try!(args.get());
+			let count: usize = try!(args.get());
+			try!(futex::wake(addr, count))
+			},
+		CORE_GETTIME => {
+			let clock: u8 = try!(args.get());
+			match Clock::try_from(clock)
+			{
+			Ok(Clock::Monotonic) => ::kernel::time::ticks(),
+			Ok(Clock::Realtime) => ::kernel::time::realtime() as u64,
+			Err(_) => return Err( Error::BadValue ),
+			}
+			},
+		CORE_PROFILE => {
+			let op: u8 = try!(args.get());
+			let depth: usize = try!(args.get());
+			let op = match ProfileOp::try_from(op)
+				{
+				Ok(v) => v,
+				Err(_) => return Err( Error::BadValue ),
+				};
+			// The profiler's buffers are kernel heap, and its output contains kernel addresses
+			if ! is_privileged() {
+				log_notice!("Profiler {:?} requested by unprivileged process", op);
+				return Ok( from_result::<u32,u32>(Err(0)) );
+			}
+			match op
+			{
+			ProfileOp::Start => ::kernel::profiler::start(depth),
+			ProfileOp::Stop => ::kernel::profiler::stop(),
+			ProfileOp::Dump => ::kernel::profiler::dump(),
+			}
+			0
+			},
+		CORE_SETPRIORITY => {
+			let prio: u8 = try!(args.get());
+			threads::set_priority( try!(threads::get_priority(prio)) );
+			0
 			},
 		// === 1: Window Manager / GUI
 		// - 1/0: New group (requires permission, has other restrictions)
@@ -212,10 +280,22 @@ fn invoke_int(call_id: u32, args: &mut Args) -> Result<u64,Error>
 			let addr: usize = try!(args.get());
 			let count: usize = try!(args.get());
 			log_debug!("MEM_ALLOCATE({:#x},{})", addr, count);
+			if let Err(e) = ::kernel::threads::charge_resource(::kernel::threads::Resource::Pages, count) {
+				log_notice!("MEM_ALLOCATE({:#x},{}) - {:?}", addr, count, e);
+				return Ok( from_result::<u32,_>(Err(::values::MemoryError::LimitExceeded)) );
+			}
 			match ::kernel::memory::virt::allocate_user(addr as *mut (), count)
 			{
 			Ok(_) => 0,
-			Err(e) => todo!("MEM_ALLOCATE - error {:?}", e),
+			Err(e) => {
+				::kernel::threads::release_resource(::kernel::threads::Resource::Pages, count);
+				log_notice!("MEM_ALLOCATE({:#x},{}) - {:?}", addr, count, e);
+				from_result::<u32,_>(Err(match e
+					{
+					::kernel::memory::virt::MapError::OutOfMemory => ::values::MemoryError::OutOfMemory,
+					::kernel::memory::virt::MapError::RangeInUse => ::values::MemoryError::RangeInUse,
+					}))
+				},
 			}
 			},
 		MEM_REPROTECT => {
@@ -242,10 +322,18 @@ fn invoke_int(call_id: u32, args: &mut Args) -> Result<u64,Error>
 			// SAFE: This internally does checks, but is marked as unsafe as a signal
 			match unsafe { ::kernel::memory::virt::reprotect_user(addr as *mut (), ::kernel::memory::virt::ProtectionMode::Unmapped) }
 			{
-			Ok( () ) => 0,
+			Ok( () ) => {
+				::kernel::threads::release_resource(::kernel::threads::Resource::Pages, 1);
+				0
+				},
 			Err( () ) => error_code(0) as u64,
 			}
 			},
+		MEM_SHM_NEW => {
+			let size: usize = try!(args.get());
+			log_debug!("MEM_SHM_NEW({:#x})", size);
+			from_result(memory_calls::new_shared(size))
+			},
 		// === 3: IPC
 		IPC_NEWPAIR => {
 			match ipc_calls::new_pair()
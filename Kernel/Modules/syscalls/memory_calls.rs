@@ -0,0 +1,136 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Modules/syscalls/memory_calls.rs
+//! Userland shared memory objects
+use kernel::prelude::*;
+use kernel::lib::mem::Arc;
+use kernel::memory::phys::FrameHandle;
+use kernel::memory::virt::ProtectionMode;
+use kernel::PAGE_SIZE;
+use args::Args;
+use values::MemoryError;
+
+/// Upper limit on the size of a single shared memory object (256MB)
+const MAX_SHM_PAGES: usize = (256 << 20) / PAGE_SIZE;
+
+/// Handle to a shared memory object, each clone refers to the same set of frames
+struct SharedMemory(Arc<SharedMemoryInner>);
+struct SharedMemoryInner
+{
+	frames: Vec<FrameHandle>,
+}
+
+impl ::objects::Object for SharedMemory
+{
+	fn class(&self) -> u16 { ::values::CLASS_MEM_SHM }
+	fn as_any(&self) -> &Any { self }
+	fn try_clone(&self) -> Option<u32> {
+		Some( ::objects::new_object( SharedMemory(self.0.clone()) ) )
+	}
+	fn handle_syscall_ref(&self, call: u16, args: &mut Args) -> Result<u64,::Error> {
+		match call
+		{
+		::values::MEM_SHM_GETSIZE => {
+			Ok( (self.0.frames.len() * PAGE_SIZE) as u64 )
+			},
+		::values::MEM_SHM_MAP => {
+			let page_ofs: usize = try!(args.get());
+			let page_count: usize = try!(args.get());
+			let addr: usize = try!(args.get());
+			let mode = match try!(args.get::<u8>())
+				{
+				0 => ProtectionMode::UserRO,
+				1 => ProtectionMode::UserRW,
+				2 => ProtectionMode::UserRX,
+				3 => ProtectionMode::UserRWX,
+				v @ _ => {
+					log_log!("MEM_SHM_MAP - Bad protection mode {}", v);
+					return Err( ::Error::BadValue );
+					},
+				};
+			log_debug!("MEM_SHM_MAP({}+{}, {:#x}, {:?})", page_ofs, page_count, addr, mode);
+			Ok( ::from_result(self.map(page_ofs, page_count, addr, mode)) )
+			},
+		_ => ::objects::object_has_no_such_method_ref("memory_calls::SharedMemory", call),
+		}
+	}
+	fn handle_syscall_val(&mut self, call: u16, _args: &mut Args) -> Result<u64,::Error> {
+		// SAFE: Valid pointer which is forgotten after call
+		let _ = unsafe { ::core::ptr::read(self) };
+		::objects::object_has_no_such_method_val("memory_calls::SharedMemory", call)
+	}
+	fn bind_wait(&self, _flags: u32, _obj: &mut ::kernel::threads::SleepObject) -> u32 { 0 }
+	fn clear_wait(&self, _flags: u32, _obj: &mut ::kernel::threads::SleepObject) -> u32 { 0 }
+}
+
+impl SharedMemory
+{
+	fn map(&self, page_ofs: usize, page_count: usize, addr: usize, mode: ProtectionMode) -> Result<u32, MemoryError>
+	{
+		let frames = &self.0.frames;
+		if page_count == 0 || addr % PAGE_SIZE != 0 {
+			return Err( MemoryError::InvalidParameter );
+		}
+		if page_ofs > frames.len() || page_count > frames.len() - page_ofs {
+			return Err( MemoryError::InvalidParameter );
+		}
+		match addr.checked_add(page_count * PAGE_SIZE)
+		{
+		Some(end) if end <= ::kernel::arch::memory::addresses::USER_END => {},
+		_ => return Err( MemoryError::InvalidParameter ),
+		}
+
+		// - Mapped pages count against the process's memory limit (released by MEM_DEALLOCATE)
+		if let Err(e) = ::kernel::threads::charge_resource(::kernel::threads::Resource::Pages, page_count) {
+			log_notice!("MEM_SHM_MAP - {:?}", e);
+			return Err( MemoryError::LimitExceeded );
+		}
+		let mut resv = match ::kernel::memory::virt::reserve(addr as *mut (), page_count)
+			{
+			Ok(v) => v,
+			Err( () ) => {
+				::kernel::threads::release_resource(::kernel::threads::Resource::Pages, page_count);
+				return Err( MemoryError::RangeInUse );
+				},
+			};
+		for (i, frame) in frames[page_ofs ..][.. page_count].iter().enumerate() {
+			resv.map_at(i, frame.clone());
+		}
+		// NOTE: Like file mappings, the pages are now part of the address space (unmapped and released by MEM_DEALLOCATE)
+		resv.finalise(mode).unwrap();
+		Ok( 0 )
+	}
+}
+
+/// Create a new shared memory object of at least `size` bytes (zero filled)
+pub fn new_shared(size: usize) -> Result<u32, MemoryError>
+{
+	let page_count = size / PAGE_SIZE + if size % PAGE_SIZE != 0 { 1 } else { 0 };
+	if page_count == 0 || page_count > MAX_SHM_PAGES {
+		return Err( MemoryError::InvalidParameter );
+	}
+
+	let mut frames = Vec::with_capacity(page_count);
+	for _ in 0 .. page_count
+	{
+		// NOTE: On failure, the frames allocated so far are released when `frames` is dropped
+		let mut page = match ::kernel::memory::virt::alloc_free()
+			{
+			Ok(v) => v,
+			Err(_) => return Err( MemoryError::OutOfMemory ),
+			};
+		for b in page.iter_mut() {
+			*b = 0;
+		}
+		frames.push( page.into_frame() );
+	}
+
+	let rv = ::objects::new_object( SharedMemory(Arc::new(SharedMemoryInner { frames: frames })) );
+	if rv == !0 {
+		Err( MemoryError::TooManyObjects )
+	}
+	else {
+		Ok( rv )
+	}
+}
@@ -146,6 +146,8 @@ impl ProcessObjects {
 			// NOTE: Move out of the collection before calling, to allow reusing the slot
 			let v = h.write().take();
 			if let Some(mut obj) = v {
+				// The object is consumed by the call (any new object is charged when it's created)
+				release_object_charge();
 				let rv = fcn(&mut *obj.data);
 				::core::mem::forget(obj);
 				rv
@@ -166,6 +168,7 @@ impl ProcessObjects {
 			if let Some(mut lh) = h.try_write()
 			{
 				if let Some(obj) = lh.take() {
+					release_object_charge();
 					Ok( obj.data )
 				}
 				else {
@@ -223,11 +226,33 @@ impl Drop for ProcessObjects {
 	}
 }
 
+/// Charge a new object to the current process (see `ProcessHandle::resources` for objects given to other processes)
+fn charge_object() -> Result<(), super::Error> {
+	::kernel::threads::charge_resource(::kernel::threads::Resource::Objects, 1).map_err(|e| {
+		log_notice!("Object limit reached - {:?}", e);
+		super::Error::TooManyObjects
+		})
+}
+/// Release the charge for an object that has been removed from the current process's list
+fn release_object_charge() {
+	::kernel::threads::release_resource(::kernel::threads::Resource::Objects, 1);
+}
+
 //pub fn new_object<T: Object+'static>(val: T) -> Result<u32, super::Error>
 pub fn new_object<T: Object+'static>(val: T) -> u32
 {
 	log_debug!("new_object() - size_of {} = {}", type_name!(T), ::core::mem::size_of::<T>());
-	get_process_local::<ProcessObjects>().find_and_fill_slot(|| UserObject::new(val)).unwrap_or(!0)
+	if let Err(_) = charge_object() {
+		return !0;
+	}
+	match get_process_local::<ProcessObjects>().find_and_fill_slot(|| UserObject::new(val))
+	{
+	Ok(v) => v,
+	Err(_) => {
+		release_object_charge();
+		!0
+		},
+	}
 }
 
 /// Startup: Pushes the specified index as an unclaimed object
@@ -273,6 +298,7 @@ fn get_unclaimed_int(class: u16, tag: &str) -> Result<u32,u32>
 				o.data.class()
 				};
 			*lh = None;
+			release_object_charge();
 			Err(real_class as u32)
 		}
 	}
@@ -315,6 +341,24 @@ pub fn clone_object(handle: u32) -> Result<u64, super::Error> {
 		})
 }
 
+/// Borrow another object owned by this process (e.g. one passed as a syscall argument)
+pub fn with_object_ref<T, O, F>(handle: u32, fcn: F) -> Result<O, super::Error>
+where
+	T: Object + 'static,
+	F: FnOnce(&T) -> Result<O, super::Error>
+{
+	get_process_local::<ProcessObjects>().with_object(handle, |obj| {
+		match obj.as_any().downcast_ref::<T>()
+		{
+		Some(v) => fcn(v),
+		None => {
+			log_log!("with_object_ref - Object #{} was the wrong type ({})", handle, obj.type_name());
+			Err( super::Error::BadValue )
+			},
+		}
+		})
+}
+
 pub fn wait_on_object(handle: u32, mask: u32, sleeper: &mut ::kernel::threads::SleepObject) -> Result<u32,super::Error> {
 	get_process_local::<ProcessObjects>().with_object(handle, |obj| {
 		Ok( obj.bind_wait(mask, sleeper) )
@@ -330,9 +374,25 @@ pub fn clear_wait(handle: u32, mask: u32, sleeper: &mut ::kernel::threads::Sleep
 pub fn give_object(target: &::kernel::threads::ProcessHandle, tag: &str, handle: u32) -> Result<(),super::Error> {
 	log_debug!("give_object(target={:?}, handle={:?})", target, handle);
 	let target_list = target.get_process_local_alloc::<ProcessObjects>();
-	let obj = try!(get_process_local::<ProcessObjects>().take_object(handle));
+	try!( target.resources().charge(::kernel::threads::Resource::Objects, 1).map_err(|_| super::Error::TooManyObjects) );
+	let obj = match get_process_local::<ProcessObjects>().take_object(handle)
+		{
+		Ok(v) => v,
+		Err(e) => {
+			target.resources().release(::kernel::threads::Resource::Objects, 1);
+			return Err(e);
+			},
+		};
 	let class_id = obj.class();
-	let id = try!( target_list.find_and_fill_slot(|| UserObject { data: obj }) );
+	// TODO: The object is lost if the target's list is full
+	let id = match target_list.find_and_fill_slot(|| UserObject { data: obj })
+		{
+		Ok(v) => v,
+		Err(e) => {
+			target.resources().release(::kernel::threads::Resource::Objects, 1);
+			return Err(e);
+			},
+		};
 	
 	log_trace!("- Giving object {} ({} {}) as '{}' (handle {})",
 		handle, class_id, ::values::get_class_name(class_id),
@@ -343,6 +403,33 @@ pub fn give_object(target: &::kernel::threads::ProcessHandle, tag: &str, handle:
 	Ok( () )
 }
 
+/// Remove an object from this process's list, for transfer to another process (see `insert_object_alloc`)
+pub fn take_object_alloc(handle: u32) -> Result<ObjectAlloc,super::Error> {
+	if handle == 0 {
+		// The "this process" object can't be moved
+		Err( super::Error::NoSuchObject(handle) )
+	}
+	else {
+		get_process_local::<ProcessObjects>().take_object(handle)
+	}
+}
+/// Insert an object obtained by `take_object_alloc` into this process's list (returning it if there's no free slot)
+pub fn insert_object_alloc(obj: ObjectAlloc) -> Result<u32,ObjectAlloc> {
+	if let Err(_) = charge_object() {
+		return Err(obj);
+	}
+	let mut obj = Some(obj);
+	let rv = get_process_local::<ProcessObjects>().find_and_fill_slot(|| UserObject { data: obj.take().unwrap() });
+	match rv
+	{
+	Ok(id) => Ok(id),
+	Err(_) => {
+		release_object_charge();
+		Err( obj.unwrap() )
+		},
+	}
+}
+
 pub fn take_object<T: Object+'static>(handle: u32) -> Result<T,super::Error> {
 	let obj = try!(get_process_local::<ProcessObjects>().take_object(handle));
 	// SAFE: ptr::read is called on a pointer to a value that is subsequently forgotten
@@ -10,6 +10,7 @@ use ObjectHandle;
 use Error;
 use values;
 use args::Args;
+use kernel::memory::freeze::FreezeMut;
 //use kernel::threads::get_process_local;
 
 /// Current process type (provides an object handle for IPC)
@@ -30,6 +31,11 @@ impl ::objects::Object for CurProcess
 			let class: u16 = try!(args.get());
 			Ok( ::objects::get_unclaimed(class, &tag) )
 			},
+		values::CORE_THISPROCESS_GETSTATS => {
+			let mut dst: FreezeMut<values::ProcessStats> = try!(args.get());
+			*dst = ::kernel::threads::with_process_resources(get_stats);
+			Ok(0)
+			},
 		_ => ::objects::object_has_no_such_method_ref("threads::CurProcess", call),
 		}
 	}
@@ -44,17 +50,76 @@ impl ::objects::Object for CurProcess
 	}
 }
 
+unsafe impl ::args::Pod for values::ProcessStats { }
+
+/// Convert the kernel's resource accounting into the userland stats structure
+fn get_stats(res: &::kernel::threads::Accounting) -> values::ProcessStats
+{
+	use kernel::threads::Resource;
+	fn limit(res: &::kernel::threads::Accounting, r: Resource) -> u32 {
+		match res.get_limit(r)
+		{
+		Some(v) => ::core::cmp::min(v, values::RESOURCE_UNLIMITED as usize - 1) as u32,
+		None => values::RESOURCE_UNLIMITED,
+		}
+	}
+	let usage = res.usage();
+	values::ProcessStats {
+		// NOTE: Ticks are milliseconds
+		cpu_time: usage.cpu_ticks as u64,
+		pages: usage.pages as u32,
+		pages_limit: limit(res, Resource::Pages),
+		objects: usage.objects as u32,
+		objects_limit: limit(res, Resource::Objects),
+		threads: usage.threads as u32,
+		threads_limit: limit(res, Resource::Threads),
+		}
+}
+
 #[inline(never)]
 pub fn exit(status: u32) {
 	::kernel::threads::exit_process(status);
 }
 #[inline(never)]
-pub fn terminate() {
-	todo!("terminate()");
+pub fn exit_thread(status: u32) {
+	::kernel::threads::exit_thread(status);
 }
 #[inline(never)]
-pub fn newthread(sp: usize, ip: usize) -> ObjectHandle {
-	todo!("newthread(sp={:#x},ip={:#x})", sp, ip);
+pub fn newthread(ip: usize, sp: usize, tlsbase: usize, arg: usize) -> ObjectHandle {
+	// NOTE: Don't need to validate these values, as they're used only in user-space
+	match ::kernel::threads::ThreadHandle::new_user(ip, sp, tlsbase, arg)
+	{
+	Ok(handle) => ::objects::new_object( Thread(handle) ),
+	Err(e) => {
+		log_notice!("CORE_STARTTHREAD - {:?}", e);
+		!0
+		},
+	}
+}
+/// Convert a userland priority value
+///
+/// Realtime threads starve every normal thread (including driver workers), so only privileged processes may use
+/// it. For others it's capped at Normal.
+pub fn get_priority(prio: u8) -> Result<::kernel::threads::Priority, Error> {
+	match values::ThreadPriority::try_from(prio)
+	{
+	Ok(values::ThreadPriority::Idle) => Ok( ::kernel::threads::Priority::Idle ),
+	Ok(values::ThreadPriority::Normal) => Ok( ::kernel::threads::Priority::Normal ),
+	Ok(values::ThreadPriority::Realtime) =>
+		if ::is_privileged() {
+			Ok( ::kernel::threads::Priority::Realtime )
+		}
+		else {
+			log_notice!("Realtime priority requested by unprivileged process, using Normal");
+			Ok( ::kernel::threads::Priority::Normal )
+		},
+	Err(_) => Err( Error::BadValue ),
+	}
+}
+#[inline(never)]
+pub fn set_priority(prio: ::kernel::threads::Priority) {
+	log_debug!("CORE_SETPRIORITY({:?})", prio);
+	::kernel::threads::set_priority(prio);
 }
 #[inline(never)]
 pub fn newprocess(name: &str,  clone_start: usize, clone_end: usize) -> ObjectHandle {
@@ -73,11 +138,12 @@ pub fn wait(events: &mut [values::WaitItem], wake_time_mono: u64) -> Result<u32,
 	for ev in events.iter() {
 		num_bound += try!(::objects::wait_on_object(ev.object, ev.flags, &mut waiter));
 	}
+	// Also wake if this process is terminated (the thread exits on return from the syscall)
+	::kernel::threads::bind_wait_process_exit(&mut waiter);
 
 	if num_bound == 0 && wake_time_mono == !0 {
-		// Attempting to sleep on no events with an infinite timeout! Would sleep forever
+		// Attempting to sleep on no events with an infinite timeout! Will sleep until the process is terminated
 		log_error!("TODO: What to do when a thread tries to sleep forever");
-		waiter.wait();
 	}
 
 	// A wake time of 0 means to not sleep at all, just check the status of the events
@@ -85,14 +151,16 @@ pub fn wait(events: &mut [values::WaitItem], wake_time_mono: u64) -> Result<u32,
 	if wake_time_mono != 0 {
 		// !0 indicates an unbounded wait (no need to set a wakeup time)
 		if wake_time_mono != !0 {
-			todo!("Set a wakeup timer at {}", wake_time_mono);
-			//waiter.wait_until(wake_time_mono);
+			// Timer is cancelled (releasing its reference to the waiter) when dropped
+			let _timer = ::kernel::time::bind_signal(&mut waiter, wake_time_mono);
+			waiter.wait();
 		}
 		else {
 			waiter.wait();
 		}
 	}
 
+	::kernel::threads::clear_wait_process_exit(&mut waiter);
 	Ok( events.iter_mut().fold(0, |total,ev| total + ::objects::clear_wait(ev.object, ev.flags, &mut waiter).unwrap()) )
 }
 
@@ -151,12 +219,42 @@ impl ::objects::Object for Process
 	fn try_clone(&self) -> Option<u32> {
 		None
 	}
-	fn handle_syscall_ref(&self, call: u16, _args: &mut Args) -> Result<u64,Error>
+	fn handle_syscall_ref(&self, call: u16, args: &mut Args) -> Result<u64,Error>
 	{
 		match call
 		{
 		// Request termination of child process
-		values::CORE_PROCESS_KILL => todo!("CORE_PROCESS_KILL"),
+		values::CORE_PROCESS_KILL => {
+			self.0.kill(values::EXITSTATUS_KILLED);
+			Ok(0)
+			},
+		values::CORE_PROCESS_GETEXIT => {
+			Ok( match self.0.get_exit_status()
+				{
+				Some(v) => v as u64,
+				None => !0,
+				} )
+			},
+		values::CORE_PROCESS_GETSTATS => {
+			let mut dst: FreezeMut<values::ProcessStats> = try!(args.get());
+			*dst = get_stats(self.0.resources());
+			Ok(0)
+			},
+		values::CORE_PROCESS_SETLIMIT => {
+			let res: u8 = try!(args.get());
+			let limit: u32 = try!(args.get());
+			let res = match values::ProcessResource::try_from(res)
+				{
+				Ok(values::ProcessResource::Pages) => ::kernel::threads::Resource::Pages,
+				Ok(values::ProcessResource::Objects) => ::kernel::threads::Resource::Objects,
+				Ok(values::ProcessResource::Threads) => ::kernel::threads::Resource::Threads,
+				Err(_) => return Err( Error::BadValue ),
+				};
+			let limit = if limit == values::RESOURCE_UNLIMITED { None } else { Some(limit as usize) };
+			log_debug!("CORE_PROCESS_SETLIMIT({:?}, {:?}) - {:?}", res, limit, self.0);
+			self.0.resources().set_limit(res, limit);
+			Ok(0)
+			},
 		_ => ::objects::object_has_no_such_method_ref("threads::Process", call),
 		}
 	}
@@ -183,3 +281,52 @@ impl ::objects::Object for Process
 		ret
 	}
 }
+
+/// Handle to a thread within the current process
+pub struct Thread(::kernel::threads::ThreadHandle);
+impl ::objects::Object for Thread
+{
+	fn class(&self) -> u16 { values::CLASS_CORE_THREAD }
+	fn as_any(&self) -> &Any { self }
+	fn try_clone(&self) -> Option<u32> {
+		None
+	}
+	fn handle_syscall_ref(&self, call: u16, args: &mut Args) -> Result<u64,Error>
+	{
+		match call
+		{
+		values::CORE_THREAD_GETEXIT => {
+			Ok( match self.0.get_exit_status()
+				{
+				Some(v) => v as u64,
+				None => !0,
+				} )
+			},
+		values::CORE_THREAD_SETPRIORITY => {
+			let prio: u8 = try!(args.get());
+			let prio = try!(get_priority(prio));
+			log_debug!("CORE_THREAD_SETPRIORITY({:?}) - {:?}", prio, self.0);
+			self.0.set_priority(prio);
+			Ok(0)
+			},
+		_ => ::objects::object_has_no_such_method_ref("threads::Thread", call),
+		}
+	}
+	fn bind_wait(&self, flags: u32, obj: &mut ::kernel::threads::SleepObject) -> u32 {
+		let mut ret = 0;
+		if flags & values::EV_THREAD_TERMINATED != 0 {
+			self.0.bind_wait_terminate(obj);
+			ret += 1;
+		}
+		ret
+	}
+	fn clear_wait(&self, flags: u32, obj: &mut ::kernel::threads::SleepObject) -> u32 {
+		let mut ret = 0;
+		if flags & values::EV_THREAD_TERMINATED != 0 {
+			if self.0.clear_wait_terminate(obj) {
+				ret |= values::EV_THREAD_TERMINATED;
+			}
+		}
+		ret
+	}
+}
@@ -12,6 +12,7 @@ use super::Error;
 use args::Args;
 use kernel::vfs::{handle,node};
 use kernel::vfs::Path;
+use kernel::lib::byte_str::ByteStr;
 
 
 macro_rules! map_enums {
@@ -37,8 +38,20 @@ impl_from! {
 		Error::PermissionDenied => VFSError::PermissionDenied,
 		Error::Locked => VFSError::FileLocked,
 		Error::MalformedPath => VFSError::MalformedPath,
-		Error::Unknown(reason) => todo!("VFS Error Unknown - '{}'", reason),
-		_ => todo!("VFS Error - {:?}", v),
+		Error::AlreadyExists => VFSError::AlreadyExists,
+		Error::NotEmpty => VFSError::NotEmpty,
+		Error::InvalidParameter => VFSError::InvalidParameter,
+		Error::NonDirComponent => VFSError::TypeError,
+		Error::RecursionDepthExceeded => VFSError::MalformedPath,
+		Error::ReadOnlyFilesystem => VFSError::ReadOnlyFilesystem,
+		Error::OutOfSpace => VFSError::OutOfSpace,
+		Error::OutOfMemory => VFSError::OutOfMemory,
+		Error::BlockIoError(_) | Error::InconsistentFilesystem => VFSError::IoError,
+		Error::TransientError => VFSError::TransientError,
+		Error::Unknown(reason) => {
+			log_notice!("VFS Error Unknown - '{}'", reason);
+			VFSError::Unknown
+			},
 		}
 	}}
 	From<node::NodeClass>(v) for ::values::VFSNodeType {
@@ -68,6 +81,8 @@ impl_from! {
 	}}
 }
 
+unsafe impl ::args::Pod for ::values::VFSMetadata { }
+
 /// Convert a VFS result into an encoded syscall result
 fn to_result<T>(r: Result<T, ::kernel::vfs::Error>) -> Result<T, u32> {
 	r.map_err( |e| Into::into( <::values::VFSError as From<_>>::from(e) ) )
@@ -113,6 +128,24 @@ impl objects::Object for Node
 			let v32: u32 = ::values::VFSNodeType::from( self.0.get_class() ).into();
 			Ok( v32 as u64 )
 			},
+		values::VFS_NODE_GETMETA => {
+			let mut dst: FreezeMut<::values::VFSMetadata> = try!(args.get());
+			log_debug!("VFS_NODE_GETMETA()");
+			let class: u32 = ::values::VFSNodeType::from( self.0.get_class() ).into();
+			let meta = self.0.get_metadata();
+			*dst = ::values::VFSMetadata {
+				size: meta.size,
+				created: meta.created,
+				modified: meta.modified,
+				accessed: meta.accessed,
+				uid: meta.uid,
+				gid: meta.gid,
+				link_count: meta.link_count,
+				mode: meta.mode,
+				class: class as u16,
+				};
+			Ok(0)
+			},
 		_ => ::objects::object_has_no_such_method_ref("vfs::Node", call),
 		}
 	}
@@ -158,6 +191,9 @@ impl objects::Object for Node
 //
 // --------------------------------------------------------------------
 
+/// Largest read/write count that can be returned in a syscall result
+const MAX_IO_LEN: usize = (1 << 31) - 1;
+
 struct File(::kernel::vfs::handle::File);
 impl objects::Object for File
 {
@@ -176,21 +212,18 @@ impl objects::Object for File
 			let ofs: u64 = try!(args.get());
 			let mut dest: FreezeMut<[u8]> = try!(args.get());
 			log_debug!("File::readat({}, {:p}+{} bytes)", ofs, dest.as_ptr(), dest.len());
-			match self.0.read(ofs, &mut dest)
-			{
-			Ok(count) => Ok(count as u64),
-			Err(e) => todo!("File::handle_syscall READAT Error {:?}", e),
-			}
+			// - Limit the count to what can be returned
+			let len = ::core::cmp::min(dest.len(), MAX_IO_LEN);
+			let res = to_result(self.0.read(ofs, &mut dest[..len])).map(|v| v as u32);
+			Ok( super::from_result(res) )
 			},
 		values::VFS_FILE_WRITEAT => {
 			let ofs: u64 = try!(args.get());
 			let src: Freeze<[u8]> = try!(args.get());
 			log_debug!("File::writeat({}, {:p}+{} bytes)", ofs, src.as_ptr(), src.len());
-			match self.0.write(ofs, &src)
-			{
-			Ok(count) => Ok(count as u64),
-			Err(e) => todo!("File::handle_syscall WRITEAT Error {:?}", e),
-			}
+			let len = ::core::cmp::min(src.len(), MAX_IO_LEN);
+			let res = to_result(self.0.write(ofs, &src[..len])).map(|v| v as u32);
+			Ok( super::from_result(res) )
 			},
 		values::VFS_FILE_MEMMAP => {
 			let ofs: u64 = try!(args.get());
@@ -209,6 +242,12 @@ impl objects::Object for File
 				};
 			log_debug!("VFS_FILE_MEMMAP({:#x}, {:#x}+{}, {:?})", ofs, addr, size, mode);
 			
+			// - Mapped pages count against the process's memory limit (released by MEM_DEALLOCATE)
+			let page_count = (size + ::kernel::PAGE_SIZE - 1) / ::kernel::PAGE_SIZE;
+			if let Err(e) = ::kernel::threads::charge_resource(::kernel::threads::Resource::Pages, page_count) {
+				log_notice!("VFS_FILE_MEMMAP - {:?}", e);
+				return Ok( super::from_result::<u32,_>(Err(::values::VFSError::OutOfMemory)) );
+			}
 			match self.0.memory_map(addr, ofs, size, mode)
 			{
 			Ok(h) => {
@@ -219,7 +258,10 @@ impl objects::Object for File
 				::core::mem::forget(h);
 				Ok(0)
 				},
-			Err(e) => todo!("File::handle_syscall MEMMAP Error {:?}", e),
+			Err(e) => {
+				::kernel::threads::release_resource(::kernel::threads::Resource::Pages, page_count);
+				Ok( super::from_result(to_result(Err::<u32,_>(e))) )
+				},
 			}
 			},
 		_ => ::objects::object_has_no_such_method_ref("vfs::File", call),
@@ -279,6 +321,69 @@ impl objects::Object for Dir
 		values::VFS_DIR_ENUMERATE => {
 			objects::new_object( DirIter::new( self.handle.clone() ) ) as u64
 			},
+		values::VFS_DIR_CREATEFILE => {
+			let name: Freeze<[u8]> = try!(args.get());
+			let mode: u8 = try!(args.get());
+
+			let mode = match ::values::VFSFileOpenMode::try_from(mode)
+				{
+				Ok(v) => v,
+				Err(_) => return Err( Error::BadValue ),
+				};
+			let name = ByteStr::new(&*name);
+			log_debug!("VFS_DIR_CREATEFILE({:?}, {:?})", name, mode);
+			super::from_result(
+				to_result( self.handle.create_file(name, mode.into()) )
+					.map( |h| objects::new_object(File(h)) )
+				)
+			},
+		values::VFS_DIR_MKDIR => {
+			let name: Freeze<[u8]> = try!(args.get());
+
+			let name = try!( ::core::str::from_utf8(&name) );
+			log_debug!("VFS_DIR_MKDIR({:?})", name);
+			super::from_result(
+				to_result( self.handle.mkdir(name) )
+					.map( |h| objects::new_object(Dir::new(h)) )
+				)
+			},
+		values::VFS_DIR_SYMLINK => {
+			let name: Freeze<[u8]> = try!(args.get());
+			let target: Freeze<[u8]> = try!(args.get());
+
+			let name = try!( ::core::str::from_utf8(&name) );
+			let target = Path::new(&target);
+			log_debug!("VFS_DIR_SYMLINK({:?}, {:?})", name, target);
+			super::from_result(
+				to_result( self.handle.symlink(name, target) )
+					.map( |_| 0u32 )
+				)
+			},
+		values::VFS_DIR_UNLINK => {
+			let name: Freeze<[u8]> = try!(args.get());
+
+			let name = ByteStr::new(&*name);
+			log_debug!("VFS_DIR_UNLINK({:?})", name);
+			super::from_result(
+				to_result( self.handle.unlink(name) )
+					.map( |_| 0u32 )
+				)
+			},
+		values::VFS_DIR_RENAME => {
+			let name: Freeze<[u8]> = try!(args.get());
+			let dest: u32 = try!(args.get());
+			let new_name: Freeze<[u8]> = try!(args.get());
+
+			let name = ByteStr::new(&*name);
+			let new_name = ByteStr::new(&*new_name);
+			log_debug!("VFS_DIR_RENAME({:?}, #{} {:?})", name, dest, new_name);
+			try!(objects::with_object_ref(dest, |dest: &Dir| {
+				Ok(super::from_result(
+					to_result( self.handle.rename(name, &dest.handle, new_name) )
+						.map( |_| 0u32 )
+					))
+				}))
+			},
 		_ => return ::objects::object_has_no_such_method_ref("vfs::Dir", call),
 		})
 	}
@@ -75,7 +75,9 @@ pub trait HostController: Send + Sync
 	fn clear_port_feature(&self, port: usize, feature: PortFeature);
 	fn get_port_feature(&self, port: usize, feature: PortFeature) -> bool;
 
-	/// Register a queue of (my_idx,port_num) pairs for changes to the root hub
-	fn set_root_waiter(&mut self, waiter: &'static ::kernel::sync::Queue<(usize,usize)>, my_idx: usize);
+	/// Register a callback for changes to the root hub, called with (my_idx,port_num)
+	///
+	/// NOTE: Called from the controller's IRQ handler, so the callback should defer any real work
+	fn set_root_waiter(&mut self, waiter: fn(usize,usize), my_idx: usize);
 }
 
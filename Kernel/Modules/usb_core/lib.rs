@@ -13,8 +13,7 @@ module_define!{usb_core, [], init}
 
 fn init()
 {
-	// Start the worker, then forget the handle
-	::core::mem::forget( ::kernel::threads::WorkerThread::new("USB Hubs", worker_thread) );
+	// Nothing to start, hub events are handled on the kernel work queue (see `queue_event`)
 }
 
 mod hub;
@@ -48,7 +47,6 @@ struct HubDevice
 //}
 
 static WATCH_LIST: ::kernel::sync::Mutex<Vec<Meta>> = ::kernel::sync::Mutex::new(Vec::new_const());
-static EVENT_QUEUE: ::kernel::sync::Queue<(usize, usize)> = ::kernel::sync::Queue::new_const();
 //static ENUM_ENDPOINTS: ::kernel::sync::Mutex<Vec<Box<Endpoint>>> = ::kernel::sync::Mutex::new(Vec::new_const());
 
 pub fn register_host(mut h: Box<host::HostController>)
@@ -57,7 +55,7 @@ pub fn register_host(mut h: Box<host::HostController>)
 	let idx = lh.len();
 	// Note: Setting the root waiter should trigger event pushes for any connected port
 	// - This doesn't race, because the list lock is still held.
-	h.set_root_waiter(&EVENT_QUEUE, idx);
+	h.set_root_waiter(queue_event, idx);
 	lh.push(Meta::RootHub(Aref::new(Host {
 		driver: h,
 		next_id: 1,
@@ -65,22 +63,30 @@ pub fn register_host(mut h: Box<host::HostController>)
 		})));
 }
 
-fn worker_thread()
+/// Event callback from HCDs, `idx` is allocated by this code and `data` is from from the HCD
+fn queue_event(idx: usize, data: usize)
 {
-	loop
-	{
-		// Wait on a queue of (usize, usize) where the first is allocated by this code, and the second is from from the HCD
-		let (idx, data) = EVENT_QUEUE.wait_pop();
-		// This needs to check:
-		// - Root hub changes (when signalled by the HCD)
-		// - Interrupt reponses from hub devices
-		let lh = WATCH_LIST.lock();
-		match lh[idx]
+	// Handled on the kernel work queue, as the HCD calls this from its IRQ handler
+	::kernel::threads::work_queue::queue(move || handle_event(idx, data));
+}
+/// Hub event handler (run on the shared work queue)
+///
+/// NOTE: This only reads/updates port status, so is short. Device enumeration blocks on transfers and must NOT be
+/// done inline (it would starve the pool), instead it should be handed to its own thread.
+fn handle_event(idx: usize, data: usize)
+{
+	// This needs to check:
+	// - Root hub changes (when signalled by the HCD)
+	// - Interrupt reponses from hub devices
+	let lh = WATCH_LIST.lock();
+	let root = match lh[idx]
 		{
-		Meta::RootHub(ref h) => h.handle_root_event(data),
-		Meta::Hub(ref h) => h.handle_int(data),
-		}
-	}
+		Meta::RootHub(ref h) => h.borrow(),
+		Meta::Hub(ref h) => return h.handle_int(data),
+		};
+	// Release the watch list before touching the controller, so concurrent events don't serialise on it
+	drop(lh);
+	root.handle_root_event(data);
 }
 //fn enum_worker_thread()
 //{
@@ -136,6 +142,7 @@ impl Host
 			else if self.driver.get_port_feature(port_idx, host::PortFeature::Enable)
 			{
 				// Allocate an ID, allocate a , send the 'set device ID' request
+				// - Enumeration blocks, so this must be pushed to a dedicated thread (not run on the work queue)
 				todo!("Push new device to enumeration");
 			}
 			else
@@ -38,7 +38,7 @@ struct HostInner
 	hcca_handle: ::kernel::memory::virt::AllocHandle,
 	nports: u8,
 	waiter_idx: AtomicUsize,
-	waiter_ptr: AtomicPtr<::kernel::sync::Queue<(usize,usize)>>,
+	waiter_fcn: ::kernel::sync::Spinlock<Option<fn(usize,usize)>>,
 }
 struct IoWrapper(::kernel::device_manager::IOBinding);
 
@@ -233,7 +233,7 @@ impl HostInner
 			nports: nports,
 			irq_handle: None,	// Filled below, once the allocation is made
 			waiter_idx: Default::default(),
-			waiter_ptr: Default::default(),
+			waiter_fcn: ::kernel::sync::Spinlock::new(None),
 			});
 		
 		// Bind interrupt
@@ -297,9 +297,9 @@ impl HostInner
 					if v & 0xFFFF_0000 != 0 {
 						log_debug!("Status change on port {} = {:#x}", i, v);
 						let host_idx = self.waiter_idx.load(Ordering::Relaxed);
-						let queue_ptr = self.waiter_ptr.load(Ordering::Relaxed);
-						// SAFE: If this is set, it's to a &'static
-						unsafe { (*queue_ptr).push( (host_idx, i as usize) ); }
+						if let Some(fcn) = *self.waiter_fcn.lock() {
+							fcn(host_idx, i as usize);
+						}
 					}
 				}
 			}
@@ -546,10 +546,10 @@ impl ::usb_core::host::HostController for UsbHost
 			};
 		v & mask != 0
 	}
-	fn set_root_waiter(&mut self, waiter: &'static ::kernel::sync::Queue<(usize,usize)>, my_idx: usize) {
-		// 1. Store the waiter pointer
+	fn set_root_waiter(&mut self, waiter: fn(usize,usize), my_idx: usize) {
+		// 1. Store the waiter callback
 		self.host.waiter_idx.store(my_idx, Ordering::SeqCst);
-		self.host.waiter_ptr.store(waiter as *const _ as *mut _, Ordering::SeqCst);
+		*self.host.waiter_fcn.lock() = Some(waiter);
 		// 2. For each connected port, push an event/item
 		// - Don't worry about duplicates from interrupts...
 		for i in 0 .. self.host.nports as usize
@@ -557,7 +557,7 @@ impl ::usb_core::host::HostController for UsbHost
 			let v = self.host.io.read_reg(self.host.get_port_reg(i));
 			log_debug!("set_root_waiter: Port {} - v={:#x}", i, v);
 			if v & 0x1 != 0 {
-				waiter.push( (my_idx, i) );
+				waiter(my_idx, i);
 			}
 		}
 	}
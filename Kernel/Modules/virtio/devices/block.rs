@@ -1,7 +1,7 @@
 
 use kernel::prelude::*;
 use kernel::metadevs::storage;
-use kernel::async;
+use kernel::_async3 as async;
 use interface::Interface;
 use queue::{Queue,Buffer};
 
@@ -17,6 +17,10 @@ pub const VIRTIO_BLK_T_SCSI_CMD_OUT	: u32 = 3;
 pub const VIRTIO_BLK_T_FLUSH	: u32 = 4;
 pub const VIRTIO_BLK_T_FLUSH_OUT: u32 = 5;
 pub const VIRTIO_BLK_T_BARRIER	: u32 = 0x8000_0000;
+
+pub const VIRTIO_BLK_S_OK    	: u8 = 0;
+pub const VIRTIO_BLK_S_IOERR 	: u8 = 1;
+pub const VIRTIO_BLK_S_UNSUPP	: u8 = 2;
 }
 use self::defs::*;
 
@@ -76,70 +80,73 @@ struct VirtioBlockReq
 }
 unsafe impl ::kernel::lib::POD for VirtioBlockReq {}
 
+/// Request header and status byte, boxed so they stay put while the device owns them
+struct RequestState
+{
+	cmd: VirtioBlockReq,
+	status: u8,
+}
+
 const BLOCK_SIZE: usize = 512;
+impl<I: Interface+Send+'static> Volume<I>
+{
+	/// Queue a request, signalling `async` with the encoded result once the device completes it
+	fn send_request<'a, 's>(&'s self, async: async::ObjectHandle, mut stack: async::StackPush<'a, 's>, type_: u32, prio: u8, idx: u64, num: usize, data: Buffer<'s>)
+	{
+		let mut state = Box::new(RequestState {
+			cmd: VirtioBlockReq {
+				type_: type_,
+				ioprio: (255 - prio) as u32,
+				sector: idx,
+				},
+			status: 0xFF,
+			});
+		// SAFE: The box is moved into the completion layer along with the request, so outlives the device's use of it
+		let (cmd, status) = unsafe {
+			let cmd = ::kernel::lib::as_byte_slice(&state.cmd);
+			let status = ::kernel::lib::as_byte_slice_mut(&mut state.status);
+			(
+				::core::slice::from_raw_parts(cmd.as_ptr(), cmd.len()),
+				::core::slice::from_raw_parts_mut(status.as_mut_ptr(), status.len()),
+				)
+			};
+		let req = self.requestq.send_buffers_async(&self.interface, &mut [
+			Buffer::Read(cmd),
+			data,
+			Buffer::Write(status),
+			], async);
+		stack.push_closure(move |_async, _stack, _bytes| {
+			let _ = req.take_result();
+			Some(storage::IoError::encode(match state.status
+				{
+				VIRTIO_BLK_S_OK => Ok(num),
+				VIRTIO_BLK_S_UNSUPP => Err( storage::IoError::InvalidParameter ),
+				_ => Err( storage::IoError::Unknown("VirtIO") ),
+				}))
+			}).expect("Insufficient space when pushing closure");
+	}
+}
 impl<I: Interface+Send+'static> storage::PhysicalVolume for Volume<I>
 {
 	fn name(&self) -> &str { "virtio0" }
 	fn blocksize(&self) -> usize { BLOCK_SIZE }
 	fn capacity(&self) -> Option<u64> { Some(self.capacity) }
 	
-	fn read<'a>(&'a self, prio: u8, idx: u64, num: usize, dst: &'a mut [u8]) -> storage::AsyncIoResult<'a,usize>
+	fn read<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, prio: u8, idx: u64, num: usize, dst: &'s mut [u8])
 	{
 		assert_eq!( dst.len(), num * BLOCK_SIZE );
-		
-		let cmd = VirtioBlockReq {
-			type_: VIRTIO_BLK_T_IN,
-			ioprio: (255 - prio) as u32,
-			sector: idx,
-			};
-		let mut status = 0u8;
-
-		let rv = {
-			let h = self.requestq.send_buffers(&self.interface, &mut[
-				Buffer::Read( ::kernel::lib::as_byte_slice(&cmd) ),
-				Buffer::Write(dst),
-				Buffer::Write( ::kernel::lib::as_byte_slice_mut(&mut status) )
-				]);
-			match h.wait_for_completion()
-				{
-				Ok(bytes) => Ok( bytes / BLOCK_SIZE ),
-				Err( () ) => Err( storage::IoError::Unknown("VirtIO") ),
-				}
-			};
-		
-		//log_debug!("read block {}", idx);
-		//::kernel::logging::hex_dump("VirtIO block data", dst);
-		
-		Box::new(async::NullResultWaiter::new( move || rv ))
+		self.send_request(async, stack, VIRTIO_BLK_T_IN, prio, idx, num, Buffer::Write(dst));
 	}
-	fn write<'a>(&'a self, prio: u8, idx: u64, num: usize, src: &'a [u8]) -> storage::AsyncIoResult<'a, usize>
+	fn write<'a, 's>(&'s self, async: async::ObjectHandle, stack: async::StackPush<'a, 's>, prio: u8, idx: u64, num: usize, src: &'s [u8])
 	{
 		assert_eq!( src.len(), num * BLOCK_SIZE );
-		let cmd = VirtioBlockReq {
-			type_: VIRTIO_BLK_T_OUT,
-			ioprio: (255 - prio) as u32,
-			sector: idx,
-			};
-		let mut status = 0u8;
-
-		let h = self.requestq.send_buffers(&self.interface, &mut[
-			Buffer::Read( ::kernel::lib::as_byte_slice(&cmd) ),
-			Buffer::Read( src ),
-			Buffer::Write( ::kernel::lib::as_byte_slice_mut(&mut status) )
-			]);
-		let rv = match h.wait_for_completion()
-			{
-			Ok(bytes) => Ok( bytes / BLOCK_SIZE ),
-			Err( () ) => Err( storage::IoError::Unknown("VirtIO") ),
-			};
-
-		Box::new(async::NullResultWaiter::new( move || rv ))
+		self.send_request(async, stack, VIRTIO_BLK_T_OUT, prio, idx, num, Buffer::Read(src));
 	}
 	
-	fn wipe<'a>(&'a self, _blockidx: u64, _count: usize) -> storage::AsyncIoResult<'a,()>
+	fn wipe<'a, 's>(&'s self, async: async::ObjectHandle, _stack: async::StackPush<'a, 's>, _blockidx: u64, _count: usize)
 	{
 		// Do nothing, no support for TRIM
-		Box::new(async::NullResultWaiter::new( || Ok( () ) ))
+		async.signal( storage::IoError::encode(Ok(0)) );
 	}
 
 }
@@ -5,6 +5,7 @@
 use kernel::prelude::*;
 use interface::Interface;
 use core::sync::atomic::{AtomicUsize,Ordering};
+use kernel::_async3 as async;
 
 pub struct Queue {
 	idx: usize,
@@ -16,6 +17,8 @@ pub struct Queue {
 	last_seen_used: AtomicUsize,
 	interrupt_flag: ::kernel::sync::Semaphore,
 	avail_ring_res: Vec<AtomicUsize>,
+	/// Async objects to signal when a request completes (indexed by first descriptor, `None` for blocking requests)
+	avail_ring_async: Vec<::kernel::sync::Spinlock<Option<async::ObjectHandle>>>,
 }
 
 pub enum Buffer<'a> {
@@ -99,6 +102,7 @@ impl Queue
 			last_seen_used: AtomicUsize::new(0),
 			interrupt_flag: ::kernel::sync::Semaphore::new(0, count as isize),
 			avail_ring_res: (0..count).map(|_| AtomicUsize::new(0)).collect(),
+			avail_ring_async: (0..count).map(|_| Default::default()).collect(),
 			}
 	}
 
@@ -110,7 +114,11 @@ impl Queue
 
 			assert!(len > 0);
 			self.avail_ring_res[id as usize].store(len as usize, Ordering::Release);
-			self.interrupt_flag.release();
+			match self.avail_ring_async[id as usize].lock().take()
+			{
+			Some(h) => h.signal(len as usize),
+			None => self.interrupt_flag.release(),
+			}
 		}
 	}
 
@@ -119,6 +127,13 @@ impl Queue
 	}
 
 	pub fn send_buffers<'a, I: Interface>(&'a self, interface: &I, buffers: &mut [Buffer<'a>]) -> Request<'a> {
+		self.send_buffers_int(interface, buffers, None)
+	}
+	/// Send buffers without blocking, `async` is signalled with the number of bytes written by the device on completion
+	pub fn send_buffers_async<'a, I: Interface>(&'a self, interface: &I, buffers: &mut [Buffer<'a>], async: async::ObjectHandle) -> Request<'a> {
+		self.send_buffers_int(interface, buffers, Some(async))
+	}
+	fn send_buffers_int<'a, I: Interface>(&'a self, interface: &I, buffers: &mut [Buffer<'a>], async: Option<async::ObjectHandle>) -> Request<'a> {
 		assert!(buffers.len() > 0);
 
 		// Allocate a descriptor for each buffer (backwards to build up linked list)
@@ -128,6 +143,8 @@ impl Queue
 		{
 			descriptor = self.allocate_descriptor(Some(descriptor), buf);
 		}
+		// Register the completion handle before the device can see the request
+		*self.avail_ring_async[descriptor.idx as usize].lock() = async;
 
 		// Add to the active queue
 		self.dispatch_descriptor(interface, descriptor)
@@ -257,6 +274,14 @@ pub struct Request<'a>
 }
 impl<'a> Request<'a>
 {
+	/// Obtain the result of a completed async request (the number of bytes written by the device)
+	pub fn take_result(&self) -> Result<usize,()> {
+		match self.queue.avail_ring_res[self.first_desc as usize].swap(0, Ordering::Acquire)
+		{
+		0 => Err( () ),
+		v => Ok(v),
+		}
+	}
 	pub fn wait_for_completion(&self) -> Result<usize,()> {
 		// XXX: HACK! No interrupts... yet
 		while self.queue.avail_ring_res[self.first_desc as usize].load(Ordering::Relaxed) == 0 {
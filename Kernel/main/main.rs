@@ -42,6 +42,10 @@ pub extern "C" fn kmain()
 	
 	// Intialise the IRQ worker
 	::kernel::irqs::init();
+	// - And the shared work queue
+	::kernel::threads::work_queue::init();
+	// - Profiler buffers (sampling is started on request)
+	::kernel::profiler::init();
 	
 	// Modules (dependency tree included)
 	// - Requests that the GUI be started as soon as possible
@@ -69,9 +73,6 @@ fn sysinit()
 	use kernel::vfs::{mount,handle};
 	use kernel::vfs::Path;
 
-	// TODO: Should I automount at startup, then use chroot magic?
-	//automount();
-	
 	// 1. Mount /system to the specified volume
 	let sysdisk = ::kernel::config::get_string(::kernel::config::Value::SysDisk);
 	match VolumeHandle::open_named(sysdisk)
@@ -94,9 +95,12 @@ fn sysinit()
 	handle::Dir::open(Path::new("/")).unwrap()
 		.symlink("sysroot", Path::new(&sysroot[..])).unwrap();
 	
+	// 3. Automount other volumes to /mount/<lvname> (now that /system has claimed its volume)
+	mount::start_automount();
+	
 	vfs_test();
 	
-	// 3. Start 'init' (root process) using the userland loader
+	// 4. Start 'init' (root process) using the userland loader
 	let loader = ::kernel::config::get_string(::kernel::config::Value::Loader);
 	let init = ::kernel::config::get_string(::kernel::config::Value::Init);
 	match spawn_init(loader, init)
@@ -142,37 +146,8 @@ fn vfs_test()
 		ls(Path::new("/"));
 		ls(Path::new("/system"));
 	}
-	
-	// *. TEST Automount
-	// - Probably shouldn't be included in the final version, but works for testing filesystem and storage drivers
-	automount();
 
-	ls(Path::new("/mount/ahci?-0p0"));
-}
-fn automount()
-{
-	use kernel::metadevs::storage::VolumeHandle;
-	use kernel::vfs::{Path,mount,handle};
-
-	let mountdir = handle::Dir::open( Path::new("/") ).and_then(|h| h.mkdir("mount")).unwrap();
-	for (_,v) in ::kernel::metadevs::storage::enum_lvs()
-	{
-		let vh = match VolumeHandle::open_named(&v)
-			{
-			Err(e) => {
-				log_log!("Unable to open '{}': {}", v, e);
-				continue;
-				},
-			Ok(v) => v,
-			};
-		mountdir.mkdir(&v).unwrap();
-		let mountpt = format!("/mount/{}",v);
-		match mount::mount( mountpt.as_ref(), vh, "", &[] )
-		{
-		Ok(_) => log_log!("Auto-mounted to {}", mountpt),
-		Err(e) => log_notice!("Unable to automount '{}': {:?}", v, e),
-		}
-	}
+	ls(Path::new("/mount"));
 }
 
 fn spawn_init(loader_path: &str, init_cmdline: &str) -> Result<::kernel::Void, &'static str>
@@ -292,6 +267,8 @@ fn load_loader(loader: &::kernel::vfs::handle::File) -> Result<(&'static LoaderH
 	forget(mh_code);
 	forget(mh_data);
 	forget(ah_bss);
+	// > The image is now part of the (user) process, so charge it like any other mapping
+	::kernel::threads::with_process_resources(|r| r.charge_unchecked(::kernel::threads::Resource::Pages, ondisk_size as usize / PAGE_SIZE + pages));
 
 
 	Ok( (header_ptr, memsize) )
@@ -322,7 +299,7 @@ enum ArchValues {
 #[cfg(arch="armv7")]	const ARCH: ArchValues = ArchValues::ARMv7;
 #[cfg(arch="armv7")]	const LOAD_MAX: usize = (1 << 31) - (4 << 20);	// Leave 4MB for the kernel to control within the user table
 #[cfg(arch="armv8")]	const ARCH: ArchValues = ArchValues::ARMv8;
-#[cfg(arch="armv8")]	const LOAD_MAX: usize = (1 << 48) - (64 << 30);	// Leave 64GB for the kernel to control within the user table
+#[cfg(arch="armv8")]	const LOAD_MAX: usize = (1 << 47) - (64 << 30);	// Leave 64GB for the kernel to control within the user table
 #[cfg(target_pointer_width="64")]	const USIZE_BYTES: u32 = 8;
 #[cfg(target_pointer_width="32")]	const USIZE_BYTES: u32 = 4;
 const MAGIC: u32 = 0x71FF1013;
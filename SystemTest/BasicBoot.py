@@ -2,8 +2,14 @@ import sys
 import TestInstance
 from TestInstance import test_assert
 
-def test(instance):
-    test_assert("Kernel image start timed out", instance.wait_for_line("OK43e6H", timeout=10))
+# Line printed by each architecture's kernel entry code (must not appear in the emulator command line)
+START_MARKERS = {
+    "amd64": "OK43e6H",
+    "armv8": "A8:OK",
+    }
+
+def test(instance, arch):
+    test_assert("Kernel image start timed out", instance.wait_for_line(START_MARKERS[arch], timeout=10))
     instance.match_line(
         "Init load timed out",
         "Entering userland at 0x[0-9a-f]+ '([^']+)' '([^']+)'",
@@ -17,8 +23,9 @@ def test(instance):
     test_assert("Initial startup idle", instance.wait_for_idle(timeout=20))
     instance.screenshot('Login')
 
+arch = sys.argv[1] if len(sys.argv) > 1 else "amd64"
 try:
-    test( TestInstance.Instance("amd64", "Basic") )
+    test( TestInstance.Instance(arch, "Basic"), arch )
 except TestInstance.TestFail as e:
     print "TEST FAILURE:",e
     sys.exit(1)
@@ -18,6 +18,14 @@ struct Connection
 	channel: ::syscalls::ipc::RpcChannel,
 }
 
+/// Send a response, logging if the client can't receive it
+fn send_response(conn: &Connection, msg: ::syscalls::ipc::RpcMessage)
+{
+	if let Err(e) = conn.channel.send(msg) {
+		kernel_log!("NOTICE: Failed to send response to '{}' - {:?}", conn.name, e);
+	}
+}
+
 fn main()
 {
 	// handle_server gets the read-write root handle for the session user
@@ -37,9 +45,10 @@ fn main()
 	{
 		::syscalls::threads::wait(&mut waits, !0);
 		let mut idx = !0;
-		while idx < handles.len()
+		let mut handles_changed = false;
+		while idx.wrapping_add(1) < handles.len()
 		{
-			idx += 1;
+			idx = idx.wrapping_add(1);
 			let (buffer, _obj) = match handles[idx].channel.try_receive()
 				{
 				Ok(v) => v,
@@ -47,7 +56,16 @@ fn main()
 				Err(::syscalls::ipc::RxError::ConnectionClosed) => {
 					kernel_log!("Connection '{}' dropped", handles[idx].name);
 					handles.swap_remove(idx);
-					idx -= 1;
+					handles_changed = true;
+					idx = idx.wrapping_sub(1);
+					continue
+					},
+				Err(::syscalls::ipc::RxError::TooManyObjects) => {
+					// The message stays queued until it can be received, so drop the connection instead of spinning
+					kernel_log!("Connection '{}' sent an object that can't be received, dropping", handles[idx].name);
+					handles.swap_remove(idx);
+					handles_changed = true;
+					idx = idx.wrapping_sub(1);
 					continue
 					},
 				};
@@ -64,17 +82,19 @@ fn main()
 					{
 					b"fileviewer" => b"/system/bin/fileviewer",
 					_ => {
-						conn.channel.send( protocol::RspError::new(0, "Unknown name").into() );
+						send_response(conn, protocol::RspError::new(0, "Unknown name").into());
 						continue
 						},
 					};
 				match filesystem_root.open_child_path(path).and_then(|x| x.into_file(::syscalls::vfs::FileOpenMode::Execute))
 				{
 				Ok(fh) => {
-					conn.channel.send_obj( protocol::RspOpenedFile::new(path).into(), fh );
+					if let Err(e) = conn.channel.send_obj( protocol::RspOpenedFile::new(path).into(), fh ) {
+						kernel_log!("NOTICE: Failed to send response to '{}' - {:?}", conn.name, e);
+					}
 					},
 				Err(_) => {
-					conn.channel.send( protocol::RspError::new(0, "Could not open executable file").into() );
+					send_response(conn, protocol::RspError::new(0, "Could not open executable file").into());
 					continue
 					},
 				}
@@ -86,13 +106,17 @@ fn main()
 				},
 			Err(protocol::UnmarshalError::BadValue) => {
 				kernel_log!("NOTICE: Malformed request from '{}' - {}", conn.name, buffer[0]);
-				conn.channel.send( protocol::RspError::new(0, "Bad request").into() );
+				send_response(conn, protocol::RspError::new(0, "Bad request").into());
 				},
 			Err(protocol::UnmarshalError::UnknownRequest) => {
 				kernel_log!("NOTICE: Unknown request from '{}' - {}", conn.name, buffer[0]);
-				conn.channel.send( protocol::RspError::new(0, "Unknown request").into() );
+				send_response(conn, protocol::RspError::new(0, "Unknown request").into());
 				},
 			}
 		}
+
+		if handles_changed {
+			waits = handles.iter().map(|x| x.channel.wait_rx()).collect();
+		}
 	}
 }
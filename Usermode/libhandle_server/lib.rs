@@ -23,6 +23,8 @@ pub enum OpenError
 	NotFound,
 	/// The application requested a file, but permission was denied
 	PermissionDenied,
+	/// The handle server couldn't be reached (its end of the channel was closed, or isn't accepting requests)
+	ConnectionLost,
 }
 
 impl Connection
@@ -56,9 +58,19 @@ impl Connection
 {
 	/// Open a named executable
 	pub fn open_executable(&self, name: &str) -> Result< ::syscalls::vfs::File, OpenError > {
-		self.channel.send( protocol::ReqOpenExecutable::new(name).into() );
-		::syscalls::threads::wait(&mut [ self.channel.wait_rx() ], !0);
-		let (rsp, obj) = self.channel.try_receive().unwrap();
+		if let Err(_) = self.channel.send( protocol::ReqOpenExecutable::new(name).into() ) {
+			return Err( OpenError::ConnectionLost );
+		}
+		let (rsp, obj) = loop
+			{
+				::syscalls::threads::wait(&mut [ self.channel.wait_rx() ], !0);
+				match self.channel.try_receive()
+				{
+				Ok(v) => break v,
+				Err(::syscalls::ipc::RxError::NoMessage) => {},
+				Err(_) => return Err( OpenError::ConnectionLost ),
+				}
+			};
 		match protocol::Response::try_from(rsp)
 		{
 		Ok(protocol::Response::OpenedFile(_v)) => {
@@ -12,6 +12,13 @@ impl File
 		let p = path.as_ref();
 		Ok( File(super::Node::open(p)?.into_file()?) )
 	}
+	/// Create a new file, opened for exclusive read-write access
+	///
+	/// NOTE: Unlike other platforms, this fails if the file already exists
+	pub fn create<P: AsRef<Path>>(path: P) -> ::io::Result<File> {
+		let (dir, name) = super::open_parent(path.as_ref())?;
+		Ok( File(dir.create_file(name, ::syscalls::vfs::FileOpenMode::ExclRW)?) )
+	}
 }
 
 impl ::io::Read for File
@@ -20,4 +27,13 @@ impl ::io::Read for File
 		::io::Read::read( &mut self.0, buf )
 	}
 }
+impl ::io::Write for File
+{
+	fn write(&mut self, buf: &[u8]) -> ::io::Result<usize> {
+		::io::Write::write( &mut self.0, buf )
+	}
+	fn flush(&mut self) -> ::io::Result<()> {
+		::io::Write::flush( &mut self.0 )
+	}
+}
 
@@ -11,6 +11,50 @@ pub use self::file::File;
 
 struct Node(::syscalls::vfs::Node);
 
+/// Create a new directory
+pub fn create_dir<P: AsRef<Path>>(path: P) -> ::io::Result<()> {
+	let (dir, name) = open_parent(path.as_ref())?;
+	dir.mkdir(name)?;
+	Ok( () )
+}
+/// Remove a file (or empty directory)
+pub fn remove_file<P: AsRef<Path>>(path: P) -> ::io::Result<()> {
+	let (dir, name) = open_parent(path.as_ref())?;
+	dir.unlink(name)?;
+	Ok( () )
+}
+/// Remove an empty directory
+pub fn remove_dir<P: AsRef<Path>>(path: P) -> ::io::Result<()> {
+	remove_file(path)
+}
+/// Rename a file or directory (both paths must be on the same volume)
+pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> ::io::Result<()> {
+	let (src_dir, src_name) = open_parent(from.as_ref())?;
+	let (dst_dir, dst_name) = open_parent(to.as_ref())?;
+	src_dir.rename(src_name, &dst_dir, dst_name)?;
+	Ok( () )
+}
+/// Create a symbolic link at `dst` pointing to `src`
+pub fn soft_link<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> ::io::Result<()> {
+	let (dir, name) = open_parent(dst.as_ref())?;
+	dir.symlink(name, src.as_ref())?;
+	Ok( () )
+}
+
+/// Open the parent directory of a path, returning it along with the final component
+fn open_parent(path: &Path) -> ::io::Result<(::syscalls::vfs::Dir, &::std::ffi::OsStr)> {
+	let (parent, name) = path.split_off_last();
+	let parent_bytes: &[u8] = parent.as_ref();
+	let dir = if path.is_absolute() && parent_bytes.len() == 0 {
+			// - Parent is the root
+			::syscalls::vfs::ROOT.clone()
+		}
+		else {
+			Node::open(parent)?.into_dir()?
+		};
+	Ok( (dir, name) )
+}
+
 impl Node
 {
 	fn open(path: &Path) -> ::io::Result<Node> {
@@ -46,6 +90,9 @@ impl Node
 		}
 	}
 	
+	fn into_dir(self) -> ::io::Result<::syscalls::vfs::Dir> {
+		Ok( self.0.into_dir()? )
+	}
 	fn into_file(self) -> ::io::Result<::syscalls::vfs::File> {
 		match self.0.into_file(::syscalls::vfs::FileOpenMode::ReadOnly)
 		{
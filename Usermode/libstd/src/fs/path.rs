@@ -56,6 +56,15 @@ impl Path
 
 		(a.as_ref(), Path::new(b))
 	}
+	/// Split the path into the parent path and the final component
+	pub fn split_off_last(&self) -> (&Path, &::std::ffi::OsStr) {
+		let (a, b) = {
+			let mut it = self.0.as_bytes().rsplitn(2, |&x| x == b'/');
+			(it.next().unwrap(), it.next().unwrap_or(&[]))
+			};
+
+		(Path::new(b), a.as_ref())
+	}
 }
 
 pub struct Display<'a>(&'a Path);
@@ -121,3 +121,5 @@ pub mod os;
 
 pub mod heap;
 
+pub mod thread;
+
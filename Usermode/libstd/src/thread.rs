@@ -0,0 +1,276 @@
+// Tifflin OS - Standard Library (clone)
+// - By John Hodge (thePowersGang)
+//
+// thread.rs
+//! Native threads
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::any::Any;
+use core::sync::atomic::{AtomicUsize,AtomicPtr,Ordering};
+
+/// Size of the stack given to spawned threads
+const STACK_SIZE: usize = 256 * 1024;
+/// Size of the thread-local storage block, taken from the top of the stack's pages
+const TLS_SIZE: usize = 256;
+
+#[cfg(arch="amd64")] const PAGE_SIZE: usize = 0x1000;
+#[cfg(arch="armv7")] const PAGE_SIZE: usize = 0x2000;
+#[cfg(arch="armv8")] const PAGE_SIZE: usize = 0x4000;
+/// Address range reserved for thread stacks (just above the heap, see liballoc_system)
+#[cfg(arch="amd64")] const STACK_REGION: (usize,usize) = (0x7000_0000_0000, 0x7800_0000_0000);
+#[cfg(arch="armv7")] const STACK_REGION: (usize,usize) = (0x7000_0000, 0x7800_0000);
+#[cfg(arch="armv8")] const STACK_REGION: (usize,usize) = (0x7000_0000, 0x8000_0000);
+/// Each stack slot is an unmapped guard page followed by the stack
+const SLOT_SIZE: usize = PAGE_SIZE + STACK_SIZE;
+
+/// Bitmap of stack slots in use
+static S_STACK_SLOTS: [AtomicUsize; 8] = [
+	AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+	AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+	];
+/// Detached threads that were still running when their handle was dropped
+static S_DETACHED: AtomicPtr<Detached> = AtomicPtr::new(0 as *mut _);
+
+/// Result of joining a thread, the error is returned if the thread exited without producing a value
+pub type Result<T> = ::core::result::Result<T, Box<Any+Send+'static>>;
+
+/// State shared between a thread and its `JoinHandle`
+struct Packet<T>
+{
+	/// Thread's stack and TLS block (only released once the thread has terminated)
+	stack: ThreadStack,
+	/// Return value of the thread's closure, written just before the thread exits
+	result: UnsafeCell<Option<T>>,
+}
+
+/// An owned permission to join on a thread (dropping the handle detaches the thread)
+pub struct JoinHandle<T>
+{
+	thread: Option<::syscalls::threads::Thread>,
+	packet: Option<Box<Packet<T>>>,
+}
+
+/// Spawn a new thread running `f`, returning a handle that can be used to obtain the result
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+	F: FnOnce() -> T + Send + 'static,
+	T: Send + 'static
+{
+	// Release the stacks of any detached threads that have since terminated
+	reap_detached();
+
+	let stack = match ThreadStack::new()
+		{
+		Some(v) => v,
+		None => panic!("Failed to allocate a thread stack"),
+		};
+	let packet = Box::new(Packet {
+		stack: stack,
+		result: UnsafeCell::new(None),
+		});
+
+	// NOTE: The packet is boxed, so this pointer stays valid until the handle is joined (or forever, if detached)
+	let result_ptr = packet.result.get() as usize;
+	let main = move || {
+		let rv = f();
+		// SAFE: Only this thread writes to the result, and it's only read after this thread has terminated
+		unsafe { *(result_ptr as *mut Option<T>) = Some(rv); }
+		};
+	let entry = get_root(&main) as usize;
+	let main = Box::into_raw(Box::new(main));
+
+	let stack_top = packet.stack.tls_base() & !15;
+	// x86_64 expects the stack to be misaligned by a return address on function entry
+	#[cfg(arch="amd64")]
+	let stack_top = stack_top - 8;
+
+	// SAFE: Entrypoint takes ownership of `main`, and the stack and TLS block are valid for the thread's lifetime
+	let thread = match unsafe { ::syscalls::threads::start_thread(entry, stack_top, packet.stack.tls_base(), main as usize) }
+		{
+		Ok(v) => v,
+		Err(e) => panic!("Failed to start thread: error {:#x}", e),
+		};
+
+	JoinHandle {
+		thread: Some(thread),
+		packet: Some(packet),
+		}
+}
+
+/// A thread's stack (below a guard page) and TLS block, unmapped when dropped
+struct ThreadStack
+{
+	slot: usize,
+}
+impl ThreadStack
+{
+	/// Claim a free slot and map its stack, returning `None` if out of slots or memory
+	fn new() -> Option<ThreadStack>
+	{
+		let slot = match claim_stack_slot() { Some(v) => v, None => return None };
+		let rv = ThreadStack { slot: slot };
+		// SAFE: The slot's address range is reserved for this stack
+		match unsafe { ::syscalls::memory::allocate(rv.base(), STACK_SIZE / PAGE_SIZE) }
+		{
+		Ok(_) => {},
+		// - Dropping unmaps anything that was mapped, and releases the slot
+		Err(_) => return None,
+		}
+		// The TLS block starts zeroed, with its first word pointing to itself (the x86_64 TCB convention)
+		// SAFE: Just mapped, and nothing else uses this memory yet
+		unsafe { *(rv.tls_base() as *mut usize) = rv.tls_base(); }
+		Some(rv)
+	}
+
+	/// Lowest mapped address (the guard page is just below this)
+	fn base(&self) -> usize {
+		STACK_REGION.0 + self.slot * SLOT_SIZE + PAGE_SIZE
+	}
+	/// Address of the TLS block, which is also the top of the usable stack
+	fn tls_base(&self) -> usize {
+		self.base() + STACK_SIZE - TLS_SIZE
+	}
+}
+impl Drop for ThreadStack
+{
+	fn drop(&mut self)
+	{
+		for page in 0 .. STACK_SIZE / PAGE_SIZE
+		{
+			// SAFE: The owning thread has terminated (or never started), so nothing is using this memory
+			// - Errors are from pages that weren't mapped (if `new` failed part way)
+			let _ = unsafe { ::syscalls::memory::deallocate(self.base() + page * PAGE_SIZE) };
+		}
+		let bits = 8 * ::core::mem::size_of::<usize>();
+		S_STACK_SLOTS[self.slot / bits].fetch_and(!(1 << self.slot % bits), Ordering::Release);
+	}
+}
+/// Claim an unused stack slot
+fn claim_stack_slot() -> Option<usize>
+{
+	let bits = 8 * ::core::mem::size_of::<usize>();
+	let max_slots = (STACK_REGION.1 - STACK_REGION.0) / SLOT_SIZE;
+	for (i, word) in S_STACK_SLOTS.iter().enumerate()
+	{
+		let mut cur = word.load(Ordering::Relaxed);
+		while cur != !0
+		{
+			let bit = (!cur).trailing_zeros() as usize;
+			if i * bits + bit >= max_slots {
+				return None;
+			}
+			let prev = word.compare_and_swap(cur, cur | 1 << bit, Ordering::Acquire);
+			if prev == cur {
+				return Some(i * bits + bit);
+			}
+			cur = prev;
+		}
+	}
+	None
+}
+
+/// A detached thread, kept (along with its stack) until it terminates
+struct Detached
+{
+	next: *mut Detached,
+	thread: ::syscalls::threads::Thread,
+	/// Type-erased `Box<Packet<T>>`, freed by `drop_packet`
+	packet: *mut (),
+	drop_packet: unsafe fn(*mut ()),
+}
+unsafe fn drop_packet<T>(p: *mut ()) {
+	drop( Box::from_raw(p as *mut Packet<T>) );
+}
+/// Add a detached thread to the list checked by `reap_detached`
+fn push_detached(ent: Box<Detached>)
+{
+	let ent = Box::into_raw(ent);
+	let mut head = S_DETACHED.load(Ordering::Relaxed);
+	loop
+	{
+		// SAFE: This thread owns `ent` until it's published
+		unsafe { (*ent).next = head; }
+		let prev = S_DETACHED.compare_and_swap(head, ent, Ordering::Release);
+		if prev == head {
+			break ;
+		}
+		head = prev;
+	}
+}
+/// Release the stacks (and packets) of detached threads that have terminated
+fn reap_detached()
+{
+	// Take the whole list, and put back the entries that are still running
+	let mut cur = S_DETACHED.swap(0 as *mut _, Ordering::Acquire);
+	while !cur.is_null()
+	{
+		// SAFE: Entries are leaked boxes, and the list was taken so this is the only owner
+		let ent = unsafe { Box::from_raw(cur) };
+		cur = ent.next;
+		if ent.thread.exit_status().is_some() {
+			// SAFE: The thread has terminated, so nothing is using the stack or result
+			unsafe { (ent.drop_packet)(ent.packet); }
+		}
+		else {
+			push_detached(ent);
+		}
+	}
+}
+
+/// Obtain the entrypoint for a closure type (which can't be named directly)
+fn get_root<F: FnOnce()>(_: &F) -> extern "C" fn(usize)->! {
+	thread_root::<F>
+}
+/// Thread entrypoint, passed a pointer to the boxed closure
+extern "C" fn thread_root<F: FnOnce()>(arg: usize) -> !
+{
+	// SAFE: `arg` is the box leaked by `spawn`, ownership is passed to this thread
+	let f = *unsafe { Box::from_raw(arg as *mut F) };
+	f();
+	::syscalls::threads::exit_thread(0);
+}
+
+impl<T> JoinHandle<T>
+{
+	/// Wait for the thread to terminate and obtain its result
+	pub fn join(mut self) -> Result<T>
+	{
+		let thread = self.thread.take().unwrap();
+		while thread.exit_status().is_none()
+		{
+			::syscalls::threads::wait(&mut [thread.wait_terminate()], !0);
+		}
+		let packet = self.packet.take().unwrap();
+		// SAFE: The thread has terminated, so nothing else is accessing the result
+		match unsafe { (*packet.result.get()).take() }
+		{
+		Some(v) => Ok(v),
+		None => Err( Box::new(thread.exit_status().unwrap()) ),
+		}
+	}
+
+	/// Obtain a wait item that fires when the thread terminates
+	pub fn wait_terminate(&self) -> ::syscalls::WaitItem {
+		self.thread.as_ref().unwrap().wait_terminate()
+	}
+}
+impl<T> Drop for JoinHandle<T>
+{
+	fn drop(&mut self)
+	{
+		if let (Some(thread), Some(packet)) = (self.thread.take(), self.packet.take())
+		{
+			// If the thread is still running, it may still be using the stack (and will write the result)
+			// - Hand it to the detached list, which releases it once the thread terminates
+			if thread.exit_status().is_none() {
+				push_detached(Box::new(Detached {
+					next: 0 as *mut _,
+					thread: thread,
+					packet: Box::into_raw(packet) as *mut (),
+					drop_packet: drop_packet::<T>,
+					}));
+			}
+		}
+		reap_detached();
+	}
+}
@@ -0,0 +1,43 @@
+// Tifflin OS - Usermode Synchronisation
+// - By John Hodge (thePowersGang)
+//
+//! Condition variable
+use core::sync::atomic::{AtomicUsize,Ordering};
+use mutex::HeldMutex;
+
+/// Condition variable (futex sequence counter)
+pub struct Condvar
+{
+	seq: AtomicUsize,
+}
+
+impl Condvar
+{
+	pub const fn new() -> Condvar {
+		Condvar {
+			seq: ::core::sync::atomic::ATOMIC_USIZE_INIT,
+		}
+	}
+
+	/// Release the lock and sleep until notified, re-acquiring the lock before returning
+	///
+	/// NOTE: Can wake spuriously, callers should re-check their condition
+	pub fn wait<'a, T>(&self, lock: HeldMutex<'a, T>) -> HeldMutex<'a, T> {
+		// Sample the sequence number before releasing, so a notify after the release is seen
+		let seq = self.seq.load(Ordering::Acquire);
+		let mutex = lock.unlock();
+		::syscalls::sync::futex_wait(&self.seq, seq);
+		mutex.lock()
+	}
+
+	/// Wake a single waiting thread
+	pub fn notify_one(&self) {
+		self.seq.fetch_add(1, Ordering::Release);
+		::syscalls::sync::futex_wake(&self.seq, 1);
+	}
+	/// Wake all waiting threads
+	pub fn notify_all(&self) {
+		self.seq.fetch_add(1, Ordering::Release);
+		::syscalls::sync::futex_wake(&self.seq, !0);
+	}
+}
@@ -9,9 +9,11 @@ extern crate syscalls;
 
 pub use mutex::Mutex;
 pub use rwlock::RwLock;
+pub use condvar::Condvar;
 
 pub mod mutex;
 pub mod rwlock;
+pub mod condvar;
 
 pub use core::sync::atomic;
 
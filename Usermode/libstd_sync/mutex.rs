@@ -58,6 +58,13 @@ pub struct HeldMutex<'a, T: 'a>
 {
 	ptr: &'a Mutex<T>,
 }
+impl<'a, T: 'a> HeldMutex<'a, T>
+{
+	/// Release the lock, returning the mutex (used by Condvar to re-acquire)
+	pub fn unlock(self) -> &'a Mutex<T> {
+		self.ptr
+	}
+}
 
 impl<'a, T: 'a> ops::Deref for HeldMutex<'a, T> {
 	type Target = T;
@@ -5,10 +5,13 @@
 use core::ops;
 use core::cell::UnsafeCell;
 use mutex::Mutex;
+use condvar::Condvar;
 
 pub struct RwLock<T: ?Sized>
 {
 	int: ::mutex::Mutex<Inner>,
+	/// Signalled when the lock is released
+	cv: Condvar,
 	data: UnsafeCell<T>,
 }
 unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
@@ -28,6 +31,7 @@ impl<T> RwLock<T>
 				readers: 0,
 				writers: 0,
 				}),
+			cv: Condvar::new(),
 			data: UnsafeCell::new(v),
 			}
 	}
@@ -36,35 +40,20 @@ impl<T> RwLock<T>
 impl<T: ?Sized> RwLock<T>
 {
 	pub fn write(&self) -> Write<T> {
-		loop {
-			let mut lh = self.int.lock();
-			if lh.readers > 0 {
-				panic!("TODO: RwLock::write - wait for readers to release");
-			}
-			else {
-				lh.writers += 1;
-				if lh.writers > 1 {
-					panic!("TODO: RwLock::write - wait for other writer to release");
-				}
-				return Write { p: self };
-			}
+		let mut lh = self.int.lock();
+		while lh.readers > 0 || lh.writers > 0 {
+			lh = self.cv.wait(lh);
 		}
+		lh.writers += 1;
+		Write { p: self }
 	}
 	pub fn read(&self) -> Read<T> {
-		loop {
-			let mut lh = self.int.lock();
-			if lh.readers > 0 {
-				lh.readers += 1;
-				return Read { p: self };
-			}
-			else if lh.writers > 0 {
-				panic!("TODO: RwLock::read - wait for writer to release");
-			}
-			else {
-				lh.readers += 1;
-				return Read { p: self };
-			}
+		let mut lh = self.int.lock();
+		while lh.writers > 0 {
+			lh = self.cv.wait(lh);
 		}
+		lh.readers += 1;
+		Read { p: self }
 	}
 
 	pub fn get_mut(&mut self) -> &mut T {
@@ -87,8 +76,9 @@ impl<'a, T: 'a + ?Sized> ops::Drop for Read<'a, T> {
 	fn drop(&mut self) {
 		let mut lh = self.p.int.lock();
 		lh.readers -= 1;
-		if lh.readers == 0 && lh.writers > 0 {
-			panic!("TODO: rwlock::Read::drop - Wake writers");
+		if lh.readers == 0 {
+			// Wake any waiting writers
+			self.p.cv.notify_all();
 		}
 	}
 }
@@ -113,15 +103,8 @@ impl<'a, T: 'a + ?Sized> ops::Drop for Write<'a, T> {
 	fn drop(&mut self) {
 		let mut lh = self.p.int.lock();
 		lh.writers -= 1;
-		if lh.writers > 0 {
-			panic!("TODO: rwlock::Write::drop - Wake writers");
-		}
-		else if lh.readers > 0 {
-			panic!("TODO: rwlock::Write::drop - Wake readers");
-		}
-		else {
-			// Uncontended release
-		}
+		// Wake all waiters (readers can all proceed, writers will race)
+		self.p.cv.notify_all();
 	}
 }
 
@@ -3,6 +3,7 @@
 //
 //! Inter-process communication
 pub use values::RpcMessage;
+use values::IpcError;
 
 pub struct RpcChannel(::ObjectHandle);
 
@@ -22,7 +23,7 @@ impl ::Object for RpcChannel
 
 	type Waits = RpcChannelWaits;
 	fn get_wait(&self, waits: Self::Waits) -> ::values::WaitItem {
-		::values::WaitItem { object: 0, flags: waits.0 }
+		self.0.get_wait(waits.0)
 	}
 	fn check_wait(&self, wi: &::values::WaitItem) -> Self::Waits {
 		RpcChannelWaits(wi.flags)
@@ -47,23 +48,44 @@ impl RpcChannel
 		}
 	}
 
-	pub fn send(&self, message: RpcMessage) {
+	/// Send a message to the other end of the channel
+	pub fn send(&self, message: RpcMessage) -> Result<(), TxError> {
 		// SAFE: Syscall
-		unsafe { self.0.call_2(::values::IPC_RPC_SEND, &message as *const _ as usize, 0); }
+		let rv = unsafe { self.0.call_2(::values::IPC_RPC_SEND, &message as *const _ as usize, 0) };
+		to_result(rv as usize).map(|_| ())
 	}
-	pub fn send_obj<T: ::Object>(&self, message: RpcMessage, object: T) {
+	/// Send a message along with an object (the object is dropped if the send fails)
+	pub fn send_obj<T: ::Object>(&self, message: RpcMessage, object: T) -> Result<(), TxError> {
+		let handle = object.into_handle();
 		// SAFE: Syscall
-		unsafe { self.0.call_2(::values::IPC_RPC_SEND, &message as *const _ as usize, object.into_handle().into_raw() as usize); }
+		let rv = unsafe { self.0.call_2(::values::IPC_RPC_SEND, &message as *const _ as usize, handle.0 as usize) };
+		match to_result(rv as usize)
+		{
+		Ok(_) => {
+			// Ownership of the handle has been passed to the channel
+			handle.into_raw();
+			Ok( () )
+			},
+		Err(e) => Err(e),
+		}
 	}
+	/// Receive a message (and attached object) if one is waiting
 	pub fn try_receive(&self) -> Result< (RpcMessage, Option<::AnyObject>), RxError> {
 		let mut msg: RpcMessage = Default::default();
 		// SAFE: Syscall
 		let rv = unsafe { self.0.call_1(::values::IPC_RPC_RECV, &mut msg as *mut _ as usize) };
-		if rv < 0x1000 {
-			Ok( (msg, if rv > 0 { Some(::AnyObject(::ObjectHandle(rv as u32))) } else { None }) )
-		}
-		else {
-			unimplemented!()
+		match super::to_result(rv as usize)
+		{
+		Ok(0) => Ok( (msg, None) ),
+		Ok(h) => Ok( (msg, Some(::AnyObject(::ObjectHandle(h)))) ),
+		Err(e) => Err(match IpcError::try_from(e)
+			{
+			Ok(IpcError::NoMessage) => RxError::NoMessage,
+			Ok(IpcError::ConnectionClosed) => RxError::ConnectionClosed,
+			Ok(IpcError::TooManyObjects) => RxError::TooManyObjects,
+			Ok(e) => panic!("RpcChannel::try_receive - Unexpected error {:?}", e),
+			Err(_) => panic!("RpcChannel::try_receive - Bad error code {}", e),
+			}),
 		}
 	}
 
@@ -72,11 +94,37 @@ impl RpcChannel
 	}
 }
 
+#[inline]
+fn to_result(val: usize) -> Result<u32, TxError> {
+	super::to_result(val).map_err(|code| match IpcError::try_from(code)
+		{
+		Ok(IpcError::ConnectionClosed) => TxError::ConnectionClosed,
+		Ok(IpcError::QueueFull) => TxError::QueueFull,
+		Ok(IpcError::InvalidObject) => TxError::InvalidObject,
+		_ => panic!("RpcChannel::send - Bad error code {}", code),
+		})
+}
+
 #[derive(Debug)]
 pub enum RxError
 {
+	/// No message is waiting
 	NoMessage,
+	/// The other end has been closed (and there are no more messages)
+	ConnectionClosed,
+	/// The attached object could not be received (message left on the queue)
+	TooManyObjects,
+}
+
+#[derive(Debug)]
+pub enum TxError
+{
+	/// The other end has been closed
 	ConnectionClosed,
+	/// Too many messages are waiting to be received by the other end
+	QueueFull,
+	/// The object to be sent was invalid
+	InvalidObject,
 }
 
 #[derive(Debug)]
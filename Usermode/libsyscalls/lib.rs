@@ -88,6 +88,8 @@ pub mod threads;
 pub mod sync;
 pub mod ipc;
 pub mod net;
+pub mod time;
+pub mod profiler;
 
 pub use values::WaitItem;
 
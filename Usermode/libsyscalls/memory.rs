@@ -34,3 +34,52 @@ pub unsafe fn deallocate(addr: usize) -> Result<(), Error> {
 		.map_err(|_| Error)
 }
 
+
+pub use ::values::MemoryError as SharedMemoryError;
+
+/// Shared memory object, can be sent to other processes and mapped into each address space
+pub struct SharedMemory(::ObjectHandle);
+impl ::Object for SharedMemory
+{
+	const CLASS: u16 = ::values::CLASS_MEM_SHM;
+	fn class() -> u16 { Self::CLASS }
+	fn from_handle(handle: ::ObjectHandle) -> Self {
+		SharedMemory(handle)
+	}
+	fn into_handle(self) -> ::ObjectHandle { self.0 }
+	fn handle(&self) -> &::ObjectHandle { &self.0 }
+
+	type Waits = ();
+}
+impl SharedMemory
+{
+	/// Create a new (zeroed) shared memory object, the size is rounded up to a whole number of pages
+	pub fn new(size: usize) -> Result<SharedMemory, SharedMemoryError> {
+		// SAFE: Syscall with no memory side-effects
+		let rv = unsafe { syscall!(MEM_SHM_NEW, size) } as usize;
+		::ObjectHandle::new(rv)
+			.map(|h| SharedMemory(h))
+			.map_err(|code| SharedMemoryError::try_from(code).expect("Bad MemoryError"))
+	}
+
+	/// Size of the object in bytes
+	pub fn size(&self) -> usize {
+		// SAFE: Syscall with no sideffects
+		unsafe { self.0.call_0(::values::MEM_SHM_GETSIZE) as usize }
+	}
+
+	/// Map `page_count` pages (starting at page `page_ofs`) of the object at `addr`
+	///
+	/// The mapping persists until the pages are released with `deallocate`
+	pub unsafe fn map(&self, page_ofs: usize, page_count: usize, addr: usize, protection: ProtectionMode) -> Result<(), SharedMemoryError> {
+		super::to_result( self.0.call_4(::values::MEM_SHM_MAP, page_ofs, page_count, addr, protection as u8 as usize) as usize )
+			.map(|_| ())
+			.map_err(|code| SharedMemoryError::try_from(code).expect("Bad MemoryError"))
+	}
+}
+impl Clone for SharedMemory
+{
+	fn clone(&self) -> SharedMemory {
+		SharedMemory( self.0.try_clone().expect("Failed to clone SharedMemory") )
+	}
+}
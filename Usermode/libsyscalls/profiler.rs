@@ -0,0 +1,35 @@
+// Tifflin OS - System Calls
+// - By John Hodge (thePowersGang)
+//
+//! Kernel sampling profiler control
+//!
+//! Only the privileged (root) process may control the profiler, calls from others fail with `Err(())`.
+
+pub use ::values::ProfileOp;
+
+/// Discard old samples and start sampling, recording up to `depth` frames per sample
+#[inline]
+pub fn start(depth: usize) -> Result<(),()> {
+	// SAFE: Syscall
+	to_unit( unsafe { syscall!(CORE_PROFILE, ProfileOp::Start as u8 as usize, depth) } as usize )
+}
+/// Stop sampling
+#[inline]
+pub fn stop() -> Result<(),()> {
+	// SAFE: Syscall
+	to_unit( unsafe { syscall!(CORE_PROFILE, ProfileOp::Stop as u8 as usize, 0) } as usize )
+}
+/// Write the collected samples to the kernel log as folded stacks (flamegraph input)
+#[inline]
+pub fn dump() -> Result<(),()> {
+	// SAFE: Syscall
+	to_unit( unsafe { syscall!(CORE_PROFILE, ProfileOp::Dump as u8 as usize, 0) } as usize )
+}
+
+fn to_unit(v: usize) -> Result<(),()> {
+	match super::to_result(v)
+	{
+	Ok(_) => Ok( () ),
+	Err(_) => Err( () ),
+	}
+}
@@ -66,6 +66,7 @@ impl<'a, T: 'a> ops::Drop for HeldMutex<'a, T>
 	}
 }
 
+/// Sleep until woken by `futex_wake`, if `addr` still contains `sleep_if_val`
 pub fn futex_wait(addr: &AtomicUsize, sleep_if_val: usize)
 {
 	// SAFE: Assumed
@@ -73,11 +74,12 @@ pub fn futex_wait(addr: &AtomicUsize, sleep_if_val: usize)
 		syscall!(CORE_FUTEX_SLEEP, addr as *const _ as usize, sleep_if_val);
 	}
 }
-pub fn futex_wake(addr: &AtomicUsize, num_to_wake: usize)
+/// Wake at most `num_to_wake` threads sleeping on `addr`, returning the number woken
+pub fn futex_wake(addr: &AtomicUsize, num_to_wake: usize) -> usize
 {
 	// SAFE: Assumed
 	unsafe {
-		syscall!(CORE_FUTEX_WAKE, addr as *const _ as usize, num_to_wake);
+		syscall!(CORE_FUTEX_WAKE, addr as *const _ as usize, num_to_wake) as usize
 	}
 }
 
@@ -3,6 +3,10 @@
 //
 //! Thread management system calls
 
+pub use ::values::ProcessStats;
+pub use ::values::ProcessResource as Resource;
+pub use ::values::ThreadPriority as Priority;
+
 #[derive(Debug)]
 pub enum RecvObjectError
 {
@@ -19,19 +23,72 @@ impl ::core::fmt::Display for RecvObjectError {
 	}
 }
 
+/// Start a new thread in this process
+///
+/// The thread starts executing at `ip` with the stack pointer set to `sp`, with `arg` as its first argument
+/// (and `tlsbase` in the architecture's TLS register).
 #[inline]
-pub unsafe fn start_thread(ip: usize, sp: usize, tlsbase: usize) -> Result<u32, u32> {
-	::to_result( syscall!(CORE_STARTTHREAD, ip, sp, tlsbase) as usize )
+pub unsafe fn start_thread(ip: usize, sp: usize, tlsbase: usize, arg: usize) -> Result<Thread, u32> {
+	::ObjectHandle::new( syscall!(CORE_STARTTHREAD, ip, sp, tlsbase, arg) as usize ).map(|h| Thread(h))
 }
+/// Terminate the current thread, passing `status` to anything waiting on it
 #[inline]
-pub fn exit_thread() -> ! {
+pub fn exit_thread(status: u32) -> ! {
 	// SAFE: Syscall
 	unsafe {
-		syscall!(CORE_EXITTHREAD);
+		syscall!(CORE_EXITTHREAD, status as usize);
 		::core::intrinsics::unreachable();
 	}
 }
 
+/// Set the current thread's scheduling priority
+#[inline]
+pub fn set_priority(prio: Priority) {
+	// SAFE: Syscall
+	unsafe { syscall!(CORE_SETPRIORITY, prio as u8 as usize); }
+}
+
+define_waits!{ ThreadWaits => (
+	terminate:get_terminate = ::values::EV_THREAD_TERMINATED,
+)}
+/// Handle to a thread in this process (dropping the handle doesn't stop the thread)
+pub struct Thread(::ObjectHandle);
+impl Thread {
+	#[inline]
+	pub fn wait_terminate(&self) -> ::values::WaitItem {
+		self.0.get_wait(::values::EV_THREAD_TERMINATED)
+	}
+
+	/// Obtain the thread's exit status (`None` if still running)
+	#[inline]
+	pub fn exit_status(&self) -> Option<u32> {
+		// SAFE: Syscall
+		match unsafe { self.0.call_0(::values::CORE_THREAD_GETEXIT) }
+		{
+		0xFFFF_FFFF_FFFF_FFFF => None,
+		v => Some(v as u32),
+		}
+	}
+
+	/// Set the thread's scheduling priority
+	#[inline]
+	pub fn set_priority(&self, prio: Priority) {
+		// SAFE: Syscall
+		unsafe { self.0.call_1(::values::CORE_THREAD_SETPRIORITY, prio as u8 as usize); }
+	}
+}
+impl ::Object for Thread {
+	const CLASS: u16 = ::values::CLASS_CORE_THREAD;
+	fn class() -> u16 { Self::CLASS }
+	fn from_handle(handle: ::ObjectHandle) -> Self {
+		Thread(handle)
+	}
+	fn into_handle(self) -> ::ObjectHandle { self.0 }
+	fn handle(&self) -> &::ObjectHandle { &self.0 }
+	
+	type Waits = ThreadWaits;
+}
+
 // Object 0 : This process
 /// Current process handle
 pub static S_THIS_PROCESS: ThisProcess = ThisProcess;//( ::ObjectHandle(0) );
@@ -64,6 +121,16 @@ impl ThisProcess
 			}
 		)
 	}
+
+	/// Obtain this process's resource usage
+	pub fn stats(&self) -> ProcessStats {
+		let mut rv = ProcessStats::default();
+		self.with_obj(|obj|
+			// SAFE: Syscall with a valid output pointer
+			unsafe { obj.call_1(::values::CORE_THISPROCESS_GETSTATS, &mut rv as *mut _ as usize); }
+		);
+		rv
+	}
 }
 impl ::Object for ThisProcess {
 	const CLASS: u16 = ::values::CLASS_CORE_THISPROCESS;
@@ -143,6 +210,38 @@ impl Process {
 	pub fn wait_terminate(&self) -> ::values::WaitItem {
 		self.0.get_wait(::values::EV_PROCESS_TERMINATED)
 	}
+
+	/// Obtain the process's exit status (`None` if still running)
+	#[inline]
+	pub fn exit_status(&self) -> Option<u32> {
+		// SAFE: Syscall
+		match unsafe { self.0.call_0(::values::CORE_PROCESS_GETEXIT) }
+		{
+		0xFFFF_FFFF_FFFF_FFFF => None,
+		v => Some(v as u32),
+		}
+	}
+
+	/// Obtain the process's resource usage
+	pub fn stats(&self) -> ProcessStats {
+		let mut rv = ProcessStats::default();
+		// SAFE: Syscall with a valid output pointer
+		unsafe { self.0.call_1(::values::CORE_PROCESS_GETSTATS, &mut rv as *mut _ as usize); }
+		rv
+	}
+
+	/// Limit the process's usage of a resource (`None` removes the limit)
+	///
+	/// Only affects later allocations, existing usage is not reduced
+	pub fn set_limit(&self, resource: Resource, limit: Option<u32>) {
+		let limit = match limit
+			{
+			Some(v) => ::core::cmp::min(v, ::values::RESOURCE_UNLIMITED - 1),
+			None => ::values::RESOURCE_UNLIMITED,
+			};
+		// SAFE: Syscall
+		unsafe { self.0.call_2(::values::CORE_PROCESS_SETLIMIT, resource as u8 as usize, limit as usize); }
+	}
 }
 impl ::Object for Process {
 	const CLASS: u16 = ::values::CLASS_CORE_PROCESS;
@@ -0,0 +1,24 @@
+// Tifflin OS - System Calls
+// - By John Hodge (thePowersGang)
+//
+//! System clocks
+
+pub use ::values::Clock;
+
+/// Read a clock (milliseconds)
+#[inline]
+pub fn get_time(clock: Clock) -> u64 {
+	// SAFE: Syscall
+	unsafe { syscall!(CORE_GETTIME, clock as u8 as usize) }
+}
+
+/// Milliseconds since system startup (the timebase for `threads::wait`)
+#[inline]
+pub fn monotonic() -> u64 {
+	get_time(Clock::Monotonic)
+}
+/// Wall-clock time, milliseconds since 1970-01-01 00:00 UTC
+#[inline]
+pub fn realtime() -> i64 {
+	get_time(Clock::Realtime) as i64
+}
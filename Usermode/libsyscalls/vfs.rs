@@ -18,6 +18,7 @@ pub use ::values::VFSError as Error;
 pub use ::values::VFSNodeType as NodeType;
 pub use ::values::VFSFileOpenMode as FileOpenMode;
 pub use ::values::VFSMemoryMapMode as MemoryMapMode;
+pub use ::values::VFSMetadata as Metadata;
 
 pub static ROOT: Dir = Dir( ::ObjectHandle(2) );
 
@@ -40,6 +41,15 @@ impl Node
 		NodeType::try_from( unsafe { self.0.call_0(::values::VFS_NODE_GETTYPE) } as u32 ).expect("Bad VFS Node Type")
 	}
 
+	/// Read the node's metadata (size, permissions, timestamps, ...)
+	#[inline]
+	pub fn get_metadata(&self) -> Metadata {
+		let mut rv = Metadata::default();
+		// SAFE: Syscall with a valid output pointer
+		unsafe { self.0.call_1(::values::VFS_NODE_GETMETA, &mut rv as *mut _ as usize); }
+		rv
+	}
+
 	/// Convert handle to a directory handle
 	#[inline]
 	pub fn into_dir(self) -> Result<Dir,Error> {
@@ -105,7 +115,16 @@ impl File
 			.map(|v| v as usize)
 	}
 	
+	/// Write bytes at the cursor (incrementing)
+	#[inline]
+	pub fn write(&mut self, data: &[u8]) -> Result<usize,Error> {
+		let count = try!( self.write_at(self.1, data) );
+		self.1 += count as u64;
+		Ok(count)
+	}
 	/// Write to an arbitary location in the file
+	///
+	/// For files opened with `FileOpenMode::Append`, the offset is ignored and data is written to the end
 	#[inline]
 	pub fn write_at(&self, ofs: u64, data: &[u8]) -> Result<usize,Error> {
 		// SAFE: All validated
@@ -170,6 +189,53 @@ impl Dir
 		Err(code) => Err( Error::try_from(code).expect("Bad VFS Error") ),
 		}
 	}
+
+	/// Create a new file in this directory, and open it with the specified mode
+	#[inline]
+	pub fn create_file<P: ?Sized+AsRef<[u8]>>(&self, name: &P, mode: FileOpenMode) -> Result<File, Error> {
+		let name = name.as_ref();
+		// SAFE: Syscall
+		to_obj( unsafe { self.0.call_3(::values::VFS_DIR_CREATEFILE, name.as_ptr() as usize, name.len(), mode as u8 as usize) } as usize )
+			.map(|h| File(h, 0))
+	}
+	/// Create a new sub-directory
+	#[inline]
+	pub fn mkdir<P: ?Sized+AsRef<[u8]>>(&self, name: &P) -> Result<Dir, Error> {
+		let name = name.as_ref();
+		// SAFE: Syscall
+		to_obj( unsafe { self.0.call_2(::values::VFS_DIR_MKDIR, name.as_ptr() as usize, name.len()) } as usize )
+			.map(|h| Dir(h))
+	}
+	/// Create a symbolic link to `target`
+	#[inline]
+	pub fn symlink<P: ?Sized+AsRef<[u8]>, T: ?Sized+AsRef<[u8]>>(&self, name: &P, target: &T) -> Result<(), Error> {
+		let name = name.as_ref();
+		let target = target.as_ref();
+		// SAFE: Syscall
+		to_result( unsafe { self.0.call_4(::values::VFS_DIR_SYMLINK, name.as_ptr() as usize, name.len(), target.as_ptr() as usize, target.len()) } as usize )
+			.map(|_| ())
+	}
+	/// Remove a name from this directory
+	#[inline]
+	pub fn unlink<P: ?Sized+AsRef<[u8]>>(&self, name: &P) -> Result<(), Error> {
+		let name = name.as_ref();
+		// SAFE: Syscall
+		to_result( unsafe { self.0.call_2(::values::VFS_DIR_UNLINK, name.as_ptr() as usize, name.len()) } as usize )
+			.map(|_| ())
+	}
+	/// Move an entry to `new_name` in `dest` (both directories must be on the same volume)
+	#[inline]
+	pub fn rename<P: ?Sized+AsRef<[u8]>, Q: ?Sized+AsRef<[u8]>>(&self, name: &P, dest: &Dir, new_name: &Q) -> Result<(), Error> {
+		let name = name.as_ref();
+		let new_name = new_name.as_ref();
+		// SAFE: Syscall
+		to_result( unsafe { self.0.call_5(::values::VFS_DIR_RENAME,
+				name.as_ptr() as usize, name.len(),
+				(dest.0).0 as usize,
+				new_name.as_ptr() as usize, new_name.len()
+				) } as usize )
+			.map(|_| ())
+	}
 }
 impl ::Object for Dir {
 	const CLASS: u16 = ::values::CLASS_VFS_DIR;
@@ -36,11 +36,11 @@ def_grp!( 0: GROUP_CORE = {
 	=2: CORE_EXITPROCESS,
 	/// Request a text string from the kernel
 	=3: CORE_TEXTINFO,
-	/// Terminate the current thread
+	/// Terminate the current thread (with an exit status)
 	=4: CORE_EXITTHREAD,
 	/// Start a new process (loader only, use loader API instead)
 	=5: CORE_STARTPROCESS,
-	/// Start a new thread in the current process
+	/// Start a new thread in the current process (entrypoint, stack, TLS base, argument), returns a CLASS_CORE_THREAD
 	=6: CORE_STARTTHREAD,
 	/// Wait for any of a set of events
 	=7: CORE_WAIT,
@@ -48,11 +48,78 @@ def_grp!( 0: GROUP_CORE = {
 	=8: CORE_FUTEX_SLEEP,
 	/// Wake a number of sleepers on a futex
 	=9: CORE_FUTEX_WAKE,
+	/// Set the current thread's scheduling priority (ThreadPriority)
+	=10: CORE_SETPRIORITY,
+	/// Read a clock (Clock), returns milliseconds
+	=11: CORE_GETTIME,
+	/// Control the kernel's sampling profiler (ProfileOp, depth), fails if the caller isn't privileged
+	=12: CORE_PROFILE,
 });
 
 /// Value for `get_text_info`'s `unit` argument, indicating kernel core
 pub const TEXTINFO_KERNEL: u32 = 0;
 
+/// Exit status of a process terminated by CORE_PROCESS_KILL
+pub const EXITSTATUS_KILLED: u32 = 0xFFFF_FFFE;
+/// Exit status of a process terminated because memory it touched couldn't be allocated
+pub const EXITSTATUS_OOM: u32 = 0xFFFF_FFFD;
+
+/// Value for "no limit" in `ProcessStats` and CORE_PROCESS_SETLIMIT
+pub const RESOURCE_UNLIMITED: u32 = !0;
+
+enum_to_from!{ ProcessResource => u8:
+	/// Memory committed by MEM_ALLOCATE (pages)
+	Pages = 0,
+	/// Open object handles
+	Objects = 1,
+	/// Threads that haven't exited
+	Threads = 2,
+}
+
+enum_to_from!{ Clock => u8:
+	/// Milliseconds since system startup (never goes backwards)
+	Monotonic = 0,
+	/// Wall-clock time, milliseconds since 1970-01-01 00:00 UTC (as a signed 64-bit value)
+	Realtime = 1,
+}
+
+enum_to_from!{ ThreadPriority => u8:
+	/// Only runs when no other thread is runnable
+	Idle = 0,
+	/// Default priority
+	Normal = 1,
+	/// Latency sensitive (e.g. the compositor), runs in preference to all normal threads
+	/// (privileged processes only, treated as `Normal` for others)
+	Realtime = 2,
+}
+
+enum_to_from!{ ProfileOp => u8:
+	/// Discard old samples and start sampling (recording up to `depth` frames per sample)
+	Start = 0,
+	/// Stop sampling
+	Stop = 1,
+	/// Write the collected samples to the kernel log as folded stacks
+	Dump = 2,
+}
+
+/// Process resource usage, as returned by CORE_PROCESS_GETSTATS and CORE_THISPROCESS_GETSTATS
+#[derive(Default,Copy,Clone,Debug)]
+#[repr(C)]
+pub struct ProcessStats
+{
+	/// CPU time used (milliseconds)
+	pub cpu_time: u64,
+	/// Committed memory (pages)
+	pub pages: u32,
+	pub pages_limit: u32,
+	/// Open object handles
+	pub objects: u32,
+	pub objects_limit: u32,
+	/// Live threads
+	pub threads: u32,
+	pub threads_limit: u32,
+}
+
 #[repr(C)]
 #[derive(Debug)]
 /// Object reference used by the CORE_WAIT system call
@@ -80,6 +147,8 @@ def_grp!( 2: GROUP_MEM = {
 	=0: MEM_ALLOCATE,
 	=1: MEM_REPROTECT,
 	=2: MEM_DEALLOCATE,
+	/// Create a shared memory object (size in bytes, rounded up to a whole number of pages)
+	=3: MEM_SHM_NEW,
 });
 
 /// Process memory management
@@ -164,6 +233,12 @@ def_classes! {
 	=1: CLASS_CORE_PROCESS = {
 		/// Request that the process be terminated
 		=0: CORE_PROCESS_KILL,
+		/// Get the process's exit status (returns !0 if still running)
+		=1: CORE_PROCESS_GETEXIT,
+		/// Read the process's resource usage (into a ProcessStats)
+		=2: CORE_PROCESS_GETSTATS,
+		/// Set a resource limit (ProcessResource, limit), RESOURCE_UNLIMITED removes the limit
+		=3: CORE_PROCESS_SETLIMIT,
 		--
 	}|{
 		/// Wakes if the child process terminates
@@ -173,12 +248,16 @@ def_classes! {
 	=2: CLASS_CORE_THISPROCESS = {
 		/// Receive a sent object
 		=0: CORE_THISPROCESS_RECVOBJ,
+		/// Read this process's resource usage (into a ProcessStats)
+		=1: CORE_THISPROCESS_GETSTATS,
 		--
 	}|{
 	},
 	/// Opened node
 	=3: CLASS_VFS_NODE = {
 		=0: VFS_NODE_GETTYPE,
+		/// Read the node's metadata (into a VFSMetadata)
+		=1: VFS_NODE_GETMETA,
 		--
 		=0: VFS_NODE_TOFILE,
 		=1: VFS_NODE_TODIR,
@@ -206,6 +285,16 @@ def_classes! {
 		=1: VFS_DIR_OPENCHILD,
 		/// Open a sub-path
 		=2: VFS_DIR_OPENPATH,
+		/// Create a new file (and open it with the provided mode)
+		=3: VFS_DIR_CREATEFILE,
+		/// Create a new sub-directory
+		=4: VFS_DIR_MKDIR,
+		/// Create a new symbolic link
+		=5: VFS_DIR_SYMLINK,
+		/// Remove a name from the directory
+		=6: VFS_DIR_UNLINK,
+		/// Move an entry to a new name/directory (within the same mount)
+		=7: VFS_DIR_RENAME,
 		--
 	}|{
 	},
@@ -262,13 +351,13 @@ def_classes! {
 
 	/// Remote procedure call channel
 	=10: CLASS_IPC_RPC = {
-		/// Send a message over the channel (RpcMessage, limited size)
+		/// Send a message over the channel (RpcMessage, optional object handle), fails with IpcError
 		=0: IPC_RPC_SEND,
-		/// Receive a message
+		/// Receive a message, returns the attached object handle (or 0) or IpcError
 		=1: IPC_RPC_RECV,
 	--
 	}|{
-		/// Fires when the channel has a message waiting
+		/// Fires when the channel has a message waiting (or the other end is closed)
 		=0: EV_IPC_RPC_RECV,
 	},
 
@@ -299,6 +388,26 @@ def_classes! {
 	--
 	}|{
 	},
+	/// Shared memory object (mappable into multiple address spaces)
+	=14: CLASS_MEM_SHM = {
+		/// Get the size of the object in bytes
+		=0: MEM_SHM_GETSIZE,
+		/// Map a range of pages into the current address space (page offset, page count, address, ProtectionMode)
+		=1: MEM_SHM_MAP,
+	--
+	}|{
+	},
+	/// Handle to a thread within this process
+	=15: CLASS_CORE_THREAD = {
+		/// Get the thread's exit status (returns !0 if still running)
+		=0: CORE_THREAD_GETEXIT,
+		/// Set the thread's scheduling priority (ThreadPriority)
+		=1: CORE_THREAD_SETPRIORITY,
+		--
+	}|{
+		/// Wakes when the thread terminates
+		=0: EV_THREAD_TERMINATED,
+	},
 /*
 	/// A registered read/write buffer
 	=12: CLASS_BUFFER = {
@@ -357,6 +466,15 @@ enum_to_from!{ VFSError => u32:
 	PermissionDenied = 2,
 	FileLocked = 3,
 	MalformedPath = 4,
+	AlreadyExists = 5,
+	NotEmpty = 6,
+	InvalidParameter = 7,
+	ReadOnlyFilesystem = 8,
+	OutOfSpace = 9,
+	OutOfMemory = 10,
+	IoError = 11,
+	TransientError = 12,
+	Unknown = 13,
 }
 enum_to_from!{ VFSNodeType => u32:
 	File = 0,
@@ -372,6 +490,27 @@ enum_to_from!{ VFSFileOpenMode => u8:
 	Append   = 5,
 	Unsynch  = 6,
 }
+/// Node metadata, as returned by VFS_NODE_GETMETA
+#[derive(Default,Copy,Clone,Debug)]
+#[repr(C)]
+pub struct VFSMetadata
+{
+	/// Size in bytes
+	pub size: u64,
+	/// Creation time (milliseconds since 1970-01-01 00:00 UTC, zero if unknown)
+	pub created: i64,
+	/// Last modification time
+	pub modified: i64,
+	/// Last access time
+	pub accessed: i64,
+	pub uid: u32,
+	pub gid: u32,
+	pub link_count: u32,
+	/// UNIX-style permission bits
+	pub mode: u16,
+	/// Node type (a VFSNodeType value)
+	pub class: u16,
+}
 
 enum_to_from!{ VFSMemoryMapMode => u8:
 	// /// Read-only mapping of a file
@@ -385,6 +524,18 @@ enum_to_from!{ VFSMemoryMapMode => u8:
 }
 
 
+enum_to_from!{ MemoryError => u32:
+	/// An invalid size/offset/address was passed
+	InvalidParameter = 0,
+	/// The requested address range is already in use
+	RangeInUse = 1,
+	OutOfMemory = 2,
+	/// No free object slots in this process
+	TooManyObjects = 3,
+	/// The process's memory limit would be exceeded
+	LimitExceeded = 4,
+}
+
 enum_to_from!{ GuiWinFlag => u8:
 	Visible = 0,
 	Maximised = 1,
@@ -495,6 +646,19 @@ pub enum GuiEvent
 
 pub type RpcMessage = [u8; 32];
 
+enum_to_from!{ IpcError => u32:
+	/// No message waiting
+	NoMessage = 0,
+	/// The other end of the channel has been dropped
+	ConnectionClosed = 1,
+	/// The receiver's message queue is full
+	QueueFull = 2,
+	/// The object to send was invalid (or couldn't be moved)
+	InvalidObject = 3,
+	/// Receiving process has no free object slots
+	TooManyObjects = 4,
+}
+
 // --------------------------------------------------------------------
 // Network
 // --------------------------------------------------------------------